@@ -1,4 +1,5 @@
 # Adds 5 + 8 and dumps the contents of the VM for inspection.
+;; expect-stack: 13
 
 main:
   PUSH 5
@@ -1,4 +1,5 @@
 # Counts from 3 to 0, printing debug info each time.
+;; expect-stack: 0
 
 main:
   PUSH 3
@@ -1,4 +1,5 @@
 # Calculates fibonacci(10) with recursion.
+;; expect-stack: 89
 
 main:
   PUSH 10
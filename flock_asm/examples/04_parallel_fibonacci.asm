@@ -1,4 +1,5 @@
 # Calculates fibonacci(N) with parallel recursion.
+;; expect-stack: 165580141
 
 main:
   ; This number is large enough that the calculation takes a while with a single core.
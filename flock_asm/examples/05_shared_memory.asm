@@ -1,5 +1,8 @@
 ; This program spawns many tasks that all check the memory value is as expected.
 ; Unset memory is 0 by default, so this program will panic if the value is 0.
+;; expect-stack: 0 0
+;; expect-memory: 0x0 = 42
+;; expect-memory: 0x1 = 1000
 
 value = 0x0
 task_list_size = 0x1
@@ -4,7 +4,7 @@ set -eo pipefail
 
 cd "$(dirname "$0")"/..
 
-cargo build --release
+cargo build --release -p flock_vm
 for file in examples/*.asm; do
-  RUST_BACKRACE=1 cargo run --release -- $file
+  RUST_BACKRACE=1 cargo run --release -p flock_vm --bin flock_asm -- --check $file
 done
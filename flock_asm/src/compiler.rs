@@ -1,38 +1,93 @@
-use flock_bytecode::{ByteCode, ConditionFlags, OpCode};
-use std::collections::HashMap;
+use flock_bytecode::{spec, Arena, ByteCode, ConditionFlags, OpCode, PackedWidth, Retry};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 
-use crate::statement::{Argument, Statement};
+use crate::parser::literal_number;
+use crate::statement::{Argument, Operator, Spanned, Statement};
 
 type LabelTable<'s> = HashMap<&'s str, usize>;
 type OpCodeThunk<'s> = dyn FnOnce(&LabelTable<'s>) -> Result<OpCode, CompilationError> + 's;
 
-pub fn to_bytecode(statements: &[Statement]) -> Result<ByteCode, Box<dyn std::error::Error>> {
+pub fn to_bytecode(
+    statements: &[Spanned<Statement>],
+) -> Result<ByteCode, Vec<Spanned<CompilationError>>> {
     let mut thunks = Vec::new();
+    let mut errors = Vec::new();
 
     let mut label_table = HashMap::new();
+    let mut labels = BTreeMap::new();
+    let mut loop_bounds = BTreeMap::new();
+    let mut idempotent = BTreeMap::new();
+    let mut open_idempotent = None;
+    let mut retry = None;
     for statement in statements {
-        let action = compile_action(statement)?;
+        let span = statement.span;
+        let action = match compile_action(&statement.value) {
+            Ok(action) => action,
+            Err(e) => {
+                errors.push(span.wrap(e));
+                continue;
+            }
+        };
         match action {
             Some(CompileAction::OpCodeThunk(thunk)) => {
-                thunks.push(thunk);
+                thunks.push((span, thunk));
             }
             Some(CompileAction::PushOpcode(code)) => {
-                thunks.push(Box::new(|_: &_| Ok(code)) as Box<OpCodeThunk>);
+                thunks.push((span, Box::new(|_: &_| Ok(code)) as Box<OpCodeThunk>));
             }
             Some(CompileAction::RegisterLabel(label)) => {
                 label_table.insert(label, thunks.len());
+                labels
+                    .entry(thunks.len())
+                    .or_insert_with(|| label.to_string());
             }
             Some(CompileAction::RegisterValue(label, value)) => {
                 label_table.insert(label, value as usize);
             }
+            Some(CompileAction::LoopBound(bound)) => {
+                loop_bounds.insert(thunks.len(), bound);
+            }
+            Some(CompileAction::StartIdempotent) => {
+                if open_idempotent.is_some() {
+                    errors.push(span.wrap(CompilationError::UnbalancedDirective(
+                        "idempotent".to_string(),
+                    )));
+                }
+                open_idempotent = Some((span, thunks.len()));
+            }
+            Some(CompileAction::EndIdempotent) => match open_idempotent.take() {
+                Some((_, start)) => {
+                    idempotent.insert(start, thunks.len());
+                }
+                None => errors.push(span.wrap(CompilationError::UnbalancedDirective(
+                    "endidempotent".to_string(),
+                ))),
+            },
+            Some(CompileAction::Retry(r)) => retry = Some(r),
             None => {}
         }
     }
-    let opcodes: Vec<OpCode> = thunks
-        .into_iter()
-        .map(move |th| th(&label_table))
-        .collect::<Result<_, _>>()?;
-    Ok(ByteCode::from(opcodes))
+    if let Some((span, _)) = open_idempotent {
+        errors.push(span.wrap(CompilationError::UnbalancedDirective(
+            "idempotent".to_string(),
+        )));
+    }
+    let mut opcodes = Vec::new();
+    for (span, thunk) in thunks {
+        match thunk(&label_table) {
+            Ok(opcode) => opcodes.push(opcode),
+            Err(e) => errors.push(span.wrap(e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(ByteCode::from(opcodes)
+        .with_loop_bounds(loop_bounds)
+        .with_idempotent(idempotent)
+        .with_retry(retry)
+        .with_labels(labels))
 }
 
 enum CompileAction<'s> {
@@ -40,6 +95,10 @@ enum CompileAction<'s> {
     OpCodeThunk(Box<OpCodeThunk<'s>>),
     RegisterLabel(&'s str),
     RegisterValue(&'s str, i64),
+    LoopBound(u64),
+    StartIdempotent,
+    EndIdempotent,
+    Retry(Retry),
 }
 
 impl<'s> From<OpCode> for CompileAction<'s> {
@@ -50,38 +109,120 @@ impl<'s> From<OpCode> for CompileAction<'s> {
 
 fn compile_action<'s>(
     statement: &'s Statement,
-) -> Result<Option<CompileAction<'s>>, Box<dyn std::error::Error>> {
+) -> Result<Option<CompileAction<'s>>, CompilationError> {
+    validate_operands(statement)?;
     let action = match statement {
         Statement::Comment(_) => return Ok(None),
         Statement::EmptyLine => return Ok(None),
         Statement::LabelDefinition(label) => CompileAction::RegisterLabel(label),
         Statement::ValueDeclaration(label, value) => CompileAction::RegisterValue(label, *value),
+        Statement::Directive("bound", Some(arg)) => {
+            let bound = nom::combinator::all_consuming(literal_number)(arg)
+                .ok()
+                .and_then(|(_, n)| u64::try_from(n).ok())
+                .ok_or_else(|| {
+                    CompilationError::InvalidDirectiveArgument("bound".to_string(), arg.to_string())
+                })?;
+            CompileAction::LoopBound(bound)
+        }
+        Statement::Directive("idempotent", None) => CompileAction::StartIdempotent,
+        Statement::Directive("endidempotent", None) => CompileAction::EndIdempotent,
+        Statement::Directive("retry", Some(arg)) => {
+            let invalid =
+                || CompilationError::InvalidDirectiveArgument("retry".to_string(), arg.to_string());
+            let mut numbers = arg.split(',').map(|n| {
+                nom::combinator::all_consuming(literal_number)(n.trim())
+                    .ok()
+                    .map(|(_, n)| n)
+            });
+            match (numbers.next(), numbers.next(), numbers.next()) {
+                (Some(Some(attempts)), Some(Some(backoff_ms)), None) => {
+                    CompileAction::Retry(Retry {
+                        attempts: u32::try_from(attempts)
+                            .ok()
+                            .filter(|&a| a > 0)
+                            .ok_or_else(invalid)?,
+                        backoff_ms: u64::try_from(backoff_ms).map_err(|_| invalid())?,
+                    })
+                }
+                _ => Err(invalid())?,
+            }
+        }
+        Statement::Directive(name, _) => Err(CompilationError::UnknownDirective(name.to_string()))?,
         Statement::Command1("PUSH", arg) => {
             thunk(move |table| Ok(OpCode::Push(resolve(arg, table)?)))
         }
+        Statement::Command1("PUSHN", _)
+        | Statement::Command2("PUSHN", _, _)
+        | Statement::CommandN("PUSHN", _) => {
+            let args = statement.arguments();
+            thunk(move |table| {
+                let values = args.into_iter().map(|a| resolve(a, table));
+                Ok(OpCode::PushN(values.collect::<Result<_, _>>()?))
+            })
+        }
+        Statement::Command1("ADDI", arg) => {
+            thunk(move |table| Ok(OpCode::AddImm(resolve(arg, table)?)))
+        }
+        Statement::Command1("MULI", arg) => {
+            thunk(move |table| Ok(OpCode::MulImm(resolve(arg, table)?)))
+        }
         Statement::Command0("ADD") => OpCode::Add.into(),
+        Statement::Command0("MUL") => OpCode::Mul.into(),
+        Statement::Command0("DIV") => OpCode::Div.into(),
+        Statement::Command0("ADD_CHECKED") => OpCode::AddChecked.into(),
+        Statement::Command0("ADD_SAT") => OpCode::AddSaturating.into(),
+        Statement::Command0("MUL_CHECKED") => OpCode::MulChecked.into(),
+        Statement::Command0("MUL_SAT") => OpCode::MulSaturating.into(),
+        Statement::Command0("MUL_WIDE") => OpCode::MulWide.into(),
+        Statement::Command0("DIV_WIDE") => OpCode::DivWide.into(),
+        Statement::Command0("AND") => OpCode::And.into(),
+        Statement::Command0("OR") => OpCode::Or.into(),
+        Statement::Command0("XOR") => OpCode::Xor.into(),
+        Statement::Command0("NOT") => OpCode::Not.into(),
+        Statement::Command0("SHL") => OpCode::Shl.into(),
+        Statement::Command0("SHR") => OpCode::Shr.into(),
+        Statement::Command0("CMP") => OpCode::Compare.into(),
+        Statement::Command0("TEST") => OpCode::Test.into(),
         Statement::Command0("DUMP_DEBUG") => OpCode::DumpDebug.into(),
         Statement::Command0("JMP") => OpCode::Jump(ConditionFlags::EMPTY, None).into(),
         Statement::Command1("JMP", Argument::LiteralStr(arg)) => {
             OpCode::Jump(parse_jump_arg(arg)?, None).into()
         }
-        Statement::Command1("JMP", ref_ @ Argument::Reference(_)) => thunk(move |table| {
-            Ok(OpCode::Jump(
-                ConditionFlags::EMPTY,
-                Some(resolve(ref_, table)?),
-            ))
-        }),
-        Statement::Command2("JMP", Argument::LiteralStr(arg), ref_ @ Argument::Reference(_)) => {
+        Statement::Command1("JMP", ref_ @ (Argument::Reference(_) | Argument::Expression(..))) => {
             thunk(move |table| {
-                let target = Some(resolve(ref_, table)?);
-                let flags = parse_jump_arg(arg)?;
-                Ok(OpCode::Jump(flags, target))
+                Ok(OpCode::Jump(
+                    ConditionFlags::EMPTY,
+                    Some(resolve(ref_, table)?),
+                ))
             })
         }
+        Statement::Command2(
+            "JMP",
+            Argument::LiteralStr(arg),
+            ref_ @ (Argument::Reference(_) | Argument::Expression(..)),
+        ) => thunk(move |table| {
+            let target = Some(resolve(ref_, table)?);
+            let flags = parse_jump_arg(arg)?;
+            Ok(OpCode::Jump(flags, target))
+        }),
         Statement::Command0("JSR") => OpCode::JumpToSubroutine(None).into(),
-        Statement::Command1("JSR", ref_ @ Argument::Reference(_)) => {
+        Statement::Command1("JSR", ref_ @ (Argument::Reference(_) | Argument::Expression(..))) => {
             thunk(move |table| Ok(OpCode::JumpToSubroutine(Some(resolve(ref_, table)?))))
         }
+        Statement::Command1("TAIL_CALL", depth) => {
+            thunk(move |table| Ok(OpCode::TailCall(resolve(depth, table)?, None)))
+        }
+        Statement::Command2(
+            "TAIL_CALL",
+            depth,
+            ref_ @ (Argument::Reference(_) | Argument::Expression(..)),
+        ) => thunk(move |table| {
+            Ok(OpCode::TailCall(
+                resolve(depth, table)?,
+                Some(resolve(ref_, table)?),
+            ))
+        }),
         Statement::Command1("BURY", arg) => {
             thunk(move |table| Ok(OpCode::Bury(resolve(arg, table)?)))
         }
@@ -90,10 +231,31 @@ fn compile_action<'s>(
         }
         Statement::Command0("DUP") => OpCode::Duplicate.into(),
         Statement::Command0("RET") => OpCode::Return.into(),
+        Statement::Command0("CALL") => OpCode::Call(None).into(),
+        Statement::Command1("CALL", ref_ @ (Argument::Reference(_) | Argument::Expression(..))) => {
+            thunk(move |table| Ok(OpCode::Call(Some(resolve(ref_, table)?))))
+        }
+        Statement::Command0("RETURN") => OpCode::CallReturn.into(),
+        // Names from `.locals` are already replaced by their indices.
+        Statement::Command1("LOAD_LOCAL" | "STORE_LOCAL", Argument::LiteralStr(name)) => {
+            Err(CompilationError::UnresolvedReference(name.to_string()))?
+        }
+        Statement::Command1("LOAD_LOCAL", arg) => {
+            thunk(move |table| Ok(OpCode::LoadLocal(resolve(arg, table)?)))
+        }
+        Statement::Command1("STORE_LOCAL", arg) => {
+            thunk(move |table| Ok(OpCode::StoreLocal(resolve(arg, table)?)))
+        }
         Statement::Command0("POP") => OpCode::Pop.into(),
         Statement::Command0("FORK") => OpCode::Fork.into(),
         Statement::Command1("JOIN", Argument::LiteralNumber(n)) => OpCode::Join(*n).into(),
+        Statement::Command1("JOIN_STATUS", Argument::LiteralNumber(n)) => {
+            OpCode::JoinStatus(*n).into()
+        }
         Statement::Command0("HALT") => OpCode::Halt.into(),
+        Statement::Command1("HALT", arg) => {
+            thunk(move |table| Ok(OpCode::HaltWith(resolve(arg, table)?)))
+        }
         Statement::Command1("STORE", arg) => {
             thunk(move |table| Ok(OpCode::Store(resolve(arg, table)? as u64)))
         }
@@ -106,12 +268,62 @@ fn compile_action<'s>(
         Statement::Command1("LOAD_REL", arg) => {
             thunk(move |table| Ok(OpCode::LoadRelative(resolve(arg, table)? as u64)))
         }
+        Statement::Command2("LOAD_PACKED", Argument::LiteralStr(width), base) => {
+            let width = parse_packed_width(width)?;
+            thunk(move |table| Ok(OpCode::LoadPacked(width, resolve(base, table)? as u64)))
+        }
+        Statement::Command2("STORE_PACKED", Argument::LiteralStr(width), base) => {
+            let width = parse_packed_width(width)?;
+            thunk(move |table| Ok(OpCode::StorePacked(width, resolve(base, table)? as u64)))
+        }
+        Statement::Command1("ALLOC_GLOBAL", arg) => {
+            thunk(move |table| Ok(OpCode::AllocGlobal(resolve(arg, table)? as u64)))
+        }
+        Statement::Command1("ALLOC", arg) => {
+            thunk(move |table| Ok(OpCode::Alloc(Arena::Shared, resolve(arg, table)? as u64)))
+        }
+        Statement::Command2("ALLOC", Argument::LiteralStr(arena), arg) => {
+            let arena = parse_arena(arena)?;
+            thunk(move |table| Ok(OpCode::Alloc(arena, resolve(arg, table)? as u64)))
+        }
+        Statement::Command0("FREE") => OpCode::Free.into(),
         Statement::Command0("PANIC") => OpCode::Panic.into(),
+        Statement::Command0("ASSERT_EQ") => OpCode::AssertEq.into(),
+        Statement::Command1("CALL_NATIVE", arg) => {
+            thunk(move |table| Ok(OpCode::CallNative(resolve(arg, table)? as u16)))
+        }
+        Statement::Command1("EXT", arg) => {
+            thunk(move |table| Ok(OpCode::Extension(resolve(arg, table)? as u16)))
+        }
+        Statement::Command1("ASSERT_DEPTH", arg) => {
+            thunk(move |table| Ok(OpCode::AssertStackDepth(resolve(arg, table)?)))
+        }
+        Statement::Command0("EMIT") => OpCode::Emit.into(),
+        Statement::Command0("IS_CHILD") => OpCode::IsChild.into(),
         s => Err(CompilationError::UnrecognizedStatement(format!("{:?}", s)))?,
     };
     Ok(Some(action))
 }
 
+pub fn validate_operands(statement: &Statement) -> Result<(), CompilationError> {
+    let (mnemonic, count) = match statement {
+        Statement::Command0(m) => (m, 0),
+        Statement::Command1(m, _) => (m, 1),
+        Statement::Command2(m, _, _) => (m, 2),
+        Statement::CommandN(m, args) => (m, args.len()),
+        _ => return Ok(()),
+    };
+    let instruction = spec::by_mnemonic(mnemonic)
+        .ok_or_else(|| CompilationError::UnknownMnemonic(mnemonic.to_string()))?;
+    if count < instruction.min_operands() || count > instruction.max_operands() {
+        return Err(CompilationError::WrongOperandCount(
+            mnemonic.to_string(),
+            count,
+        ));
+    }
+    Ok(())
+}
+
 fn thunk<'s>(
     func: impl FnOnce(&LabelTable<'s>) -> Result<OpCode, CompilationError> + 's,
 ) -> CompileAction<'s> {
@@ -125,6 +337,14 @@ fn resolve(argument: &Argument, label_table: &LabelTable) -> Result<i64, Compila
             .get(r)
             .map(|index| *index as i64)
             .ok_or(CompilationError::UnresolvedReference(r.to_string())),
+        Argument::Expression(lhs, op, rhs) => {
+            let lhs = resolve(lhs, label_table)?;
+            let rhs = resolve(rhs, label_table)?;
+            Ok(match op {
+                Operator::Add => lhs.wrapping_add(rhs),
+                Operator::Subtract => lhs.wrapping_sub(rhs),
+            })
+        }
         Argument::LiteralStr(_) => unreachable!(),
     }
 }
@@ -134,6 +354,34 @@ pub enum CompilationError {
     UnresolvedReference(String),
     UnrecognizedStatement(String),
     UnrecognizedConditionFlags(String),
+    UnknownMnemonic(String),
+    WrongOperandCount(String, usize),
+    UnbalancedDirective(String),
+    UnknownDirective(String),
+    InvalidDirectiveArgument(String, String),
+    InvalidDefine(String),
+    UnrecognizedPackedWidth(String),
+    InvalidExpectation(String),
+    UnrecognizedArena(String),
+}
+
+impl CompilationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompilationError::UnresolvedReference(_) => "E0001",
+            CompilationError::UnrecognizedStatement(_) => "E0002",
+            CompilationError::UnrecognizedConditionFlags(_) => "E0003",
+            CompilationError::UnknownMnemonic(_) => "E0004",
+            CompilationError::WrongOperandCount(_, _) => "E0005",
+            CompilationError::UnbalancedDirective(_) => "E0006",
+            CompilationError::UnknownDirective(_) => "E0007",
+            CompilationError::InvalidDirectiveArgument(_, _) => "E0008",
+            CompilationError::InvalidDefine(_) => "E0009",
+            CompilationError::UnrecognizedPackedWidth(_) => "E0010",
+            CompilationError::InvalidExpectation(_) => "E0011",
+            CompilationError::UnrecognizedArena(_) => "E0012",
+        }
+    }
 }
 
 impl std::error::Error for CompilationError {}
@@ -146,11 +394,43 @@ impl std::fmt::Display for CompilationError {
 
 // TODO(shelbyd): Don't parse in the compiler.
 fn parse_jump_arg(arg: &str) -> Result<ConditionFlags, CompilationError> {
-    arg.chars()
-        .map(|c| match c {
-            'z' => Ok(ConditionFlags::ZERO),
-            'f' => Ok(ConditionFlags::FORK),
-            c => Err(CompilationError::UnrecognizedConditionFlags(c.to_string())),
-        })
-        .fold(Ok(ConditionFlags::EMPTY), |flags, c| Ok(flags? | c?))
+    let unrecognized = || CompilationError::UnrecognizedConditionFlags(arg.to_string());
+    let mut flags = ConditionFlags::EMPTY;
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        flags |= match c {
+            'z' => ConditionFlags::ZERO,
+            'f' => ConditionFlags::FORK,
+            'n' => ConditionFlags::NEGATIVE,
+            'c' => ConditionFlags::CARRY,
+            'o' => ConditionFlags::OVERFLOW,
+            '!' => match chars.next() {
+                Some('z') => ConditionFlags::NOT_ZERO,
+                Some('f') => ConditionFlags::NOT_FORK,
+                Some('n') => ConditionFlags::NOT_NEGATIVE,
+                Some('c') => ConditionFlags::NOT_CARRY,
+                Some('o') => ConditionFlags::NOT_OVERFLOW,
+                _ => return Err(unrecognized()),
+            },
+            _ => return Err(unrecognized()),
+        };
+    }
+    Ok(flags)
+}
+
+fn parse_arena(arg: &str) -> Result<Arena, CompilationError> {
+    match arg {
+        "shared" => Ok(Arena::Shared),
+        "task" => Ok(Arena::Task),
+        _ => Err(CompilationError::UnrecognizedArena(arg.to_string())),
+    }
+}
+
+fn parse_packed_width(arg: &str) -> Result<PackedWidth, CompilationError> {
+    match arg {
+        "u8" => Ok(PackedWidth::U8),
+        "i16" => Ok(PackedWidth::I16),
+        "i32" => Ok(PackedWidth::I32),
+        _ => Err(CompilationError::UnrecognizedPackedWidth(arg.to_string())),
+    }
 }
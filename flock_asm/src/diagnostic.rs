@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+use crate::compiler::CompilationError;
+use crate::statement::{Span, Spanned};
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub file: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn compilation(file: &str, error: &Spanned<CompilationError>) -> Diagnostic {
+        Diagnostic {
+            code: error.value.code(),
+            message: error.value.to_string(),
+            file: file.to_string(),
+            span: error.span,
+        }
+    }
+
+    pub fn parse(file: &str, source: &str, remaining: &str) -> Diagnostic {
+        let line = remaining.trim_start_matches(&['\r', '\n'][..]);
+        let line = &line[..line.find(&['\r', '\n'][..]).unwrap_or(line.len())];
+        Diagnostic {
+            code: "E0000",
+            message: "Unable to parse statement".to_string(),
+            file: file.to_string(),
+            span: Span::of(source, line),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file,
+            self.span.line + 1,
+            self.span.column + 1,
+            self.code,
+            self.message
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for diagnostic in &self.0 {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
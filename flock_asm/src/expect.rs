@@ -0,0 +1,83 @@
+//! `;; expect-stack: 3 5` and `;; expect-memory: 0x10 = 42` annotations, describing what a
+//! program leaves behind so `flock_asm --check` can run it and compare.
+
+use nom::{
+    character::complete::{char, space0, space1},
+    combinator::all_consuming,
+    multi::separated_list0,
+    sequence::{delimited, separated_pair},
+};
+
+use crate::compiler::CompilationError;
+use crate::parser::literal_number;
+use crate::statement::{Spanned, Statement};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// The main task's final stack, bottom first.
+    Stack(Vec<i64>),
+    /// The value at an address once the program finishes.
+    Memory(u64, i64),
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expectation::Stack(stack) => write!(f, "stack {:?}", stack),
+            Expectation::Memory(addr, value) => write!(f, "0x{:x} = {}", addr, value),
+        }
+    }
+}
+
+/// Expectations in `;;` comments, in source order. Other comments are ignored.
+pub fn expectations(
+    statements: &[Spanned<Statement>],
+) -> Result<Vec<Spanned<Expectation>>, Vec<Spanned<CompilationError>>> {
+    let mut expectations = Vec::new();
+    let mut errors = Vec::new();
+    for statement in statements {
+        let annotation = match statement.value {
+            Statement::Comment(text) => match text.strip_prefix(';') {
+                Some(a) => a.trim(),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let (kind, arg) = match annotation.split_once(':') {
+            Some((kind, arg)) if kind.starts_with("expect-") => (kind, arg.trim()),
+            _ => continue,
+        };
+        match parse(kind, arg) {
+            Some(e) => expectations.push(statement.span.wrap(e)),
+            None => errors.push(
+                statement
+                    .span
+                    .wrap(CompilationError::InvalidExpectation(annotation.to_string())),
+            ),
+        }
+    }
+    if errors.is_empty() {
+        Ok(expectations)
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse(kind: &str, arg: &str) -> Option<Expectation> {
+    match kind {
+        "expect-stack" => {
+            let (_, stack) = all_consuming(separated_list0(space1, literal_number))(arg).ok()?;
+            Some(Expectation::Stack(stack))
+        }
+        "expect-memory" => {
+            let (_, (addr, value)) = all_consuming(separated_pair(
+                literal_number,
+                delimited(space0, char('='), space0),
+                literal_number,
+            ))(arg)
+            .ok()?;
+            Some(Expectation::Memory(addr as u64, value))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,71 @@
+pub mod compiler;
+pub mod diagnostic;
+pub mod expect;
+pub mod optimize;
+pub mod parser;
+pub mod preprocess;
+pub mod statement;
+pub mod wasm;
+
+use flock_bytecode::ByteCode;
+
+use compiler::CompilationError;
+use diagnostic::{Diagnostic, Diagnostics};
+use expect::Expectation;
+use preprocess::{Defines, Extensions};
+use statement::{Spanned, Statement};
+
+pub fn assemble(source: &str) -> Result<ByteCode, Diagnostics> {
+    assemble_with(
+        "<source>",
+        source,
+        &Defines::new(),
+        &Extensions::new(),
+        false,
+    )
+}
+
+pub fn assemble_with<'s>(
+    file: &str,
+    source: &'s str,
+    defines: &Defines<'s>,
+    extensions: &Extensions,
+    optimize: bool,
+) -> Result<ByteCode, Diagnostics> {
+    let statements = parse(file, source)?;
+    let statements = preprocess::preprocess(statements, defines)
+        .map_err(|e| Diagnostics(vec![Diagnostic::compilation(file, &e)]))?;
+    let statements = preprocess::expand_extensions(statements, extensions);
+    let statements = if optimize {
+        optimize::optimize(statements)
+    } else {
+        statements
+    };
+
+    compiler::to_bytecode(&statements).map_err(|errors| compilation(file, errors))
+}
+
+/// The `;; expect-stack:` and `;; expect-memory:` annotations in the source.
+pub fn expectations(file: &str, source: &str) -> Result<Vec<Spanned<Expectation>>, Diagnostics> {
+    let statements = parse(file, source)?;
+    expect::expectations(&statements).map_err(|errors| compilation(file, errors))
+}
+
+fn parse<'s>(file: &str, source: &'s str) -> Result<Vec<Spanned<Statement<'s>>>, Diagnostics> {
+    match parser::parse_asm(source) {
+        Ok((_, s)) => Ok(s),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            Err(Diagnostics(vec![Diagnostic::parse(file, source, e.input)]))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(Diagnostics(vec![Diagnostic::parse(file, source, "")])),
+    }
+}
+
+fn compilation(file: &str, errors: Vec<Spanned<CompilationError>>) -> Diagnostics {
+    Diagnostics(
+        errors
+            .iter()
+            .map(|e| Diagnostic::compilation(file, e))
+            .collect(),
+    )
+}
@@ -1,37 +0,0 @@
-mod compiler;
-use compiler::to_bytecode;
-
-mod parser;
-use parser::parse_asm;
-
-mod statement;
-
-type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-fn main() -> DynResult<()> {
-    pretty_env_logger::init_timed();
-    let args = gflags::parse_os();
-
-    let file_path = args
-        .get(0)
-        .ok_or("Must provide 1 positional argument as file to compile")?;
-    let contents = String::from_utf8(std::fs::read(file_path)?)?;
-
-    let asm_statements = match parse_asm(&contents) {
-        Ok(s) => s.1,
-        Err(e @ nom::Err::Error(_)) | Err(e @ nom::Err::Failure(_)) => {
-            log::error!("Parse Error:\n{:#?}", e);
-            std::process::exit(1);
-        }
-        Err(nom::Err::Incomplete(_)) => {
-            log::error!("Incomplete input");
-            std::process::exit(1);
-        }
-    };
-
-    let bytecode = to_bytecode(&asm_statements)?;
-
-    flock_vm::run(bytecode)?;
-
-    Ok(())
-}
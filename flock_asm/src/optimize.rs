@@ -0,0 +1,357 @@
+use std::convert::TryFrom;
+
+use crate::statement::{Argument, Spanned, Statement};
+
+type Statements<'s> = Vec<Spanned<Statement<'s>>>;
+
+pub fn optimize(statements: Statements) -> Statements {
+    immediates(shuffles(dead_stores(fold_constants(statements))))
+}
+
+/// Replaces `PUSH b; PUSH a; ADD` (and `MUL`, `DIV`) on literals with the result, unless the
+/// operation would overflow or divide by zero and so depend on the VM's runtime behavior.
+fn fold_constants(statements: Statements) -> Statements {
+    let mut output: Statements = Vec::new();
+    for statement in statements {
+        let op: fn(i64, i64) -> Option<i64> = match statement.value {
+            Statement::Command0("ADD") => i64::checked_add,
+            Statement::Command0("MUL") => i64::checked_mul,
+            Statement::Command0("DIV") => i64::checked_div,
+            _ => {
+                output.push(statement);
+                continue;
+            }
+        };
+        let folded = match output.as_slice() {
+            [.., b, a] => match (literal_push(&b.value), literal_push(&a.value)) {
+                (Some(b), Some(a)) => op(b, a),
+                _ => None,
+            },
+            _ => None,
+        };
+        match folded {
+            Some(value) => {
+                output.pop();
+                let span = output.pop().unwrap().span;
+                output.push(span.wrap(Statement::Command1("PUSH", Argument::LiteralNumber(value))));
+            }
+            None => output.push(statement),
+        }
+    }
+    output
+}
+
+fn literal_push(statement: &Statement) -> Option<i64> {
+    match statement {
+        Statement::Command1("PUSH", Argument::LiteralNumber(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Turns a `STORE` into a `POP` when a later `STORE` in the same block overwrites the address
+/// before anything could read it. Arithmetic that fails in between leaves the earlier value
+/// unwritten, which no task can observe.
+fn dead_stores(mut statements: Statements) -> Statements {
+    for i in 0..statements.len() {
+        let address = match &statements[i].value {
+            Statement::Command1("STORE", address) => address.clone(),
+            _ => continue,
+        };
+        let overwritten = statements[i + 1..]
+            .iter()
+            .map(|s| store_effect(&s.value, &address))
+            .find(|effect| *effect != StoreEffect::None)
+            == Some(StoreEffect::Overwrite);
+        if overwritten {
+            statements[i].value = Statement::Command0("POP");
+        }
+    }
+    statements
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum StoreEffect {
+    None,
+    Overwrite,
+    /// The address may be read, or control may leave the block.
+    Barrier,
+}
+
+fn store_effect(statement: &Statement, address: &Argument) -> StoreEffect {
+    match statement {
+        Statement::Comment(_) | Statement::EmptyLine | Statement::ValueDeclaration(..) => {
+            StoreEffect::None
+        }
+        Statement::Command1("STORE", a) if a == address => StoreEffect::Overwrite,
+        Statement::Command1("STORE", _) => StoreEffect::None,
+        Statement::Command1("LOAD", a) => match (a, address) {
+            (Argument::LiteralNumber(a), Argument::LiteralNumber(b)) if a != b => StoreEffect::None,
+            _ => StoreEffect::Barrier,
+        },
+        Statement::Command0(m) | Statement::Command1(m, _) | Statement::CommandN(m, _)
+            if PURE.contains(m) =>
+        {
+            StoreEffect::None
+        }
+        _ => StoreEffect::Barrier,
+    }
+}
+
+const PURE: &[&str] = &[
+    "PUSH",
+    "PUSHN",
+    "ADD",
+    "MUL",
+    "ADDI",
+    "MULI",
+    "DIV",
+    "ADD_CHECKED",
+    "ADD_SAT",
+    "MUL_CHECKED",
+    "MUL_SAT",
+    "MUL_WIDE",
+    "DIV_WIDE",
+    "AND",
+    "OR",
+    "XOR",
+    "NOT",
+    "SHL",
+    "SHR",
+    "DUP",
+    "POP",
+    "BURY",
+    "DREDGE",
+];
+
+/// Rewrites each straight-line run of `PUSH`/`DUP`/`POP`/`BURY`/`DREDGE` into a shorter sequence
+/// with the same effect, when one exists. Runs end at anything else, including labels, so jump
+/// targets are unaffected.
+fn shuffles(statements: Statements) -> Statements {
+    let mut output = Vec::new();
+    let mut run = Vec::new();
+    for statement in statements {
+        let trivia = matches!(
+            statement.value,
+            Statement::Comment(_) | Statement::EmptyLine
+        );
+        if Shuffle::parse(&statement.value).is_some() || (trivia && !run.is_empty()) {
+            run.push(statement);
+            continue;
+        }
+        output.extend(rewrite_run(std::mem::take(&mut run)));
+        output.push(statement);
+    }
+    output.extend(rewrite_run(run));
+    output
+}
+
+fn rewrite_run(run: Statements) -> Statements {
+    let shuffles: Vec<_> = run
+        .iter()
+        .filter_map(|s| Shuffle::parse(&s.value))
+        .collect();
+
+    let mut stack = Stack::default();
+    for shuffle in &shuffles {
+        stack.apply(shuffle);
+    }
+    let minimized = match minimize(stack.inputs, &stack.values) {
+        Some(m) if m.len() < shuffles.len() => m,
+        _ => return run,
+    };
+
+    let mut check = Stack::with_inputs(stack.inputs);
+    for shuffle in &minimized {
+        check.apply(shuffle);
+    }
+    if check.inputs != stack.inputs || check.values != stack.values {
+        return run;
+    }
+
+    let span = run[0].span;
+    minimized
+        .into_iter()
+        .map(|shuffle| span.wrap(shuffle.into_statement()))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value<'s> {
+    /// A value that was on the stack before the run, counted from the top.
+    Input(usize),
+    Const(Argument<'s>),
+}
+
+#[derive(Debug, Clone)]
+enum Shuffle<'s> {
+    Push(Argument<'s>),
+    Duplicate,
+    Pop,
+    Bury(usize),
+    Dredge(usize),
+}
+
+impl<'s> Shuffle<'s> {
+    fn parse(statement: &Statement<'s>) -> Option<Self> {
+        let depth = |n: &i64| usize::try_from(*n).ok();
+        match statement {
+            Statement::Command1("PUSH", arg) => Some(Shuffle::Push(arg.clone())),
+            Statement::Command0("DUP") => Some(Shuffle::Duplicate),
+            Statement::Command0("POP") => Some(Shuffle::Pop),
+            Statement::Command1("BURY", Argument::LiteralNumber(n)) => depth(n).map(Shuffle::Bury),
+            Statement::Command1("DREDGE", Argument::LiteralNumber(n)) => {
+                depth(n).map(Shuffle::Dredge)
+            }
+            _ => None,
+        }
+    }
+
+    fn into_statement(self) -> Statement<'s> {
+        let literal = |n: usize| Argument::LiteralNumber(n as i64);
+        match self {
+            Shuffle::Push(arg) => Statement::Command1("PUSH", arg),
+            Shuffle::Duplicate => Statement::Command0("DUP"),
+            Shuffle::Pop => Statement::Command0("POP"),
+            Shuffle::Bury(n) => Statement::Command1("BURY", literal(n)),
+            Shuffle::Dredge(n) => Statement::Command1("DREDGE", literal(n)),
+        }
+    }
+}
+
+/// Symbolic stack, bottom first, that pulls in inputs from below as the run reaches for them.
+#[derive(Default)]
+struct Stack<'s> {
+    values: Vec<Value<'s>>,
+    inputs: usize,
+}
+
+impl<'s> Stack<'s> {
+    fn with_inputs(inputs: usize) -> Self {
+        Stack {
+            values: (0..inputs).rev().map(Value::Input).collect(),
+            inputs,
+        }
+    }
+
+    fn ensure(&mut self, depth: usize) {
+        while self.values.len() < depth {
+            self.values.insert(0, Value::Input(self.inputs));
+            self.inputs += 1;
+        }
+    }
+
+    fn apply(&mut self, shuffle: &Shuffle<'s>) {
+        match shuffle {
+            Shuffle::Push(arg) => self.values.push(Value::Const(arg.clone())),
+            Shuffle::Duplicate => {
+                self.ensure(1);
+                let top = self.values[self.values.len() - 1].clone();
+                self.values.push(top);
+            }
+            Shuffle::Pop => {
+                self.ensure(1);
+                self.values.pop();
+            }
+            Shuffle::Bury(n) => {
+                self.ensure(n + 1);
+                let value = self.values.pop().unwrap();
+                self.values.insert(self.values.len() - n, value);
+            }
+            Shuffle::Dredge(n) => {
+                self.ensure(n + 1);
+                let value = self.values.remove(self.values.len() - 1 - n);
+                self.values.push(value);
+            }
+        }
+    }
+}
+
+/// Fuses `PUSH c; ADD` into `ADDI c` and `PUSH c; MUL` into `MULI c`, then each run of `PUSH`es
+/// into one `PUSHN`, so they cost a single dispatch. Only adjacent statements are fused, so jump
+/// targets are unaffected.
+fn immediates(statements: Statements) -> Statements {
+    let mut fused: Statements = Vec::new();
+    for statement in statements {
+        let op = match statement.value {
+            Statement::Command0("ADD") => "ADDI",
+            Statement::Command0("MUL") => "MULI",
+            _ => {
+                fused.push(statement);
+                continue;
+            }
+        };
+        match fused.last_mut() {
+            Some(last) if matches!(last.value, Statement::Command1("PUSH", _)) => {
+                let constant = last.value.arguments()[0].clone();
+                last.value = Statement::Command1(op, constant);
+            }
+            _ => fused.push(statement),
+        }
+    }
+
+    let mut output: Statements = Vec::new();
+    for statement in fused {
+        let pushed = match &statement.value {
+            Statement::Command1("PUSH", arg) => arg.clone(),
+            _ => {
+                output.push(statement);
+                continue;
+            }
+        };
+        match output.last_mut().map(|s| &mut s.value) {
+            Some(Statement::CommandN("PUSHN", args)) => args.push(pushed),
+            Some(last @ Statement::Command1("PUSH", _)) => {
+                let first = last.arguments()[0].clone();
+                *last = Statement::CommandN("PUSHN", vec![first, pushed]);
+            }
+            _ => output.push(statement),
+        }
+    }
+    output
+}
+
+/// Builds `target` bottom-up from `inputs` entry values, moving a value into place when no
+/// later slot needs it and copying it otherwise.
+fn minimize<'s>(inputs: usize, target: &[Value<'s>]) -> Option<Vec<Shuffle<'s>>> {
+    let mut stack = Stack::with_inputs(inputs);
+    let mut output = Vec::new();
+    let mut emit = |stack: &mut Stack<'s>, shuffle: Shuffle<'s>| {
+        stack.apply(&shuffle);
+        output.push(shuffle);
+    };
+
+    for (j, value) in target.iter().enumerate() {
+        let needed_later = target[j + 1..].iter().filter(|v| *v == value).count();
+        let available = stack.values[j..].iter().filter(|v| *v == value).count();
+        let copy = available <= needed_later;
+        if !copy && stack.values.get(j) == Some(value) {
+            continue;
+        }
+
+        match stack.values[j..].iter().rposition(|v| v == value) {
+            Some(p) => {
+                let depth = stack.values.len() - 1 - (p + j);
+                if depth > 0 {
+                    emit(&mut stack, Shuffle::Dredge(depth));
+                }
+                if copy {
+                    emit(&mut stack, Shuffle::Duplicate);
+                }
+            }
+            None => match value {
+                Value::Const(arg) => emit(&mut stack, Shuffle::Push(arg.clone())),
+                Value::Input(_) => return None,
+            },
+        }
+
+        if stack.values[j] != *value {
+            let depth = stack.values.len() - 1 - j;
+            emit(&mut stack, Shuffle::Bury(depth));
+        }
+    }
+
+    while stack.values.len() > target.len() {
+        emit(&mut stack, Shuffle::Pop);
+    }
+    Some(output)
+}
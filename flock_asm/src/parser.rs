@@ -1,43 +1,79 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while, take_while_m_n},
+    bytes::complete::{tag, take_while, take_while1, take_while_m_n},
     character::complete::{
-        alpha1, alphanumeric1, digit1, line_ending, multispace0, one_of, space0, space1,
+        alphanumeric1, digit1, line_ending, multispace0, one_of, space0, space1,
     },
     character::is_hex_digit,
-    combinator::{all_consuming, eof, map, opt, peek, recognize},
-    multi::{separated_list0, separated_list1},
+    combinator::{all_consuming, consumed, eof, map, opt, peek, recognize},
+    multi::{fold_many0, many0, many_m_n, separated_list0, separated_list1},
     sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
 
-use crate::statement::{Argument, Statement};
+use crate::statement::{Argument, Operator, Span, Spanned, Statement};
 
-pub fn parse_asm(input: &str) -> IResult<&str, Vec<Statement>> {
-    all_consuming(separated_list0(line_ending, single_statement))(input)
+pub fn parse_asm(input: &str) -> IResult<&str, Vec<Spanned<Statement>>> {
+    let source = input;
+    map(
+        all_consuming(separated_list0(line_ending, line)),
+        move |lines| {
+            lines
+                .into_iter()
+                .flatten()
+                .map(|(consumed, statement)| Span::of(source, consumed).wrap(statement))
+                .collect()
+        },
+    )(input)
+}
+
+// A `;` only starts a comment at the beginning of a line, elsewhere it separates statements.
+fn line(input: &str) -> IResult<&str, Vec<(&str, Statement)>> {
+    map(
+        tuple((
+            single_statement,
+            many0(preceded(tag(";"), separated_statement)),
+        )),
+        |(first, mut rest)| {
+            rest.insert(0, first);
+            rest
+        },
+    )(input)
 }
 
-fn single_statement(input: &str) -> IResult<&str, Statement> {
+fn single_statement(input: &str) -> IResult<&str, (&str, Statement)> {
     delimited(
         space0,
-        alt((
-            empty_line,
-            comment,
-            label_definition,
-            value_declaration,
-            command_2_arg,
-            command_1_arg,
-            command_0_arg,
-        )),
+        consumed(alt((empty_line, comment(";#"), statement))),
+        space0,
+    )(input)
+}
+
+fn separated_statement(input: &str) -> IResult<&str, (&str, Statement)> {
+    delimited(
+        space0,
+        consumed(alt((empty_line, comment("#"), statement))),
         space0,
     )(input)
 }
 
-fn comment(input: &str) -> IResult<&str, Statement> {
+fn statement(input: &str) -> IResult<&str, Statement> {
+    alt((
+        label_definition,
+        value_declaration,
+        directive,
+        command_n_arg,
+        command_2_arg,
+        command_1_arg,
+        command_0_arg,
+    ))(input)
+}
+
+fn comment<'s>(markers: &'static str) -> impl FnMut(&'s str) -> IResult<&'s str, Statement<'s>> {
     map(
-        preceded(one_of("#;"), take_while(|c| c != '\n' && c != '\r')),
+        preceded(one_of(markers), take_while(|c| c != '\n' && c != '\r')),
         |s: &str| Statement::Comment(s),
-    )(input)
+    )
 }
 
 fn empty_line(input: &str) -> IResult<&str, Statement> {
@@ -61,6 +97,19 @@ fn value_declaration(input: &str) -> IResult<&str, Statement> {
     )(input)
 }
 
+fn directive(input: &str) -> IResult<&str, Statement> {
+    map(
+        tuple((
+            preceded(tag("."), ident),
+            opt(preceded(
+                space1,
+                take_while1(|c| c != '\n' && c != '\r' && c != ';'),
+            )),
+        )),
+        |(name, arg): (&str, Option<&str>)| Statement::Directive(name, arg.map(str::trim_end)),
+    )(input)
+}
+
 fn command_0_arg(input: &str) -> IResult<&str, Statement> {
     map(tuple((multispace0, command)), |(_, command)| {
         Statement::Command0(command)
@@ -78,7 +127,7 @@ fn command(input: &str) -> IResult<&str, &str> {
     ident(input)
 }
 
-fn ident(input: &str) -> IResult<&str, &str> {
+pub fn ident(input: &str) -> IResult<&str, &str> {
     recognize(separated_list1(tag("_"), alphanumeric1))(input)
 }
 
@@ -98,14 +147,58 @@ fn command_2_arg(input: &str) -> IResult<&str, Statement> {
     )(input)
 }
 
+fn command_n_arg(input: &str) -> IResult<&str, Statement> {
+    map(
+        tuple((
+            multispace0,
+            command,
+            space1,
+            argument,
+            many_m_n(
+                2,
+                usize::MAX,
+                preceded(tuple((space0, tag(","), space0)), argument),
+            ),
+        )),
+        |(_, command, _, first, mut rest)| {
+            rest.insert(0, first);
+            Statement::CommandN(command, rest)
+        },
+    )(input)
+}
+
 fn argument(input: &str) -> IResult<&str, Argument> {
-    let literal_number = map(literal_number, |n| Argument::LiteralNumber(n));
-    let literal_str = map(alpha1, Argument::LiteralStr);
+    // `!` negates jump condition flags, digits are for packed widths like `i16`.
+    let literal_str = map(
+        take_while1(|c: char| c.is_ascii_alphanumeric() || c == '!'),
+        Argument::LiteralStr,
+    );
+    alt((expression, literal_str))(input)
+}
+
+fn expression(input: &str) -> IResult<&str, Argument> {
+    let (input, first) = term(input)?;
+    fold_many0(
+        tuple((delimited(space0, operator, space0), term)),
+        first,
+        |lhs, (op, rhs)| Argument::Expression(Box::new(lhs), op, Box::new(rhs)),
+    )(input)
+}
+
+fn term(input: &str) -> IResult<&str, Argument> {
+    let literal_number = map(literal_number, Argument::LiteralNumber);
     let reference = map(preceded(tag("$"), ident), Argument::Reference);
-    alt((literal_number, reference, literal_str))(input)
+    alt((literal_number, reference))(input)
+}
+
+fn operator(input: &str) -> IResult<&str, Operator> {
+    alt((
+        map(tag("+"), |_| Operator::Add),
+        map(tag("-"), |_| Operator::Subtract),
+    ))(input)
 }
 
-fn literal_number(input: &str) -> IResult<&str, i64> {
+pub fn literal_number(input: &str) -> IResult<&str, i64> {
     alt((hex_number, decimal_number))(input)
 }
 
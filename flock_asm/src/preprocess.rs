@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::compiler::CompilationError;
+use crate::parser::{ident, literal_number};
+use crate::statement::{Argument, Span, Spanned, Statement};
+
+pub type Defines<'s> = HashMap<&'s str, i64>;
+
+/// Host extension mnemonics and the `EXT` codes they stand for.
+pub type Extensions = HashMap<String, u16>;
+
+struct Conditional {
+    active: bool,
+    in_else: bool,
+}
+
+type Statements<'s> = Vec<Spanned<Statement<'s>>>;
+type Result<T> = std::result::Result<T, Spanned<CompilationError>>;
+
+pub fn preprocess<'s>(statements: Statements<'s>, defines: &Defines<'s>) -> Result<Statements<'s>> {
+    let statements = resolve_conditionals(statements, defines)?;
+    let statements = expand_repeats(statements, defines)?;
+    let mut output = resolve_locals(statements)?;
+    output.extend(
+        defines
+            .iter()
+            .map(|(name, value)| Span::default().wrap(Statement::ValueDeclaration(name, *value))),
+    );
+    Ok(output)
+}
+
+pub fn expand_extensions<'s>(
+    statements: Statements<'s>,
+    extensions: &Extensions,
+) -> Statements<'s> {
+    statements
+        .into_iter()
+        .map(|statement| match statement.value {
+            Statement::Command0(mnemonic) if extensions.contains_key(mnemonic) => {
+                let code = Argument::LiteralNumber(extensions[mnemonic] as i64);
+                statement.span.wrap(Statement::Command1("EXT", code))
+            }
+            _ => statement,
+        })
+        .collect()
+}
+
+pub fn parse_defines(flag: &str) -> std::result::Result<Defines<'_>, CompilationError> {
+    flag.split(',')
+        .map(|define| match define.split_once('=') {
+            None => Ok((define, 1)),
+            Some((name, value)) => match nom::combinator::all_consuming(literal_number)(value) {
+                Ok((_, n)) => Ok((name, n)),
+                Err(_) => Err(CompilationError::InvalidDefine(define.to_string())),
+            },
+        })
+        .collect()
+}
+
+fn resolve_conditionals<'s>(
+    statements: Statements<'s>,
+    defines: &Defines<'s>,
+) -> Result<Statements<'s>> {
+    let mut conditionals: Vec<(Span, Conditional)> = Vec::new();
+    let mut output = Vec::new();
+
+    for statement in statements {
+        let span = statement.span;
+        let unbalanced =
+            |name: &str| span.wrap(CompilationError::UnbalancedDirective(name.to_string()));
+        let active = conditionals.iter().all(|(_, c)| c.active);
+        match statement.value {
+            Statement::Directive("ifdef", Some(name)) => conditionals.push((
+                span,
+                Conditional {
+                    active: defines.contains_key(name),
+                    in_else: false,
+                },
+            )),
+            Statement::Directive("ifndef", Some(name)) => conditionals.push((
+                span,
+                Conditional {
+                    active: !defines.contains_key(name),
+                    in_else: false,
+                },
+            )),
+            Statement::Directive("else", None) => match conditionals.last_mut() {
+                Some((_, c)) if !c.in_else => {
+                    c.active = !c.active;
+                    c.in_else = true;
+                }
+                _ => return Err(unbalanced("else")),
+            },
+            Statement::Directive("endif", None) => {
+                conditionals.pop().ok_or_else(|| unbalanced("endif"))?;
+            }
+            value if active => output.push(span.wrap(value)),
+            _ => {}
+        }
+    }
+
+    if let Some((span, _)) = conditionals.pop() {
+        return Err(span.wrap(CompilationError::UnbalancedDirective("ifdef".to_string())));
+    }
+    Ok(output)
+}
+
+fn expand_repeats<'s>(statements: Statements<'s>, defines: &Defines<'s>) -> Result<Statements<'s>> {
+    let mut output = Vec::new();
+    let mut statements = statements.into_iter();
+
+    while let Some(statement) = statements.next() {
+        let span = statement.span;
+        let unbalanced =
+            |name: &str| span.wrap(CompilationError::UnbalancedDirective(name.to_string()));
+        let arg = match statement.value {
+            Statement::Directive("rept", Some(arg)) => arg,
+            Statement::Directive("endr", None) => return Err(unbalanced("endr")),
+            _ => {
+                output.push(statement);
+                continue;
+            }
+        };
+        let (count, counter) = parse_rept(arg, defines).map_err(|e| span.wrap(e))?;
+
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop {
+            let s = statements.next().ok_or_else(|| unbalanced("rept"))?;
+            match s.value {
+                Statement::Directive("endr", None) if depth == 0 => break,
+                Statement::Directive("rept", _) => depth += 1,
+                Statement::Directive("endr", _) => depth -= 1,
+                _ => {}
+            }
+            body.push(s);
+        }
+        let body = expand_repeats(body, defines)?;
+
+        for i in 0..count {
+            output.extend(body.iter().map(|s| match counter {
+                Some(counter) => s.span.wrap(substitute(&s.value, counter, i)),
+                None => s.clone(),
+            }));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Replaces the names a `.locals` directive gives call frame locals, in order from 0, with their
+/// indices until the next `.locals` or `.endlocals`. They're written as references, or bare as
+/// the operand of `LOAD_LOCAL` and `STORE_LOCAL`.
+fn resolve_locals(statements: Statements<'_>) -> Result<Statements<'_>> {
+    let mut locals = Vec::new();
+    let mut output = Vec::new();
+    for statement in statements {
+        let span = statement.span;
+        match statement.value {
+            Statement::Directive("locals", arg) => {
+                locals = parse_locals(arg.unwrap_or("")).map_err(|e| span.wrap(e))?;
+            }
+            Statement::Directive("endlocals", None) => locals.clear(),
+            Statement::Command1(
+                command @ ("LOAD_LOCAL" | "STORE_LOCAL"),
+                Argument::LiteralStr(name),
+            ) => {
+                let arg = match locals.iter().position(|l| *l == name) {
+                    Some(index) => Argument::LiteralNumber(index as i64),
+                    None => Argument::LiteralStr(name),
+                };
+                output.push(span.wrap(Statement::Command1(command, arg)));
+            }
+            value => {
+                let value = locals
+                    .iter()
+                    .enumerate()
+                    .fold(value, |s, (index, name)| substitute(&s, name, index as i64));
+                output.push(span.wrap(value));
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn parse_locals(arg: &str) -> std::result::Result<Vec<&str>, CompilationError> {
+    let invalid =
+        || CompilationError::InvalidDirectiveArgument("locals".to_string(), arg.to_string());
+    let mut locals: Vec<&str> = Vec::new();
+    for name in arg.split(',').map(str::trim) {
+        let valid = nom::combinator::all_consuming(ident)(name).is_ok();
+        if !valid || locals.contains(&name) {
+            return Err(invalid());
+        }
+        locals.push(name);
+    }
+    Ok(locals)
+}
+
+fn parse_rept<'s>(
+    arg: &'s str,
+    defines: &Defines<'s>,
+) -> std::result::Result<(i64, Option<&'s str>), CompilationError> {
+    let invalid =
+        || CompilationError::InvalidDirectiveArgument("rept".to_string(), arg.to_string());
+    let mut parts = arg.split_whitespace();
+    let count = match parts.next().ok_or_else(invalid)? {
+        reference if reference.starts_with('$') => {
+            *defines.get(&reference[1..]).ok_or_else(invalid)?
+        }
+        literal => {
+            nom::combinator::all_consuming(literal_number)(literal)
+                .map_err(|_| invalid())?
+                .1
+        }
+    };
+    let counter = parts.next();
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok((count, counter))
+}
+
+fn substitute<'s>(statement: &Statement<'s>, counter: &str, value: i64) -> Statement<'s> {
+    let arg = |a: &Argument<'s>| substitute_argument(a, counter, value);
+    match statement {
+        Statement::Command1(c, a) => Statement::Command1(c, arg(a)),
+        Statement::Command2(c, a, b) => Statement::Command2(c, arg(a), arg(b)),
+        Statement::CommandN(c, args) => Statement::CommandN(c, args.iter().map(arg).collect()),
+        s => s.clone(),
+    }
+}
+
+fn substitute_argument<'s>(argument: &Argument<'s>, counter: &str, value: i64) -> Argument<'s> {
+    match argument {
+        Argument::Reference(r) if *r == counter => Argument::LiteralNumber(value),
+        Argument::Expression(lhs, op, rhs) => Argument::Expression(
+            Box::new(substitute_argument(lhs, counter, value)),
+            *op,
+            Box::new(substitute_argument(rhs, counter, value)),
+        ),
+        a => a.clone(),
+    }
+}
@@ -1,18 +1,83 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum Statement<'s> {
     Comment(&'s str),
     EmptyLine,
     LabelDefinition(&'s str),
     ValueDeclaration(&'s str, i64),
+    Directive(&'s str, Option<&'s str>),
     Command0(&'s str),
     Command1(&'s str, Argument<'s>),
     Command2(&'s str, Argument<'s>, Argument<'s>),
+    /// A command with any number of operands, which the parser only produces for three or more.
+    CommandN(&'s str, Vec<Argument<'s>>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl<'s> Statement<'s> {
+    /// The command's operands, none for other statements.
+    pub fn arguments(&self) -> Vec<&Argument<'s>> {
+        match self {
+            Statement::Command1(_, a) => vec![a],
+            Statement::Command2(_, a, b) => vec![a, b],
+            Statement::CommandN(_, args) => args.iter().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Argument<'s> {
     LiteralNumber(i64),
     LiteralStr(&'s str),
     Reference(&'s str),
+    Expression(Box<Argument<'s>>, Operator, Box<Argument<'s>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn of(source: &str, slice: &str) -> Span {
+        let offset = slice.as_ptr() as usize - source.as_ptr() as usize;
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Span {
+            line: source[..offset].matches('\n').count(),
+            column: offset - line_start,
+            length: slice.len(),
+        }
+    }
+
+    pub fn wrap<T>(self, value: T) -> Spanned<T> {
+        Spanned { span: self, value }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub value: T,
 }
+
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line + 1,
+            self.span.column + 1,
+            self.value
+        )
+    }
+}
+
+impl<T: std::error::Error> std::error::Error for Spanned<T> {}
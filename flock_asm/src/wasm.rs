@@ -0,0 +1,1034 @@
+//! Lowers a program to a WebAssembly module, so a compute kernel can be checked against the VM
+//! or reused outside flock. Only programs that run as a single task translate: forking,
+//! joining, call frames, wide arithmetic, packed memory, `ALLOC_GLOBAL`, the heap and native or
+//! extension calls have no lowering.
+//!
+//! The module exports `run() -> i64`, which runs the program from its first instruction and
+//! returns the code it halts with, 0 if none or it runs off the end. Its stack lives in the
+//! exported `memory`, read back with `stack_len() -> i32` and `stack_get(i32) -> i64` from the
+//! bottom. Memory and `EMIT` are imported from the host as `flock.load(i64) -> i64`,
+//! `flock.store(i64, i64)` and `flock.emit(i64)`. Arithmetic wraps, as under the default
+//! `--int-overflow`, and anything the VM fails with traps instead.
+
+use flock_bytecode::cfg::Successor;
+use flock_bytecode::{ByteCode, ConditionFlags, OpCode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WasmError {
+    /// The instruction at this index, by mnemonic, has no lowering.
+    Unsupported(usize, &'static str),
+}
+
+impl std::fmt::Display for WasmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WasmError::Unsupported(pc, mnemonic) => {
+                write!(f, "{} at {} has no WebAssembly lowering", mnemonic, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WasmError {}
+
+/// The module's functions, imports first.
+mod func {
+    pub const LOAD: u32 = 0;
+    pub const STORE: u32 = 1;
+    pub const EMIT: u32 = 2;
+    pub const PUSH: u32 = 3;
+    pub const POP: u32 = 4;
+    pub const PEEK: u32 = 5;
+    pub const DREDGE: u32 = 6;
+    pub const BURY: u32 = 7;
+    pub const RUN: u32 = 8;
+    pub const STACK_LEN: u32 = 9;
+    pub const STACK_GET: u32 = 10;
+}
+
+/// Signatures, indexed by `TYPES`.
+const I64_TO_I64: u32 = 0;
+const I64_I64_TO_NONE: u32 = 1;
+const I64_TO_NONE: u32 = 2;
+const NONE_TO_I64: u32 = 3;
+const NONE_TO_I32: u32 = 4;
+const I32_TO_I64: u32 = 5;
+
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+const EMPTY: u8 = 0x40;
+
+const TYPES: &[(&[u8], &[u8])] = &[
+    (&[I64], &[I64]),
+    (&[I64, I64], &[]),
+    (&[I64], &[]),
+    (&[], &[I64]),
+    (&[], &[I32]),
+    (&[I32], &[I64]),
+];
+
+/// Global holding the byte offset of the top of the stack in memory.
+const SP: u32 = 0;
+
+/// Locals of `run`.
+const PC: u32 = 0;
+const A: u32 = 1;
+const B: u32 = 2;
+const R: u32 = 3;
+const CARRY: u32 = 4;
+const OVERFLOW: u32 = 5;
+
+mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0B;
+    pub const BR: u8 = 0x0C;
+    pub const BR_IF: u8 = 0x0D;
+    pub const BR_TABLE: u8 = 0x0E;
+    pub const RETURN: u8 = 0x0F;
+    pub const CALL: u8 = 0x10;
+    pub const DROP: u8 = 0x1A;
+    pub const SELECT: u8 = 0x1B;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const GLOBAL_GET: u8 = 0x23;
+    pub const GLOBAL_SET: u8 = 0x24;
+    pub const I64_LOAD: u8 = 0x29;
+    pub const I64_STORE: u8 = 0x37;
+    pub const MEMORY_SIZE: u8 = 0x3F;
+    pub const MEMORY_GROW: u8 = 0x40;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I64_CONST: u8 = 0x42;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_EQ: u8 = 0x46;
+    pub const I32_LE_U: u8 = 0x4D;
+    pub const I32_GE_U: u8 = 0x4F;
+    pub const I64_EQZ: u8 = 0x50;
+    pub const I64_EQ: u8 = 0x51;
+    pub const I64_NE: u8 = 0x52;
+    pub const I64_LT_S: u8 = 0x53;
+    pub const I64_LT_U: u8 = 0x54;
+    pub const I64_GT_S: u8 = 0x55;
+    pub const I64_GT_U: u8 = 0x56;
+    pub const I64_GE_U: u8 = 0x5A;
+    pub const I32_ADD: u8 = 0x6A;
+    pub const I32_SUB: u8 = 0x6B;
+    pub const I32_AND: u8 = 0x71;
+    pub const I32_SHL: u8 = 0x74;
+    pub const I32_SHR_U: u8 = 0x76;
+    pub const I64_ADD: u8 = 0x7C;
+    pub const I64_SUB: u8 = 0x7D;
+    pub const I64_MUL: u8 = 0x7E;
+    pub const I64_DIV_S: u8 = 0x7F;
+    pub const I64_AND: u8 = 0x83;
+    pub const I64_OR: u8 = 0x84;
+    pub const I64_XOR: u8 = 0x85;
+    pub const I64_SHL: u8 = 0x86;
+    pub const I64_SHR_U: u8 = 0x88;
+    pub const I32_WRAP_I64: u8 = 0xA7;
+    pub const I64_EXTEND_I32_S: u8 = 0xAC;
+    pub const I64_EXTEND_I32_U: u8 = 0xAD;
+}
+
+/// The program as a binary WebAssembly module.
+pub fn to_wasm(bytecode: &ByteCode) -> Result<Vec<u8>, WasmError> {
+    let run = run(bytecode)?;
+
+    let mut module = b"\0asm".to_vec();
+    module.extend(1u32.to_le_bytes());
+
+    let types = TYPES.iter().map(|(params, results)| {
+        let mut t = vec![0x60];
+        t.extend(vector(params.iter().map(|&p| vec![p])));
+        t.extend(vector(results.iter().map(|&r| vec![r])));
+        t
+    });
+    section(&mut module, 1, vector(types));
+
+    let imports = [
+        ("load", I64_TO_I64),
+        ("store", I64_I64_TO_NONE),
+        ("emit", I64_TO_NONE),
+    ]
+    .iter()
+    .map(|&(field, ty)| {
+        let mut import = name("flock");
+        import.extend(name(field));
+        import.push(0x00);
+        import.extend(uleb(ty as u64));
+        import
+    });
+    section(&mut module, 2, vector(imports));
+
+    let functions = [
+        I64_TO_NONE,
+        NONE_TO_I64,
+        NONE_TO_I64,
+        I64_TO_NONE,
+        I64_TO_NONE,
+        NONE_TO_I64,
+        NONE_TO_I32,
+        I32_TO_I64,
+    ];
+    section(
+        &mut module,
+        3,
+        vector(functions.iter().map(|&ty| uleb(ty as u64))),
+    );
+
+    // One page to start, grown by `push` as the stack needs.
+    section(&mut module, 5, vector(std::iter::once(vec![0x00, 0x01])));
+
+    let sp = vec![I32, 0x01, op::I32_CONST, 0x00, op::END];
+    section(&mut module, 6, vector(std::iter::once(sp)));
+
+    let exports = [
+        ("memory", 0x02, 0),
+        ("run", 0x00, func::RUN),
+        ("stack_len", 0x00, func::STACK_LEN),
+        ("stack_get", 0x00, func::STACK_GET),
+    ]
+    .iter()
+    .map(|&(field, kind, index)| {
+        let mut export = name(field);
+        export.push(kind);
+        export.extend(uleb(index as u64));
+        export
+    });
+    section(&mut module, 7, vector(exports));
+
+    let bodies = vec![
+        body(&[], push()),
+        body(&[], pop()),
+        body(&[], peek()),
+        body(&[(2, I32), (1, I64)], dredge()),
+        body(&[(2, I32), (1, I64)], bury()),
+        body(&[(1, I32), (3, I64), (2, I32)], run),
+        body(&[], stack_len()),
+        body(&[], stack_get()),
+    ];
+    section(&mut module, 10, vector(bodies.into_iter()));
+
+    Ok(module)
+}
+
+/// `run`: a loop around a `br_table` on `PC` into nested blocks, each followed by the code of
+/// the block of the program starting there. Straight-line code falls from one into the next,
+/// and every jump sets `PC` and goes back to the dispatch.
+fn run(bytecode: &ByteCode) -> Result<Code, WasmError> {
+    let cfg = bytecode.cfg();
+    // A target popped at runtime could be any instruction.
+    let starts: Vec<usize> = if cfg.edges.iter().any(|e| e.to == Successor::Dynamic) {
+        (0..bytecode.len()).collect()
+    } else {
+        cfg.blocks.iter().map(|b| b.start).collect()
+    };
+    let blocks = starts.len() as u32;
+    let (end, trap) = (blocks, blocks + 1);
+
+    let mut code = Code::default();
+    code.block(op::LOOP);
+    code.block(op::BLOCK);
+    code.block(op::BLOCK);
+    for _ in &starts {
+        code.block(op::BLOCK);
+    }
+    let table: Vec<u32> = (0..=bytecode.len())
+        .map(|pc| match starts.binary_search(&pc) {
+            Ok(block) => block as u32,
+            Err(_) if pc == bytecode.len() => end,
+            Err(_) => trap,
+        })
+        .collect();
+    code.local_get(PC);
+    code.push(op::BR_TABLE);
+    code.extend(uleb(table.len() as u64));
+    for label in table {
+        code.extend(uleb(label as u64));
+    }
+    code.extend(uleb(trap as u64));
+
+    for (block, &start) in starts.iter().enumerate() {
+        code.push(op::END);
+        let stop = starts
+            .get(block + 1)
+            .cloned()
+            .unwrap_or_else(|| bytecode.len());
+        let mut lowering = Lowering {
+            code: &mut code,
+            bytecode,
+            dispatch: blocks - 1 - block as u32 + 2,
+        };
+        for pc in start..stop {
+            lowering.instruction(pc, bytecode.get(pc).unwrap())?;
+        }
+    }
+    code.push(op::END);
+    code.i64_const(0);
+    code.push(op::RETURN);
+    code.push(op::END);
+    code.push(op::UNREACHABLE);
+    code.push(op::END);
+    code.push(op::UNREACHABLE);
+    Ok(code)
+}
+
+struct Lowering<'c> {
+    code: &'c mut Code,
+    bytecode: &'c ByteCode,
+    /// Depth of the dispatch loop from the code being written.
+    dispatch: u32,
+}
+
+/// Where a jump goes.
+enum Target {
+    Static(i64),
+    /// Popped into `A`.
+    Popped,
+}
+
+impl Lowering<'_> {
+    fn instruction(&mut self, pc: usize, opcode: &OpCode) -> Result<(), WasmError> {
+        let code = &mut *self.code;
+        match opcode {
+            OpCode::Push(v) => {
+                code.i64_const(*v);
+                code.call(func::PUSH);
+            }
+            OpCode::PushN(values) => {
+                for v in values {
+                    code.i64_const(*v);
+                    code.call(func::PUSH);
+                }
+            }
+            OpCode::Add | OpCode::Mul => {
+                code.pop_a_b();
+                code.local_get(B);
+                code.local_get(A);
+                code.push(match opcode {
+                    OpCode::Add => op::I64_ADD,
+                    _ => op::I64_MUL,
+                });
+                code.call(func::PUSH);
+            }
+            OpCode::AddImm(v) | OpCode::MulImm(v) => {
+                code.call(func::POP);
+                code.i64_const(*v);
+                code.push(match opcode {
+                    OpCode::AddImm(_) => op::I64_ADD,
+                    _ => op::I64_MUL,
+                });
+                code.call(func::PUSH);
+            }
+            OpCode::AddChecked => {
+                code.pop_a_b();
+                code.add_overflow();
+                code.local_set(OVERFLOW);
+                code.local_get(R);
+                code.call(func::PUSH);
+                code.local_get(OVERFLOW);
+                code.push(op::I64_EXTEND_I32_U);
+                code.call(func::PUSH);
+            }
+            OpCode::AddSaturating => {
+                code.pop_a_b();
+                code.add_overflow();
+                code.local_set(OVERFLOW);
+                // Both operands have the sign the result saturates towards.
+                code.saturated(B);
+                code.local_get(R);
+                code.local_get(OVERFLOW);
+                code.push(op::SELECT);
+                code.call(func::PUSH);
+            }
+            OpCode::MulChecked => {
+                code.pop_a_b();
+                code.mul_overflow();
+                code.local_set(OVERFLOW);
+                code.local_get(R);
+                code.call(func::PUSH);
+                code.local_get(OVERFLOW);
+                code.push(op::I64_EXTEND_I32_U);
+                code.call(func::PUSH);
+            }
+            OpCode::MulSaturating => {
+                code.pop_a_b();
+                code.mul_overflow();
+                code.local_set(OVERFLOW);
+                // Towards the sign the product would have, negative when the operands differ.
+                code.local_get(A);
+                code.local_get(B);
+                code.push(op::I64_XOR);
+                code.local_set(A);
+                code.saturated(A);
+                code.local_get(R);
+                code.local_get(OVERFLOW);
+                code.push(op::SELECT);
+                code.call(func::PUSH);
+            }
+            OpCode::Div => {
+                // Traps dividing by zero as the VM fails, but `div_s` would trap on `i64::MIN / -1`
+                // too, which wraps.
+                code.pop_a_b();
+                code.local_get(A);
+                code.i64_const(-1);
+                code.push(op::I64_EQ);
+                code.push(op::IF);
+                code.push(I64);
+                code.i64_const(0);
+                code.local_get(B);
+                code.push(op::I64_SUB);
+                code.push(op::ELSE);
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_DIV_S);
+                code.push(op::END);
+                code.call(func::PUSH);
+            }
+            OpCode::And | OpCode::Or | OpCode::Xor | OpCode::Test => {
+                code.pop_a_b();
+                code.local_get(B);
+                code.local_get(A);
+                code.push(match opcode {
+                    OpCode::Or => op::I64_OR,
+                    OpCode::Xor => op::I64_XOR,
+                    _ => op::I64_AND,
+                });
+                code.call(func::PUSH);
+                if let OpCode::Test = opcode {
+                    code.i32_const(0);
+                    code.local_set(CARRY);
+                    code.i32_const(0);
+                    code.local_set(OVERFLOW);
+                }
+            }
+            OpCode::Not => {
+                code.call(func::POP);
+                code.i64_const(-1);
+                code.push(op::I64_XOR);
+                code.call(func::PUSH);
+            }
+            OpCode::Shl | OpCode::Shr => {
+                code.call(func::POP);
+                code.local_tee(A);
+                code.i64_const(64);
+                code.push(op::I64_GE_U);
+                code.trap_if();
+                code.call(func::POP);
+                code.local_get(A);
+                code.push(match opcode {
+                    OpCode::Shl => op::I64_SHL,
+                    _ => op::I64_SHR_U,
+                });
+                code.call(func::PUSH);
+            }
+            OpCode::Compare => {
+                code.pop_a_b();
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_LT_U);
+                code.local_set(CARRY);
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_SUB);
+                code.local_set(R);
+                // Operands of different signs, and a result with a different sign from `b`.
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_XOR);
+                code.local_get(B);
+                code.local_get(R);
+                code.push(op::I64_XOR);
+                code.push(op::I64_AND);
+                code.i64_const(0);
+                code.push(op::I64_LT_S);
+                code.local_set(OVERFLOW);
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_GT_S);
+                code.local_get(B);
+                code.local_get(A);
+                code.push(op::I64_LT_S);
+                code.push(op::I32_SUB);
+                code.push(op::I64_EXTEND_I32_S);
+                code.call(func::PUSH);
+            }
+            // Only pauses the VM to print where it is.
+            OpCode::DumpDebug => {}
+            OpCode::Jump(flags, target) => {
+                let target = self.target(*target);
+                if flags.is_empty() {
+                    self.jump(target, 0);
+                } else {
+                    self.condition(*flags);
+                    self.code.block(op::IF);
+                    self.jump(target, 1);
+                    self.code.push(op::END);
+                }
+            }
+            OpCode::JumpToSubroutine(target) => {
+                let target = self.target(*target);
+                self.code.i64_const(pc as i64 + 1);
+                self.code.call(func::PUSH);
+                self.jump(target, 0);
+            }
+            OpCode::TailCall(depth, target) => {
+                let target = self.target(*target);
+                self.code.i64_const(*depth);
+                self.code.call(func::DREDGE);
+                self.jump(target, 0);
+            }
+            OpCode::Return => {
+                let target = self.target(None);
+                self.jump(target, 0);
+            }
+            OpCode::Bury(depth) => {
+                code.i64_const(*depth);
+                code.call(func::BURY);
+            }
+            OpCode::Dredge(depth) => {
+                code.i64_const(*depth);
+                code.call(func::DREDGE);
+            }
+            OpCode::Duplicate => {
+                code.call(func::PEEK);
+                code.call(func::PUSH);
+            }
+            OpCode::Pop => {
+                code.call(func::POP);
+                code.push(op::DROP);
+            }
+            // Never forked, so always the parent.
+            OpCode::IsChild => {
+                code.i64_const(0);
+                code.call(func::PUSH);
+            }
+            OpCode::Halt | OpCode::HaltWith(_) => {
+                let halt = match opcode {
+                    OpCode::HaltWith(c) => *c,
+                    _ => 0,
+                };
+                code.i64_const(halt);
+                code.push(op::RETURN);
+            }
+            OpCode::Store(addr) => {
+                code.call(func::POP);
+                code.local_set(A);
+                code.i64_const(*addr as i64);
+                code.local_get(A);
+                code.call(func::STORE);
+            }
+            OpCode::StoreRelative(base) => {
+                code.call(func::POP);
+                code.i64_const(*base as i64);
+                code.push(op::I64_ADD);
+                code.local_set(A);
+                code.call(func::POP);
+                code.local_set(B);
+                code.local_get(A);
+                code.local_get(B);
+                code.call(func::STORE);
+            }
+            OpCode::Load(addr) => {
+                code.i64_const(*addr as i64);
+                code.call(func::LOAD);
+                code.call(func::PUSH);
+            }
+            OpCode::LoadRelative(base) => {
+                code.call(func::POP);
+                code.i64_const(*base as i64);
+                code.push(op::I64_ADD);
+                code.call(func::LOAD);
+                code.call(func::PUSH);
+            }
+            OpCode::Emit => {
+                code.call(func::POP);
+                code.call(func::EMIT);
+            }
+            OpCode::AssertEq => {
+                code.call(func::POP);
+                code.call(func::POP);
+                code.push(op::I64_NE);
+                code.trap_if();
+            }
+            OpCode::AssertStackDepth(depth) => {
+                code.stack_len();
+                code.push(op::I64_EXTEND_I32_U);
+                code.i64_const(*depth);
+                code.push(op::I64_NE);
+                code.trap_if();
+            }
+            OpCode::Panic => code.push(op::UNREACHABLE),
+            op => return Err(WasmError::Unsupported(pc, op.instruction().mnemonic)),
+        }
+        Ok(())
+    }
+
+    fn target(&mut self, target: Option<i64>) -> Target {
+        match target {
+            Some(t) => Target::Static(t),
+            None => {
+                self.code.call(func::POP);
+                self.code.local_set(A);
+                Target::Popped
+            }
+        }
+    }
+
+    /// Continues at `target`, from `nesting` blocks inside the current instruction.
+    fn jump(&mut self, target: Target, nesting: u32) {
+        let code = &mut *self.code;
+        match target {
+            Target::Static(t) if self.bytecode.in_bounds(t) => code.i32_const(t as i32),
+            Target::Static(_) => {
+                code.push(op::UNREACHABLE);
+                return;
+            }
+            Target::Popped => {
+                code.local_get(A);
+                code.i64_const(self.bytecode.len() as i64);
+                code.push(op::I64_GT_U);
+                code.trap_if();
+                code.local_get(A);
+                code.push(op::I32_WRAP_I64);
+            }
+        }
+        code.local_set(PC);
+        code.push(op::BR);
+        code.extend(uleb((self.dispatch + nesting) as u64));
+    }
+
+    /// Leaves whether `JMP` with `flags` jumps as an i32.
+    fn condition(&mut self, flags: ConditionFlags) {
+        let code = &mut *self.code;
+        let on_top = ConditionFlags::ZERO
+            | ConditionFlags::NOT_ZERO
+            | ConditionFlags::NEGATIVE
+            | ConditionFlags::NOT_NEGATIVE;
+        if flags.intersects(on_top) {
+            code.call(func::PEEK);
+            code.local_set(R);
+        }
+        code.i32_const(1);
+        // Each flag is a condition and its negation, which holds when it doesn't.
+        let conditions = [
+            (ConditionFlags::ZERO, ConditionFlags::NOT_ZERO),
+            (ConditionFlags::NEGATIVE, ConditionFlags::NOT_NEGATIVE),
+            (ConditionFlags::CARRY, ConditionFlags::NOT_CARRY),
+            (ConditionFlags::OVERFLOW, ConditionFlags::NOT_OVERFLOW),
+            (ConditionFlags::FORK, ConditionFlags::NOT_FORK),
+        ];
+        for &(holds, negated) in &conditions {
+            for &flag in [holds, negated].iter().filter(|&&f| flags.contains(f)) {
+                match holds {
+                    ConditionFlags::ZERO => {
+                        code.local_get(R);
+                        code.push(op::I64_EQZ);
+                    }
+                    ConditionFlags::NEGATIVE => {
+                        code.local_get(R);
+                        code.i64_const(0);
+                        code.push(op::I64_LT_S);
+                    }
+                    ConditionFlags::CARRY => code.local_get(CARRY),
+                    ConditionFlags::OVERFLOW => code.local_get(OVERFLOW),
+                    // Never forked.
+                    _ => code.i32_const(0),
+                }
+                if flag == negated {
+                    code.push(op::I32_EQZ);
+                }
+                code.push(op::I32_AND);
+            }
+        }
+    }
+}
+
+/// `push(value)`, growing memory a page at a time.
+fn push() -> Code {
+    let mut code = Code::default();
+    code.global_get(SP);
+    code.push(op::MEMORY_SIZE);
+    code.push(0x00);
+    code.i32_const(16);
+    code.push(op::I32_SHL);
+    code.push(op::I32_GE_U);
+    code.block(op::IF);
+    code.i32_const(1);
+    code.push(op::MEMORY_GROW);
+    code.push(0x00);
+    code.i32_const(-1);
+    code.push(op::I32_EQ);
+    code.trap_if();
+    code.push(op::END);
+    code.global_get(SP);
+    code.local_get(0);
+    code.i64_store(0);
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_ADD);
+    code.global_set(SP);
+    code
+}
+
+fn pop() -> Code {
+    let mut code = Code::default();
+    code.global_get(SP);
+    code.push(op::I32_EQZ);
+    code.trap_if();
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.global_set(SP);
+    code.global_get(SP);
+    code.i64_load(0);
+    code
+}
+
+fn peek() -> Code {
+    let mut code = Code::default();
+    code.global_get(SP);
+    code.push(op::I32_EQZ);
+    code.trap_if();
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.i64_load(0);
+    code
+}
+
+/// `dredge(depth)`, with locals `position` and `value`.
+fn dredge() -> Code {
+    let (depth, position, value) = (0, 1, 3);
+    let mut code = Code::default();
+    code.local_get(depth);
+    code.stack_len();
+    code.push(op::I64_EXTEND_I32_U);
+    code.push(op::I64_GE_U);
+    code.trap_if();
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.local_get(depth);
+    code.push(op::I32_WRAP_I64);
+    code.i32_const(3);
+    code.push(op::I32_SHL);
+    code.push(op::I32_SUB);
+    code.local_tee(position);
+    code.i64_load(0);
+    code.local_set(value);
+    // Moves everything above it down one.
+    code.block(op::BLOCK);
+    code.block(op::LOOP);
+    code.local_get(position);
+    code.i32_const(8);
+    code.push(op::I32_ADD);
+    code.global_get(SP);
+    code.push(op::I32_GE_U);
+    code.push(op::BR_IF);
+    code.push(1);
+    code.local_get(position);
+    code.local_get(position);
+    code.i64_load(8);
+    code.i64_store(0);
+    code.local_get(position);
+    code.i32_const(8);
+    code.push(op::I32_ADD);
+    code.local_set(position);
+    code.push(op::BR);
+    code.push(0);
+    code.push(op::END);
+    code.push(op::END);
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.local_get(value);
+    code.i64_store(0);
+    code
+}
+
+/// `bury(depth)`, with locals `position`, `bottom` and `value`.
+fn bury() -> Code {
+    let (depth, position, bottom, value) = (0, 1, 2, 3);
+    let mut code = Code::default();
+    code.call(func::POP);
+    code.local_set(value);
+    code.local_get(depth);
+    code.stack_len();
+    code.push(op::I64_EXTEND_I32_U);
+    code.push(op::I64_GT_U);
+    code.trap_if();
+    code.local_get(value);
+    code.call(func::PUSH);
+    code.global_get(SP);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.local_tee(position);
+    code.local_get(depth);
+    code.push(op::I32_WRAP_I64);
+    code.i32_const(3);
+    code.push(op::I32_SHL);
+    code.push(op::I32_SUB);
+    code.local_set(bottom);
+    // Moves everything from there up one.
+    code.block(op::BLOCK);
+    code.block(op::LOOP);
+    code.local_get(position);
+    code.local_get(bottom);
+    code.push(op::I32_LE_U);
+    code.push(op::BR_IF);
+    code.push(1);
+    code.local_get(position);
+    code.local_get(position);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.i64_load(0);
+    code.i64_store(0);
+    code.local_get(position);
+    code.i32_const(8);
+    code.push(op::I32_SUB);
+    code.local_set(position);
+    code.push(op::BR);
+    code.push(0);
+    code.push(op::END);
+    code.push(op::END);
+    code.local_get(bottom);
+    code.local_get(value);
+    code.i64_store(0);
+    code
+}
+
+fn stack_len() -> Code {
+    let mut code = Code::default();
+    code.stack_len();
+    code
+}
+
+fn stack_get() -> Code {
+    let mut code = Code::default();
+    code.local_get(0);
+    code.stack_len();
+    code.push(op::I32_GE_U);
+    code.trap_if();
+    code.local_get(0);
+    code.i32_const(3);
+    code.push(op::I32_SHL);
+    code.i64_load(0);
+    code
+}
+
+/// An expression being written.
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn push(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    fn extend(&mut self, bytes: Vec<u8>) {
+        self.0.extend(bytes);
+    }
+
+    /// Opens a `block`, `loop` or `if` without a result.
+    fn block(&mut self, kind: u8) {
+        self.push(kind);
+        self.push(EMPTY);
+    }
+
+    fn trap_if(&mut self) {
+        self.block(op::IF);
+        self.push(op::UNREACHABLE);
+        self.push(op::END);
+    }
+
+    fn i32_const(&mut self, v: i32) {
+        self.push(op::I32_CONST);
+        self.extend(sleb(v as i64));
+    }
+
+    fn i64_const(&mut self, v: i64) {
+        self.push(op::I64_CONST);
+        self.extend(sleb(v));
+    }
+
+    fn local_get(&mut self, index: u32) {
+        self.push(op::LOCAL_GET);
+        self.extend(uleb(index as u64));
+    }
+
+    fn local_set(&mut self, index: u32) {
+        self.push(op::LOCAL_SET);
+        self.extend(uleb(index as u64));
+    }
+
+    fn local_tee(&mut self, index: u32) {
+        self.push(op::LOCAL_TEE);
+        self.extend(uleb(index as u64));
+    }
+
+    fn global_get(&mut self, index: u32) {
+        self.push(op::GLOBAL_GET);
+        self.extend(uleb(index as u64));
+    }
+
+    fn global_set(&mut self, index: u32) {
+        self.push(op::GLOBAL_SET);
+        self.extend(uleb(index as u64));
+    }
+
+    fn call(&mut self, function: u32) {
+        self.push(op::CALL);
+        self.extend(uleb(function as u64));
+    }
+
+    fn i64_load(&mut self, offset: u32) {
+        self.push(op::I64_LOAD);
+        self.push(3);
+        self.extend(uleb(offset as u64));
+    }
+
+    fn i64_store(&mut self, offset: u32) {
+        self.push(op::I64_STORE);
+        self.push(3);
+        self.extend(uleb(offset as u64));
+    }
+
+    fn stack_len(&mut self) {
+        self.global_get(SP);
+        self.i32_const(3);
+        self.push(op::I32_SHR_U);
+    }
+
+    fn pop_a_b(&mut self) {
+        self.call(func::POP);
+        self.local_set(A);
+        self.call(func::POP);
+        self.local_set(B);
+    }
+
+    /// Sets `R` to `b + a` wrapped and leaves whether it overflowed.
+    fn add_overflow(&mut self) {
+        self.local_get(B);
+        self.local_get(A);
+        self.push(op::I64_ADD);
+        self.local_set(R);
+        // The result's sign differs from both operands'.
+        self.local_get(B);
+        self.local_get(R);
+        self.push(op::I64_XOR);
+        self.local_get(A);
+        self.local_get(R);
+        self.push(op::I64_XOR);
+        self.push(op::I64_AND);
+        self.i64_const(0);
+        self.push(op::I64_LT_S);
+    }
+
+    /// Sets `R` to `b * a` wrapped and leaves whether it overflowed.
+    fn mul_overflow(&mut self) {
+        self.local_get(B);
+        self.local_get(A);
+        self.push(op::I64_MUL);
+        self.local_set(R);
+        // `r / a` traps on `i64::MIN / -1`, so that's checked first.
+        self.local_get(A);
+        self.i64_const(-1);
+        self.push(op::I64_EQ);
+        self.push(op::IF);
+        self.push(I32);
+        self.local_get(B);
+        self.i64_const(i64::MIN);
+        self.push(op::I64_EQ);
+        self.push(op::ELSE);
+        self.local_get(A);
+        self.push(op::I64_EQZ);
+        self.push(op::IF);
+        self.push(I32);
+        self.i32_const(0);
+        self.push(op::ELSE);
+        self.local_get(R);
+        self.local_get(A);
+        self.push(op::I64_DIV_S);
+        self.local_get(B);
+        self.push(op::I64_NE);
+        self.push(op::END);
+        self.push(op::END);
+    }
+
+    /// Leaves `i64::MIN` if the local is negative, `i64::MAX` otherwise.
+    fn saturated(&mut self, sign: u32) {
+        self.i64_const(i64::MIN);
+        self.i64_const(i64::MAX);
+        self.local_get(sign);
+        self.i64_const(0);
+        self.push(op::I64_LT_S);
+        self.push(op::SELECT);
+    }
+}
+
+/// A function body declaring `locals` as runs of one type.
+fn body(locals: &[(u32, u8)], code: Code) -> Vec<u8> {
+    let mut body = vector(locals.iter().map(|&(count, ty)| {
+        let mut run = uleb(count as u64);
+        run.push(ty);
+        run
+    }));
+    body.extend(code.0);
+    body.push(op::END);
+    let mut sized = uleb(body.len() as u64);
+    sized.extend(body);
+    sized
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    module.push(id);
+    module.extend(uleb(contents.len() as u64));
+    module.extend(contents);
+}
+
+fn vector(items: impl Iterator<Item = Vec<u8>>) -> Vec<u8> {
+    let items: Vec<_> = items.collect();
+    let mut out = uleb(items.len() as u64);
+    for item in items {
+        out.extend(item);
+    }
+    out
+}
+
+fn name(s: &str) -> Vec<u8> {
+    let mut out = uleb(s.len() as u64);
+    out.extend(s.as_bytes());
+    out
+}
+
+fn uleb(mut v: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(mut v: i64) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        let done = (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,35 @@
+use flock_asm::assemble;
+use flock_bytecode::OpCode;
+
+#[test]
+fn assembles_source_to_bytecode() {
+    let bytecode = assemble("PUSH 5\nPUSH 8\nADD\nHALT").unwrap();
+    let opcodes: Vec<_> = (0..bytecode.len())
+        .map(|i| bytecode.get(i).unwrap().clone())
+        .collect();
+    assert_eq!(
+        opcodes,
+        vec![OpCode::Push(5), OpCode::Push(8), OpCode::Add, OpCode::Halt]
+    );
+}
+
+#[test]
+fn reports_every_error_with_its_line() {
+    let source = "
+  PUSH 1
+  JMP $nowhere
+  FROB
+  HALT
+";
+    let diagnostics = assemble(source).unwrap_err();
+    let mut found: Vec<_> = diagnostics
+        .0
+        .iter()
+        .map(|d| (d.code, d.file.as_str(), d.span.line))
+        .collect();
+    found.sort_unstable();
+    assert_eq!(
+        found,
+        vec![("E0001", "<source>", 2), ("E0004", "<source>", 3)]
+    );
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use flock_asm::assemble;
+use flock_bytecode::disasm::disassemble;
+use flock_bytecode::{Arena, ByteCode, ConditionFlags, OpCode, PackedWidth, Retry};
+
+/// Everything assembly determines, which is all but the labels synthesized for targets.
+fn without_labels(bytecode: &ByteCode) -> serde_json::Value {
+    let mut json = serde_json::to_value(bytecode).unwrap();
+    json.as_object_mut().unwrap().remove("labels");
+    json
+}
+
+fn assert_round_trips(bytecode: &ByteCode) {
+    let source = disassemble(bytecode);
+    let reassembled = assemble(&source).unwrap_or_else(|e| panic!("{}\n{}", e, source));
+    assert_eq!(
+        without_labels(&reassembled),
+        without_labels(bytecode),
+        "{}",
+        source
+    );
+}
+
+#[test]
+fn every_opcode_round_trips() {
+    let opcodes = vec![
+        OpCode::Push(i64::MIN),
+        OpCode::Push(i64::MAX),
+        OpCode::Add,
+        OpCode::Mul,
+        OpCode::Div,
+        OpCode::AddChecked,
+        OpCode::AddSaturating,
+        OpCode::MulChecked,
+        OpCode::MulSaturating,
+        OpCode::MulWide,
+        OpCode::DivWide,
+        OpCode::DumpDebug,
+        OpCode::Jump(ConditionFlags::EMPTY, Some(3)),
+        OpCode::Jump(ConditionFlags::EMPTY, None),
+        OpCode::Jump(ConditionFlags::ZERO | ConditionFlags::FORK, None),
+        OpCode::Jump(ConditionFlags::NOT_ZERO | ConditionFlags::NOT_FORK, Some(1)),
+        OpCode::JumpToSubroutine(Some(40)),
+        OpCode::JumpToSubroutine(None),
+        OpCode::TailCall(2, Some(9)),
+        OpCode::TailCall(0, None),
+        OpCode::Call(Some(2)),
+        OpCode::Call(None),
+        OpCode::CallReturn,
+        OpCode::LoadLocal(0),
+        OpCode::StoreLocal(3),
+        OpCode::Bury(1),
+        OpCode::Dredge(2),
+        OpCode::Duplicate,
+        OpCode::Return,
+        OpCode::Pop,
+        OpCode::Fork,
+        OpCode::IsChild,
+        OpCode::Join(2),
+        OpCode::JoinStatus(1),
+        OpCode::Halt,
+        OpCode::HaltWith(-3),
+        OpCode::Store(u64::MAX),
+        OpCode::Load(0),
+        OpCode::StoreRelative(1 << 63),
+        OpCode::LoadRelative(5),
+        OpCode::LoadPacked(PackedWidth::U8, 7),
+        OpCode::StorePacked(PackedWidth::I32, u64::MAX),
+        OpCode::AllocGlobal(16),
+        OpCode::Alloc(Arena::Shared, 4),
+        OpCode::Alloc(Arena::Task, 1 << 20),
+        OpCode::Free,
+        OpCode::Panic,
+        OpCode::AssertEq,
+        OpCode::AssertStackDepth(4),
+        OpCode::Extension(u16::MAX),
+        OpCode::CallNative(3),
+        OpCode::Emit,
+        OpCode::PushN(vec![1, -2, 3]),
+        OpCode::PushN(vec![i64::MIN]),
+        OpCode::AddImm(-1),
+        OpCode::MulImm(7),
+        OpCode::And,
+        OpCode::Or,
+        OpCode::Xor,
+        OpCode::Not,
+        OpCode::Shl,
+        OpCode::Shr,
+        OpCode::Compare,
+        OpCode::Test,
+        OpCode::Jump(
+            ConditionFlags::NEGATIVE | ConditionFlags::NOT_CARRY,
+            Some(3),
+        ),
+        OpCode::Jump(
+            ConditionFlags::NOT_NEGATIVE | ConditionFlags::OVERFLOW,
+            None,
+        ),
+        OpCode::Jump(ConditionFlags::CARRY | ConditionFlags::NOT_OVERFLOW, None),
+        // Outside the program.
+        OpCode::Jump(ConditionFlags::ZERO, Some(-1)),
+        OpCode::JumpToSubroutine(Some(1000)),
+    ];
+    let mut bounds = BTreeMap::new();
+    bounds.insert(4, 100);
+    let mut idempotent = BTreeMap::new();
+    idempotent.insert(2, 6);
+    idempotent.insert(6, 6);
+    idempotent.insert(7, 9);
+    let mut labels = BTreeMap::new();
+    labels.insert(3, "body".to_string());
+    labels.insert(9, "L1".to_string());
+    labels.insert(12, "not a label".to_string());
+    let bytecode = ByteCode::from(opcodes)
+        .with_loop_bounds(bounds)
+        .with_idempotent(idempotent)
+        .with_retry(Some(Retry {
+            attempts: 5,
+            backoff_ms: 20,
+        }))
+        .with_labels(labels);
+
+    assert_round_trips(&bytecode);
+}
+
+#[test]
+fn examples_round_trip() {
+    let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+    for entry in std::fs::read_dir(examples).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("asm".as_ref()) {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path).unwrap();
+        match assemble(&source) {
+            Ok(bytecode) => assert_round_trips(&bytecode),
+            Err(e) => panic!("{:?}: {}", path, e),
+        }
+    }
+}
+
+#[test]
+fn keeps_labels_and_names_other_targets() {
+    let bytecode = assemble(
+        "
+  PUSH 3
+loop:
+  PUSH -1
+  ADD
+  JMP !z, $loop
+  JMP $loop + 4
+  HALT
+",
+    )
+    .unwrap();
+    assert_eq!(
+        disassemble(&bytecode),
+        "  PUSH 3
+
+loop:
+  PUSH -1
+  ADD
+  JMP !z, $loop
+  JMP $L5
+
+L5:
+  HALT
+"
+    );
+}
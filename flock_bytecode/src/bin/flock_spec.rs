@@ -0,0 +1,4 @@
+fn main() -> serde_json::Result<()> {
+    println!("{}", serde_json::to_string_pretty(flock_bytecode::spec())?);
+    Ok(())
+}
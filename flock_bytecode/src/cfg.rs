@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::{ByteCode, ConditionFlags, OpCode};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+}
+
+/// Instructions `start..end` that always execute in sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Successor,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Successor {
+    Block(usize),
+    /// The target is popped from the stack at runtime.
+    Dynamic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    ConditionalJump,
+    Call,
+    Return,
+}
+
+impl ByteCode {
+    pub fn cfg(&self) -> Cfg {
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (index, opcode) in self.opcodes.iter().enumerate() {
+            if let Some(target) = static_target(opcode) {
+                leaders.insert(target);
+            }
+            if ends_block(opcode) {
+                leaders.insert(index + 1);
+            }
+        }
+        let leaders: Vec<_> = leaders.into_iter().filter(|&l| l < self.len()).collect();
+
+        let blocks: Vec<_> = leaders
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| Block {
+                start,
+                end: leaders.get(i + 1).cloned().unwrap_or_else(|| self.len()),
+            })
+            .collect();
+
+        let block_at = |index: usize| match leaders.binary_search(&index) {
+            Ok(block) => Successor::Block(block),
+            Err(_) => Successor::Dynamic,
+        };
+        let mut edges = Vec::new();
+        for (from, block) in blocks.iter().enumerate() {
+            let mut edge = |to, kind| edges.push(Edge { from, to, kind });
+            let last = &self.opcodes[block.end - 1];
+            let next = block_at(block.end);
+            let target = static_target(last).map_or(Successor::Dynamic, block_at);
+            match last {
+                OpCode::Jump(flags, _) if *flags == ConditionFlags::EMPTY => {
+                    edge(target, EdgeKind::Jump)
+                }
+                OpCode::Jump(_, _) => {
+                    edge(target, EdgeKind::ConditionalJump);
+                    edge(next, EdgeKind::Fallthrough);
+                }
+                OpCode::JumpToSubroutine(_) | OpCode::Call(_) => {
+                    edge(target, EdgeKind::Call);
+                    edge(next, EdgeKind::Fallthrough);
+                }
+                OpCode::TailCall(_, _) => edge(target, EdgeKind::Jump),
+                OpCode::Return | OpCode::CallReturn => edge(Successor::Dynamic, EdgeKind::Return),
+                OpCode::Halt | OpCode::HaltWith(_) | OpCode::Panic => {}
+                _ if block.end < self.len() => edge(next, EdgeKind::Fallthrough),
+                _ => {}
+            }
+        }
+
+        Cfg { blocks, edges }
+    }
+}
+
+fn static_target(opcode: &OpCode) -> Option<usize> {
+    match opcode {
+        OpCode::Jump(_, Some(t))
+        | OpCode::JumpToSubroutine(Some(t))
+        | OpCode::TailCall(_, Some(t))
+        | OpCode::Call(Some(t)) => Some(*t as usize),
+        _ => None,
+    }
+}
+
+fn ends_block(opcode: &OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::Jump(_, _)
+            | OpCode::JumpToSubroutine(_)
+            | OpCode::TailCall(_, _)
+            | OpCode::Call(_)
+            | OpCode::Return
+            | OpCode::CallReturn
+            | OpCode::Halt
+            | OpCode::HaltWith(_)
+            | OpCode::Panic
+    )
+}
+
+impl Cfg {
+    pub fn dot(&self, bytecode: &ByteCode) -> String {
+        let mut dot = String::from("digraph cfg {\n  node [shape=box, fontname=monospace];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for index in block.start..block.end {
+                write!(label, "{:04}: {:?}\\l", index, bytecode.opcodes[index]).unwrap();
+            }
+            writeln!(dot, "  b{} [label=\"{}\"];", i, label.replace('"', "\\\"")).unwrap();
+        }
+        if self.edges.iter().any(|e| e.to == Successor::Dynamic) {
+            dot += "  dynamic [shape=diamond, label=\"?\"];\n";
+        }
+        for edge in &self.edges {
+            let to = match edge.to {
+                Successor::Block(b) => format!("b{}", b),
+                Successor::Dynamic => "dynamic".to_string(),
+            };
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [label=\"jmp\"]",
+                EdgeKind::ConditionalJump => " [label=\"jmp?\"]",
+                EdgeKind::Call => " [label=\"call\"]",
+                EdgeKind::Return => " [label=\"ret\", style=dashed]",
+            };
+            writeln!(dot, "  b{} -> {}{};", edge.from, to, style).unwrap();
+        }
+        dot += "}\n";
+        dot
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::cfg::{Cfg, EdgeKind, Successor};
+use crate::{ByteCode, ConditionFlags, OpCode};
+
+/// Iterations assumed for a loop without a `.bound` annotation when estimating typical cost.
+pub const TYPICAL_ITERATIONS: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Cost {
+    /// Upper bound on executed instructions, `None` if an unannotated loop, recursion, indirect
+    /// jump or `JOIN` makes it unbounded.
+    pub worst: Option<u64>,
+    pub typical: u64,
+}
+
+impl Cost {
+    fn then(self, other: Cost) -> Cost {
+        Cost {
+            worst: self
+                .worst
+                .and_then(|a| other.worst.map(|b| a.saturating_add(b))),
+            typical: self.typical.saturating_add(other.typical),
+        }
+    }
+
+    fn max(self, other: Cost) -> Cost {
+        Cost {
+            worst: self.worst.and_then(|a| other.worst.map(|b| a.max(b))),
+            typical: self.typical.max(other.typical),
+        }
+    }
+
+    fn times(self, n: u64) -> Cost {
+        Cost {
+            worst: self.worst.map(|w| w.saturating_mul(n)),
+            typical: self.typical.saturating_mul(n),
+        }
+    }
+}
+
+impl ByteCode {
+    /// Estimated instructions executed from `pc` until the code halts or returns, counting
+    /// subroutine calls by their callee's cost.
+    pub fn cost_from(&self, pc: usize) -> Cost {
+        Analysis::new(self, false).cost_from(pc)
+    }
+
+    /// Like `cost_from`, for a child resuming at `pc` after `FORK`. The child takes every
+    /// `JMP f`, and is unbounded if it forks again since that clears its fork flag.
+    pub fn fork_child_cost(&self, pc: usize) -> Cost {
+        Analysis::new(self, true).cost_from(pc)
+    }
+
+    /// Estimates for the program entry and every statically called subroutine.
+    pub fn function_costs(&self) -> BTreeMap<usize, Cost> {
+        let mut analysis = Analysis::new(self, false);
+        self.entry_points()
+            .into_iter()
+            .map(|e| (e, analysis.cost_from(e)))
+            .collect()
+    }
+
+    /// The program entry and every statically called subroutine, in order.
+    pub fn entry_points(&self) -> Vec<usize> {
+        let mut entries = vec![0];
+        entries.extend(self.opcodes.iter().filter_map(|op| match op {
+            OpCode::JumpToSubroutine(Some(t))
+            | OpCode::TailCall(_, Some(t))
+            | OpCode::Call(Some(t)) => Some(*t as usize),
+            _ => None,
+        }));
+        entries.sort_unstable();
+        entries.dedup();
+        entries.retain(|&e| e < self.len());
+        entries
+    }
+}
+
+struct Analysis<'b> {
+    bytecode: &'b ByteCode,
+    cfg: Cfg,
+    successors: Vec<Vec<usize>>,
+    /// Blocks ending in a jump whose target is only known at runtime.
+    indirect: Vec<bool>,
+    forked: bool,
+    /// `None` while the function is being analyzed, so recursion is detected.
+    functions: HashMap<usize, Option<Cost>>,
+}
+
+impl<'b> Analysis<'b> {
+    fn new(bytecode: &'b ByteCode, forked: bool) -> Self {
+        let cfg = bytecode.cfg();
+        let mut successors = vec![Vec::new(); cfg.blocks.len()];
+        let mut indirect = vec![false; cfg.blocks.len()];
+        for edge in &cfg.edges {
+            let last = &bytecode.opcodes[cfg.blocks[edge.from].end - 1];
+            // A forked task always takes `JMP f` and never `JMP !f`.
+            let untaken = match last {
+                OpCode::Jump(flags, _) if *flags == ConditionFlags::FORK => {
+                    Some(EdgeKind::Fallthrough)
+                }
+                OpCode::Jump(flags, _) if *flags == ConditionFlags::NOT_FORK => {
+                    Some(EdgeKind::ConditionalJump)
+                }
+                _ => None,
+            };
+            if forked && untaken == Some(edge.kind) {
+                continue;
+            }
+            let intraprocedural = matches!(
+                edge.kind,
+                EdgeKind::Fallthrough | EdgeKind::Jump | EdgeKind::ConditionalJump
+            );
+            match (intraprocedural, edge.to) {
+                (true, Successor::Block(to)) => successors[edge.from].push(to),
+                (true, Successor::Dynamic) => indirect[edge.from] = true,
+                (false, _) => {}
+            }
+        }
+        Analysis {
+            bytecode,
+            cfg,
+            successors,
+            indirect,
+            forked,
+            functions: HashMap::new(),
+        }
+    }
+
+    fn cost_from(&mut self, pc: usize) -> Cost {
+        if let Some(known) = self.functions.get(&pc) {
+            return known.unwrap_or(Cost {
+                worst: None,
+                typical: 0,
+            });
+        }
+        let start = match self.cfg.blocks.iter().rposition(|b| b.start <= pc) {
+            Some(s) if pc < self.cfg.blocks[s].end => s,
+            _ => {
+                return Cost {
+                    worst: Some(0),
+                    typical: 0,
+                }
+            }
+        };
+
+        self.functions.insert(pc, None);
+        let sccs = Tarjan::sccs(&self.successors, start);
+        let mut scc_of = HashMap::new();
+        for (i, scc) in sccs.iter().enumerate() {
+            for &block in scc {
+                scc_of.insert(block, i);
+            }
+        }
+
+        // Tarjan yields components sinks first, so successors are always already costed.
+        let mut costs: Vec<Cost> = Vec::with_capacity(sccs.len());
+        for (i, scc) in sccs.iter().enumerate() {
+            let mut body = Cost {
+                worst: Some(0),
+                typical: 0,
+            };
+            for &block in scc {
+                let from = if block == start { pc } else { 0 };
+                body = body.then(self.block_cost(block, from));
+            }
+            let cyclic = scc.len() > 1 || self.successors[scc[0]].contains(&scc[0]);
+            if cyclic {
+                let bound = scc
+                    .iter()
+                    .filter_map(|&b| self.bytecode.loop_bound(self.cfg.blocks[b].start))
+                    .max();
+                body = match bound {
+                    Some(n) => body.times(n),
+                    None => Cost {
+                        worst: None,
+                        ..body.times(TYPICAL_ITERATIONS)
+                    },
+                };
+            }
+            let after = scc
+                .iter()
+                .flat_map(|&b| &self.successors[b])
+                .map(|s| scc_of[s])
+                .filter(|&s| s != i)
+                .map(|s| costs[s])
+                .reduce(Cost::max);
+            costs.push(match after {
+                Some(after) => body.then(after),
+                None => body,
+            });
+        }
+
+        let cost = costs[scc_of[&start]];
+        self.functions.insert(pc, Some(cost));
+        cost
+    }
+
+    fn block_cost(&mut self, index: usize, from: usize) -> Cost {
+        let block = self.cfg.blocks[index];
+        let start = block.start.max(from);
+        let mut cost = Cost {
+            worst: Some((block.end - start) as u64),
+            typical: (block.end - start) as u64,
+        };
+        if self.indirect[index] {
+            cost.worst = None;
+        }
+        for pc in start..block.end {
+            match self.bytecode.opcodes[pc] {
+                OpCode::Join(_)
+                | OpCode::JoinStatus(_)
+                | OpCode::JumpToSubroutine(None)
+                | OpCode::Call(None) => cost.worst = None,
+                OpCode::Fork if self.forked => cost.worst = None,
+                OpCode::JumpToSubroutine(Some(t)) | OpCode::Call(Some(t)) => {
+                    cost = cost.then(self.cost_from(t as usize))
+                }
+                _ => {}
+            }
+        }
+        cost
+    }
+}
+
+struct Tarjan<'g> {
+    successors: &'g [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next: usize,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl<'g> Tarjan<'g> {
+    fn sccs(successors: &'g [Vec<usize>], start: usize) -> Vec<Vec<usize>> {
+        let n = successors.len();
+        let mut tarjan = Tarjan {
+            successors,
+            index: vec![None; n],
+            low: vec![0; n],
+            on_stack: vec![false; n],
+            stack: Vec::new(),
+            next: 0,
+            sccs: Vec::new(),
+        };
+        tarjan.visit(start);
+        tarjan.sccs
+    }
+
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next);
+        self.low[v] = self.next;
+        self.next += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+
+        for &w in &self.successors[v] {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.low[v] = self.low[v].min(self.low[w]);
+                }
+                Some(i) if self.on_stack[w] => self.low[v] = self.low[v].min(i),
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low[v]) == self.index[v] {
+            let mut scc = Vec::new();
+            loop {
+                let w = self.stack.pop().unwrap();
+                self.on_stack[w] = false;
+                scc.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Assembly text for bytecode, which `flock_asm` assembles back into the same instructions,
+//! loop bounds, idempotent regions and retry policy.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+
+use crate::{Arena, ByteCode, ConditionFlags, OpCode, PackedWidth};
+
+/// The program as assembly, one instruction per line under the labels that mark it. Jump,
+/// call and tail call targets without a label of their own get one named for their index.
+pub fn disassemble(bytecode: &ByteCode) -> String {
+    let labels = labels(bytecode);
+    let target = |t: i64| {
+        usize::try_from(t)
+            .ok()
+            .and_then(|t| labels.get(&t))
+            .map(|label| format!("${}", label))
+            // Outside the program, so never reached by a well-formed one. Written as an
+            // expression since a plain number isn't a target.
+            .unwrap_or_else(|| format!("{} + 0", t))
+    };
+
+    let mut out = String::new();
+    if let Some(retry) = bytecode.retry {
+        out.push_str(&format!(
+            ".retry {}, {}\n",
+            retry.attempts, retry.backoff_ms
+        ));
+    }
+    for index in 0..=bytecode.opcodes.len() {
+        let ends = |(&start, &end): (&usize, &usize)| end == index && start < index;
+        if bytecode.idempotent.iter().any(ends) {
+            out.push_str(".endidempotent\n");
+        }
+        if let Some(label) = labels.get(&index) {
+            if index > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!("{}:\n", label));
+        }
+        if let Some(&end) = bytecode.idempotent.get(&index) {
+            out.push_str(".idempotent\n");
+            if end == index {
+                out.push_str(".endidempotent\n");
+            }
+        }
+        if let Some(bound) = bytecode.loop_bounds.get(&index) {
+            out.push_str(&format!(".bound {}\n", bound));
+        }
+        if let Some(op) = bytecode.opcodes.get(index) {
+            out.push_str(&format!("  {}\n", instruction(op, &target)));
+        }
+    }
+    out
+}
+
+/// The bytecode's own labels that `flock_asm` can parse, plus one for every other target.
+fn labels(bytecode: &ByteCode) -> BTreeMap<usize, String> {
+    let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+    let mut taken = HashSet::new();
+    for (&index, label) in &bytecode.labels {
+        if index <= bytecode.opcodes.len() && is_ident(label) && taken.insert(label.clone()) {
+            labels.insert(index, label.clone());
+        }
+    }
+
+    let targets = bytecode.opcodes.iter().filter_map(|op| match op {
+        OpCode::Jump(_, Some(t))
+        | OpCode::JumpToSubroutine(Some(t))
+        | OpCode::TailCall(_, Some(t))
+        | OpCode::Call(Some(t)) => Some(*t),
+        _ => None,
+    });
+    for target in targets.filter(|&t| bytecode.in_bounds(t)) {
+        let index = target as usize;
+        if labels.contains_key(&index) {
+            continue;
+        }
+        let mut label = format!("L{}", index);
+        while !taken.insert(label.clone()) {
+            label.push_str("_0");
+        }
+        labels.insert(index, label);
+    }
+    labels
+}
+
+/// Whether `flock_asm` reads `s` as a label: alphanumeric runs joined by single underscores.
+fn is_ident(s: &str) -> bool {
+    s.split('_')
+        .all(|run| !run.is_empty() && run.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn instruction(op: &OpCode, target: &dyn Fn(i64) -> String) -> String {
+    let mnemonic = op.instruction().mnemonic;
+    let operands = match op {
+        OpCode::Push(n)
+        | OpCode::AddImm(n)
+        | OpCode::MulImm(n)
+        | OpCode::Bury(n)
+        | OpCode::Dredge(n)
+        | OpCode::Join(n)
+        | OpCode::JoinStatus(n)
+        | OpCode::HaltWith(n)
+        | OpCode::LoadLocal(n)
+        | OpCode::StoreLocal(n)
+        | OpCode::AssertStackDepth(n) => vec![n.to_string()],
+        // Assembled through `i64`, so written as one for addresses past `i64::MAX`.
+        OpCode::Store(a)
+        | OpCode::Load(a)
+        | OpCode::StoreRelative(a)
+        | OpCode::LoadRelative(a)
+        | OpCode::AllocGlobal(a) => vec![(*a as i64).to_string()],
+        OpCode::LoadPacked(width, base) | OpCode::StorePacked(width, base) => {
+            vec![packed_width(*width).to_string(), (*base as i64).to_string()]
+        }
+        OpCode::Alloc(Arena::Shared, n) => vec![(*n as i64).to_string()],
+        OpCode::Alloc(Arena::Task, n) => vec!["task".to_string(), (*n as i64).to_string()],
+        OpCode::PushN(values) => values.iter().map(i64::to_string).collect(),
+        OpCode::Extension(code) | OpCode::CallNative(code) => vec![code.to_string()],
+        OpCode::Jump(flags, t) => {
+            let flags = (!flags.is_empty()).then(|| condition_flags(*flags));
+            flags.into_iter().chain(t.map(target)).collect()
+        }
+        OpCode::JumpToSubroutine(t) | OpCode::Call(t) => t.map(target).into_iter().collect(),
+        OpCode::TailCall(depth, t) => std::iter::once(depth.to_string())
+            .chain(t.map(target))
+            .collect(),
+        _ => vec![],
+    };
+    if operands.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operands.join(", "))
+    }
+}
+
+fn condition_flags(flags: ConditionFlags) -> String {
+    [
+        (ConditionFlags::ZERO, "z"),
+        (ConditionFlags::NOT_ZERO, "!z"),
+        (ConditionFlags::FORK, "f"),
+        (ConditionFlags::NOT_FORK, "!f"),
+        (ConditionFlags::NEGATIVE, "n"),
+        (ConditionFlags::NOT_NEGATIVE, "!n"),
+        (ConditionFlags::CARRY, "c"),
+        (ConditionFlags::NOT_CARRY, "!c"),
+        (ConditionFlags::OVERFLOW, "o"),
+        (ConditionFlags::NOT_OVERFLOW, "!o"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, s)| *s)
+    .collect()
+}
+
+fn packed_width(width: PackedWidth) -> &'static str {
+    match width {
+        PackedWidth::U8 => "u8",
+        PackedWidth::I16 => "i16",
+        PackedWidth::I32 => "i32",
+    }
+}
@@ -0,0 +1,78 @@
+//! Instructions added since the first instruction set, grouped into features a program lists in
+//! its header, so a node can tell it can't run one before it reads the code.
+
+use crate::{ByteCode, ConditionFlags, OpCode};
+
+bitflags::bitflags! {
+    /// Bits are never reused, so any node can tell which of a program's it doesn't know.
+    pub struct Features: u64 {
+        /// `PUSHN`, `ADDI` and `MULI`.
+        const IMMEDIATES = 1;
+        /// `AND`, `OR`, `XOR`, `NOT`, `SHL` and `SHR`.
+        const BITWISE = 1 << 1;
+        /// `CMP`, `TEST` and the jump conditions past zero and fork.
+        const COMPARE = 1 << 2;
+        /// `CALL`, `RETURN`, `LOAD_LOCAL` and `STORE_LOCAL`.
+        const CALL_FRAMES = 1 << 3;
+        /// `ALLOC` and `FREE`.
+        const HEAP = 1 << 4;
+    }
+}
+
+/// Features this build implements. Programs requiring any other are rejected when decoded.
+pub const SUPPORTED: Features = Features::all();
+
+const NAMES: &[(Features, &str)] = &[
+    (Features::IMMEDIATES, "immediates"),
+    (Features::BITWISE, "bitwise"),
+    (Features::COMPARE, "compare"),
+    (Features::CALL_FRAMES, "call_frames"),
+    (Features::HEAP, "heap"),
+];
+
+/// Names of the features set in `bits`, with `bit N` for those this build doesn't know.
+pub fn names(bits: u64) -> Vec<String> {
+    (0..64)
+        .map(|bit| 1 << bit)
+        .filter(|flag| bits & flag != 0)
+        .map(|flag| {
+            NAMES
+                .iter()
+                .find(|(feature, _)| feature.bits() == flag)
+                .map(|(_, name)| name.to_string())
+                .unwrap_or_else(|| format!("bit {}", flag.trailing_zeros()))
+        })
+        .collect()
+}
+
+impl OpCode {
+    /// What a node needs to run this instruction, empty for the first instruction set.
+    pub fn features(&self) -> Features {
+        let original = ConditionFlags::ZERO
+            | ConditionFlags::FORK
+            | ConditionFlags::NOT_ZERO
+            | ConditionFlags::NOT_FORK;
+        match self {
+            OpCode::PushN(_) | OpCode::AddImm(_) | OpCode::MulImm(_) => Features::IMMEDIATES,
+            OpCode::And | OpCode::Or | OpCode::Xor | OpCode::Not | OpCode::Shl | OpCode::Shr => {
+                Features::BITWISE
+            }
+            OpCode::Compare | OpCode::Test => Features::COMPARE,
+            OpCode::Jump(flags, _) if !original.contains(*flags) => Features::COMPARE,
+            OpCode::Call(_) | OpCode::CallReturn | OpCode::LoadLocal(_) | OpCode::StoreLocal(_) => {
+                Features::CALL_FRAMES
+            }
+            OpCode::Alloc(_, _) | OpCode::Free => Features::HEAP,
+            _ => Features::empty(),
+        }
+    }
+}
+
+impl ByteCode {
+    /// Features of every instruction, as written to the header.
+    pub fn features(&self) -> Features {
+        self.opcodes
+            .iter()
+            .fold(Features::empty(), |all, op| all | op.features())
+    }
+}
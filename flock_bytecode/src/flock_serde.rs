@@ -0,0 +1,144 @@
+//! The container on-disk artifacts, such as compiled bytecode and `--dump` files, are written
+//! in. JSON is `{"flock_artifact": <kind>, "version": <n>, "data": ...}` to stay readable,
+//! binary is `FLOCK\0`, the version byte, then the kind and data in bincode. Reading detects
+//! which.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Bumped whenever the container itself changes.
+pub const VERSION: u8 = 1;
+
+const MAGIC: &[u8] = b"FLOCK\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "json" => Ok(Format::Json),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!(
+                "Unknown artifact format {:?}, expected json or binary",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// Neither container, such as assembly source.
+    NotAnArtifact,
+    UnsupportedVersion(u8),
+    WrongKind {
+        expected: String,
+        found: String,
+    },
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::Binary(e) => write!(f, "{}", e),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[derive(Serialize)]
+struct Container<'a, T> {
+    flock_artifact: &'a str,
+    version: u8,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct OwnedContainer {
+    flock_artifact: String,
+    version: u8,
+    data: serde_json::Value,
+}
+
+pub fn to_vec<T: Serialize>(kind: &str, value: &T, format: Format) -> Result<Vec<u8>, Error> {
+    match format {
+        Format::Json => {
+            let container = Container {
+                flock_artifact: kind,
+                version: VERSION,
+                data: value,
+            };
+            serde_json::to_vec_pretty(&container).map_err(Error::Json)
+        }
+        Format::Binary => {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bincode::serialize_into(&mut bytes, &(kind, value)).map_err(Error::Binary)?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Reads an artifact of `kind` in either format.
+pub fn from_slice<T: DeserializeOwned>(kind: &str, bytes: &[u8]) -> Result<T, Error> {
+    let check_kind = |found: String| {
+        if found == kind {
+            Ok(())
+        } else {
+            Err(Error::WrongKind {
+                expected: kind.to_string(),
+                found,
+            })
+        }
+    };
+    if let Some(rest) = bytes.strip_prefix(MAGIC) {
+        let (&version, payload) = rest.split_first().ok_or(Error::NotAnArtifact)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let (found, value): (String, T) = bincode::deserialize(payload).map_err(Error::Binary)?;
+        check_kind(found)?;
+        return Ok(value);
+    }
+    let container: OwnedContainer =
+        serde_json::from_slice(bytes).map_err(|_| Error::NotAnArtifact)?;
+    if container.version != VERSION {
+        return Err(Error::UnsupportedVersion(container.version));
+    }
+    check_kind(container.flock_artifact)?;
+    serde_json::from_value(container.data).map_err(Error::Json)
+}
+
+pub fn write<T: Serialize>(
+    path: impl AsRef<Path>,
+    kind: &str,
+    value: &T,
+    format: Format,
+) -> Result<(), Error> {
+    Ok(std::fs::write(path, to_vec(kind, value, format)?)?)
+}
+
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>, kind: &str) -> Result<T, Error> {
+    from_slice(kind, &std::fs::read(path)?)
+}
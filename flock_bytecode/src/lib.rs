@@ -1,8 +1,40 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+pub mod cfg;
+pub mod cost;
+pub mod disasm;
+pub mod features;
+pub mod flock_serde;
+pub mod spec;
+pub use spec::spec;
+pub mod stats;
+pub mod wire;
+
+/// Kind of `flock_serde` artifact compiled bytecode is written as.
+pub const ARTIFACT: &str = "bytecode";
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(into = "wire::WireByteCode", try_from = "wire::WireByteCode")]
 pub struct ByteCode {
     opcodes: Vec<OpCode>,
+    /// Iteration limits from `.bound` annotations, keyed by the loop's first instruction.
+    loop_bounds: BTreeMap<usize, u64>,
+    /// Ends of `.idempotent` regions, keyed by their first instruction.
+    idempotent: BTreeMap<usize, usize>,
+    retry: Option<Retry>,
+    /// Names of assembly labels, keyed by the instruction they mark.
+    labels: BTreeMap<usize, String>,
+}
+
+/// How often a task is retried after a transient failure, from a `.retry` directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Retry {
+    /// Attempts in total, the first included.
+    pub attempts: u32,
+    /// Wait before the first retry, doubling with each further one.
+    pub backoff_ms: u64,
 }
 
 impl ByteCode {
@@ -10,6 +42,91 @@ impl ByteCode {
         self.opcodes.get(index)
     }
 
+    pub fn with_loop_bounds(mut self, loop_bounds: BTreeMap<usize, u64>) -> Self {
+        self.loop_bounds = loop_bounds;
+        self
+    }
+
+    pub fn loop_bound(&self, index: usize) -> Option<u64> {
+        self.loop_bounds.get(&index).cloned()
+    }
+
+    pub fn with_idempotent(mut self, idempotent: BTreeMap<usize, usize>) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    /// Whether the instruction is in an `.idempotent` region, so a task resuming there may run
+    /// again.
+    pub fn is_idempotent(&self, index: usize) -> bool {
+        self.idempotent
+            .range(..=index)
+            .next_back()
+            .is_some_and(|(_, &end)| index < end)
+    }
+
+    pub fn with_retry(mut self, retry: Option<Retry>) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry(&self) -> Option<Retry> {
+        self.retry
+    }
+
+    pub fn with_labels(mut self, labels: BTreeMap<usize, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// The label marking the instruction, if any.
+    pub fn label(&self, index: usize) -> Option<&str> {
+        self.labels.get(&index).map(String::as_str)
+    }
+
+    /// The nearest label at or before the instruction, with how far past it the instruction is,
+    /// such as `loop+3`.
+    pub fn symbolize(&self, index: usize) -> Option<String> {
+        let (&start, label) = self.labels.range(..=index).next_back()?;
+        Some(match index - start {
+            0 => label.clone(),
+            offset => format!("{}+{}", label, offset),
+        })
+    }
+
+    /// The first jump, call or tail call with a target outside the program written into it, as
+    /// its index and target. Jumping just past the last instruction ends the program instead.
+    pub fn out_of_bounds_jump(&self) -> Option<(usize, i64)> {
+        self.opcodes
+            .iter()
+            .enumerate()
+            .find_map(|(index, opcode)| match opcode {
+                OpCode::Jump(_, Some(t))
+                | OpCode::JumpToSubroutine(Some(t))
+                | OpCode::TailCall(_, Some(t))
+                | OpCode::Call(Some(t))
+                    if !self.in_bounds(*t) =>
+                {
+                    Some((index, *t))
+                }
+                _ => None,
+            })
+    }
+
+    /// Whether execution can continue at `target`, including ending just past the last
+    /// instruction.
+    pub fn in_bounds(&self, target: i64) -> bool {
+        target >= 0 && target as usize <= self.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.opcodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.opcodes.is_empty()
+    }
+
     pub fn surrounding(
         &self,
         index: usize,
@@ -23,7 +140,13 @@ impl ByteCode {
 
 impl From<Vec<OpCode>> for ByteCode {
     fn from(opcodes: Vec<OpCode>) -> ByteCode {
-        ByteCode { opcodes }
+        ByteCode {
+            opcodes,
+            loop_bounds: BTreeMap::new(),
+            idempotent: BTreeMap::new(),
+            retry: None,
+            labels: BTreeMap::new(),
+        }
     }
 }
 
@@ -31,30 +154,206 @@ impl From<Vec<OpCode>> for ByteCode {
 #[non_exhaustive]
 pub enum OpCode {
     Push(i64),
+    /// Pushes each value in order, like that many `Push`es in one instruction.
+    PushN(Vec<i64>),
+    /// Pops `a` then `b` and pushes `b + a`. Overflow wraps or traps according to the
+    /// `--int-overflow` mode of the node the program was started on.
     Add,
+    /// Pops `a` then `b` and pushes `b * a`, with the same overflow handling as `Add`.
+    Mul,
+    /// Like `Push(v)` then `Add`.
+    AddImm(i64),
+    /// Like `Push(v)` then `Mul`.
+    MulImm(i64),
+    /// Pops the divisor `a` then the dividend `b` and pushes `b / a`, rounding toward zero.
+    /// Dividing by zero is always an error; `i64::MIN / -1` is treated as overflow.
+    Div,
+    /// Like `Add`, but pushes the wrapped sum followed by 1 if it overflowed, otherwise 0.
+    AddChecked,
+    /// Like `Add`, but clamps to `i64::MIN`/`i64::MAX` instead of overflowing.
+    AddSaturating,
+    /// Like `Mul`, but pushes the wrapped product followed by 1 if it overflowed, otherwise 0.
+    MulChecked,
+    /// Like `Mul`, but clamps to `i64::MIN`/`i64::MAX` instead of overflowing.
+    MulSaturating,
+    /// Pops `a` then `b` and pushes the low then the high 64 bits of the 128-bit product `b * a`.
+    MulWide,
+    /// Pops the divisor `a`, then `hi`, then `lo`, divides the 128-bit value `hi:lo` by `a` and
+    /// pushes the quotient then the remainder. A quotient outside `i64` is treated as overflow.
+    DivWide,
+    /// Pops `a` then `b` and pushes their bitwise and.
+    And,
+    /// Pops `a` then `b` and pushes their bitwise or.
+    Or,
+    /// Pops `a` then `b` and pushes their bitwise exclusive or.
+    Xor,
+    /// Flips every bit of the top value.
+    Not,
+    /// Pops the shift `a` then `b` and pushes `b << a`. Shifts outside `0..64` are an error.
+    Shl,
+    /// Like `Shl`, shifting right and filling with zeroes whatever the sign.
+    Shr,
+    /// Pops `a` then `b` and pushes -1, 0 or 1 as `b` is less than, equal to or greater than
+    /// `a`, for `JMP` to branch on with `n` and `z`. Sets `CARRY` and `OVERFLOW` for `b - a`.
+    Compare,
+    /// Like `And`, also clearing `CARRY` and `OVERFLOW`.
+    Test,
     DumpDebug,
     Jump(ConditionFlags, Option<i64>),
     JumpToSubroutine(Option<i64>),
+    /// Dredges the caller's return address from `depth` values down, then jumps. Equivalent to
+    /// `JSR target; DREDGE depth; RET` without growing the stack.
+    TailCall(i64, Option<i64>),
+    /// Saves the return address and the caller's locals in a new call frame, kept apart from
+    /// the stack so the callee can't overwrite them, then jumps.
+    Call(Option<i64>),
+    /// Returns from the innermost `Call`, restoring the caller's locals.
+    CallReturn,
+    /// Pushes local `n` of the current call frame, 0 until stored.
+    LoadLocal(i64),
+    /// Pops into local `n` of the current call frame.
+    StoreLocal(i64),
     Bury(i64),
     Dredge(i64),
     Duplicate,
     Return,
     Pop,
     Fork,
+    /// Pushes 1 if the `FORK` flag is set, 0 otherwise.
+    IsChild,
     Join(i64),
+    /// Like `Join`, then pushes the task's halt code and 1, or 0 and 0 if it ran off the end of
+    /// the program.
+    JoinStatus(i64),
     Halt,
+    /// Like `Halt`, with `code` instead of 0. Joining a task that halted with a nonzero code
+    /// fails unless through `JoinStatus`.
+    HaltWith(i64),
     Store(u64),
     Load(u64),
     StoreRelative(u64),
     LoadRelative(u64),
+    /// Pops an element index and pushes that element of the array packed into the cells from
+    /// `base`. `u8`s are zero-extended, `i16`s and `i32`s sign-extended.
+    LoadPacked(PackedWidth, u64),
+    /// Pops an element index then a value, and writes the value's low bits as that element of
+    /// the array packed into the cells from `base`. Peers are sent the whole cell, so tasks on
+    /// different nodes writing elements of the same cell race.
+    StorePacked(PackedWidth, u64),
+    /// Reserves `n` consecutive addresses no other task of the program is given, and pushes the
+    /// first. The node the program was submitted to hands them out for every peer.
+    AllocGlobal(u64),
+    /// Reserves a block of `n` consecutive addresses from the heap and pushes its first, for
+    /// `StoreRelative` and `LoadRelative`. The block isn't cleared, and is handed out again
+    /// once freed.
+    Alloc(Arena, u64),
+    /// Pops the first address of a block `Alloc` reserved and returns it to the heap.
+    Free,
     Panic,
+    /// Pops two values and fails the task if they differ.
+    AssertEq,
+    /// Fails the task unless the stack holds exactly `n` values.
+    AssertStackDepth(i64),
+    /// Runs the handler the embedding host registered for this code.
+    Extension(u16),
+    /// Calls a function from the origin node's `--native-library`. Pops an argument count and
+    /// the arguments, then pushes the function's results.
+    CallNative(u16),
+    /// Pops a value and streams it to the VM that started the program, tagged with the task id.
+    Emit,
 }
 
 bitflags::bitflags! {
     #[derive(Deserialize, Serialize)]
-    pub struct ConditionFlags: u8 {
+    pub struct ConditionFlags: u16 {
         const EMPTY = 0b0;
         const ZERO = 0b1;
+        /// Set in the child by `FORK` and cleared in the parent, so it holds until the task
+        /// forks again. Tasks that never forked are parents.
         const FORK = 0b10;
+        /// Holds when `ZERO` doesn't.
+        const NOT_ZERO = 0b100;
+        /// Holds when `FORK` doesn't.
+        const NOT_FORK = 0b1000;
+        /// Holds when the top value is negative, as `ZERO` does when it's zero.
+        const NEGATIVE = 0b1_0000;
+        /// Holds when `NEGATIVE` doesn't.
+        const NOT_NEGATIVE = 0b10_0000;
+        /// Set by `CMP` when `b` is below `a` compared as unsigned, cleared by `TEST`.
+        const CARRY = 0b100_0000;
+        /// Holds when `CARRY` doesn't.
+        const NOT_CARRY = 0b1000_0000;
+        /// Set by `CMP` when `b - a` doesn't fit in an i64, cleared by `TEST`.
+        const OVERFLOW = 0b1_0000_0000;
+        /// Holds when `OVERFLOW` doesn't.
+        const NOT_OVERFLOW = 0b10_0000_0000;
+    }
+}
+
+/// Who owns a block `Alloc` reserves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Arena {
+    /// Kept until any task frees it.
+    Shared,
+    /// The allocating task's, freed when it ends if it hasn't freed the block itself. Other
+    /// tasks may use it but not free it.
+    Task,
+}
+
+/// Width of the elements `LoadPacked` and `StorePacked` pack into each i64 cell, lowest bits
+/// first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PackedWidth {
+    U8,
+    I16,
+    I32,
+}
+
+impl PackedWidth {
+    pub fn from_bits(bits: u32) -> Option<PackedWidth> {
+        match bits {
+            8 => Some(PackedWidth::U8),
+            16 => Some(PackedWidth::I16),
+            32 => Some(PackedWidth::I32),
+            _ => None,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            PackedWidth::U8 => 8,
+            PackedWidth::I16 => 16,
+            PackedWidth::I32 => 32,
+        }
+    }
+
+    /// The cell holding element `index` of the array packed from `base`, and the element's bit
+    /// offset within it.
+    pub fn locate(self, base: u64, index: i64) -> (u64, u32) {
+        let per_cell = u64::from(64 / self.bits());
+        let index = index as u64;
+        (
+            base.wrapping_add(index / per_cell),
+            (index % per_cell) as u32 * self.bits(),
+        )
+    }
+
+    pub fn extract(self, cell: i64, shift: u32) -> i64 {
+        let raw = (cell as u64 >> shift) & self.mask();
+        match self {
+            PackedWidth::U8 => raw as i64,
+            PackedWidth::I16 => raw as u16 as i16 as i64,
+            PackedWidth::I32 => raw as u32 as i32 as i64,
+        }
+    }
+
+    /// `cell` with the element at `shift` replaced by the low bits of `value`.
+    pub fn insert(self, cell: i64, shift: u32, value: i64) -> i64 {
+        let mask = self.mask() << shift;
+        ((cell as u64 & !mask) | (((value as u64) << shift) & mask)) as i64
+    }
+
+    fn mask(self) -> u64 {
+        (1 << self.bits()) - 1
     }
 }
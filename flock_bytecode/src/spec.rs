@@ -0,0 +1,526 @@
+use serde::Serialize;
+
+use crate::{ConditionFlags, OpCode};
+
+#[derive(Debug, Serialize)]
+pub struct Instruction {
+    pub name: &'static str,
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    pub stack_effect: &'static str,
+    pub reads_flags: ConditionFlags,
+    pub sets_flags: ConditionFlags,
+    pub description: &'static str,
+}
+
+impl Instruction {
+    pub fn min_operands(&self) -> usize {
+        self.operands.iter().filter(|o| !o.optional).count()
+    }
+
+    pub fn max_operands(&self) -> usize {
+        if self.operands.iter().any(|o| o.repeated) {
+            return usize::MAX;
+        }
+        self.operands.len()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Operand {
+    pub kind: OperandKind,
+    pub optional: bool,
+    /// May be given any number of times, at least once unless optional.
+    pub repeated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OperandKind {
+    Value,
+    Target,
+    Flags,
+    Depth,
+    Count,
+    Address,
+    Width,
+    /// `shared` or `task`, see `crate::Arena`.
+    Arena,
+    /// Index of a local in the current call frame.
+    Local,
+}
+
+const fn required(kind: OperandKind) -> Operand {
+    Operand {
+        kind,
+        optional: false,
+        repeated: false,
+    }
+}
+
+const fn optional(kind: OperandKind) -> Operand {
+    Operand {
+        kind,
+        optional: true,
+        repeated: false,
+    }
+}
+
+const fn repeated(kind: OperandKind) -> Operand {
+    Operand {
+        kind,
+        optional: false,
+        repeated: true,
+    }
+}
+
+const NO_FLAGS: ConditionFlags = ConditionFlags::EMPTY;
+
+macro_rules! instruction {
+    ($name:literal, $mnemonic:literal, [$($operand:expr),*], $effect:literal, $description:literal) => {
+        instruction!($name, $mnemonic, [$($operand),*], $effect, NO_FLAGS, NO_FLAGS, $description)
+    };
+    ($name:literal, $mnemonic:literal, [$($operand:expr),*], $effect:literal, $reads:expr, $sets:expr, $description:literal) => {
+        Instruction {
+            name: $name,
+            mnemonic: $mnemonic,
+            operands: &[$($operand),*],
+            stack_effect: $effect,
+            reads_flags: $reads,
+            sets_flags: $sets,
+            description: $description,
+        }
+    };
+}
+
+use OperandKind::*;
+
+static SPEC: &[Instruction] = &[
+    instruction!(
+        "Push",
+        "PUSH",
+        [required(Value)],
+        "-- v",
+        "Push a constant."
+    ),
+    instruction!(
+        "PushN",
+        "PUSHN",
+        [repeated(Value)],
+        "-- v...",
+        "Push several constants in order."
+    ),
+    instruction!(
+        "Add",
+        "ADD",
+        [],
+        "b a -- b+a",
+        "Add, overflow per --int-overflow."
+    ),
+    instruction!(
+        "Mul",
+        "MUL",
+        [],
+        "b a -- b*a",
+        "Multiply, overflow per --int-overflow."
+    ),
+    instruction!(
+        "AddImm",
+        "ADDI",
+        [required(Value)],
+        "b -- b+v",
+        "Add a constant, overflow per --int-overflow."
+    ),
+    instruction!(
+        "MulImm",
+        "MULI",
+        [required(Value)],
+        "b -- b*v",
+        "Multiply by a constant, overflow per --int-overflow."
+    ),
+    instruction!(
+        "Div",
+        "DIV",
+        [],
+        "b a -- b/a",
+        "Divide toward zero, errors on zero."
+    ),
+    instruction!(
+        "AddChecked",
+        "ADD_CHECKED",
+        [],
+        "b a -- b+a overflowed",
+        "Wrapping add followed by an overflow flag."
+    ),
+    instruction!(
+        "AddSaturating",
+        "ADD_SAT",
+        [],
+        "b a -- b+a",
+        "Add clamped to the i64 range."
+    ),
+    instruction!(
+        "MulChecked",
+        "MUL_CHECKED",
+        [],
+        "b a -- b*a overflowed",
+        "Wrapping multiply followed by an overflow flag."
+    ),
+    instruction!(
+        "MulSaturating",
+        "MUL_SAT",
+        [],
+        "b a -- b*a",
+        "Multiply clamped to the i64 range."
+    ),
+    instruction!(
+        "MulWide",
+        "MUL_WIDE",
+        [],
+        "b a -- lo hi",
+        "128-bit product split into two words."
+    ),
+    instruction!(
+        "DivWide",
+        "DIV_WIDE",
+        [],
+        "lo hi a -- quotient remainder",
+        "Divide a 128-bit value by a 64-bit divisor."
+    ),
+    instruction!("And", "AND", [], "b a -- b&a", "Bitwise and."),
+    instruction!("Or", "OR", [], "b a -- b|a", "Bitwise or."),
+    instruction!("Xor", "XOR", [], "b a -- b^a", "Bitwise exclusive or."),
+    instruction!("Not", "NOT", [], "a -- !a", "Flip every bit."),
+    instruction!(
+        "Shl",
+        "SHL",
+        [],
+        "b a -- b<<a",
+        "Shift left, errors unless 0 <= a < 64."
+    ),
+    instruction!(
+        "Shr",
+        "SHR",
+        [],
+        "b a -- b>>a",
+        "Shift right filling with zeroes, errors unless 0 <= a < 64."
+    ),
+    instruction!(
+        "Compare",
+        "CMP",
+        [],
+        "b a -- sign",
+        NO_FLAGS,
+        ConditionFlags::CARRY.union(ConditionFlags::OVERFLOW),
+        "Push -1, 0 or 1 as b is less than, equal to or greater than a. Sets CARRY when b is below a unsigned and OVERFLOW when b - a overflows."
+    ),
+    instruction!(
+        "Test",
+        "TEST",
+        [],
+        "b a -- b&a",
+        NO_FLAGS,
+        ConditionFlags::CARRY.union(ConditionFlags::OVERFLOW),
+        "Bitwise and, clearing CARRY and OVERFLOW."
+    ),
+    instruction!(
+        "DumpDebug",
+        "DUMP_DEBUG",
+        [],
+        "--",
+        "Print the task state to stderr."
+    ),
+    instruction!(
+        "Jump",
+        "JMP",
+        [optional(Flags), optional(Target)],
+        "[target] --",
+        ConditionFlags::all(),
+        NO_FLAGS,
+        "Jump if all condition flags hold, `!` negating one, popping the target when not given."
+    ),
+    instruction!(
+        "JumpToSubroutine",
+        "JSR",
+        [optional(Target)],
+        "[target] -- return",
+        "Push the return address and jump."
+    ),
+    instruction!(
+        "TailCall",
+        "TAIL_CALL",
+        [required(Depth), optional(Target)],
+        "return args... [target] -- args... return",
+        "Call so the callee returns straight to the current caller."
+    ),
+    instruction!(
+        "Call",
+        "CALL",
+        [optional(Target)],
+        "[target] --",
+        "Jump, saving the return address and locals in a new call frame off the stack."
+    ),
+    instruction!(
+        "CallReturn",
+        "RETURN",
+        [],
+        "--",
+        "Return from the innermost CALL, restoring the caller's locals."
+    ),
+    instruction!(
+        "LoadLocal",
+        "LOAD_LOCAL",
+        [required(Local)],
+        "-- v",
+        "Read a local of the current call frame, 0 until written."
+    ),
+    instruction!(
+        "StoreLocal",
+        "STORE_LOCAL",
+        [required(Local)],
+        "v --",
+        "Write a local of the current call frame."
+    ),
+    instruction!(
+        "Bury",
+        "BURY",
+        [required(Depth)],
+        "v --",
+        "Move the top value n places down."
+    ),
+    instruction!(
+        "Dredge",
+        "DREDGE",
+        [required(Depth)],
+        "-- v",
+        "Move the value n places down to the top."
+    ),
+    instruction!(
+        "Duplicate",
+        "DUP",
+        [],
+        "v -- v v",
+        "Duplicate the top value."
+    ),
+    instruction!(
+        "Return",
+        "RET",
+        [],
+        "return --",
+        "Jump to a popped return address."
+    ),
+    instruction!("Pop", "POP", [], "v --", "Discard the top value."),
+    instruction!(
+        "Fork",
+        "FORK",
+        [],
+        "-- other_id",
+        NO_FLAGS,
+        ConditionFlags::FORK,
+        "Split into parent and child, each receiving the other's id. Sets the FORK flag in the child and clears it in the parent, until either forks again."
+    ),
+    instruction!(
+        "IsChild",
+        "IS_CHILD",
+        [],
+        "-- is_child",
+        ConditionFlags::FORK,
+        NO_FLAGS,
+        "Push 1 if the FORK flag is set, 0 otherwise."
+    ),
+    instruction!(
+        "Join",
+        "JOIN",
+        [required(Count)],
+        "id -- values...",
+        "Wait for a task and push the top n values of its final stack."
+    ),
+    instruction!(
+        "JoinStatus",
+        "JOIN_STATUS",
+        [required(Count)],
+        "id -- values... code halted",
+        "Like JOIN, then push the task's halt code and 1, or 0 0 if it ran off the end."
+    ),
+    instruction!(
+        "Halt",
+        "HALT",
+        [optional(Value)],
+        "--",
+        "Terminate the task, with code 0 unless given."
+    ),
+    instruction!(
+        "Store",
+        "STORE",
+        [required(Address)],
+        "v --",
+        "Write to shared memory."
+    ),
+    instruction!(
+        "Load",
+        "LOAD",
+        [required(Address)],
+        "-- v",
+        "Read from shared memory."
+    ),
+    instruction!(
+        "StoreRelative",
+        "STORE_REL",
+        [required(Address)],
+        "v offset --",
+        "Write to shared memory at base + offset."
+    ),
+    instruction!(
+        "LoadRelative",
+        "LOAD_REL",
+        [required(Address)],
+        "offset -- v",
+        "Read from shared memory at base + offset."
+    ),
+    instruction!(
+        "LoadPacked",
+        "LOAD_PACKED",
+        [required(Width), required(Address)],
+        "index -- v",
+        "Read element index of a u8, i16 or i32 array packed into shared memory from base."
+    ),
+    instruction!(
+        "StorePacked",
+        "STORE_PACKED",
+        [required(Width), required(Address)],
+        "v index --",
+        "Write element index of a u8, i16 or i32 array packed into shared memory from base."
+    ),
+    instruction!(
+        "AllocGlobal",
+        "ALLOC_GLOBAL",
+        [required(Count)],
+        "-- base",
+        "Reserve n consecutive addresses no other task of the program is given."
+    ),
+    instruction!(
+        "Alloc",
+        "ALLOC",
+        [optional(Arena), required(Count)],
+        "-- base",
+        "Reserve a block of n addresses from the heap, shared or owned by the task until it ends."
+    ),
+    instruction!(
+        "Free",
+        "FREE",
+        [],
+        "base --",
+        "Return a block ALLOC reserved to the heap."
+    ),
+    instruction!("Panic", "PANIC", [], "--", "Fail the task with an error."),
+    instruction!(
+        "AssertEq",
+        "ASSERT_EQ",
+        [],
+        "b a --",
+        "Fail the task unless both values are equal."
+    ),
+    instruction!(
+        "Extension",
+        "EXT",
+        [required(Value)],
+        "args... -- results...",
+        "Call a host-registered extension with its declared stack effect."
+    ),
+    instruction!(
+        "CallNative",
+        "CALL_NATIVE",
+        [required(Value)],
+        "args... n -- results...",
+        "Call a function from a shared library loaded on the origin node."
+    ),
+    instruction!(
+        "AssertStackDepth",
+        "ASSERT_DEPTH",
+        [required(Depth)],
+        "--",
+        "Fail the task unless the stack holds exactly n values."
+    ),
+    instruction!(
+        "Emit",
+        "EMIT",
+        [],
+        "v --",
+        "Stream a value to the origin VM without waiting for the task to finish."
+    ),
+];
+
+pub fn spec() -> &'static [Instruction] {
+    SPEC
+}
+
+pub fn by_mnemonic(mnemonic: &str) -> Option<&'static Instruction> {
+    SPEC.iter().find(|i| i.mnemonic == mnemonic)
+}
+
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Push(_) => "Push",
+            OpCode::PushN(_) => "PushN",
+            OpCode::Add => "Add",
+            OpCode::Mul => "Mul",
+            OpCode::AddImm(_) => "AddImm",
+            OpCode::MulImm(_) => "MulImm",
+            OpCode::Div => "Div",
+            OpCode::AddChecked => "AddChecked",
+            OpCode::AddSaturating => "AddSaturating",
+            OpCode::MulChecked => "MulChecked",
+            OpCode::MulSaturating => "MulSaturating",
+            OpCode::MulWide => "MulWide",
+            OpCode::DivWide => "DivWide",
+            OpCode::And => "And",
+            OpCode::Or => "Or",
+            OpCode::Xor => "Xor",
+            OpCode::Not => "Not",
+            OpCode::Shl => "Shl",
+            OpCode::Shr => "Shr",
+            OpCode::Compare => "Compare",
+            OpCode::Test => "Test",
+            OpCode::DumpDebug => "DumpDebug",
+            OpCode::Jump(_, _) => "Jump",
+            OpCode::JumpToSubroutine(_) => "JumpToSubroutine",
+            OpCode::TailCall(_, _) => "TailCall",
+            OpCode::Bury(_) => "Bury",
+            OpCode::Dredge(_) => "Dredge",
+            OpCode::Duplicate => "Duplicate",
+            OpCode::Return => "Return",
+            OpCode::Call(_) => "Call",
+            OpCode::CallReturn => "CallReturn",
+            OpCode::LoadLocal(_) => "LoadLocal",
+            OpCode::StoreLocal(_) => "StoreLocal",
+            OpCode::Pop => "Pop",
+            OpCode::Fork => "Fork",
+            OpCode::IsChild => "IsChild",
+            OpCode::Join(_) => "Join",
+            OpCode::JoinStatus(_) => "JoinStatus",
+            OpCode::Halt | OpCode::HaltWith(_) => "Halt",
+            OpCode::Store(_) => "Store",
+            OpCode::Load(_) => "Load",
+            OpCode::StoreRelative(_) => "StoreRelative",
+            OpCode::LoadRelative(_) => "LoadRelative",
+            OpCode::LoadPacked(_, _) => "LoadPacked",
+            OpCode::StorePacked(_, _) => "StorePacked",
+            OpCode::AllocGlobal(_) => "AllocGlobal",
+            OpCode::Alloc(_, _) => "Alloc",
+            OpCode::Free => "Free",
+            OpCode::Panic => "Panic",
+            OpCode::AssertEq => "AssertEq",
+            OpCode::AssertStackDepth(_) => "AssertStackDepth",
+            OpCode::Extension(_) => "Extension",
+            OpCode::CallNative(_) => "CallNative",
+            OpCode::Emit => "Emit",
+        }
+    }
+
+    pub fn instruction(&self) -> &'static Instruction {
+        let name = self.name();
+        SPEC.iter()
+            .find(|i| i.name == name)
+            .unwrap_or_else(|| panic!("OpCode {} missing from instruction spec", name))
+    }
+}
@@ -0,0 +1,66 @@
+//! What a program is made of, for `flock_asm --stats`.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{ByteCode, OpCode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub instructions: usize,
+    /// Instructions by mnemonic, most common first.
+    pub opcodes: Vec<(&'static str, usize)>,
+    /// Values written into `PUSH`, `PUSHN`, `ADDI` and `MULI`.
+    pub constants: usize,
+    pub distinct_constants: usize,
+    /// Bytes each part of the program takes in `ByteCode::to_bytes`, in encoding order.
+    pub sections: Vec<(&'static str, usize)>,
+}
+
+impl Stats {
+    pub fn bytes(&self) -> usize {
+        self.sections.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+impl ByteCode {
+    pub fn stats(&self) -> Stats {
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        let mut constants = Vec::new();
+        for op in &self.opcodes {
+            *counts.entry(op.instruction().mnemonic).or_default() += 1;
+            match op {
+                OpCode::Push(v) | OpCode::AddImm(v) | OpCode::MulImm(v) => constants.push(*v),
+                OpCode::PushN(values) => constants.extend(values),
+                _ => {}
+            }
+        }
+        let mut opcodes: Vec<_> = counts.into_iter().collect();
+        opcodes.sort_by_key(|&(mnemonic, count)| (std::cmp::Reverse(count), mnemonic));
+        Stats {
+            instructions: self.opcodes.len(),
+            opcodes,
+            constants: constants.len(),
+            distinct_constants: constants.iter().collect::<HashSet<_>>().len(),
+            sections: self.section_sizes(),
+        }
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{} instructions", self.instructions)?;
+        for (mnemonic, count) in &self.opcodes {
+            writeln!(f, "  {:<14} {:>8}", mnemonic, count)?;
+        }
+        writeln!(
+            f,
+            "{} constants, {} distinct",
+            self.constants, self.distinct_constants
+        )?;
+        writeln!(f, "{} bytes encoded", self.bytes())?;
+        for (section, bytes) in &self.sections {
+            writeln!(f, "  {:<14} {:>8}", section, bytes)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! The encoding `ByteCode` is sent between nodes and journaled in. Each instruction is a list
+//! of integers, a stable opcode number followed by its operands, so the format depends neither
+//! on `OpCode`'s declaration order nor on the host's integer widths.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use base64::Engine;
+use bincode::Options;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::features::{self, SUPPORTED};
+use crate::{Arena, ByteCode, ConditionFlags, OpCode, PackedWidth, Retry};
+
+/// Version of the instruction set, bumped whenever an existing opcode number or operand layout
+/// changes meaning. Adding instructions only needs new numbers and a feature bit, which older
+/// nodes reject with `MissingFeatures`.
+pub const VERSION: u32 = 1;
+
+/// Starts every `ByteCode::to_bytes` encoding, telling it apart from JSON and assembly.
+const MAGIC: &[u8] = b"FLBC";
+
+/// Bumped whenever the binary layout changes so older readers can't skip what they don't know.
+/// Fields appended to the end without bumping it are ignored by readers that predate them.
+pub const FORMAT: u8 = 1;
+
+/// Varint bincode, so small operands take a byte or two.
+fn binary() -> impl Options {
+    bincode::DefaultOptions::new().allow_trailing_bytes()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WireByteCode {
+    version: u32,
+    code: Vec<Vec<i64>>,
+    #[serde(default)]
+    loop_bounds: Vec<(u64, u64)>,
+    #[serde(default)]
+    idempotent: Vec<(u64, u64)>,
+    #[serde(default)]
+    retry: Option<Retry>,
+    #[serde(default)]
+    labels: Vec<(u64, String)>,
+    /// `Features` the code requires.
+    #[serde(default)]
+    features: u64,
+}
+
+/// Fields added after the first version are left out of human-readable encodings while unused,
+/// keeping them as they were. Binary encodings aren't self-describing, so they always have
+/// every field.
+impl Serialize for WireByteCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let all = !serializer.is_human_readable();
+        let mut s = serializer.serialize_struct("WireByteCode", 7)?;
+        s.serialize_field("version", &self.version)?;
+        s.serialize_field("code", &self.code)?;
+        s.serialize_field("loop_bounds", &self.loop_bounds)?;
+        if all || !self.idempotent.is_empty() {
+            s.serialize_field("idempotent", &self.idempotent)?;
+        }
+        if all || self.retry.is_some() {
+            s.serialize_field("retry", &self.retry)?;
+        }
+        if all || !self.labels.is_empty() {
+            s.serialize_field("labels", &self.labels)?;
+        }
+        if all || self.features != 0 {
+            s.serialize_field("features", &self.features)?;
+        }
+        s.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    EmptyInstruction,
+    UnsupportedVersion(u32),
+    /// Names of the features the program requires that this build doesn't implement.
+    MissingFeatures(Vec<String>),
+    UnknownOpCode(i64),
+    InvalidOperands(i64, Vec<i64>),
+    /// Not from `ByteCode::to_bytes`.
+    NotBinary,
+    /// From a newer `ByteCode::to_bytes` than this one.
+    UnsupportedFormat(u8),
+    Malformed(String),
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl From<ByteCode> for WireByteCode {
+    fn from(bytecode: ByteCode) -> WireByteCode {
+        WireByteCode {
+            version: VERSION,
+            features: bytecode.features().bits(),
+            code: bytecode.opcodes.iter().map(encode).collect(),
+            loop_bounds: bytecode
+                .loop_bounds
+                .iter()
+                .map(|(&pc, &n)| (pc as u64, n))
+                .collect(),
+            idempotent: bytecode
+                .idempotent
+                .iter()
+                .map(|(&start, &end)| (start as u64, end as u64))
+                .collect(),
+            retry: bytecode.retry,
+            labels: bytecode
+                .labels
+                .iter()
+                .map(|(&pc, label)| (pc as u64, label.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<WireByteCode> for ByteCode {
+    type Error = WireError;
+
+    fn try_from(wire: WireByteCode) -> Result<ByteCode, WireError> {
+        if wire.version != VERSION {
+            return Err(WireError::UnsupportedVersion(wire.version));
+        }
+        // Before the code, whose new instructions would only show up as unknown opcodes.
+        let missing = wire.features & !SUPPORTED.bits();
+        if missing != 0 {
+            return Err(WireError::MissingFeatures(features::names(missing)));
+        }
+        let opcodes = wire
+            .code
+            .into_iter()
+            .map(decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let loop_bounds: BTreeMap<usize, u64> = wire
+            .loop_bounds
+            .into_iter()
+            .filter_map(|(pc, n)| Some((usize::try_from(pc).ok()?, n)))
+            .collect();
+        let idempotent: BTreeMap<usize, usize> = wire
+            .idempotent
+            .into_iter()
+            .filter_map(|(start, end)| {
+                Some((usize::try_from(start).ok()?, usize::try_from(end).ok()?))
+            })
+            .collect();
+        let labels: BTreeMap<usize, String> = wire
+            .labels
+            .into_iter()
+            .filter_map(|(pc, label)| Some((usize::try_from(pc).ok()?, label)))
+            .collect();
+        Ok(ByteCode::from(opcodes)
+            .with_loop_bounds(loop_bounds)
+            .with_idempotent(idempotent)
+            .with_retry(wire.retry)
+            .with_labels(labels))
+    }
+}
+
+impl ByteCode {
+    /// Compact binary encoding: `FLBC`, the format byte, then the `WireByteCode` in varint bincode.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT);
+        binary()
+            .serialize_into(&mut bytes, &WireByteCode::from(self.clone()))
+            .expect("bytecode always serializes");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ByteCode, WireError> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or(WireError::NotBinary)?;
+        let (&format, payload) = rest.split_first().ok_or(WireError::NotBinary)?;
+        if format != FORMAT {
+            return Err(WireError::UnsupportedFormat(format));
+        }
+        let wire: WireByteCode = binary()
+            .deserialize(payload)
+            .map_err(|e| WireError::Malformed(e.to_string()))?;
+        ByteCode::try_from(wire)
+    }
+
+    /// Bytes each field takes in `to_bytes`, in order, the magic, format and version as `header`.
+    pub fn section_sizes(&self) -> Vec<(&'static str, usize)> {
+        fn size(value: &impl Serialize) -> usize {
+            binary().serialized_size(value).unwrap() as usize
+        }
+        let wire = WireByteCode::from(self.clone());
+        vec![
+            ("header", MAGIC.len() + 1 + size(&wire.version)),
+            ("code", size(&wire.code)),
+            ("loop_bounds", size(&wire.loop_bounds)),
+            ("idempotent", size(&wire.idempotent)),
+            ("retry", size(&wire.retry)),
+            ("labels", size(&wire.labels)),
+            ("features", size(&wire.features)),
+        ]
+    }
+
+    /// Whether `bytes` look like they're from `to_bytes`, of any format.
+    pub fn is_binary(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+}
+
+/// Bytecode serialized with `ByteCode::to_bytes`, as base64 for human-readable formats such as
+/// JSON RPCs.
+#[derive(Debug, Clone)]
+pub struct Compact(pub ByteCode);
+
+impl Serialize for Compact {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.0.to_bytes();
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(&bytes)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Compact {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Compact, D::Error> {
+        use serde::de::Error;
+
+        let bytes = if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(D::Error::custom)?
+        } else {
+            byte_buf(deserializer)?
+        };
+        ByteCode::from_bytes(&bytes)
+            .map(Compact)
+            .map_err(D::Error::custom)
+    }
+}
+
+fn byte_buf<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct Bytes;
+    impl<'de> serde::de::Visitor<'de> for Bytes {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "bytes")
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+    deserializer.deserialize_byte_buf(Bytes)
+}
+
+/// Encodes one instruction. Unsigned operands are stored as their two's complement bit pattern.
+pub fn encode(op: &OpCode) -> Vec<i64> {
+    let with_target = |code: i64, mut operands: Vec<i64>, target: &Option<i64>| {
+        operands.insert(0, code);
+        operands.extend(target);
+        operands
+    };
+    match op {
+        OpCode::Push(v) => vec![0, *v],
+        OpCode::Add => vec![1],
+        OpCode::Mul => vec![2],
+        OpCode::Div => vec![3],
+        OpCode::AddChecked => vec![4],
+        OpCode::AddSaturating => vec![5],
+        OpCode::MulChecked => vec![6],
+        OpCode::MulSaturating => vec![7],
+        OpCode::MulWide => vec![8],
+        OpCode::DivWide => vec![9],
+        OpCode::DumpDebug => vec![10],
+        OpCode::Jump(flags, target) => with_target(11, vec![flags.bits() as i64], target),
+        OpCode::JumpToSubroutine(target) => with_target(12, vec![], target),
+        OpCode::TailCall(depth, target) => with_target(13, vec![*depth], target),
+        OpCode::Bury(d) => vec![14, *d],
+        OpCode::Dredge(d) => vec![15, *d],
+        OpCode::Duplicate => vec![16],
+        OpCode::Return => vec![17],
+        OpCode::Pop => vec![18],
+        OpCode::Fork => vec![19],
+        OpCode::Join(n) => vec![20, *n],
+        OpCode::Halt => vec![21],
+        OpCode::Store(a) => vec![22, *a as i64],
+        OpCode::Load(a) => vec![23, *a as i64],
+        OpCode::StoreRelative(a) => vec![24, *a as i64],
+        OpCode::LoadRelative(a) => vec![25, *a as i64],
+        OpCode::Panic => vec![26],
+        OpCode::AssertEq => vec![27],
+        OpCode::AssertStackDepth(n) => vec![28, *n],
+        OpCode::Extension(code) => vec![29, *code as i64],
+        OpCode::CallNative(index) => vec![30, *index as i64],
+        OpCode::Emit => vec![31],
+        OpCode::IsChild => vec![32],
+        OpCode::LoadPacked(w, a) => vec![33, w.bits() as i64, *a as i64],
+        OpCode::StorePacked(w, a) => vec![34, w.bits() as i64, *a as i64],
+        OpCode::AllocGlobal(n) => vec![35, *n as i64],
+        OpCode::JoinStatus(n) => vec![36, *n],
+        OpCode::HaltWith(code) => vec![37, *code],
+        OpCode::PushN(values) => std::iter::once(38).chain(values.iter().cloned()).collect(),
+        OpCode::AddImm(v) => vec![39, *v],
+        OpCode::MulImm(v) => vec![40, *v],
+        OpCode::And => vec![41],
+        OpCode::Or => vec![42],
+        OpCode::Xor => vec![43],
+        OpCode::Not => vec![44],
+        OpCode::Shl => vec![45],
+        OpCode::Shr => vec![46],
+        OpCode::Compare => vec![47],
+        OpCode::Test => vec![48],
+        OpCode::Call(target) => with_target(49, vec![], target),
+        OpCode::CallReturn => vec![50],
+        OpCode::LoadLocal(n) => vec![51, *n],
+        OpCode::StoreLocal(n) => vec![52, *n],
+        OpCode::Alloc(arena, n) => vec![53, *arena as i64, *n as i64],
+        OpCode::Free => vec![54],
+    }
+}
+
+pub fn decode(instruction: Vec<i64>) -> Result<OpCode, WireError> {
+    let (&code, operands) = match instruction.split_first() {
+        Some(split) => split,
+        None => return Err(WireError::EmptyInstruction),
+    };
+    let invalid = || WireError::InvalidOperands(code, operands.to_vec());
+    let u16_operand = |v: i64| u16::try_from(v).map_err(|_| invalid());
+    let packed_width = |v: i64| {
+        u32::try_from(v)
+            .ok()
+            .and_then(PackedWidth::from_bits)
+            .ok_or_else(invalid)
+    };
+    let arena = |v: i64| match v {
+        0 => Ok(Arena::Shared),
+        1 => Ok(Arena::Task),
+        _ => Err(invalid()),
+    };
+    let op = match (code, operands) {
+        (0, &[v]) => OpCode::Push(v),
+        (1, &[]) => OpCode::Add,
+        (2, &[]) => OpCode::Mul,
+        (3, &[]) => OpCode::Div,
+        (4, &[]) => OpCode::AddChecked,
+        (5, &[]) => OpCode::AddSaturating,
+        (6, &[]) => OpCode::MulChecked,
+        (7, &[]) => OpCode::MulSaturating,
+        (8, &[]) => OpCode::MulWide,
+        (9, &[]) => OpCode::DivWide,
+        (10, &[]) => OpCode::DumpDebug,
+        (11, &[flags, ref target @ ..]) if target.len() <= 1 => {
+            let flags = u16::try_from(flags)
+                .ok()
+                .and_then(ConditionFlags::from_bits)
+                .ok_or_else(invalid)?;
+            OpCode::Jump(flags, target.first().cloned())
+        }
+        (12, target) if target.len() <= 1 => OpCode::JumpToSubroutine(target.first().cloned()),
+        (13, &[depth, ref target @ ..]) if target.len() <= 1 => {
+            OpCode::TailCall(depth, target.first().cloned())
+        }
+        (14, &[d]) => OpCode::Bury(d),
+        (15, &[d]) => OpCode::Dredge(d),
+        (16, &[]) => OpCode::Duplicate,
+        (17, &[]) => OpCode::Return,
+        (18, &[]) => OpCode::Pop,
+        (19, &[]) => OpCode::Fork,
+        (20, &[n]) => OpCode::Join(n),
+        (21, &[]) => OpCode::Halt,
+        (22, &[a]) => OpCode::Store(a as u64),
+        (23, &[a]) => OpCode::Load(a as u64),
+        (24, &[a]) => OpCode::StoreRelative(a as u64),
+        (25, &[a]) => OpCode::LoadRelative(a as u64),
+        (26, &[]) => OpCode::Panic,
+        (27, &[]) => OpCode::AssertEq,
+        (28, &[n]) => OpCode::AssertStackDepth(n),
+        (29, &[c]) => OpCode::Extension(u16_operand(c)?),
+        (30, &[i]) => OpCode::CallNative(u16_operand(i)?),
+        (31, &[]) => OpCode::Emit,
+        (32, &[]) => OpCode::IsChild,
+        (33, &[w, a]) => OpCode::LoadPacked(packed_width(w)?, a as u64),
+        (34, &[w, a]) => OpCode::StorePacked(packed_width(w)?, a as u64),
+        (35, &[n]) => OpCode::AllocGlobal(n as u64),
+        (36, &[n]) => OpCode::JoinStatus(n),
+        (37, &[code]) => OpCode::HaltWith(code),
+        (38, values) if !values.is_empty() => OpCode::PushN(values.to_vec()),
+        (39, &[v]) => OpCode::AddImm(v),
+        (40, &[v]) => OpCode::MulImm(v),
+        (41, &[]) => OpCode::And,
+        (42, &[]) => OpCode::Or,
+        (43, &[]) => OpCode::Xor,
+        (44, &[]) => OpCode::Not,
+        (45, &[]) => OpCode::Shl,
+        (46, &[]) => OpCode::Shr,
+        (47, &[]) => OpCode::Compare,
+        (48, &[]) => OpCode::Test,
+        (49, target) if target.len() <= 1 => OpCode::Call(target.first().cloned()),
+        (50, &[]) => OpCode::CallReturn,
+        (51, &[n]) => OpCode::LoadLocal(n),
+        (52, &[n]) => OpCode::StoreLocal(n),
+        (53, &[a, n]) => OpCode::Alloc(arena(a)?, n as u64),
+        (54, &[]) => OpCode::Free,
+        (0..=54, _) => return Err(invalid()),
+        _ => return Err(WireError::UnknownOpCode(code)),
+    };
+    Ok(op)
+}
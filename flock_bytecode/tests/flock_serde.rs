@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use flock_bytecode::flock_serde::{from_slice, to_vec, Error, Format};
+use flock_bytecode::{ByteCode, ConditionFlags, OpCode, Retry, ARTIFACT};
+
+fn bytecode() -> ByteCode {
+    let mut idempotent = BTreeMap::new();
+    idempotent.insert(1, 2);
+    let mut labels = BTreeMap::new();
+    labels.insert(2, "done".to_string());
+    ByteCode::from(vec![
+        OpCode::Push(-7),
+        OpCode::Jump(ConditionFlags::ZERO, Some(2)),
+        OpCode::Halt,
+    ])
+    .with_idempotent(idempotent)
+    .with_retry(Some(Retry {
+        attempts: 3,
+        backoff_ms: 10,
+    }))
+    .with_labels(labels)
+}
+
+#[test]
+fn bytecode_round_trips_in_both_formats() {
+    let original = bytecode();
+    for &format in &[Format::Json, Format::Binary] {
+        let bytes = to_vec(ARTIFACT, &original, format).unwrap();
+        let decoded: ByteCode = from_slice(ARTIFACT, &bytes).unwrap();
+
+        assert_eq!(decoded.len(), original.len(), "{:?}", format);
+        for i in 0..original.len() {
+            assert_eq!(decoded.get(i), original.get(i), "{:?}", format);
+        }
+        assert!(decoded.is_idempotent(1), "{:?}", format);
+        assert_eq!(decoded.retry(), original.retry(), "{:?}", format);
+        assert_eq!(
+            decoded.symbolize(2),
+            Some("done".to_string()),
+            "{:?}",
+            format
+        );
+    }
+}
+
+#[test]
+fn binary_is_smaller() {
+    let json = to_vec(ARTIFACT, &bytecode(), Format::Json).unwrap();
+    let binary = to_vec(ARTIFACT, &bytecode(), Format::Binary).unwrap();
+    assert!(binary.len() < json.len());
+}
+
+#[test]
+fn rejects_other_kinds() {
+    for &format in &[Format::Json, Format::Binary] {
+        let bytes = to_vec("dump", &bytecode(), format).unwrap();
+        match from_slice::<ByteCode>(ARTIFACT, &bytes) {
+            Err(Error::WrongKind { expected, found }) => {
+                assert_eq!((expected.as_str(), found.as_str()), (ARTIFACT, "dump"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut binary = to_vec(ARTIFACT, &bytecode(), Format::Binary).unwrap();
+    binary[6] = 2;
+    assert!(matches!(
+        from_slice::<ByteCode>(ARTIFACT, &binary),
+        Err(Error::UnsupportedVersion(2))
+    ));
+
+    let json = r#"{"flock_artifact": "bytecode", "version": 9, "data": null}"#;
+    assert!(matches!(
+        from_slice::<ByteCode>(ARTIFACT, json.as_bytes()),
+        Err(Error::UnsupportedVersion(9))
+    ));
+}
+
+#[test]
+fn source_is_not_an_artifact() {
+    assert!(matches!(
+        from_slice::<ByteCode>(ARTIFACT, b"  PUSH 1\n  HALT\n"),
+        Err(Error::NotAnArtifact)
+    ));
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use flock_bytecode::{ByteCode, OpCode};
+
+#[test]
+fn counts_opcodes_constants_and_encoded_bytes() {
+    let mut labels = BTreeMap::new();
+    labels.insert(1, "loop".to_string());
+    let bytecode = ByteCode::from(vec![
+        OpCode::Push(3),
+        OpCode::AddImm(-1),
+        OpCode::Duplicate,
+        OpCode::PushN(vec![3, 1 << 40]),
+        OpCode::Add,
+        OpCode::Add,
+        OpCode::Halt,
+    ])
+    .with_labels(labels);
+
+    let stats = bytecode.stats();
+    assert_eq!(stats.instructions, 7);
+    assert_eq!(
+        stats.opcodes,
+        vec![
+            ("ADD", 2),
+            ("ADDI", 1),
+            ("DUP", 1),
+            ("HALT", 1),
+            ("PUSH", 1),
+            ("PUSHN", 1)
+        ]
+    );
+    assert_eq!((stats.constants, stats.distinct_constants), (4, 3));
+    assert_eq!(stats.bytes(), bytecode.to_bytes().len());
+
+    let labels = stats.sections.iter().find(|(s, _)| *s == "labels").unwrap();
+    // Count, then the index and the length-prefixed name.
+    assert_eq!(labels.1, 1 + 1 + 1 + "loop".len());
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use flock_bytecode::features::Features;
+use flock_bytecode::wire::{decode, encode, Compact, WireError, FORMAT};
+use flock_bytecode::{Arena, ByteCode, ConditionFlags, OpCode, PackedWidth, Retry};
+
+fn every_opcode() -> Vec<OpCode> {
+    vec![
+        OpCode::Push(i64::MIN),
+        OpCode::Push(-1),
+        OpCode::Push(i64::MAX),
+        OpCode::PushN(vec![1, i64::MIN, 3]),
+        OpCode::Add,
+        OpCode::Mul,
+        OpCode::AddImm(-5),
+        OpCode::MulImm(i64::MAX),
+        OpCode::Div,
+        OpCode::AddChecked,
+        OpCode::AddSaturating,
+        OpCode::MulChecked,
+        OpCode::MulSaturating,
+        OpCode::MulWide,
+        OpCode::DivWide,
+        OpCode::And,
+        OpCode::Or,
+        OpCode::Xor,
+        OpCode::Not,
+        OpCode::Shl,
+        OpCode::Shr,
+        OpCode::Compare,
+        OpCode::Test,
+        OpCode::DumpDebug,
+        OpCode::Jump(ConditionFlags::EMPTY, Some(3)),
+        OpCode::Jump(ConditionFlags::ZERO | ConditionFlags::FORK, None),
+        OpCode::Jump(ConditionFlags::NOT_ZERO | ConditionFlags::NOT_FORK, Some(1)),
+        OpCode::Jump(ConditionFlags::all(), Some(2)),
+        OpCode::JumpToSubroutine(Some(7)),
+        OpCode::JumpToSubroutine(None),
+        OpCode::TailCall(2, Some(9)),
+        OpCode::TailCall(0, None),
+        OpCode::Call(Some(4)),
+        OpCode::Call(None),
+        OpCode::CallReturn,
+        OpCode::LoadLocal(0),
+        OpCode::StoreLocal(3),
+        OpCode::Bury(1),
+        OpCode::Dredge(2),
+        OpCode::Duplicate,
+        OpCode::Return,
+        OpCode::Pop,
+        OpCode::Fork,
+        OpCode::IsChild,
+        OpCode::Join(2),
+        OpCode::JoinStatus(1),
+        OpCode::Halt,
+        OpCode::HaltWith(-3),
+        OpCode::Store(u64::MAX),
+        OpCode::Load(0),
+        OpCode::StoreRelative(1 << 63),
+        OpCode::LoadRelative(5),
+        OpCode::LoadPacked(PackedWidth::U8, 7),
+        OpCode::StorePacked(PackedWidth::I32, u64::MAX),
+        OpCode::AllocGlobal(16),
+        OpCode::Alloc(Arena::Shared, 4),
+        OpCode::Alloc(Arena::Task, 1 << 20),
+        OpCode::Free,
+        OpCode::Panic,
+        OpCode::AssertEq,
+        OpCode::AssertStackDepth(4),
+        OpCode::Extension(u16::MAX),
+        OpCode::CallNative(3),
+        OpCode::Emit,
+    ]
+}
+
+#[test]
+fn every_opcode_round_trips() {
+    for op in every_opcode() {
+        assert_eq!(decode(encode(&op)), Ok(op.clone()), "{:?}", op);
+    }
+}
+
+#[test]
+fn bytecode_round_trips_through_json() {
+    let mut bounds = BTreeMap::new();
+    bounds.insert(4, 100);
+    let mut idempotent = BTreeMap::new();
+    idempotent.insert(2, 6);
+    let retry = Retry {
+        attempts: 5,
+        backoff_ms: 20,
+    };
+    let mut labels = BTreeMap::new();
+    labels.insert(3, "body".to_string());
+    let bytecode = ByteCode::from(every_opcode())
+        .with_loop_bounds(bounds)
+        .with_idempotent(idempotent)
+        .with_retry(Some(retry))
+        .with_labels(labels);
+
+    let json = serde_json::to_string(&bytecode).unwrap();
+    let decoded: ByteCode = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(decoded.len(), bytecode.len());
+    for i in 0..bytecode.len() {
+        assert_eq!(decoded.get(i), bytecode.get(i));
+    }
+    assert_eq!(decoded.loop_bound(4), Some(100));
+    assert_eq!(
+        (0..8).map(|i| decoded.is_idempotent(i)).collect::<Vec<_>>(),
+        vec![false, false, true, true, true, true, false, false]
+    );
+    assert_eq!(decoded.retry(), Some(retry));
+    assert_eq!(decoded.symbolize(2), None);
+    assert_eq!(decoded.symbolize(3), Some("body".to_string()));
+    assert_eq!(decoded.symbolize(5), Some("body+2".to_string()));
+}
+
+#[test]
+fn encoding_is_stable() {
+    let bytecode = ByteCode::from(vec![
+        OpCode::Push(1),
+        OpCode::Jump(ConditionFlags::FORK, Some(3)),
+        OpCode::Halt,
+    ]);
+    assert_eq!(
+        serde_json::to_string(&bytecode).unwrap(),
+        r#"{"version":1,"code":[[0,1],[11,2,3],[21]],"loop_bounds":[]}"#
+    );
+}
+
+#[test]
+fn rejects_other_versions() {
+    let result = serde_json::from_str::<ByteCode>(r#"{"version":2,"code":[[21]]}"#);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("UnsupportedVersion(2)"));
+}
+
+#[test]
+fn header_lists_required_features() {
+    let bytecode = ByteCode::from(vec![
+        OpCode::AddImm(1),
+        OpCode::Jump(ConditionFlags::NOT_ZERO, Some(0)),
+        OpCode::Halt,
+    ]);
+    assert_eq!(bytecode.features(), Features::IMMEDIATES);
+    assert_eq!(
+        serde_json::to_string(&bytecode).unwrap(),
+        r#"{"version":1,"code":[[39,1],[11,4,0],[21]],"loop_bounds":[],"features":1}"#
+    );
+
+    let bytecode = ByteCode::from(vec![
+        OpCode::Call(Some(2)),
+        OpCode::Jump(ConditionFlags::NEGATIVE, Some(0)),
+        OpCode::CallReturn,
+    ]);
+    assert_eq!(
+        bytecode.features(),
+        Features::COMPARE | Features::CALL_FRAMES
+    );
+    let decoded = ByteCode::from_bytes(&bytecode.to_bytes()).unwrap();
+    assert_eq!(decoded.features(), bytecode.features());
+}
+
+#[test]
+fn rejects_features_it_lacks() {
+    let json = format!(
+        r#"{{"version":1,"code":[[21]],"features":{}}}"#,
+        Features::BITWISE.bits() | 1 << 40 | 1 << 63
+    );
+    let result = serde_json::from_str::<ByteCode>(&json);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains(r#"MissingFeatures(["bit 40", "bit 63"])"#));
+}
+
+#[test]
+fn rejects_malformed_instructions() {
+    assert_eq!(decode(vec![]), Err(WireError::EmptyInstruction));
+    assert_eq!(decode(vec![99]), Err(WireError::UnknownOpCode(99)));
+    assert_eq!(decode(vec![0]), Err(WireError::InvalidOperands(0, vec![])));
+    assert_eq!(
+        decode(vec![11, 0b100_0000_0000]),
+        Err(WireError::InvalidOperands(11, vec![0b100_0000_0000]))
+    );
+    assert_eq!(
+        decode(vec![33, 12, 0]),
+        Err(WireError::InvalidOperands(33, vec![12, 0]))
+    );
+    assert_eq!(
+        decode(vec![29, 1 << 16]),
+        Err(WireError::InvalidOperands(29, vec![1 << 16]))
+    );
+}
+
+#[test]
+fn binary_round_trips_and_is_smaller() {
+    let mut labels = BTreeMap::new();
+    labels.insert(3, "body".to_string());
+    let bytecode = ByteCode::from(every_opcode())
+        .with_retry(Some(Retry {
+            attempts: 2,
+            backoff_ms: 5,
+        }))
+        .with_labels(labels);
+
+    let bytes = bytecode.to_bytes();
+    assert!(ByteCode::is_binary(&bytes));
+    let decoded = ByteCode::from_bytes(&bytes).unwrap();
+    for i in 0..bytecode.len() {
+        assert_eq!(decoded.get(i), bytecode.get(i));
+    }
+    assert_eq!(decoded.len(), bytecode.len());
+    assert_eq!(decoded.retry(), bytecode.retry());
+    assert_eq!(decoded.symbolize(4), Some("body+1".to_string()));
+    assert!(bytes.len() * 2 < serde_json::to_vec(&bytecode).unwrap().len());
+
+    let compact = serde_json::to_string(&Compact(bytecode)).unwrap();
+    let Compact(decoded) = serde_json::from_str(&compact).unwrap();
+    assert_eq!(decoded.len(), every_opcode().len());
+}
+
+#[test]
+fn binary_checks_its_header() {
+    let mut bytes = ByteCode::from(vec![OpCode::Halt]).to_bytes();
+    assert_eq!(
+        ByteCode::from_bytes(b"{\"version\":1}").unwrap_err(),
+        WireError::NotBinary
+    );
+
+    // Fields a later release appends are skipped.
+    bytes.extend([7, 7, 7]);
+    assert_eq!(ByteCode::from_bytes(&bytes).unwrap().len(), 1);
+
+    bytes[4] = FORMAT + 1;
+    assert_eq!(
+        ByteCode::from_bytes(&bytes).unwrap_err(),
+        WireError::UnsupportedFormat(FORMAT + 1)
+    );
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use flock_asm::{
+    compiler::validate_operands,
+    parser::parse_asm,
+    statement::{Argument, Span, Spanned, Statement},
+};
+use flock_bytecode::spec;
+
+pub struct Analysis<'s> {
+    lines: Vec<Line<'s>>,
+    labels: HashMap<&'s str, usize>,
+}
+
+struct Line<'s> {
+    text: &'s str,
+    statements: Option<Vec<Spanned<Statement<'s>>>>,
+}
+
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub message: String,
+}
+
+impl<'s> Analysis<'s> {
+    pub fn new(source: &'s str) -> Self {
+        let lines: Vec<Line> = source
+            .lines()
+            .map(|text| Line {
+                text,
+                statements: parse_asm(text).ok().map(|(_, statements)| statements),
+            })
+            .collect();
+
+        let labels = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                line.statements
+                    .iter()
+                    .flatten()
+                    .flat_map(move |statement| match &statement.value {
+                        Statement::LabelDefinition(label) => vec![(*label, i)],
+                        Statement::ValueDeclaration(label, _) => vec![(*label, i)],
+                        Statement::Directive("rept", Some(arg)) => arg
+                            .split_whitespace()
+                            .nth(1)
+                            .map(|counter| (counter, i))
+                            .into_iter()
+                            .collect(),
+                        Statement::Directive("locals", Some(arg)) => {
+                            arg.split(',').map(|local| (local.trim(), i)).collect()
+                        }
+                        _ => vec![],
+                    })
+            })
+            .collect();
+
+        Analysis { lines, labels }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            let mut report = |span: Span, message: String| {
+                diagnostics.push(Diagnostic {
+                    line: i,
+                    column: span.column,
+                    length: span.length,
+                    message,
+                })
+            };
+            let statements = match &line.statements {
+                Some(s) => s,
+                None => {
+                    let whole_line = Span {
+                        line: i,
+                        column: 0,
+                        length: line.text.len(),
+                    };
+                    report(whole_line, "Unable to parse statement".to_string());
+                    continue;
+                }
+            };
+            for statement in statements {
+                if let Err(e) = validate_operands(&statement.value) {
+                    report(statement.span, e.to_string());
+                }
+                for reference in references(&statement.value) {
+                    if !self.labels.contains_key(reference) {
+                        report(
+                            statement.span,
+                            format!("Unresolved reference ${}", reference),
+                        );
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    pub fn definition(&self, line: usize, character: usize) -> Option<usize> {
+        let word = self.word_at(line, character)?;
+        let label = word.strip_prefix('$')?;
+        self.labels.get(label).cloned()
+    }
+
+    pub fn hover(&self, line: usize, character: usize) -> Option<String> {
+        let word = self.word_at(line, character)?;
+        if let Some(label) = word.strip_prefix('$') {
+            let defined = self.labels.get(label)?;
+            return Some(format!(
+                "`{}` defined on line {}: `{}`",
+                label,
+                defined + 1,
+                self.lines[*defined].text.trim()
+            ));
+        }
+        let instruction = spec::by_mnemonic(word)?;
+        Some(format!(
+            "`{}` ({})\n\nStack: `{}`\n\n{}",
+            instruction.mnemonic,
+            instruction.name,
+            instruction.stack_effect,
+            instruction.description
+        ))
+    }
+
+    fn word_at(&self, line: usize, character: usize) -> Option<&'s str> {
+        let text = self.lines.get(line)?.text;
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+        let character = character.min(text.len());
+        let start = text[..character]
+            .rfind(|c| !is_word(c))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let end = text[character..]
+            .find(|c| !is_word(c))
+            .map(|i| i + character)
+            .unwrap_or_else(|| text.len());
+        if start < end {
+            Some(&text[start..end])
+        } else {
+            None
+        }
+    }
+}
+
+fn references<'s>(statement: &Statement<'s>) -> Vec<&'s str> {
+    let mut references = Vec::new();
+    for argument in statement.arguments() {
+        argument_references(argument, &mut references);
+    }
+    references
+}
+
+fn argument_references<'s>(argument: &Argument<'s>, references: &mut Vec<&'s str>) {
+    match argument {
+        Argument::Reference(r) => references.push(r),
+        Argument::Expression(lhs, _, rhs) => {
+            argument_references(lhs, references);
+            argument_references(rhs, references);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+    },
+    request::{GotoDefinition, HoverRequest, Request as _},
+    *,
+};
+
+mod analysis;
+use analysis::Analysis;
+
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn main() -> DynResult<()> {
+    pretty_env_logger::init_timed();
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    Server {
+        connection,
+        documents: HashMap::new(),
+    }
+    .run()?;
+
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn run(&mut self) -> DynResult<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.handle_request(request)?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> DynResult<()> {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let TextDocumentPositionParams {
+                    text_document,
+                    position,
+                } = params.text_document_position_params;
+                let uri = text_document.uri;
+                let line = self.analyze(&uri, |a| {
+                    a.definition(position.line as usize, position.character as usize)
+                });
+                serde_json::to_value(line.map(|line| {
+                    GotoDefinitionResponse::Scalar(Location::new(uri.clone(), line_range(line, 0)))
+                }))?
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let TextDocumentPositionParams {
+                    text_document,
+                    position,
+                } = params.text_document_position_params;
+                let text = self.analyze(&text_document.uri, |a| {
+                    a.hover(position.line as usize, position.character as usize)
+                });
+                serde_json::to_value(text.map(|value| Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value,
+                    }),
+                    range: None,
+                }))?
+            }
+            method => {
+                log::warn!("Unhandled request {}", method);
+                serde_json::Value::Null
+            }
+        };
+        self.connection
+            .sender
+            .send(Message::Response(Response::new_ok(request.id, result)))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> DynResult<()> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                match params.content_changes.pop() {
+                    Some(change) => (params.text_document.uri, change.text),
+                    None => return Ok(()),
+                }
+            }
+            _ => return Ok(()),
+        };
+        self.documents.insert(uri.clone(), text);
+        self.publish_diagnostics(uri)
+    }
+
+    fn publish_diagnostics(&self, uri: Url) -> DynResult<()> {
+        let diagnostics = self
+            .analyze(&uri, |a| Some(a.diagnostics()))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|d| {
+                let range = Range::new(
+                    Position::new(d.line as u32, d.column as u32),
+                    Position::new(d.line as u32, (d.column + d.length) as u32),
+                );
+                let mut diagnostic = Diagnostic::new_simple(range, d.message);
+                diagnostic.severity = Some(DiagnosticSeverity::ERROR);
+                diagnostic
+            })
+            .collect();
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_string(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    fn analyze<T>(&self, uri: &Url, f: impl FnOnce(&Analysis) -> Option<T>) -> Option<T> {
+        let source = self.documents.get(uri)?;
+        f(&Analysis::new(source))
+    }
+}
+
+fn line_range(line: usize, length: usize) -> Range {
+    Range::new(
+        Position::new(line as u32, 0),
+        Position::new(line as u32, length as u32),
+    )
+}
@@ -1,109 +1,563 @@
 use flume::*;
 use serde::*;
+use std::cell::Cell;
 use std::collections::*;
 use std::io::*;
+use std::marker::PhantomData;
 use std::net::*;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::*;
+use std::time::{Duration, Instant};
+
+mod memory;
 
 pub type Result<T> = std::result::Result<T, RpcError>;
 
+type Peers = Arc<Mutex<HashMap<PeerId, Peer>>>;
+
+/// A connection's write half, locked on its own so writing to a peer that stopped reading only
+/// holds up writes to that peer.
+type Writer = Arc<Mutex<BufWriter<Box<dyn Write + Send>>>>;
+
+type TopicQueue = (
+    Sender<Message<serde_json::Value>>,
+    Receiver<Message<serde_json::Value>>,
+);
+
+/// Queues of messages by topic, created by the first subscription to it.
+type Topics = Arc<Mutex<HashMap<String, TopicQueue>>>;
+
+fn topic(topics: &Topics, name: &str) -> TopicQueue {
+    topics
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_insert_with(flume::unbounded)
+        .clone()
+}
+
+/// Queues `message` for the topic's channels, returning false if there are none to take it, so
+/// peers can't fill memory here with topics nobody reads.
+fn deliver(topics: &Topics, name: &str, message: Message<serde_json::Value>) -> bool {
+    let topics = topics.lock().unwrap();
+    // Besides those of channels, the map holds a receiver of its own.
+    match topics.get(name) {
+        Some((sender, _)) if sender.receiver_count() > 1 => sender.send(message).is_ok(),
+        _ => false,
+    }
+}
+
+/// Hooks called as a node's connections carry frames, pings included.
+pub trait Instrument: Send + Sync {
+    fn sent(&self, _peer: PeerId, _bytes: usize) {}
+    fn received(&self, _peer: PeerId, _bytes: usize) {}
+    /// The connection closed or failed.
+    fn dropped(&self, _peer: PeerId) {}
+}
+
+#[derive(Default)]
+struct Instruments(RwLock<Vec<Arc<dyn Instrument>>>);
+
+impl Instruments {
+    fn each(&self, f: impl Fn(&dyn Instrument)) {
+        for instrument in self.0.read().unwrap().iter() {
+            f(instrument.as_ref());
+        }
+    }
+}
+
+impl std::fmt::Debug for Instruments {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Instruments({})", self.0.read().unwrap().len())
+    }
+}
+
+/// Transport counters for one peer, from `Node::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Received, but not yet taken from `Node::messages` or a `Channel`.
+    pub queued: u64,
+    pub connected: bool,
+}
+
+/// Where a connection's reader hands off what it receives.
+#[derive(Debug)]
+struct Routes<M> {
+    messages: Sender<Message<M>>,
+    events: Sender<Event>,
+    topics: Topics,
+    instruments: Arc<Instruments>,
+}
+
+impl<M> Clone for Routes<M> {
+    fn clone(&self) -> Self {
+        Routes {
+            messages: self.messages.clone(),
+            events: self.events.clone(),
+            topics: self.topics.clone(),
+            instruments: self.instruments.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Node<M> {
     messages: Receiver<Message<M>>,
-    messages_tx: Sender<Message<M>>,
-    peers: Arc<Mutex<HashMap<PeerId, Peer>>>,
+    events: Receiver<Event>,
+    routes: Routes<M>,
+    peers: Peers,
+    /// Dropped with the node to stop the heartbeat thread.
+    heartbeat_stop: Option<Sender<()>>,
+}
+
+/// How often to ping peers, and how long one can go unheard from before it's unresponsive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// What's on the wire, so pings can share the stream with messages.
+#[derive(Debug, Serialize, Deserialize)]
+enum Frame<M> {
+    Ping,
+    Pong,
+    Message(M),
+    /// A message for the `Channel` of that name.
+    Topic(String, serde_json::Value),
+}
+
+impl<M> Frame<M> {
+    fn is_message(&self) -> bool {
+        matches!(self, Frame::Message(_) | Frame::Topic(..))
+    }
+}
+
+/// Counts bytes read through it, to attribute them to frames.
+struct Counting<R> {
+    inner: R,
+    count: Rc<Cell<usize>>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n);
+        Ok(n)
+    }
 }
 
 fn spawn_stream_worker<'de, M: Deserialize<'de> + Send + 'static>(
-    stream: TcpStream,
-    message_sender: &Sender<Message<M>>,
-    peers: &Mutex<HashMap<PeerId, Peer>>,
-) -> Result<()> {
-    let peer = Peer::new(stream.try_clone()?)?;
-    let peer_id = peer.id();
+    peer_id: PeerId,
+    stream: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+    routes: &Routes<M>,
+    peers: &Peers,
+) {
+    let peer = Peer::new(writer, routes.instruments.clone());
     peers.lock().unwrap().insert(peer_id, peer);
 
-    let message_tx = message_sender.clone();
+    let routes = routes.clone();
+    let peers = peers.clone();
     std::thread::spawn(move || {
-        let de = serde_json::Deserializer::new(serde_json::de::IoRead::new(BufReader::new(stream)))
-            .into_iter();
-        for message in de {
-            message_tx
-                .send(Message {
-                    peer: peer_id,
-                    contents: message.unwrap(),
-                })
-                .unwrap();
+        let count = Rc::new(Cell::new(0));
+        let reader = Counting {
+            inner: BufReader::new(stream),
+            count: count.clone(),
+        };
+        let de = serde_json::Deserializer::new(serde_json::de::IoRead::new(reader)).into_iter();
+        for frame in de {
+            let frame: Frame<M> = match frame {
+                Ok(f) => f,
+                Err(_) => break,
+            };
+            let bytes = count.replace(0);
+            routes.instruments.each(|i| i.received(peer_id, bytes));
+            let mut pong_to = None;
+            if let Some(peer) = peers.lock().unwrap().get_mut(&peer_id) {
+                peer.stats.bytes_received += bytes as u64;
+                if frame.is_message() {
+                    peer.stats.messages_received += 1;
+                    peer.stats.queued += 1;
+                }
+                if peer.heard_from() {
+                    let _ = routes.events.send(Event::PeerResponsive(peer_id));
+                }
+                if let Frame::Ping = frame {
+                    pong_to = Some((peer_id, peer.writer.clone()));
+                }
+            }
+            if let Some(to) = pong_to {
+                // A failed write shows up as the connection dropping.
+                let _ = send(&peers, &to, &Frame::<()>::Pong);
+            }
+            match frame {
+                Frame::Message(contents) => routes
+                    .messages
+                    .send(Message {
+                        peer: peer_id,
+                        contents,
+                    })
+                    .unwrap(),
+                Frame::Topic(name, contents) => {
+                    let message = Message {
+                        peer: peer_id,
+                        contents,
+                    };
+                    if !deliver(&routes.topics, &name, message) {
+                        dequeued(&peers, peer_id);
+                    }
+                }
+                Frame::Ping | Frame::Pong => {}
+            }
         }
+
+        if let Some(peer) = peers.lock().unwrap().get_mut(&peer_id) {
+            peer.stats.connected = false;
+        }
+        routes.instruments.each(|i| i.dropped(peer_id));
     });
+}
+
+/// A message from `peer` was taken from its queue.
+fn dequeued(peers: &Peers, peer: PeerId) {
+    if let Some(peer) = peers.lock().unwrap().get_mut(&peer) {
+        peer.stats.queued = peer.stats.queued.saturating_sub(1);
+    }
+}
+
+fn spawn_tcp_worker<'de, M: Deserialize<'de> + Send + 'static>(
+    stream: TcpStream,
+    routes: &Routes<M>,
+    peers: &Peers,
+) -> Result<()> {
+    let peer_id = PeerId(Endpoint::Tcp(stream.peer_addr()?));
+    spawn_stream_worker(peer_id, stream.try_clone()?, stream, routes, peers);
     Ok(())
 }
 
+#[cfg(unix)]
+fn spawn_unix_worker<'de, M: Deserialize<'de> + Send + 'static>(
+    stream: UnixStream,
+    routes: &Routes<M>,
+    peers: &Peers,
+) -> Result<()> {
+    spawn_stream_worker(PeerId::local(), stream.try_clone()?, stream, routes, peers);
+    Ok(())
+}
+
+/// Pings every peer each `interval`, reporting those not heard from within `timeout`.
+fn spawn_heartbeat(heartbeat: Heartbeat, peers: Peers, events: Sender<Event>) -> Sender<()> {
+    let (stop_tx, stop_rx) = flume::bounded::<()>(0);
+    std::thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(heartbeat.interval) {
+            for (id, peer) in peers.lock().unwrap().iter_mut() {
+                if peer.check(heartbeat.timeout) {
+                    let _ = events.send(Event::PeerUnresponsive(*id));
+                }
+            }
+            for to in writers(&peers) {
+                // A failed write shows up as the peer going quiet.
+                let _ = send(&peers, &to, &Frame::<()>::Ping);
+            }
+        }
+    });
+    stop_tx
+}
+
 impl<'de, M: Deserialize<'de> + Serialize + Send + 'static> Node<M> {
     pub fn new(port: u16) -> Result<Node<M>> {
-        let (message_tx, message_rx) = flume::unbounded();
-
-        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let node = Node::unconnected();
 
-        let thread_messages = message_tx.clone();
-        let thread_peers = peers.clone();
+        let thread_routes = node.routes.clone();
+        let thread_peers = node.peers.clone();
         std::thread::spawn(move || {
             let listener = TcpListener::bind(("0.0.0.0", port)).unwrap();
             for stream in listener.incoming() {
-                spawn_stream_worker(stream.unwrap(), &thread_messages, &thread_peers).unwrap();
+                spawn_tcp_worker(stream.unwrap(), &thread_routes, &thread_peers).unwrap();
             }
         });
 
-        Ok(Node {
+        Ok(node)
+    }
+
+    /// Like `new`, but also pings peers to track whether they're still responding.
+    pub fn with_heartbeat(port: u16, heartbeat: Heartbeat) -> Result<Node<M>> {
+        let mut node = Node::new(port)?;
+        node.heartbeat(heartbeat);
+        Ok(node)
+    }
+
+    /// Listens on a unix socket at `path` instead of a TCP port, for nodes on the same host.
+    #[cfg(unix)]
+    pub fn new_unix(path: impl AsRef<Path>) -> Result<Node<M>> {
+        let node = Node::unconnected();
+        let listener = UnixListener::bind(path)?;
+
+        let thread_routes = node.routes.clone();
+        let thread_peers = node.peers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                spawn_unix_worker(stream.unwrap(), &thread_routes, &thread_peers).unwrap();
+            }
+        });
+
+        Ok(node)
+    }
+
+    /// Two nodes connected to each other in memory, for tests that shouldn't depend on sockets.
+    pub fn pair() -> (Node<M>, Node<M>) {
+        let (a, b) = (Node::unconnected(), Node::unconnected());
+        let (a_writer, b_reader) = memory::pipe();
+        let (b_writer, a_reader) = memory::pipe();
+        spawn_stream_worker(PeerId::local(), a_reader, a_writer, &a.routes, &a.peers);
+        spawn_stream_worker(PeerId::local(), b_reader, b_writer, &b.routes, &b.peers);
+        (a, b)
+    }
+
+    fn unconnected() -> Node<M> {
+        let (message_tx, message_rx) = flume::unbounded();
+        let (event_tx, event_rx) = flume::unbounded();
+        Node {
             messages: message_rx,
-            messages_tx: message_tx,
-            peers,
-        })
+            events: event_rx,
+            routes: Routes {
+                messages: message_tx,
+                events: event_tx,
+                topics: Default::default(),
+                instruments: Default::default(),
+            },
+            peers: Default::default(),
+            heartbeat_stop: None,
+        }
+    }
+
+    /// Pings peers to track whether they're still responding, replacing any earlier heartbeat.
+    pub fn heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat_stop = Some(spawn_heartbeat(
+            heartbeat,
+            self.peers.clone(),
+            self.routes.events.clone(),
+        ));
     }
 
     pub fn connect(&mut self, s: &str) -> Result<()> {
         let stream = TcpStream::connect(s)?;
-        spawn_stream_worker(stream, &self.messages_tx, &self.peers)?;
+        spawn_tcp_worker(stream, &self.routes, &self.peers)?;
 
         Ok(())
     }
 
-    pub fn broadcast(&mut self, message: M) -> Result<()> {
-        for peer in self.peers.lock().unwrap().values_mut() {
-            peer.send(&message)?;
-        }
+    #[cfg(unix)]
+    pub fn connect_unix(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let stream = UnixStream::connect(path)?;
+        spawn_unix_worker(stream, &self.routes, &self.peers)?;
+
         Ok(())
     }
 
+    pub fn broadcast(&mut self, message: M) -> Result<()> {
+        broadcast(&self.peers, &Frame::Message(&message))
+    }
+
     pub fn messages(&mut self) -> impl Iterator<Item = Message<M>> + '_ {
-        self.messages.iter()
+        let peers = &self.peers;
+        self.messages
+            .iter()
+            .inspect(move |m| dequeued(peers, m.peer))
+    }
+
+    /// Blocks for changes in peer liveness. Only sent with a `Heartbeat`.
+    pub fn events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.iter()
+    }
+
+    /// Messages of type `T` sent on `name` over the same connections as the node's own. Handles
+    /// for the same topic share its messages, which are queued until one is taken. Messages
+    /// arriving while there's no handle for their topic are dropped.
+    pub fn channel<T: Serialize + de::DeserializeOwned>(&self, name: &str) -> Channel<T> {
+        Channel {
+            name: name.to_string(),
+            messages: topic(&self.routes.topics, name).1,
+            peers: self.peers.clone(),
+            _contents: PhantomData,
+        }
+    }
+
+    /// Calls `instrument` for traffic on every connection from now on.
+    pub fn instrument(&mut self, instrument: Arc<dyn Instrument>) {
+        self.routes.instruments.0.write().unwrap().push(instrument);
+    }
+
+    pub fn stats(&self) -> HashMap<PeerId, PeerStats> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, peer)| (*id, peer.stats))
+            .collect()
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.peers.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Whether `peer` was heard from within the heartbeat timeout, as of the last ping. Always
+    /// `Alive` without a `Heartbeat`.
+    pub fn liveness(&self, peer: PeerId) -> Option<Liveness> {
+        self.peers.lock().unwrap().get(&peer).map(|p| p.liveness)
     }
 }
 
+/// A typed topic multiplexed over a `Node`'s connections, from `Node::channel`.
 #[derive(Debug)]
+pub struct Channel<T> {
+    name: String,
+    messages: Receiver<Message<serde_json::Value>>,
+    peers: Peers,
+    _contents: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + de::DeserializeOwned> Channel<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn broadcast(&mut self, message: T) -> Result<()> {
+        let frame = Frame::<()>::Topic(self.name.clone(), serde_json::to_value(&message)?);
+        broadcast(&self.peers, &frame)
+    }
+
+    /// Blocks for messages on this topic, failing those that aren't a `T`.
+    pub fn messages(&mut self) -> impl Iterator<Item = Result<Message<T>>> + '_ {
+        let peers = &self.peers;
+        self.messages.iter().map(move |m| {
+            dequeued(peers, m.peer);
+            Ok(Message {
+                peer: m.peer,
+                contents: serde_json::from_value(m.contents)?,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    Alive,
+    Unresponsive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Not heard from within the heartbeat timeout.
+    PeerUnresponsive(PeerId),
+    /// Heard from again after being unresponsive.
+    PeerResponsive(PeerId),
+}
+
 struct Peer {
-    peer_addr: SocketAddr,
-    writer: BufWriter<TcpStream>,
+    writer: Writer,
+    last_heard: Instant,
+    liveness: Liveness,
+    stats: PeerStats,
+    instruments: Arc<Instruments>,
+}
+
+impl std::fmt::Debug for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Peer")
+            .field("last_heard", &self.last_heard)
+            .field("liveness", &self.liveness)
+            .field("stats", &self.stats)
+            .finish()
+    }
 }
 
 impl Peer {
-    fn new(stream: TcpStream) -> Result<Peer> {
-        Ok(Peer {
-            peer_addr: stream.peer_addr()?,
-            writer: BufWriter::new(stream),
-        })
+    fn new(writer: impl Write + Send + 'static, instruments: Arc<Instruments>) -> Peer {
+        Peer {
+            writer: Arc::new(Mutex::new(BufWriter::new(Box::new(writer)))),
+            last_heard: Instant::now(),
+            liveness: Liveness::Alive,
+            stats: PeerStats {
+                bytes_sent: 0,
+                bytes_received: 0,
+                messages_sent: 0,
+                messages_received: 0,
+                queued: 0,
+                connected: true,
+            },
+            instruments,
+        }
     }
 
-    fn id(&self) -> PeerId {
-        PeerId(self.peer_addr)
+    /// Returns whether the peer was unresponsive until now.
+    fn heard_from(&mut self) -> bool {
+        self.last_heard = Instant::now();
+        std::mem::replace(&mut self.liveness, Liveness::Alive) == Liveness::Unresponsive
     }
 
-    fn send<M: Serialize>(&mut self, message: &M) -> Result<()> {
-        let mut ser = serde_json::Serializer::new(&mut self.writer);
-        message.serialize(&mut ser)?;
-        self.writer.flush()?;
-        Ok(())
+    /// Returns whether the peer just became unresponsive.
+    fn check(&mut self, timeout: Duration) -> bool {
+        if self.liveness == Liveness::Unresponsive || self.last_heard.elapsed() <= timeout {
+            return false;
+        }
+        self.liveness = Liveness::Unresponsive;
+        true
+    }
+
+}
+
+/// The writers of every connected peer, taken out so `peers` isn't locked while writing to them.
+fn writers(peers: &Peers) -> Vec<(PeerId, Writer)> {
+    let peers = peers.lock().unwrap();
+    peers
+        .iter()
+        .filter(|(_, p)| p.stats.connected)
+        .map(|(id, p)| (*id, p.writer.clone()))
+        .collect()
+}
+
+/// Sends `frame` to every connected peer, returning the first error once all were tried.
+fn broadcast<M: Serialize>(peers: &Peers, frame: &Frame<M>) -> Result<()> {
+    let mut result = Ok(());
+    for to in writers(peers) {
+        let sent = send(peers, &to, frame);
+        result = result.and(sent);
+    }
+    result
+}
+
+/// Writes `frame` to a peer from `writers`, only locking `peers` afterwards to count it.
+fn send<M: Serialize>(
+    peers: &Peers,
+    (id, writer): &(PeerId, Writer),
+    frame: &Frame<M>,
+) -> Result<()> {
+    let bytes = serde_json::to_vec(frame)?;
+    {
+        let mut writer = writer.lock().unwrap();
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+    }
+
+    if let Some(peer) = peers.lock().unwrap().get_mut(id) {
+        peer.stats.bytes_sent += bytes.len() as u64;
+        if frame.is_message() {
+            peer.stats.messages_sent += 1;
+        }
+        peer.instruments.each(|i| i.sent(*id, bytes.len()));
     }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -113,7 +567,21 @@ pub struct Message<M> {
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct PeerId(SocketAddr);
+pub struct PeerId(Endpoint);
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Endpoint {
+    Tcp(SocketAddr),
+    /// Unix socket and in-memory connections have no address telling them apart, so are numbered.
+    Local(u64),
+}
+
+impl PeerId {
+    fn local() -> PeerId {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        PeerId(Endpoint::Local(NEXT.fetch_add(1, Ordering::Relaxed)))
+    }
+}
 
 #[derive(Debug)]
 pub enum RpcError {
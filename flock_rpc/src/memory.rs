@@ -0,0 +1,58 @@
+//! Byte pipes standing in for a socket between nodes in the same process.
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use flume::{Receiver, Sender};
+
+pub(crate) struct PipeWriter(Sender<Vec<u8>>);
+
+pub(crate) struct PipeReader {
+    chunks: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+/// What's written to one end can be read from the other, which reaches the end once the writer
+/// is dropped.
+pub(crate) fn pipe() -> (PipeWriter, PipeReader) {
+    let (tx, rx) = flume::unbounded();
+    (
+        PipeWriter(tx),
+        PipeReader {
+            chunks: rx,
+            chunk: Vec::new(),
+            read: 0,
+        },
+    )
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.read == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.read = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.chunk.len() - self.read);
+        buf[..n].copy_from_slice(&self.chunk[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,112 @@
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use flock_rpc::{Channel, Node, RpcError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Task {
+    id: u64,
+    pc: usize,
+}
+
+/// A node listening on a free port, and another connected to it.
+fn connected() -> (Node<String>, Node<String>) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let listening = Node::new(port).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let mut connecting = Node::new(0).unwrap();
+    connecting.connect(&format!("127.0.0.1:{}", port)).unwrap();
+    (listening, connecting)
+}
+
+fn next<T: Serialize + serde::de::DeserializeOwned>(channel: &mut Channel<T>) -> T {
+    channel.messages().next().unwrap().unwrap().contents
+}
+
+#[test]
+fn topics_share_a_connection() {
+    let (mut listening, mut connecting) = connected();
+    let mut tasks = listening.channel::<Task>("tasks");
+    let mut admin = listening.channel::<Vec<String>>("admin");
+
+    connecting
+        .channel::<Vec<String>>("admin")
+        .broadcast(vec!["drain".to_string()])
+        .unwrap();
+    connecting
+        .channel("tasks")
+        .broadcast(Task { id: 7, pc: 12 })
+        .unwrap();
+    connecting.broadcast("untyped".to_string()).unwrap();
+
+    assert_eq!(next(&mut tasks), Task { id: 7, pc: 12 });
+    assert_eq!(next(&mut admin), vec!["drain".to_string()]);
+    assert_eq!(listening.messages().next().unwrap().contents, "untyped");
+}
+
+#[test]
+fn messages_of_another_type_fail() {
+    let (listening, connecting) = connected();
+    let mut tasks = listening.channel::<Task>("tasks");
+    connecting.channel("tasks").broadcast(42u64).unwrap();
+
+    assert!(matches!(
+        tasks.messages().next(),
+        Some(Err(RpcError::Parse(_)))
+    ));
+}
+
+#[test]
+fn messages_for_topics_nobody_reads_are_dropped() {
+    let (listening, connecting) = connected();
+    let mut untyped = listening.channel::<String>("untyped");
+    connecting
+        .channel("unread")
+        .broadcast("dropped".to_string())
+        .unwrap();
+    // Arrives after the first, on the same connection.
+    connecting
+        .channel("untyped")
+        .broadcast("marker".to_string())
+        .unwrap();
+    assert_eq!(next(&mut untyped), "marker");
+
+    let mut unread = listening.channel::<String>("unread");
+    connecting
+        .channel("unread")
+        .broadcast("kept".to_string())
+        .unwrap();
+    assert_eq!(next(&mut unread), "kept");
+    assert_eq!(listening.stats()[&listening.peers()[0]].queued, 0);
+}
+
+#[test]
+fn broadcasts_reach_peers_past_a_closed_connection() {
+    let (mut listening, mut connecting) = connected();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    connecting
+        .connect(&closed.local_addr().unwrap().to_string())
+        .unwrap();
+    drop(closed.accept().unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while connecting.stats().values().all(|s| s.connected) {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    connecting.broadcast("after".to_string()).unwrap();
+    connecting
+        .channel("tasks")
+        .broadcast(Task { id: 7, pc: 12 })
+        .unwrap();
+    assert_eq!(listening.messages().next().unwrap().contents, "after");
+}
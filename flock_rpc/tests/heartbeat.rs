@@ -0,0 +1,56 @@
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use flock_rpc::{Event, Heartbeat, Liveness, Node, PeerId};
+
+const HEARTBEAT: Heartbeat = Heartbeat {
+    interval: Duration::from_millis(20),
+    timeout: Duration::from_millis(100),
+};
+
+fn wait_for(node: &Node<i64>, peer: PeerId, liveness: Liveness) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while node.liveness(peer) != Some(liveness) {
+        assert!(Instant::now() < deadline, "{:?}", node.liveness(peer));
+        std::thread::sleep(HEARTBEAT.interval);
+    }
+}
+
+#[test]
+fn silent_peer_becomes_unresponsive_until_heard_from() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut node = Node::<i64>::with_heartbeat(0, HEARTBEAT).unwrap();
+    node.connect(&listener.local_addr().unwrap().to_string())
+        .unwrap();
+    let (mut silent, _) = listener.accept().unwrap();
+    let peer = node.peers()[0];
+
+    wait_for(&node, peer, Liveness::Unresponsive);
+    assert_eq!(node.events().next(), Some(Event::PeerUnresponsive(peer)));
+
+    silent.write_all(br#""Pong""#).unwrap();
+    wait_for(&node, peer, Liveness::Alive);
+    assert_eq!(node.events().next(), Some(Event::PeerResponsive(peer)));
+}
+
+#[test]
+fn node_answers_pings() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _other = Node::<i64>::new(port).unwrap();
+    let mut node = Node::<i64>::with_heartbeat(0, HEARTBEAT).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    node.connect(&format!("127.0.0.1:{}", port)).unwrap();
+    let peer = node.peers()[0];
+
+    std::thread::sleep(HEARTBEAT.timeout * 3);
+    assert_eq!(node.liveness(peer), Some(Liveness::Alive));
+}
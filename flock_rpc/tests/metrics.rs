@@ -0,0 +1,64 @@
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use flock_rpc::{Instrument, Node, PeerId};
+
+#[derive(Default)]
+struct Recorded {
+    received: Mutex<usize>,
+    dropped: Mutex<Vec<PeerId>>,
+}
+
+impl Instrument for Recorded {
+    fn received(&self, _: PeerId, bytes: usize) {
+        *self.received.lock().unwrap() += bytes;
+    }
+
+    fn dropped(&self, peer: PeerId) {
+        self.dropped.lock().unwrap().push(peer);
+    }
+}
+
+#[test]
+fn counts_traffic_and_queue_depth_per_peer() {
+    let (mut a, mut b) = Node::<String>::pair();
+    let recorded = Arc::new(Recorded::default());
+    b.instrument(recorded.clone());
+
+    a.broadcast("first".to_string()).unwrap();
+    a.channel("admin").broadcast(1u8).unwrap();
+    a.broadcast("second".to_string()).unwrap();
+    assert_eq!(b.messages().next().unwrap().contents, "first");
+    assert_eq!(b.messages().next().unwrap().contents, "second");
+
+    let sent = a.stats().into_iter().next().unwrap().1;
+    let received = b.stats().into_iter().next().unwrap().1;
+    assert_eq!(sent.messages_sent, 3);
+    assert_eq!(received.messages_received, 3);
+    assert_eq!(received.bytes_received, sent.bytes_sent);
+    assert_eq!(*recorded.received.lock().unwrap() as u64, sent.bytes_sent);
+    // The admin message hasn't been taken.
+    assert_eq!(received.queued, 1);
+    assert!(received.connected);
+}
+
+#[test]
+fn reports_closed_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut node = Node::<String>::new(0).unwrap();
+    let recorded = Arc::new(Recorded::default());
+    node.instrument(recorded.clone());
+    node.connect(&listener.local_addr().unwrap().to_string())
+        .unwrap();
+    drop(listener.accept().unwrap());
+    let peer = node.peers()[0];
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while recorded.dropped.lock().unwrap().is_empty() {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*recorded.dropped.lock().unwrap(), vec![peer]);
+    assert!(!node.stats()[&peer].connected);
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use flock_rpc::Node;
+
+#[test]
+fn pair_is_connected_both_ways() {
+    let (mut a, mut b) = Node::<String>::pair();
+    let mut tasks = b.channel::<u64>("tasks");
+
+    a.broadcast("to b".to_string()).unwrap();
+    a.channel("tasks").broadcast(3u64).unwrap();
+    b.broadcast("to a".to_string()).unwrap();
+
+    assert_eq!(b.messages().next().unwrap().contents, "to b");
+    assert_eq!(tasks.messages().next().unwrap().unwrap().contents, 3);
+    assert_eq!(a.messages().next().unwrap().contents, "to a");
+    assert_eq!(a.peers().len(), 1);
+    assert_eq!(b.peers().len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_carries_messages() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("flock_rpc_unix.sock");
+    let other = path.with_extension("other");
+    for p in &[&path, &other] {
+        let _ = std::fs::remove_file(p);
+    }
+    let mut listening = Node::<String>::new_unix(&path).unwrap();
+    let mut connecting = Node::<String>::new_unix(&other).unwrap();
+
+    connecting.connect_unix(&path).unwrap();
+    connecting.broadcast("hello".to_string()).unwrap();
+    let message = listening.messages().next().unwrap();
+    assert_eq!(message.contents, "hello");
+
+    assert_eq!(listening.peers(), vec![message.peer]);
+    listening.broadcast("back".to_string()).unwrap();
+    assert_eq!(connecting.messages().next().unwrap().contents, "back");
+}
@@ -0,0 +1,118 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+gflags::define! {
+    /// JSON lines file recording every operation requested by a peer.
+    pub --audit-log <PATH>: &str
+}
+
+gflags::define! {
+    /// Size at which the audit log is rotated to `<PATH>.1`.
+    pub --audit-log-max-bytes: u64 = 10_000_000
+}
+
+gflags::define! {
+    /// Number of rotated audit logs kept besides the current one.
+    pub --audit-log-keep: usize = 5
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    unix_ms: u128,
+    origin: Option<SocketAddr>,
+    operation: &'a str,
+    subject: u64,
+    size: usize,
+    duration_us: u128,
+    result: &'a str,
+}
+
+struct AuditLog {
+    path: &'static str,
+    file: File,
+    written: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref LOG: Option<Mutex<AuditLog>> = if AUDIT_LOG.is_present() {
+        Some(Mutex::new(AuditLog::open(AUDIT_LOG.flag)))
+    } else {
+        None
+    };
+}
+
+impl AuditLog {
+    fn open(path: &'static str) -> AuditLog {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Unable to open audit log {}: {}", path, e));
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        AuditLog {
+            path,
+            file,
+            written,
+        }
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written + line.len() as u64 > AUDIT_LOG_MAX_BYTES.flag {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let keep = AUDIT_LOG_KEEP.flag;
+        if keep == 0 {
+            std::fs::remove_file(self.path)?;
+        } else {
+            for n in (1..keep).rev() {
+                let from = format!("{}.{}", self.path, n);
+                if std::path::Path::new(&from).exists() {
+                    std::fs::rename(from, format!("{}.{}", self.path, n + 1))?;
+                }
+            }
+            std::fs::rename(self.path, format!("{}.1", self.path))?;
+        }
+        *self = AuditLog::open(self.path);
+        Ok(())
+    }
+}
+
+pub fn record(
+    origin: Option<SocketAddr>,
+    operation: &str,
+    subject: u64,
+    size: usize,
+    started: Instant,
+    result: &str,
+) {
+    let log = match &*LOG {
+        Some(log) => log,
+        None => return,
+    };
+    let entry = Entry {
+        unix_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis()),
+        origin,
+        operation,
+        subject,
+        size,
+        duration_us: started.elapsed().as_micros(),
+        result,
+    };
+    let line = serde_json::to_string(&entry).unwrap() + "\n";
+    if let Err(e) = log.lock().unwrap().write(&line) {
+        log::error!("Unable to write audit log: {}", e);
+    }
+}
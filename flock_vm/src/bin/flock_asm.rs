@@ -0,0 +1,180 @@
+use flock_bytecode::ByteCode;
+use flock_vm::asm::{
+    assemble_with,
+    diagnostic::Diagnostics,
+    expectations,
+    preprocess::{parse_defines, Defines, Extensions},
+};
+
+gflags::define! {
+    /// Comma-separated symbols for `.ifdef` and `$NAME` references, e.g. `-D LOCAL,FANOUT=4`.
+    -D, --define <DEFINES>: &str
+}
+
+gflags::define! {
+    /// How to print compilation errors: `human` or `json` (one object per line).
+    --error-format <FORMAT>: &str = "human"
+}
+
+gflags::define! {
+    /// Run optimization passes over the assembled program before executing it.
+    --optimize: bool = false
+}
+
+gflags::define! {
+    /// Print the control-flow graph in FORMAT (only `dot`) instead of running the program.
+    --emit-cfg <FORMAT>: &str
+}
+
+gflags::define! {
+    /// Print estimated instruction counts for the entry point and each subroutine, then exit.
+    --emit-costs: bool = false
+}
+
+gflags::define! {
+    /// Print instruction counts by opcode, how many constants the program holds and its encoded
+    /// size by section, then exit.
+    --stats: bool = false
+}
+
+gflags::define! {
+    /// Run the program and compare what it leaves against its `;; expect-stack:` and
+    /// `;; expect-memory:` annotations, exiting with 1 if any differ.
+    --check: bool = false
+}
+
+gflags::define! {
+    /// Write the assembled bytecode to PATH in `--artifact-format` instead of running it, or in
+    /// the compact binary encoding if PATH ends in `.flockc`. Passing that file to `flock_asm`
+    /// or `flock_vm --run` runs it without assembling again.
+    -o, --output <PATH>: &str
+}
+
+gflags::define! {
+    /// Older name for `--output`.
+    --emit-bytecode <PATH>: &str
+}
+
+gflags::define! {
+    /// Print the program as assembly, with labels for every jump target, instead of running it.
+    /// Useful on bytecode from `--output`.
+    --emit-asm: bool = false
+}
+
+gflags::define! {
+    /// Write the program to PATH as a WebAssembly module instead of running it. Programs that
+    /// fork, join or call out of the VM can't be written.
+    --emit-wasm <PATH>: &str
+}
+
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> DynResult<()> {
+    pretty_env_logger::init_timed();
+    let args = gflags::parse_os();
+
+    let file_path = args
+        .get(0)
+        .ok_or("Must provide 1 positional argument as file to compile")?;
+    let bytes = std::fs::read(file_path)?;
+    let (bytecode, contents) = match flock_vm::compiled::decode(&bytes) {
+        Ok(bytecode) => (bytecode, String::new()),
+        Err(e) if e.not_bytecode() => {
+            let contents = String::from_utf8(bytes)?;
+            (assemble(file_path, &contents)?, contents)
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let output = [&OUTPUT, &EMIT_BYTECODE].into_iter().find(|f| f.is_present());
+    if let Some(output) = output {
+        flock_vm::compiled::write(output.flag, &bytecode)?;
+        return Ok(());
+    }
+
+    if EMIT_WASM.is_present() {
+        std::fs::write(EMIT_WASM.flag, flock_vm::asm::wasm::to_wasm(&bytecode)?)?;
+        return Ok(());
+    }
+
+    if EMIT_ASM.flag {
+        print!("{}", flock_bytecode::disasm::disassemble(&bytecode));
+        return Ok(());
+    }
+
+    if EMIT_CFG.is_present() {
+        match EMIT_CFG.flag {
+            "dot" => print!("{}", bytecode.cfg().dot(&bytecode)),
+            format => return Err(format!("Unknown --emit-cfg format {:?}", format).into()),
+        }
+        return Ok(());
+    }
+
+    if EMIT_COSTS.flag {
+        for (entry, cost) in bytecode.function_costs() {
+            let worst = cost
+                .worst
+                .map_or("unbounded".to_string(), |w| w.to_string());
+            println!("{:04}: worst {}, typical {}", entry, worst, cost.typical);
+        }
+        return Ok(());
+    }
+
+    if STATS.flag {
+        print!("{}", bytecode.stats());
+        return Ok(());
+    }
+
+    if CHECK.flag {
+        let file = file_path.to_string_lossy();
+        let expectations = expectations(&file, &contents).unwrap_or_else(|d| report(d));
+        let mut vm = flock_vm::Vm::create()?;
+        let unmet = flock_vm::check(&mut vm, bytecode, &expectations)?;
+        for u in &unmet {
+            eprintln!(
+                "{}:{}: expected {}, found {}",
+                file,
+                u.expectation.span.line + 1,
+                u.expectation.value,
+                u.found
+            );
+        }
+        if !unmet.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{}: {} expectations met", file, expectations.len());
+        return Ok(());
+    }
+
+    flock_vm::run(bytecode)?;
+
+    Ok(())
+}
+
+fn assemble(file_path: &std::ffi::OsStr, contents: &str) -> DynResult<ByteCode> {
+    let defines = if DEFINE.is_present() {
+        parse_defines(DEFINE.flag)?
+    } else {
+        Defines::new()
+    };
+    match assemble_with(
+        &file_path.to_string_lossy(),
+        contents,
+        &defines,
+        &Extensions::new(),
+        OPTIMIZE.flag,
+    ) {
+        Ok(b) => Ok(b),
+        Err(diagnostics) => report(diagnostics),
+    }
+}
+
+fn report(diagnostics: Diagnostics) -> ! {
+    for diagnostic in diagnostics.0 {
+        match ERROR_FORMAT.flag {
+            "json" => eprintln!("{}", serde_json::to_string(&diagnostic).unwrap()),
+            _ => log::error!("{}", diagnostic),
+        }
+    }
+    std::process::exit(1);
+}
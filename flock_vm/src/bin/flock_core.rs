@@ -0,0 +1,36 @@
+use flock_vm::core_dump::CoreDump;
+
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> DynResult<()> {
+    let args = gflags::parse();
+    if args.len() != 1 {
+        return Err("Usage: flock_core <task>.flockdump".into());
+    }
+    let dump = CoreDump::read(args[0])?;
+    let at = |pc: usize| match dump.bytecode.symbolize(pc) {
+        Some(label) => format!("{} ({})", pc, label),
+        None => pc.to_string(),
+    };
+
+    println!("Task {} failed on {}", dump.task_id, dump.node);
+    println!("{}", dump.error.symbolized(&dump.bytecode));
+    println!();
+    print!("{}", dump.snapshot());
+    println!();
+    println!("Last {} instructions:", dump.trace.len());
+    for &pc in &dump.trace {
+        match dump.bytecode.get(pc) {
+            Some(op) => println!("  {:<16} {:?}", at(pc), op),
+            None => println!("  {}", at(pc)),
+        }
+    }
+    if !dump.memory.is_empty() {
+        println!();
+        println!("Memory:");
+        for (addr, value) in &dump.memory {
+            println!("  0x{:x} = {}", addr, value);
+        }
+    }
+    Ok(())
+}
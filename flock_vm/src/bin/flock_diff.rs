@@ -0,0 +1,52 @@
+use flock_bytecode::flock_serde;
+use flock_vm::dump::{self, Dump};
+
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> DynResult<()> {
+    let args = gflags::parse();
+    if args.len() != 2 {
+        return Err("Usage: flock_diff <dump a> <dump b>".into());
+    }
+    let a = load(args[0])?;
+    let b = load(args[1])?;
+
+    let divergences = [
+        first_divergence("store", &a.stores, &b.stores),
+        first_divergence("emitted value", &a.emitted, &b.emitted),
+        first_divergence(
+            "memory cell",
+            &a.memory.iter().collect::<Vec<_>>(),
+            &b.memory.iter().collect::<Vec<_>>(),
+        ),
+    ];
+    let mut diverged = false;
+    for divergence in divergences.iter().flatten() {
+        println!("{}", divergence);
+        diverged = true;
+    }
+    if diverged {
+        std::process::exit(1);
+    }
+    println!("No divergence");
+    Ok(())
+}
+
+fn load(path: &str) -> DynResult<Dump> {
+    Ok(flock_serde::read(path, dump::ARTIFACT)?)
+}
+
+fn first_divergence<T: PartialEq + std::fmt::Debug>(
+    what: &str,
+    a: &[T],
+    b: &[T],
+) -> Option<String> {
+    let index = (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i))?;
+    Some(format!(
+        "{} #{} differs: {:?} vs {:?}",
+        what,
+        index,
+        a.get(index),
+        b.get(index)
+    ))
+}
@@ -0,0 +1,13 @@
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> DynResult<()> {
+    let args = gflags::parse();
+    if args.is_empty() {
+        return Err("Usage: flock_status <host:port>...".into());
+    }
+    for addr in args {
+        let status = flock_vm::cluster::fetch_status(addr)?;
+        println!("{}", serde_json::to_string(&status)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "Usage: flockctl programs <host:port>...";
+
+fn main() -> DynResult<()> {
+    let args = gflags::parse();
+    match args.split_first() {
+        Some((&"programs", addrs)) if !addrs.is_empty() => {
+            for addr in addrs {
+                let programs = flock_vm::cluster::fetch_programs(addr)?;
+                println!("{}", serde_json::to_string(&programs)?);
+            }
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
@@ -0,0 +1,331 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cluster::{listen_port, remote_connections, Cluster, LISTEN};
+use crate::config::{config, setting};
+use crate::core_dump;
+use crate::coverage;
+use crate::deferred;
+use crate::identity::NodeIdentity;
+use crate::journal;
+use crate::reservations::Reservations;
+use crate::sandbox::OpCodePolicy;
+use crate::sanitize::SANITIZE;
+use crate::shared_memory::{self, SharedRegion};
+use crate::simulate;
+use crate::task_queue::{self, QueueOrder, TaskQueue};
+use crate::zone::Topology;
+use crate::{IntOverflow, Vm, VmHandle, AFFINITY_QUEUE_DEPTH, MAX_LOCAL_WORKERS};
+
+/// Configures a `Vm` without going through flags, so VMs configured differently can share a
+/// process.
+///
+/// ```
+/// let mut vm = flock_vm::Vm::builder().workers(2).build()?;
+/// let bytecode = flock_vm::asm::assemble("PUSH 5\nPUSH 8\nADD")?;
+/// assert_eq!(vm.execute(bytecode, &[])?, vec![13]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct VmBuilder {
+    workers: usize,
+    listen: Option<SocketAddr>,
+    peers: Vec<String>,
+    topology: Topology,
+    shared_memory: Option<(PathBuf, u64, u64)>,
+    simulate: Option<(usize, Duration)>,
+    coverage: bool,
+    sanitize: bool,
+    core_dumps: Option<PathBuf>,
+    remote_opcodes: OpCodePolicy,
+    affinity_queue_depth: usize,
+    ordered_completion: bool,
+    queue_order: (QueueOrder, Duration),
+    /// Added to every message served, for simulated nodes.
+    latency: Duration,
+    identity: Option<NodeIdentity>,
+    /// Replaces `--finished-ttl-secs` when set.
+    finished_ttl: Option<Duration>,
+    /// Replaces `--steal-back-after-ms` when set.
+    steal_back_after: Option<Duration>,
+    /// Replaces `--journal` when set.
+    journal: Option<PathBuf>,
+    /// Replaces `--max-fork-depth` when set.
+    max_fork_depth: Option<u64>,
+    /// Replaces `--int-overflow` when set.
+    int_overflow: Option<IntOverflow>,
+}
+
+impl Default for VmBuilder {
+    /// One worker per CPU, not listening and without peers, running the newest queued task
+    /// first and keeping tasks from peers while fewer than 8 wait.
+    fn default() -> Self {
+        VmBuilder {
+            workers: num_cpus::get(),
+            listen: None,
+            peers: Vec::new(),
+            topology: Topology::default(),
+            shared_memory: None,
+            simulate: None,
+            coverage: false,
+            sanitize: false,
+            core_dumps: None,
+            remote_opcodes: OpCodePolicy::default(),
+            affinity_queue_depth: 8,
+            ordered_completion: false,
+            queue_order: (QueueOrder::Lifo, Duration::MAX),
+            latency: Duration::ZERO,
+            identity: None,
+            finished_ttl: None,
+            steal_back_after: None,
+            journal: None,
+            max_fork_depth: None,
+            int_overflow: None,
+        }
+    }
+}
+
+impl VmBuilder {
+    /// Configured by `--max-local-workers`, `--listen-port`, `--listen`, `--remote-connections`,
+    /// `--zone`, `--shared-memory`, `--simulate-cluster`, `--coverage`, `--hot-spots`,
+    /// `--sanitize`, `--core-dump-dir`, `--queue-order`, `--remote-allowed-opcodes`, `--remote-denied-opcodes`,
+    /// `--affinity-queue-depth`, `--ordered-completion` and `--config`.
+    pub fn from_flags() -> VmBuilder {
+        let mut builder = VmBuilder::default()
+            .workers(local_workers())
+            .peers(remote_connections());
+        builder.topology = Topology::configured();
+        builder.shared_memory = shared_memory::configured();
+        builder.simulate = simulate::configured();
+        builder.coverage = coverage::configured();
+        builder.sanitize = SANITIZE.flag;
+        builder.core_dumps = core_dump::configured();
+        builder.remote_opcodes = OpCodePolicy::configured();
+        builder.affinity_queue_depth =
+            setting(&AFFINITY_QUEUE_DEPTH, &config().affinity_queue_depth);
+        builder.ordered_completion = deferred::configured();
+        builder.queue_order = task_queue::configured();
+        if setting(&LISTEN, &config().listen) {
+            builder.listen(SocketAddr::new(IpAddr::from([0, 0, 0, 0]), listen_port()))
+        } else {
+            builder
+        }
+    }
+
+    /// Threads running tasks locally.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Serve peers on `addr`, port 0 for any free one.
+    pub fn listen(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.listen = Some(addr.into());
+        self
+    }
+
+    /// Addresses of peers to send tasks to.
+    pub fn peers(mut self, peers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.peers = peers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Zone, such as a rack or region, this VM runs in.
+    pub fn zone(mut self, zone: impl Into<String>) -> Self {
+        self.topology.zone = Some(zone.into());
+        self
+    }
+
+    /// Zone of the peer at `addr`. Peers in other zones only get work same-zone ones can't take.
+    pub fn peer_zone(mut self, addr: impl Into<String>, zone: impl Into<String>) -> Self {
+        self.topology.peer_zones.insert(addr.into(), zone.into());
+        self
+    }
+
+    /// Maps `cells` addresses from `base` to the file at `path`, shared with every other VM on
+    /// the host that maps it. Peers sharing it aren't sent stores to those addresses.
+    pub fn shared_memory(mut self, path: impl Into<PathBuf>, base: u64, cells: u64) -> Self {
+        self.shared_memory = Some((path.into(), base, cells));
+        self
+    }
+
+    /// Runs `nodes` nodes in this process, this VM and leaves it sends tasks to, splitting the
+    /// workers between them. Every message between them is delayed by `latency` each way.
+    pub fn simulate_cluster(mut self, nodes: usize, latency: Duration) -> Self {
+        self.simulate = Some((nodes, latency));
+        self
+    }
+
+    /// Counts how often each instruction runs, see `Vm::coverage` and `Vm::hot_spots`.
+    pub fn coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    /// Reports data races between tasks run on this VM, see `Vm::races`.
+    pub fn sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// Writes a `.flockdump` of each task that fails here to `dir`, see `core_dump::CoreDump`.
+    pub fn core_dumps(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.core_dumps = Some(dir.into());
+        self
+    }
+
+    /// Instructions bytecode defined by peers may use. Peers sending bytecode with any other
+    /// run those tasks themselves.
+    pub fn remote_opcodes(mut self, policy: OpCodePolicy) -> Self {
+        self.remote_opcodes = policy;
+        self
+    }
+
+    /// Tasks from peers, and their children, are sent on to this VM's own peers only while more
+    /// than `depth` tasks wait here. 0 always sends them on.
+    pub fn affinity_queue_depth(mut self, depth: usize) -> Self {
+        self.affinity_queue_depth = depth;
+        self
+    }
+
+    /// Applies forked tasks' `EMIT`s and stores as they're joined, for output that doesn't
+    /// depend on timing. See `--ordered-completion`.
+    pub fn ordered_completion(mut self, ordered: bool) -> Self {
+        self.ordered_completion = ordered;
+        self
+    }
+
+    /// Which of a worker's own queued tasks it runs next. `max_age` caps how long one waits
+    /// behind newer ones under `QueueOrder::Hybrid`.
+    pub fn queue_order(mut self, order: QueueOrder, max_age: Duration) -> Self {
+        self.queue_order = (order, max_age);
+        self
+    }
+
+    /// How long results of tasks peers sent here are kept for them to claim before being
+    /// dropped. Defaults to `--finished-ttl-secs`.
+    pub fn finished_ttl(mut self, ttl: Duration) -> Self {
+        self.finished_ttl = Some(ttl);
+        self
+    }
+
+    /// How long a task may wait on a peer before idle workers here run it instead, if it's
+    /// `.idempotent`. Defaults to `--steal-back-after-ms`.
+    pub fn steal_back_after(mut self, after: Duration) -> Self {
+        self.steal_back_after = Some(after);
+        self
+    }
+
+    /// Records task transitions in the journal at `path`, recovering the tasks it left queued
+    /// and the results it left undelivered first. Defaults to `--journal`.
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal = Some(path.into());
+        self
+    }
+
+    /// Generations below the main task a task may be forked before failing with
+    /// `ForkDepthExceeded`. Defaults to `--max-fork-depth`.
+    pub fn max_fork_depth(mut self, depth: u64) -> Self {
+        self.max_fork_depth = Some(depth);
+        self
+    }
+
+    /// What arithmetic overflow does in programs started here, wherever their tasks run.
+    /// Defaults to `--int-overflow`.
+    pub fn int_overflow(mut self, mode: IntOverflow) -> Self {
+        self.int_overflow = Some(mode);
+        self
+    }
+
+    pub(crate) fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Used instead of the one persisted in `--node-id-file`.
+    pub(crate) fn identity(mut self, identity: NodeIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    pub fn build(mut self) -> std::io::Result<Vm> {
+        let mut simulated = Vec::new();
+        if let Some((nodes, latency)) = self.simulate.filter(|(n, _)| *n > 1) {
+            self.workers = std::cmp::max(1, self.workers / nodes);
+            self.latency = latency;
+            simulated = simulate::start_leaves(
+                nodes - 1,
+                self.workers,
+                latency,
+                self.queue_order,
+                self.coverage,
+            )?;
+            self.peers.extend(
+                simulated
+                    .iter()
+                    .map(|leaf| leaf.listen_addr().unwrap().to_string()),
+            );
+        }
+
+        let (order, max_age) = self.queue_order;
+        let task_queue = TaskQueue::new(order, max_age);
+        let region = match self.shared_memory {
+            Some((path, base, cells)) => Some(SharedRegion::open(&path, base, cells)?),
+            None => None,
+        };
+        let identity = self.identity.unwrap_or_else(NodeIdentity::load);
+        if self.sanitize && !self.peers.is_empty() {
+            log::warn!("Sanitizing only checks tasks run on this node, not those sent to peers");
+        }
+        let mut shared = VmHandle::new(
+            &task_queue,
+            region,
+            identity,
+            self.coverage,
+            self.sanitize,
+            self.remote_opcodes,
+            self.affinity_queue_depth,
+            self.journal.or_else(journal::configured),
+        );
+        shared.core_dumps = self.core_dumps;
+        shared.ordered_completion = self.ordered_completion;
+        if let Some(ttl) = self.finished_ttl {
+            shared.finished.set_ttl(ttl);
+        }
+        if let Some(after) = self.steal_back_after {
+            shared.reservations = Reservations::new(after);
+        }
+        if let Some(depth) = self.max_fork_depth {
+            shared.max_fork_depth = depth;
+        }
+        if let Some(mode) = self.int_overflow {
+            shared.int_overflow = mode;
+        }
+        let shared = Arc::new(shared);
+        let cluster = Cluster::connect_to(
+            &shared,
+            self.listen,
+            self.peers,
+            self.topology,
+            self.latency,
+        )?;
+        Ok(Vm {
+            cluster: Some(Arc::new(cluster)),
+            _simulated: simulated,
+            shared,
+            task_queue,
+            workers: Vec::new(),
+            hand_off: false,
+            program: 0,
+        }
+        .spawn_workers(self.workers))
+    }
+}
+
+pub(crate) fn local_workers() -> usize {
+    std::cmp::min(
+        num_cpus::get(),
+        setting(&MAX_LOCAL_WORKERS, &config().max_local_workers),
+    )
+}
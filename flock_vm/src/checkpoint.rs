@@ -0,0 +1,302 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// Also keep the `--journal` in this object store, so a node that loses its disk recovers from
+    /// it: `s3://BUCKET/PREFIX` (with the `s3` feature), or a directory. Give each node its own.
+    pub --checkpoint-store <URL>: &str
+}
+
+gflags::define! {
+    /// S3-compatible endpoint for `s3://` checkpoint stores, otherwise AWS in `AWS_REGION`.
+    pub --checkpoint-endpoint <URL>: &str
+}
+
+gflags::define! {
+    /// How often what was appended to the journal is uploaded.
+    pub --checkpoint-interval-secs: u64 = 30
+}
+
+gflags::define! {
+    /// Uncompressed size of each piece the journal is uploaded in. Only the last, partial one is
+    /// uploaded again when the journal grows.
+    pub --checkpoint-chunk-bytes: u64 = 4 << 20
+}
+
+/// Compression level for chunks, zstd's default.
+const LEVEL: i32 = 3;
+
+/// Somewhere to keep checkpoints off this node. Keys are `/`-separated relative paths.
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+
+    /// `None` if there is no such object.
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// Succeeds if there is no such object.
+    fn delete(&self, key: &str) -> std::io::Result<()>;
+}
+
+/// The store for `--checkpoint-store`, if any.
+pub(crate) fn configured() -> Option<Arc<dyn ObjectStore>> {
+    let url = if CHECKPOINT_STORE.is_present() {
+        CHECKPOINT_STORE.flag.to_string()
+    } else {
+        config().checkpoint_store.clone()?
+    };
+    match open(&url) {
+        Ok(store) => Some(store),
+        Err(e) => panic!("Unable to open checkpoint store {}: {}", url, e),
+    }
+}
+
+fn open(url: &str) -> std::io::Result<Arc<dyn ObjectStore>> {
+    if let Some(_location) = url.strip_prefix("s3://") {
+        #[cfg(feature = "s3")]
+        {
+            let endpoint = if CHECKPOINT_ENDPOINT.is_present() {
+                Some(CHECKPOINT_ENDPOINT.flag.to_string())
+            } else {
+                config().checkpoint_endpoint.clone()
+            };
+            return Ok(Arc::new(crate::s3::S3::from_env(_location, endpoint)?));
+        }
+        #[cfg(not(feature = "s3"))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "flock_vm was built without the s3 feature",
+        ));
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    Ok(Arc::new(Directory::new(path)?))
+}
+
+/// Objects as files under a directory, such as a network mount.
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    pub fn new(root: impl Into<PathBuf>) -> std::io::Result<Directory> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Directory { root })
+    }
+}
+
+impl ObjectStore for Directory {
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Renamed into place so a reader never sees part of an object.
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(partial, path)
+    }
+
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        let path = self.root.join(key);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        // Object stores have no directories, so one goes with its last object.
+        if let Some(parent) = path.parent().filter(|p| *p != self.root) {
+            let _ = std::fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+}
+
+/// Which upload of a file is current and how to put it back together.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// Changes whenever the file is rewritten rather than appended to.
+    generation: String,
+    chunk_bytes: u64,
+    chunks: u64,
+    bytes: u64,
+}
+
+/// Keeps an append-only file in an object store as zstd-compressed chunks under `name/`.
+pub struct Checkpointer {
+    store: Arc<dyn ObjectStore>,
+    name: String,
+    path: PathBuf,
+    generation: String,
+    chunk_bytes: u64,
+    /// Chunks uploaded that were already full, so won't change.
+    full: u64,
+    synced: Option<u64>,
+    /// The upload this one replaces, deleted once it's in place.
+    superseded: Option<Manifest>,
+}
+
+impl Checkpointer {
+    /// Starts a new upload of the file at `path`, which was just written from scratch.
+    pub fn new(
+        store: Arc<dyn ObjectStore>,
+        name: &str,
+        path: impl Into<PathBuf>,
+        chunk_bytes: u64,
+    ) -> Checkpointer {
+        let superseded = match manifest(&*store, name) {
+            Ok(m) => m,
+            Err(e) => {
+                log::warn!("Unable to read checkpoint manifest of {}: {}", name, e);
+                None
+            }
+        };
+        Checkpointer {
+            store,
+            name: name.to_string(),
+            path: path.into(),
+            generation: format!("{:016x}", rand::random::<u64>()),
+            chunk_bytes: std::cmp::max(chunk_bytes, 1),
+            full: 0,
+            synced: None,
+            superseded,
+        }
+    }
+
+    /// Uploads whatever was appended since the last sync.
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let bytes = file.metadata()?.len();
+        if self.synced == Some(bytes) {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(self.full * self.chunk_bytes))?;
+        let mut rest = Vec::new();
+        file.take(bytes - self.full * self.chunk_bytes)
+            .read_to_end(&mut rest)?;
+        for (i, piece) in rest.chunks(self.chunk_bytes as usize).enumerate() {
+            let index = self.full + i as u64;
+            let compressed = zstd::encode_all(piece, LEVEL)?;
+            self.store
+                .put(&chunk_key(&self.name, &self.generation, index), &compressed)?;
+        }
+
+        let manifest = Manifest {
+            generation: self.generation.clone(),
+            chunk_bytes: self.chunk_bytes,
+            chunks: bytes.div_ceil(self.chunk_bytes),
+            bytes,
+        };
+        let json = serde_json::to_vec(&manifest).unwrap();
+        self.store.put(&manifest_key(&self.name), &json)?;
+        self.full = bytes / self.chunk_bytes;
+        self.synced = Some(bytes);
+
+        if let Some(old) = self.superseded.take() {
+            for index in 0..old.chunks {
+                self.store
+                    .delete(&chunk_key(&self.name, &old.generation, index))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs every `interval` on a thread until the returned `Syncing` is dropped, then once more.
+    pub fn spawn(mut self, interval: Duration) -> Syncing {
+        let (stop, stopped) = flume::bounded::<()>(0);
+        let thread = crate::spawn_named(format!("flock-checkpoint-{}", self.name), move || loop {
+            let last = matches!(
+                stopped.recv_timeout(interval),
+                Err(flume::RecvTimeoutError::Disconnected)
+            );
+            if let Err(e) = self.sync() {
+                log::error!("Unable to upload checkpoint of {}: {}", self.name, e);
+            }
+            if last {
+                return;
+            }
+        });
+        Syncing {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// A `Checkpointer` syncing in the background.
+pub struct Syncing {
+    stop: Option<flume::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for Syncing {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes the file last uploaded as `name` to `path`, if there is one. Returns whether there was.
+pub fn restore(store: &dyn ObjectStore, name: &str, path: &Path) -> std::io::Result<bool> {
+    let manifest = match manifest(store, name)? {
+        Some(m) => m,
+        None => return Ok(false),
+    };
+    let restoring = path.with_extension("restoring");
+    let mut file = File::create(&restoring)?;
+    for index in 0..manifest.chunks {
+        let key = chunk_key(name, &manifest.generation, index);
+        let compressed = store.get(&key)?.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, format!("Missing {}", key))
+        })?;
+        file.write_all(&zstd::decode_all(&compressed[..])?)?;
+    }
+    file.set_len(manifest.bytes)?;
+    file.sync_all()?;
+    std::fs::rename(restoring, path)?;
+    Ok(true)
+}
+
+fn manifest(store: &dyn ObjectStore, name: &str) -> std::io::Result<Option<Manifest>> {
+    match store.get(&manifest_key(name))? {
+        None => Ok(None),
+        Some(json) => serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+    }
+}
+
+fn manifest_key(name: &str) -> String {
+    format!("{}/manifest.json", name)
+}
+
+fn chunk_key(name: &str, generation: &str, index: u64) -> String {
+    format!("{}/{}/{:08}.zst", name, generation, index)
+}
+
+/// Chunk size and upload interval from `--checkpoint-chunk-bytes` and
+/// `--checkpoint-interval-secs`.
+pub(crate) fn schedule() -> (u64, Duration) {
+    (
+        setting(&CHECKPOINT_CHUNK_BYTES, &config().checkpoint_chunk_bytes),
+        Duration::from_secs(setting(
+            &CHECKPOINT_INTERVAL_SECS,
+            &config().checkpoint_interval_secs,
+        )),
+    )
+}
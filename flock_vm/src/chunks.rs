@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use flock_bytecode::{wire::WireError, ByteCode};
+
+/// Bytecode is relayed in pieces of this many bytes of its binary form.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+pub type ChunkHash = [u8; 32];
+
+pub fn hash(chunk: &[u8]) -> ChunkHash {
+    *blake3::hash(chunk).as_bytes()
+}
+
+/// Splits bytecode's binary form into chunks to relay.
+pub fn split(bytecode: &ByteCode) -> Vec<Vec<u8>> {
+    bytecode
+        .to_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+pub fn join(chunks: &[Arc<Vec<u8>>]) -> Result<ByteCode, WireError> {
+    let bytes: Vec<u8> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+    ByteCode::from_bytes(&bytes)
+}
+
+/// Chunks of relayed bytecode by content hash, served to peers assembling the same program.
+#[derive(Default)]
+pub struct Chunks(DashMap<ChunkHash, Arc<Vec<u8>>>);
+
+impl Chunks {
+    pub fn insert(&self, chunk: Vec<u8>) -> Arc<Vec<u8>> {
+        let chunk = Arc::new(chunk);
+        self.0.insert(hash(&chunk), chunk.clone());
+        chunk
+    }
+
+    pub fn get(&self, hash: &ChunkHash) -> Option<Arc<Vec<u8>>> {
+        self.0.get(hash).map(|c| c.clone())
+    }
+}
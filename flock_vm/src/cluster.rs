@@ -1,6 +1,23 @@
+use flock_bytecode::wire::Compact;
+use flock_bytecode::Arena;
 use serde::{Deserialize, Serialize};
 
-use crate::{ExecutionError, TaskOrder, VmHandle};
+use crate::{
+    audit,
+    chunks::{self, ChunkHash, Chunks},
+    config::{config, setting},
+    finished::TaskResult,
+    identity::NodeIdentity,
+    peer_stats::PeerStatus,
+    programs::ProgramInfo,
+    redact::Payload,
+    sandbox::{Active, Sandbox},
+    simulate,
+    watchdog::{self, TaskFailure},
+    zone::{Capacity, Slot, Topology},
+    Emitted, ExecutionError, TaskKey, TaskOrder, VmHandle,
+};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 use tokio_serde::formats::Json;
@@ -9,148 +26,627 @@ gflags::define! {
     pub --listen-port: u16 = 18454
 }
 
+gflags::define! {
+    /// Serve peers on `--listen-port`. Schedulers that only connect out can pass `--nolisten`.
+    pub --listen: bool = true
+}
+
 gflags::define! {
     --remote-connections: &str
 }
 
+gflags::define! {
+    /// Push bytecode to every peer when it is registered instead of on first use.
+    pub --prewarm-peers: bool = false
+}
+
+gflags::define! {
+    /// Prewarm bytecode larger than this by seeding each peer with a share of its chunks, which
+    /// peers then fetch from each other, so the origin uploads about one copy. 0 never relays.
+    pub --relay-bytecode-over-kib: u64 = 1024
+}
+
+gflags::define! {
+    /// Refuse tasks from peers with `Busy` while this many are already queued.
+    pub --max-queued-tasks: usize = 10000
+}
+
+/// How long a refused peer is asked to wait before sending more work.
+const BUSY_RETRY_AFTER: std::time::Duration = std::time::Duration::from_millis(100);
+
+pub fn listen_port() -> u16 {
+    setting(&LISTEN_PORT, &config().listen_port)
+}
+
+pub(crate) fn remote_connections() -> Vec<String> {
+    if REMOTE_CONNECTIONS.is_present() {
+        REMOTE_CONNECTIONS
+            .flag
+            .split(',')
+            .map(String::from)
+            .collect()
+    } else {
+        config().peers.clone()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
     Test,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub identity: NodeIdentity,
+    pub served: usize,
+    pub queued: usize,
+    pub peers: Vec<PeerStatus>,
+    /// Background tasks that have died, see `VmHandle::task_failures`.
+    #[serde(default)]
+    pub failures: Vec<TaskFailure>,
+}
+
+impl NodeStatus {
+    /// Whether a background task is down and wasn't restarted.
+    pub fn degraded(&self) -> bool {
+        self.failures.iter().any(|f| !f.restarted)
+    }
+}
+
+/// Asks the node at `addr` for its status.
+pub fn fetch_status(addr: &str) -> std::io::Result<NodeStatus> {
+    async {
+        let transport = tarpc::serde_transport::tcp::connect(addr, Json::default).await?;
+        let mut client =
+            ClusterServiceClient::new(tarpc::client::Config::default(), transport).spawn()?;
+        client.status(tarpc::context::current()).await
+    }
+    .await_block()
+}
+
+/// Asks the node at `addr` which bytecode it holds.
+pub fn fetch_programs(addr: &str) -> std::io::Result<Vec<ProgramInfo>> {
+    async {
+        let transport = tarpc::serde_transport::tcp::connect(addr, Json::default).await?;
+        let mut client =
+            ClusterServiceClient::new(tarpc::client::Config::default(), transport).spawn()?;
+        client.programs(tarpc::context::current()).await
+    }
+    .await_block()
+}
+
+/// A connected peer's client, identity and the address it was reached at.
+type Connection = (ClusterServiceClient, NodeIdentity, String);
+
 pub struct Cluster {
     runtime: Arc<Runtime>,
-    peers: Vec<ClusterServiceClient>,
+    /// The address served, and the task serving it.
+    listener: Option<(SocketAddr, tokio::task::JoinHandle<()>)>,
+    peers: std::sync::RwLock<Vec<Connection>>,
+    topology: Topology,
+    capacity: Arc<Capacity>,
+    /// Peers mapping the same `--shared-memory` region, by address.
+    co_located: dashmap::DashSet<String>,
     vm: Arc<VmHandle>,
 }
 
 impl Cluster {
-    pub fn connect(handle: &Arc<VmHandle>) -> Cluster {
-        let runtime = Arc::new(tokio::runtime::Runtime::new().unwrap());
+    /// Serves peers on `listen`, if given, and connects to `peers`.
+    pub fn connect_to(
+        handle: &Arc<VmHandle>,
+        listen: Option<SocketAddr>,
+        peers: Vec<String>,
+        topology: Topology,
+        latency: std::time::Duration,
+    ) -> std::io::Result<Cluster> {
+        let runtime = Arc::new(tokio::runtime::Runtime::new()?);
+        // Stops with the runtime, when the cluster is dropped.
+        runtime.spawn(evict_expired(handle.clone()));
 
-        runtime.spawn(ClusterServer::new(handle).listen());
+        let listener = match listen {
+            Some(addr) => {
+                let server = ClusterServer::new(handle).with_latency(latency);
+                let (addr, serve) = runtime.block_on(server.clone().bind(addr))?;
+                // Bound again on the same port if it dies, so peers reconnect to it.
+                let restart = move || {
+                    let server = server.clone();
+                    async move {
+                        let (_, serve) = server.bind(addr).await?;
+                        serve.await;
+                        Ok(())
+                    }
+                };
+                let serve = async move {
+                    serve.await;
+                    Ok(())
+                };
+                let name = format!("cluster listener on {}", addr);
+                let health = handle.health.clone();
+                Some((
+                    addr,
+                    runtime.spawn(watchdog::supervise(name, health, serve, restart)),
+                ))
+            }
+            None => None,
+        };
 
         // TODO(shelbyd): Include client in Cluster upon new connection.
         let peers = runtime.block_on(async {
-            if REMOTE_CONNECTIONS.is_present() {
-                let mut clients = Vec::new();
-                for addr in REMOTE_CONNECTIONS.flag.split(',') {
-                    let transport = tarpc::serde_transport::tcp::connect(addr, Json::default)
-                        .await
-                        .unwrap();
-                    let client =
-                        ClusterServiceClient::new(tarpc::client::Config::default(), transport)
-                            .spawn()
-                            .unwrap();
-                    clients.push(client);
-                }
-                clients
-            } else {
-                Vec::new()
+            let mut connections = Vec::new();
+            for addr in peers {
+                connections.push(connect_peer(addr).await?);
             }
-        });
+            Ok::<_, std::io::Error>(connections)
+        })?;
 
-        Cluster {
+        let cluster = Cluster {
             runtime,
-            peers,
+            listener,
+            peers: std::sync::RwLock::new(peers),
+            topology,
+            capacity: Arc::default(),
+            co_located: Default::default(),
             vm: handle.clone(),
+        };
+        for connection in cluster.connections() {
+            cluster.check_co_located(connection);
+        }
+        Ok(cluster)
+    }
+
+    /// Notes whether the peer maps the same shared memory, so stores to it can skip the peer.
+    fn check_co_located(&self, (mut client, identity, addr): Connection) {
+        let own = match &self.vm.shared_memory {
+            Some(s) => s.id(),
+            None => return,
+        };
+        let theirs = self
+            .runtime
+            .block_on(async move { client.shared_memory(tarpc::context::current()).await });
+        match theirs {
+            Ok(Some(id)) if id == own => {
+                log::info!("Peer {} shares memory {}", identity, id);
+                self.co_located.insert(addr);
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Unable to ask {} for its shared memory: {}", identity, e),
+        }
+    }
+
+    fn connections(&self) -> Vec<Connection> {
+        self.peers.read().unwrap().clone()
+    }
+
+    fn peer(&self, (client, identity, addr): Connection) -> Peer {
+        Peer {
+            client,
+            identity,
+            near: self.topology.remote_zone(&addr).is_none(),
+            capacity: self.capacity.clone(),
+            listen_port: self
+                .listener
+                .as_ref()
+                .map_or_else(listen_port, |(addr, _)| addr.port()),
+            runtime: self.runtime.clone(),
+            vm: self.vm.clone(),
         }
     }
 
     pub(crate) fn peers(&self) -> Vec<Peer> {
-        self.peers
-            .iter()
-            .map(|client| Peer {
-                client: client.clone(),
-                runtime: self.runtime.clone(),
-                vm: self.vm.clone(),
-            })
+        self.connections()
+            .into_iter()
+            .map(|c| self.peer(c))
+            .collect()
+    }
+
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().map(|(addr, _)| *addr)
+    }
+
+    pub fn peer_identities(&self) -> Vec<NodeIdentity> {
+        self.connections()
+            .into_iter()
+            .map(|(_, identity, _)| identity)
             .collect()
     }
 
+    /// Connects to a peer that joined after startup.
+    pub(crate) fn add_peer(&self, addr: String) -> std::io::Result<Peer> {
+        let connection = self.runtime.block_on(connect_peer(addr))?;
+        self.check_co_located(connection.clone());
+        self.peers.write().unwrap().push(connection.clone());
+        Ok(self.peer(connection))
+    }
+
+    /// Asks the most recently added peer to finish its queued work and shut down, and stops
+    /// sending it tasks.
+    pub(crate) fn drain_peer(&self) -> Option<Draining> {
+        let (client, identity, addr) = self.peers.write().unwrap().pop()?;
+        log::info!("Draining peer {} at {}", identity, addr);
+        let mut drain_client = client.clone();
+        let drained = self
+            .runtime
+            .block_on(async move { drain_client.drain(tarpc::context::current()).await });
+        if let Err(e) = drained {
+            log::error!("Unable to drain {}: {}", identity, e);
+        }
+        Some(Draining {
+            identity,
+            addr,
+            client,
+            runtime: self.runtime.clone(),
+        })
+    }
+
     pub(crate) fn store(&self, addr: u64, value: i64) {
         log::debug!("Storing remotely {} @ {:x}", value, addr);
-        for mut peer in self.peers() {
-            loop {
-                match peer.store(addr, value) {
-                    Ok(()) => break,
-                    Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => break,
-                    Err(e) => {
-                        log::error!("Store error: {}", e);
+        // Other zones get one copy each, which their first peer relays to the rest.
+        let shared = self
+            .vm
+            .shared_memory
+            .as_ref()
+            .is_some_and(|s| s.contains(addr));
+        let connections: Vec<_> = self
+            .connections()
+            .into_iter()
+            .filter(|c| !(shared && self.co_located.contains(&c.2)))
+            .collect();
+        let mut targets: Vec<(Connection, Vec<String>)> = Vec::new();
+        let mut relays: std::collections::HashMap<&str, usize> = Default::default();
+        for connection in connections.iter().cloned() {
+            match self.topology.remote_zone(&connection.2) {
+                Some(zone) => match relays.get(zone) {
+                    Some(&i) => targets[i].1.push(connection.2),
+                    None => {
+                        relays.insert(zone, targets.len());
+                        targets.push((connection, Vec::new()));
                     }
+                },
+                None => targets.push((connection, Vec::new())),
+            }
+        }
+
+        for (connection, relay_to) in targets {
+            for unreached in self.store_to(connection, addr, value, &relay_to) {
+                if let Some(c) = connections.iter().find(|c| c.2 == unreached) {
+                    self.store_to(c.clone(), addr, value, &[]);
+                }
+            }
+        }
+    }
+
+    /// Returns the peers in `relay_to` the store didn't reach.
+    fn store_to(
+        &self,
+        connection: Connection,
+        addr: u64,
+        value: i64,
+        relay_to: &[String],
+    ) -> Vec<String> {
+        let mut peer = self.peer(connection);
+        loop {
+            match peer.store(addr, value, relay_to) {
+                Ok(unreached) => return unreached,
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => return Vec::new(),
+                Err(e) => {
+                    log::error!("Store error: {}", e);
                 }
             }
         }
     }
+
+    pub(crate) fn prewarm(&self, id: u64, bytecode: &flock_bytecode::ByteCode) {
+        let connections = self.connections();
+        let threshold = setting(&RELAY_BYTECODE_OVER_KIB, &config().relay_bytecode_over_kib);
+        if threshold != 0 && connections.len() > 1 {
+            let chunks = chunks::split(bytecode);
+            let size: usize = chunks.iter().map(Vec::len).sum();
+            if size as u64 > threshold * 1024 {
+                return self.relay_bytecode(id, bytecode, chunks, connections);
+            }
+        }
+        log::info!("Pushing bytecode {} to {} peers", id, connections.len());
+        let defines = connections.into_iter().map(|(mut client, identity, _)| {
+            let bytecode = bytecode.clone();
+            async move {
+                match client
+                    .define_bytecode(tarpc::context::current(), id, Compact(bytecode))
+                    .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(r)) => log::warn!("{} refused bytecode {}: {:?}", identity, id, r),
+                    Err(e) => log::error!("Unable to push bytecode {} to {}: {}", id, identity, e),
+                }
+            }
+        });
+        self.runtime.block_on(futures::future::join_all(defines));
+    }
+
+    /// Seeds each peer with every `n`th chunk, then has them all assemble the bytecode from each
+    /// other. Peers that can't are sent it whole.
+    fn relay_bytecode(
+        &self,
+        id: u64,
+        bytecode: &flock_bytecode::ByteCode,
+        chunks: Vec<Vec<u8>>,
+        connections: Vec<Connection>,
+    ) {
+        log::info!(
+            "Relaying bytecode {} in {} chunks to {} peers",
+            id,
+            chunks.len(),
+            connections.len()
+        );
+        let mut manifest = Vec::new();
+        let mut shares = vec![Vec::new(); connections.len()];
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let seed = i % connections.len();
+            manifest.push((chunks::hash(&chunk), connections[seed].2.clone()));
+            shares[seed].push(chunk);
+        }
+
+        let seeds = connections.iter().cloned().zip(shares).map(
+            |((mut client, identity, _), share)| async move {
+                if let Err(e) = client.put_chunks(tarpc::context::current(), share).await {
+                    log::error!("Unable to seed {} with bytecode {}: {}", identity, id, e);
+                }
+            },
+        );
+        self.runtime.block_on(futures::future::join_all(seeds));
+
+        let assembles = connections.into_iter().map(|(mut client, identity, _)| {
+            let manifest = manifest.clone();
+            async move {
+                match client
+                    .assemble_bytecode(tarpc::context::current(), id, manifest)
+                    .await
+                {
+                    Ok(true) => return,
+                    Ok(false) => log::warn!("{} couldn't assemble bytecode {}", identity, id),
+                    Err(e) => log::warn!("Unable to relay bytecode {} to {}: {}", id, identity, e),
+                }
+                match client
+                    .define_bytecode(tarpc::context::current(), id, Compact(bytecode.clone()))
+                    .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(r)) => log::warn!("{} refused bytecode {}: {:?}", identity, id, r),
+                    Err(e) => log::error!("Unable to push bytecode {} to {}: {}", id, identity, e),
+                }
+            }
+        });
+        self.runtime.block_on(futures::future::join_all(assembles));
+    }
+
+    pub(crate) fn coverage(&self, id: u64) -> Vec<Vec<u64>> {
+        let requests = self
+            .connections()
+            .into_iter()
+            .map(|(mut client, identity, _)| async move {
+                client
+                    .coverage(tarpc::context::current(), id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::error!("Unable to fetch coverage from {}: {}", identity, e);
+                        Vec::new()
+                    })
+            });
+        self.runtime.block_on(futures::future::join_all(requests))
+    }
+
+    /// Has every peer cancel the submission's tasks.
+    pub(crate) fn cancel(&self, submission: u64) {
+        let requests = self
+            .connections()
+            .into_iter()
+            .map(|(mut client, identity, _)| async move {
+                if let Err(e) = client.cancel(tarpc::context::current(), submission).await {
+                    log::error!("Unable to cancel {} on {}: {}", submission, identity, e);
+                }
+            });
+        self.runtime.block_on(futures::future::join_all(requests));
+    }
+
+    pub(crate) fn redefine_bytecode(&self, id: u64, bytecode: &flock_bytecode::ByteCode) {
+        let peers = self.peers();
+        log::info!("Redefining bytecode {} on {} peers", id, peers.len());
+        for mut peer in peers {
+            match peer.redefine_bytecode(id, bytecode.clone()) {
+                Ok(Ok(())) => {}
+                Ok(Err(r)) => log::warn!("{:?} refused bytecode {}: {:?}", peer, id, r),
+                Err(e) => log::error!("Unable to redefine bytecode {} on {:?}: {}", id, peer, e),
+            }
+        }
+    }
+}
+
+impl Drop for Cluster {
+    /// Stops serving and closes the connections to peers, so the port is free again once the VM
+    /// is dropped. The runtime shuts down with the last `Peer` still using it.
+    fn drop(&mut self) {
+        if let Some((_, listener)) = &mut self.listener {
+            listener.abort();
+            let _ = futures::executor::block_on(listener);
+        }
+        self.peers.write().unwrap().clear();
+    }
+}
+
+pub(crate) struct Draining {
+    pub identity: NodeIdentity,
+    pub addr: String,
+    client: ClusterServiceClient,
+    runtime: Arc<Runtime>,
+}
+
+impl Draining {
+    /// Blocks until the peer has handed over every result and closed the connection.
+    pub(crate) fn wait(self) {
+        let mut client = self.client;
+        self.runtime.block_on(async move {
+            let mut interval = tokio::time::interval(core::time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if client.status(tarpc::context::current()).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+async fn connect_peer(addr: String) -> std::io::Result<Connection> {
+    let transport = tarpc::serde_transport::tcp::connect(&addr, Json::default).await?;
+    let mut client =
+        ClusterServiceClient::new(tarpc::client::Config::default(), transport).spawn()?;
+    let identity = client.identity(tarpc::context::current()).await?;
+    log::info!("Connected to peer {} at {}", identity, addr);
+    Ok((client, identity, addr))
 }
 
 pub(crate) enum RunError {
-    Execution(ExecutionError),
+    Busy(std::time::Duration),
+    /// The peer is shutting down and takes no more tasks.
+    Draining,
+    /// The peer's `--remote-denied-opcodes` or `--remote-allowed-opcodes` refuse an instruction
+    /// in the task's bytecode.
+    ForbiddenOpCode(String),
     ConnectionReset,
     Unknown,
 }
 
 pub struct Peer {
     client: ClusterServiceClient,
+    identity: NodeIdentity,
+    /// In this node's zone, or not tagged with another.
+    near: bool,
+    capacity: Arc<Capacity>,
+    /// Where the peer reaches this node for `EMIT`.
+    listen_port: u16,
     runtime: Arc<Runtime>,
     vm: Arc<VmHandle>,
 }
 
 impl Peer {
-    pub(crate) fn try_run(&mut self, task_order: &TaskOrder) -> Result<TaskOrder, RunError> {
-        log::info!("Requesting remote execution of task {}", task_order.id);
-        self.runtime.clone().block_on(async {
-            match self.run_loop(&task_order).await {
-                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => {
-                    Err(RunError::ConnectionReset)
-                }
-                Err(e) => {
-                    log::error!("{}", e);
-                    Err(RunError::Unknown)
-                }
-                Ok(Ok(to)) => Ok(to),
-                Ok(Err(e)) => Err(RunError::Execution(e)),
-            }
-        })
+    pub(crate) fn identity(&self) -> &NodeIdentity {
+        &self.identity
+    }
+
+    pub(crate) fn zone_slot(&self) -> Slot {
+        self.capacity.join(self.near)
     }
 
-    async fn run_loop(
+    /// Queues the task on the peer, defining its bytecode there first if needed.
+    pub(crate) fn submit(&mut self, task_order: &TaskOrder) -> Result<(), RunError> {
+        log::info!("Submitting task {} to {}", task_order.id, self.identity);
+        log::trace!(
+            "Task {} stack {}",
+            task_order.id,
+            Payload(&task_order.task.stack)
+        );
+        let started = std::time::Instant::now();
+        match self.runtime.clone().block_on(self.submit_loop(task_order)) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(Refused::Busy { retry_after })) => Err(RunError::Busy(retry_after)),
+            Ok(Err(Refused::Draining)) => Err(RunError::Draining),
+            Ok(Err(Refused::ForbiddenOpCode(m))) => Err(RunError::ForbiddenOpCode(m)),
+            Ok(Err(Refused::UnknownByteCode(id))) => unreachable!("bytecode {} was defined", id),
+            Err(e) => Err(self.failed(e, started)),
+        }
+    }
+
+    async fn submit_loop(
         &mut self,
         task_order: &TaskOrder,
-    ) -> std::io::Result<Result<TaskOrder, ExecutionError>> {
+    ) -> std::io::Result<Result<(), Refused>> {
+        let mut task_order = task_order.clone();
+        // The peer fills in our address as it sees it.
+        task_order.emit_to.get_or_insert(SocketAddr::new(
+            IpAddr::from([0, 0, 0, 0]),
+            self.listen_port,
+        ));
         loop {
-            use std::time::*;
-            let mut context = tarpc::context::current();
-            context.deadline = SystemTime::now() + Duration::from_secs(300);
             match self
                 .client
-                .run_to_completion(context, task_order.clone())
+                .submit(tarpc::context::current(), task_order.clone())
                 .await?
             {
-                Ok(result) => return Ok(result),
-                Err(UnknownByteCode(id)) => {
+                Err(Refused::UnknownByteCode(id)) => {
                     let bytecode = self.vm.bytecode_registry.get(&id).unwrap().as_ref().clone();
-                    self.client
-                        .define_bytecode(tarpc::context::current(), id, bytecode)
-                        .await?;
+                    if let Err(refused) = self
+                        .client
+                        .define_bytecode(tarpc::context::current(), id, Compact(bytecode))
+                        .await?
+                    {
+                        return Ok(Err(refused));
+                    }
                 }
+                result => return Ok(result),
             }
         }
     }
 
-    fn store(&mut self, addr: u64, value: i64) -> std::io::Result<()> {
+    /// Collects whichever of the submitted tasks have finished.
+    pub(crate) fn poll(
+        &mut self,
+        tasks: Vec<TaskKey>,
+    ) -> Result<Vec<(TaskKey, TaskResult)>, RunError> {
+        let started = std::time::Instant::now();
+        let mut client = self.client.clone();
+        self.runtime
+            .block_on(async move { client.poll(tarpc::context::current(), tasks).await })
+            .map_err(|e| self.failed(e, started))
+    }
+
+    fn failed(&self, e: std::io::Error, started: std::time::Instant) -> RunError {
+        self.vm
+            .peer_stats
+            .record(&self.identity, started.elapsed(), false);
+        if e.kind() == std::io::ErrorKind::ConnectionReset {
+            RunError::ConnectionReset
+        } else {
+            log::error!("{}", e);
+            RunError::Unknown
+        }
+    }
+
+    fn redefine_bytecode(
+        &mut self,
+        id: u64,
+        bytecode: flock_bytecode::ByteCode,
+    ) -> std::io::Result<Result<(), Refused>> {
         self.runtime.clone().block_on(async {
             self.client
-                .store(tarpc::context::current(), addr, value)
-                .await?;
-            Ok(())
+                .redefine_bytecode(tarpc::context::current(), id, Compact(bytecode))
+                .await
+        })
+    }
+
+    /// Stores on the peer, which also relays to the peers at `relay_to`. Returns those it
+    /// couldn't reach.
+    fn store(
+        &mut self,
+        addr: u64,
+        value: i64,
+        relay_to: &[String],
+    ) -> std::io::Result<Vec<String>> {
+        self.runtime.clone().block_on(async {
+            let context = tarpc::context::current();
+            if relay_to.is_empty() {
+                self.client.store(context, addr, value).await?;
+                Ok(Vec::new())
+            } else {
+                self.client
+                    .store_relayed(context, addr, value, relay_to.to_vec())
+                    .await
+            }
         })
     }
 }
 
 impl std::fmt::Debug for Peer {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.client.fmt(formatter)
+        write!(formatter, "{}", self.identity)
     }
 }
 
@@ -158,91 +654,815 @@ impl std::fmt::Debug for Peer {
 trait ClusterService {
     async fn run_to_completion(
         task_order: TaskOrder,
-    ) -> Result<Result<TaskOrder, ExecutionError>, UnknownByteCode>;
+    ) -> Result<Result<TaskOrder, ExecutionError>, Refused>;
+
+    /// Queues a task without waiting for it. Its submission and task ids are the ticket to `poll`
+    /// with.
+    async fn submit(task_order: TaskOrder) -> Result<(), Refused>;
+
+    /// Results of whichever of the submitted tasks have finished.
+    async fn poll(tasks: Vec<TaskKey>) -> Vec<(TaskKey, Result<TaskOrder, ExecutionError>)>;
+
+    /// Refused if this node forbids an instruction in it to peers.
+    async fn define_bytecode(id: u64, bytecode: Compact) -> Result<(), Refused>;
+
+    /// Refused, dropping the old definition, if this node forbids an instruction in it to peers.
+    async fn redefine_bytecode(id: u64, bytecode: Compact) -> Result<(), Refused>;
+
+    /// Holds chunks of bytecode for peers to fetch while assembling it.
+    async fn put_chunks(chunks: Vec<Vec<u8>>);
 
-    async fn define_bytecode(id: u64, bytecode: flock_bytecode::ByteCode);
+    async fn chunk(hash: ChunkHash) -> Option<Vec<u8>>;
+
+    /// Defines bytecode from its chunks, each fetched from the peer at the address beside its
+    /// hash unless already held. Returns false if any couldn't be.
+    async fn assemble_bytecode(id: u64, manifest: Vec<(ChunkHash, String)>) -> bool;
 
     async fn store(addr: u64, value: i64);
+
+    /// Stores, then relays the store to the peers at `relay_to`. Returns those it couldn't reach.
+    async fn store_relayed(addr: u64, value: i64, relay_to: Vec<String>) -> Vec<String>;
+
+    async fn result_expiring(task_id: usize);
+
+    async fn identity() -> NodeIdentity;
+
+    async fn emit(task_id: usize, value: i64);
+
+    /// Reserves addresses for `ALLOC_GLOBAL` in a task this node sent the peer.
+    async fn alloc_global(count: u64) -> Option<u64>;
+
+    /// Reserves a block for `ALLOC` in a task this node sent the peer.
+    async fn alloc(arena: Arena, size: u64) -> Option<u64>;
+
+    /// Frees blocks for `FREE` or the end of a task this node sent the peer, returning the
+    /// first address that wasn't a block of `arena`.
+    async fn free(arena: Arena, addrs: Vec<u64>) -> Option<u64>;
+
+    /// Prints the `DUMP_DEBUG` listing of a task this node sent the peer.
+    async fn dump_debug(task_id: usize, listing: String);
+
+    async fn coverage(bytecode_id: u64) -> Vec<u64>;
+
+    async fn status() -> NodeStatus;
+
+    /// The bytecode the node holds, by id.
+    async fn programs() -> Vec<ProgramInfo>;
+
+    /// Stops taking tasks, and shuts the node down once every accepted task's result is claimed.
+    async fn drain();
+
+    /// Id of the `--shared-memory` region the node maps, if any.
+    async fn shared_memory() -> Option<uuid::Uuid>;
+
+    /// Stops running the submission's tasks, another task of it having failed.
+    async fn cancel(submission: u64);
+
+    /// Errors of tasks a peer forked for a submission started here.
+    async fn report_errors(submission: u64, errors: Vec<ExecutionError>);
 }
 
 #[derive(Clone)]
 pub struct ClusterServer {
     vm: Arc<VmHandle>,
+    origin: Option<SocketAddr>,
+    /// Added to each request and response, simulating a network.
+    latency: std::time::Duration,
 }
 
 impl ClusterServer {
     pub fn new(vm: &Arc<VmHandle>) -> Self {
-        ClusterServer { vm: vm.clone() }
+        ClusterServer {
+            vm: vm.clone(),
+            origin: None,
+            latency: std::time::Duration::ZERO,
+        }
+    }
+
+    pub fn with_latency(mut self, latency: std::time::Duration) -> Self {
+        self.latency = latency;
+        self
     }
 
     pub async fn listen(self) -> std::io::Result<()> {
+        let (_, serve) = self
+            .bind(SocketAddr::new(IpAddr::from([0, 0, 0, 0]), listen_port()))
+            .await?;
+        serve.await;
+        Ok(())
+    }
+
+    /// Binds `addr`, port 0 for any free one, returning the bound address and the future serving
+    /// it.
+    pub async fn bind(
+        self,
+        addr: SocketAddr,
+    ) -> std::io::Result<(SocketAddr, impl std::future::Future<Output = ()>)> {
         use futures::*;
         use tarpc::{
-            server::{Channel, Handler},
+            server::{Channel, Handler, Serve},
             *,
         };
-        let mut listener =
-            tarpc::serde_transport::tcp::listen(("0.0.0.0", LISTEN_PORT.flag), Json::default)
-                .await?;
+        let mut listener = tarpc::serde_transport::tcp::listen(addr, Json::default).await?;
         listener.config_mut().max_frame_length(4294967296);
+        let addr = listener.local_addr();
+        log::info!(
+            "Node {} listening on port {}",
+            self.vm.identity,
+            addr.port()
+        );
 
-        listener
+        let drained = drained(self.vm.clone());
+        // Polled with the server rather than spawned, so they stop when it does.
+        let vm = self.vm.clone();
+        let background = async move {
+            if vm.fair.is_some() {
+                share_fairly(vm).await;
+            } else {
+                future::pending::<()>().await;
+            }
+        };
+
+        let serve = listener
             .filter_map(|r| future::ready(r.ok()))
             .map(server::BaseChannel::with_defaults)
             .max_channels_per_key(1, |t| t.as_ref().peer_addr().unwrap().ip())
-            .map(|channel| channel.respond_with(self.clone().serve()).execute())
+            .map(move |channel| {
+                let server = ClusterServer {
+                    origin: channel.get_ref().as_ref().peer_addr().ok(),
+                    ..self.clone()
+                };
+                let (latency, serve) = (server.latency, server.serve());
+                channel
+                    .respond_with(move |context, request| {
+                        simulate::delayed(latency, serve.clone().serve(context, request))
+                    })
+                    .execute()
+            })
             .buffer_unordered(10)
-            .for_each(|_| async {})
-            .await;
-        Ok(())
+            .for_each(|_| async {});
+        let stop = future::select(Box::pin(drained), Box::pin(background));
+        let serve = future::select(Box::pin(serve), stop);
+        Ok((addr, serve.map(|_| ())))
     }
-}
 
-#[tarpc::server]
-impl ClusterService for ClusterServer {
-    async fn run_to_completion(
-        self,
-        _: tarpc::context::Context,
-        task_order: TaskOrder,
-    ) -> Result<Result<TaskOrder, ExecutionError>, UnknownByteCode> {
+    async fn execute(self, task_order: TaskOrder) -> Result<TaskResult, Refused> {
         log::info!("Requested to execute task {}", task_order.id);
+        let key = task_order.key();
+        self.admit(task_order)?;
+        let mut interval = tokio::time::interval(core::time::Duration::from_millis(1));
+
+        loop {
+            interval.tick().await;
+            if let Some(result) = self.claim(key) {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn admit(&self, mut task_order: TaskOrder) -> Result<(), Refused> {
+        if self.vm.draining.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(Refused::Draining);
+        }
         if !self
             .vm
             .bytecode_registry
             .contains_key(&task_order.bytecode_id)
         {
             // TODO(shelbyd): Request ByteCode from client.
-            return Err(UnknownByteCode(task_order.bytecode_id));
+            return Err(Refused::UnknownByteCode(task_order.bytecode_id));
         }
-        let id = task_order.id;
-        self.vm.queue_handle.push_nonworker(task_order);
-        let mut interval = tokio::time::interval(core::time::Duration::from_millis(1));
+        log::trace!(
+            "Admitting task {} with stack {}",
+            task_order.id,
+            Payload(&task_order.task.stack)
+        );
+        let (id, key) = (task_order.id, task_order.key());
+        if let Some(origin) = self.origin {
+            task_order.sandbox = Active::for_origin(origin.ip()).map(Arc::new);
+            if let Some(emit_to) = &mut task_order.emit_to {
+                if emit_to.ip().is_unspecified() {
+                    emit_to.set_ip(origin.ip());
+                }
+            }
+            let reply_to = task_order.emit_to.unwrap_or(origin);
+            self.vm.remote_origins.insert(key, reply_to);
+        }
+        // A task recovered from the journal is already queued or finished, so it isn't rerun.
+        let recovered = self.vm.finished.contains(key)
+            || self
+                .vm
+                .journal
+                .as_ref()
+                .is_some_and(|j| j.is_recovering(key));
+        if !recovered {
+            let limit = setting(&MAX_QUEUED_TASKS, &config().max_queued_tasks);
+            if self.vm.queued() >= limit {
+                log::warn!(
+                    "Refusing task {} from {:?}, {} tasks already queued",
+                    id,
+                    self.origin,
+                    limit
+                );
+                self.vm.remote_origins.remove(&key);
+                return Err(Refused::Busy {
+                    retry_after: BUSY_RETRY_AFTER,
+                });
+            }
+            if let Some(j) = &self.vm.journal {
+                j.queued(&task_order, self.origin);
+            }
+            self.vm
+                .submissions
+                .admitted(&task_order.submission, task_order.emit_to);
+            let program = (self.origin.map(|o| o.ip()), task_order.bytecode_id);
+            self.vm.queue_remote(program, task_order);
+        }
+        Ok(())
+    }
 
-        loop {
-            interval.tick().await;
-            if let Some(task_order) = self.vm.finished.remove(&id) {
-                return Ok(task_order.1);
+    /// Refuses bytecode from a peer using an instruction `--remote-denied-opcodes` or
+    /// `--remote-allowed-opcodes` forbid.
+    fn verify(&self, id: u64, bytecode: &flock_bytecode::ByteCode) -> Result<(), Refused> {
+        if self.origin.is_none() {
+            return Ok(());
+        }
+        match self.vm.remote_opcodes.refuses(bytecode) {
+            None => Ok(()),
+            Some(mnemonic) => {
+                log::warn!(
+                    "Refusing bytecode {} from {:?} using forbidden {}",
+                    id,
+                    self.origin,
+                    mnemonic
+                );
+                Err(Refused::ForbiddenOpCode(mnemonic.to_string()))
             }
         }
     }
 
+    /// Returns false if the origin's sandbox refuses the store.
+    fn store_local(&self, addr: u64, value: i64) -> bool {
+        log::debug!("Storing from remote {} @ 0x{:x}", value, addr);
+        let started = std::time::Instant::now();
+        let sandbox = self.origin.and_then(|o| Sandbox::for_origin(o.ip()));
+        if let Some(s) = sandbox {
+            if s.refuse_foreign_stores && !s.allows(addr) {
+                log::warn!(
+                    "Refusing store to 0x{:x} from {:?} outside its sandbox",
+                    addr,
+                    self.origin
+                );
+                audit::record(self.origin, "store", addr, 1, started, "refused");
+                return false;
+            }
+        }
+        self.vm.store(addr, value);
+        audit::record(self.origin, "store", addr, 1, started, "ok");
+        true
+    }
+
+    /// Takes the task's result if it has finished, handing it over to the requesting peer.
+    fn claim(&self, key: TaskKey) -> Option<TaskResult> {
+        let result = self.vm.finished.remove(&key)?;
+        self.vm.remote_origins.remove(&key);
+        if let Some(j) = &self.vm.journal {
+            j.delivered(key);
+        }
+        if result.is_ok() {
+            self.vm
+                .served
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        Some(result)
+    }
+}
+
+#[tarpc::server]
+impl ClusterService for ClusterServer {
+    async fn run_to_completion(
+        self,
+        _: tarpc::context::Context,
+        task_order: TaskOrder,
+    ) -> Result<Result<TaskOrder, ExecutionError>, Refused> {
+        let started = std::time::Instant::now();
+        let (origin, id, size) = (self.origin, task_order.id, task_order.task.stack.len());
+        let result = self.execute(task_order).await;
+        let outcome = match &result {
+            Ok(Ok(_)) => "ok".to_string(),
+            Ok(Err(e)) => e.to_string(),
+            Err(Refused::UnknownByteCode(_)) => "unknown bytecode".to_string(),
+            Err(Refused::Busy { .. }) => "busy".to_string(),
+            Err(Refused::Draining) => "draining".to_string(),
+            Err(Refused::ForbiddenOpCode(_)) => "forbidden opcode".to_string(),
+        };
+        audit::record(
+            origin,
+            "run_to_completion",
+            id as u64,
+            size,
+            started,
+            &outcome,
+        );
+        result
+    }
+
+    async fn submit(
+        self,
+        _: tarpc::context::Context,
+        task_order: TaskOrder,
+    ) -> Result<(), Refused> {
+        let started = std::time::Instant::now();
+        let (id, size) = (task_order.id, task_order.task.stack.len());
+        let result = self.admit(task_order);
+        let outcome = match &result {
+            Ok(()) => "ok",
+            Err(Refused::UnknownByteCode(_)) => "unknown bytecode",
+            Err(Refused::Busy { .. }) => "busy",
+            Err(Refused::Draining) => "draining",
+            Err(Refused::ForbiddenOpCode(_)) => "forbidden opcode",
+        };
+        audit::record(self.origin, "submit", id as u64, size, started, outcome);
+        result
+    }
+
+    async fn poll(
+        self,
+        _: tarpc::context::Context,
+        tasks: Vec<TaskKey>,
+    ) -> Vec<(TaskKey, Result<TaskOrder, ExecutionError>)> {
+        tasks
+            .into_iter()
+            .filter_map(|key| Some((key, self.claim(key)?)))
+            .collect()
+    }
+
     async fn define_bytecode(
         self,
         _: tarpc::context::Context,
         id: u64,
-        bytecode: flock_bytecode::ByteCode,
-    ) {
-        self.vm.bytecode_registry.insert(id, Arc::new(bytecode));
+        Compact(bytecode): Compact,
+    ) -> Result<(), Refused> {
+        let started = std::time::Instant::now();
+        let size = bytecode.len();
+        if let Err(refused) = self.verify(id, &bytecode) {
+            audit::record(
+                self.origin,
+                "define_bytecode",
+                id,
+                size,
+                started,
+                "forbidden opcode",
+            );
+            return Err(refused);
+        }
+        if !self.vm.bytecode_registry.contains_key(&id) {
+            self.vm.define_bytecode(id, Arc::new(bytecode));
+        }
+        audit::record(self.origin, "define_bytecode", id, size, started, "ok");
+        Ok(())
+    }
+
+    async fn redefine_bytecode(
+        self,
+        _: tarpc::context::Context,
+        id: u64,
+        Compact(bytecode): Compact,
+    ) -> Result<(), Refused> {
+        log::info!("Redefining bytecode {} from {:?}", id, self.origin);
+        if let Err(refused) = self.verify(id, &bytecode) {
+            self.vm.bytecode_registry.remove(&id);
+            self.vm.registered.remove(&id);
+            return Err(refused);
+        }
+        self.vm.redefine_bytecode(id, Arc::new(bytecode));
+        Ok(())
+    }
+
+    async fn put_chunks(self, _: tarpc::context::Context, chunks: Vec<Vec<u8>>) {
+        let started = std::time::Instant::now();
+        let size = chunks.iter().map(Vec::len).sum();
+        for chunk in chunks {
+            self.vm.chunks.insert(chunk);
+        }
+        audit::record(self.origin, "put_chunks", 0, size, started, "ok");
+    }
+
+    async fn chunk(self, _: tarpc::context::Context, hash: ChunkHash) -> Option<Vec<u8>> {
+        self.vm.chunks.get(&hash).map(|c| c.as_ref().clone())
+    }
+
+    async fn assemble_bytecode(
+        self,
+        _: tarpc::context::Context,
+        id: u64,
+        manifest: Vec<(ChunkHash, String)>,
+    ) -> bool {
+        if self.vm.bytecode_registry.contains_key(&id) {
+            return true;
+        }
+        let started = std::time::Instant::now();
+        let fetches = manifest
+            .iter()
+            .map(|(hash, source)| fetch_chunk(&self.vm.chunks, hash, source));
+        let chunks = futures::future::join_all(fetches).await;
+        let chunks = match chunks.into_iter().collect::<Option<Vec<_>>>() {
+            Some(c) => c,
+            None => return false,
+        };
+        let bytecode = match chunks::join(&chunks) {
+            Ok(b) => b,
+            Err(e) => {
+                log::error!("Relayed bytecode {} is invalid: {}", id, e);
+                return false;
+            }
+        };
+        let size = bytecode.len();
+        if self.verify(id, &bytecode).is_err() {
+            audit::record(
+                self.origin,
+                "assemble_bytecode",
+                id,
+                size,
+                started,
+                "forbidden opcode",
+            );
+            return false;
+        }
+        self.vm.define_bytecode(id, Arc::new(bytecode));
+        audit::record(self.origin, "assemble_bytecode", id, size, started, "ok");
+        true
     }
 
     async fn store(self, _: tarpc::context::Context, addr: u64, value: i64) {
-        log::debug!("Storing from remote {} @ 0x{:x}", value, addr);
-        self.vm.memory.insert(addr, value);
+        self.store_local(addr, value);
+    }
+
+    async fn store_relayed(
+        self,
+        _: tarpc::context::Context,
+        addr: u64,
+        value: i64,
+        relay_to: Vec<String>,
+    ) -> Vec<String> {
+        // A refused store is left for the origin to send to each peer itself.
+        if !self.store_local(addr, value) {
+            return relay_to;
+        }
+        relay_store(relay_to, addr, value).await
+    }
+
+    async fn result_expiring(self, _: tarpc::context::Context, task_id: usize) {
+        log::warn!(
+            "Peer {:?} is dropping unclaimed result of task {}",
+            self.origin,
+            task_id
+        );
+    }
+
+    async fn identity(self, _: tarpc::context::Context) -> NodeIdentity {
+        self.vm.identity.clone()
+    }
+
+    async fn emit(self, _: tarpc::context::Context, task_id: usize, value: i64) {
+        self.vm.emit(None, Emitted { task_id, value });
+    }
+
+    async fn alloc_global(self, _: tarpc::context::Context, count: u64) -> Option<u64> {
+        self.vm.alloc_global(count)
+    }
+
+    async fn alloc(self, _: tarpc::context::Context, arena: Arena, size: u64) -> Option<u64> {
+        self.vm.heap.alloc(arena, size)
+    }
+
+    async fn free(self, _: tarpc::context::Context, arena: Arena, addrs: Vec<u64>) -> Option<u64> {
+        addrs.into_iter().find(|a| !self.vm.heap.free(arena, *a))
+    }
+
+    async fn dump_debug(self, _: tarpc::context::Context, task_id: usize, listing: String) {
+        eprintln!("Task {} on peer {:?}:", task_id, self.origin);
+        self.vm.dump_debug(None, task_id, listing);
+    }
+
+    async fn coverage(self, _: tarpc::context::Context, bytecode_id: u64) -> Vec<u64> {
+        self.vm
+            .coverage
+            .as_ref()
+            .map(|c| c.snapshot(bytecode_id))
+            .unwrap_or_default()
+    }
+
+    async fn status(self, _: tarpc::context::Context) -> NodeStatus {
+        NodeStatus {
+            identity: self.vm.identity.clone(),
+            served: self.vm.served_requests(),
+            queued: self.vm.queued(),
+            peers: self.vm.peer_status(),
+            failures: self.vm.task_failures(),
+        }
+    }
+
+    async fn programs(self, _: tarpc::context::Context) -> Vec<ProgramInfo> {
+        self.vm.programs()
+    }
+
+    async fn drain(self, _: tarpc::context::Context) {
+        log::info!("Draining at the request of {:?}", self.origin);
+        self.vm
+            .draining
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    async fn shared_memory(self, _: tarpc::context::Context) -> Option<uuid::Uuid> {
+        self.vm.shared_memory.as_ref().map(|s| s.id())
+    }
+
+    async fn cancel(self, _: tarpc::context::Context, submission: u64) {
+        log::info!(
+            "Cancelling {} at the request of {:?}",
+            submission,
+            self.origin
+        );
+        self.vm.submissions.cancel(submission);
+    }
+
+    async fn report_errors(
+        self,
+        _: tarpc::context::Context,
+        submission: u64,
+        errors: Vec<ExecutionError>,
+    ) {
+        log::info!(
+            "{:?} reported {} errors in {}",
+            self.origin,
+            errors.len(),
+            submission
+        );
+        self.vm.submissions.reported(submission, errors);
+    }
+}
+
+async fn evict_expired(vm: Arc<VmHandle>) {
+    let mut interval = tokio::time::interval(core::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        vm.submissions.expire(vm.finished.ttl());
+        for key in vm.finished.expired() {
+            // Results of a program still running here wait for the task joining them, however
+            // long that takes.
+            if !vm.remote_origins.contains_key(&key) && vm.submissions.is_running(key.0) {
+                continue;
+            }
+            if vm.finished.evict(key).is_none() {
+                continue;
+            }
+            if let Some((_, origin)) = vm.remote_origins.remove(&key) {
+                // Spawned so an unreachable origin doesn't hold up evicting the others.
+                tokio::spawn(notify_expiring(origin, key.1));
+            }
+        }
+    }
+}
+
+/// Resolves once the node was asked to drain and has handed over every result it owes.
+async fn drained(vm: Arc<VmHandle>) {
+    let mut interval = tokio::time::interval(core::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if vm.draining.load(std::sync::atomic::Ordering::Relaxed) && vm.remote_origins.is_empty() {
+            log::info!("Node {} drained, shutting down", vm.identity);
+            return;
+        }
+    }
+}
+
+async fn share_fairly(vm: Arc<VmHandle>) {
+    let fair = vm.fair.as_ref().unwrap();
+    let mut interval = tokio::time::interval(core::time::Duration::from_millis(1));
+    loop {
+        interval.tick().await;
+        // Kept short so a newly arrived program gets the next free worker.
+        fair.release(&vm.queue_handle, num_cpus::get());
+    }
+}
+
+/// How long the origin of an expiring result gets to hear about it.
+const NOTIFY_EXPIRING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+async fn notify_expiring(origin: SocketAddr, task_id: usize) {
+    let notify = async {
+        let mut client = origin_client(origin).await?;
+        client
+            .result_expiring(tarpc::context::current(), task_id)
+            .await
+    };
+    let result = tokio::time::timeout(NOTIFY_EXPIRING_TIMEOUT, notify)
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, e)));
+    if let Err(e) = result {
+        log::error!(
+            "Unable to notify {} of expiring task {}: {}",
+            origin,
+            task_id,
+            e
+        );
+        EMIT_CLIENTS.remove(&origin);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref EMIT_CLIENTS: dashmap::DashMap<SocketAddr, ClusterServiceClient> = Default::default();
+}
+
+/// A client for the node at `origin`, reused for everything sent back to it while running its
+/// tasks.
+async fn origin_client(origin: SocketAddr) -> std::io::Result<ClusterServiceClient> {
+    if let Some(c) = EMIT_CLIENTS.get(&origin) {
+        return Ok(c.clone());
+    }
+    let transport = tarpc::serde_transport::tcp::connect(origin, Json::default).await?;
+    let client = ClusterServiceClient::new(tarpc::client::Config::default(), transport).spawn()?;
+    EMIT_CLIENTS.insert(origin, client.clone());
+    Ok(client)
+}
+
+pub(crate) fn emit_remote(origin: SocketAddr, emitted: Emitted) {
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client
+            .emit(tarpc::context::current(), emitted.task_id, emitted.value)
+            .await
+    }
+    .await_block();
+    if let Err(e) = result {
+        log::error!(
+            "Unable to emit {} from task {} to {}: {}",
+            Payload(&[emitted.value]),
+            emitted.task_id,
+            origin,
+            e
+        );
+        EMIT_CLIENTS.remove(&origin);
+    }
+}
+
+pub(crate) fn report_errors(origin: SocketAddr, submission: u64, errors: Vec<ExecutionError>) {
+    let count = errors.len();
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client
+            .report_errors(tarpc::context::current(), submission, errors)
+            .await
+    }
+    .await_block();
+    if let Err(e) = result {
+        log::error!(
+            "Unable to report {} errors in {} to {}: {}",
+            count,
+            submission,
+            origin,
+            e
+        );
+        EMIT_CLIENTS.remove(&origin);
+    }
+}
+
+pub(crate) fn alloc_global_remote(origin: SocketAddr, count: u64) -> std::io::Result<Option<u64>> {
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client.alloc_global(tarpc::context::current(), count).await
+    }
+    .await_block();
+    if result.is_err() {
+        EMIT_CLIENTS.remove(&origin);
+    }
+    result
+}
+
+pub(crate) fn alloc_remote(
+    origin: SocketAddr,
+    arena: Arena,
+    size: u64,
+) -> std::io::Result<Option<u64>> {
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client.alloc(tarpc::context::current(), arena, size).await
+    }
+    .await_block();
+    if result.is_err() {
+        EMIT_CLIENTS.remove(&origin);
+    }
+    result
+}
+
+pub(crate) fn free_remote(
+    origin: SocketAddr,
+    arena: Arena,
+    addrs: Vec<u64>,
+) -> std::io::Result<Option<u64>> {
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client.free(tarpc::context::current(), arena, addrs).await
+    }
+    .await_block();
+    if result.is_err() {
+        EMIT_CLIENTS.remove(&origin);
+    }
+    result
+}
+
+/// Falls back to printing the listing here if the origin can't be reached.
+pub(crate) fn dump_debug_remote(origin: SocketAddr, task_id: usize, listing: String) {
+    let result = async {
+        let mut client = origin_client(origin).await?;
+        client
+            .dump_debug(tarpc::context::current(), task_id, listing.clone())
+            .await
+    }
+    .await_block();
+    if let Err(e) = result {
+        log::error!(
+            "Unable to send debug dump of task {} to {}: {}",
+            task_id,
+            origin,
+            e
+        );
+        EMIT_CLIENTS.remove(&origin);
+        eprint!("{}", listing);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RELAY_CLIENTS: dashmap::DashMap<String, ClusterServiceClient> = Default::default();
+}
+
+async fn relay_client(peer: &str) -> std::io::Result<ClusterServiceClient> {
+    if let Some(c) = RELAY_CLIENTS.get(peer) {
+        return Ok(c.clone());
+    }
+    let transport = tarpc::serde_transport::tcp::connect(peer, Json::default).await?;
+    let client = ClusterServiceClient::new(tarpc::client::Config::default(), transport).spawn()?;
+    RELAY_CLIENTS.insert(peer.to_string(), client.clone());
+    Ok(client)
+}
+
+/// Stores to each of `peers`, returning those that couldn't be reached.
+async fn relay_store(peers: Vec<String>, addr: u64, value: i64) -> Vec<String> {
+    let mut unreached = Vec::new();
+    for peer in peers {
+        let result = async {
+            let mut client = relay_client(&peer).await?;
+            client.store(tarpc::context::current(), addr, value).await
+        }
+        .await;
+        if let Err(e) = result {
+            log::warn!("Unable to relay store to {}: {}", peer, e);
+            RELAY_CLIENTS.remove(&peer);
+            unreached.push(peer);
+        }
+    }
+    unreached
+}
+
+/// The chunk with `hash`, fetched from `source` unless already held.
+async fn fetch_chunk(chunks: &Chunks, hash: &ChunkHash, source: &str) -> Option<Arc<Vec<u8>>> {
+    if let Some(c) = chunks.get(hash) {
+        return Some(c);
+    }
+    let fetched = async {
+        let mut client = relay_client(source).await?;
+        client.chunk(tarpc::context::current(), *hash).await
+    }
+    .await;
+    match fetched {
+        Ok(Some(chunk)) if chunks::hash(&chunk) == *hash => Some(chunks.insert(chunk)),
+        Ok(Some(_)) => {
+            log::warn!("Chunk from {} doesn't match its hash", source);
+            None
+        }
+        Ok(None) => {
+            log::warn!("{} doesn't hold a chunk it was seeded with", source);
+            None
+        }
+        Err(e) => {
+            log::warn!("Unable to fetch chunk from {}: {}", source, e);
+            RELAY_CLIENTS.remove(source);
+            None
+        }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct UnknownByteCode(u64);
+enum Refused {
+    UnknownByteCode(u64),
+    Busy {
+        retry_after: std::time::Duration,
+    },
+    Draining,
+    /// Mnemonic of the instruction this node won't run for peers.
+    ForbiddenOpCode(String),
+}
 
 trait AwaitBlock {
     type Output;
@@ -0,0 +1,52 @@
+//! Files holding compiled bytecode, as `flock_asm --output` writes them and `flock_asm` and
+//! `flock_vm --run` read them.
+
+use std::path::Path;
+
+use flock_bytecode::{flock_serde, wire::WireError, ByteCode};
+
+use crate::artifact_format;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The compact `.flockc` encoding, but malformed or from a newer build.
+    Binary(WireError),
+    /// Not in an artifact container, `flock_serde::Error::NotAnArtifact` for assembly source.
+    Artifact(flock_serde::Error),
+}
+
+impl Error {
+    /// Whether the file holds something other than bytecode, such as assembly to compile.
+    pub fn not_bytecode(&self) -> bool {
+        matches!(self, Error::Artifact(flock_serde::Error::NotAnArtifact))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Binary(e) => write!(f, "{}", e),
+            Error::Artifact(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Bytecode in the compact binary encoding, or an artifact container in either format.
+pub fn decode(bytes: &[u8]) -> Result<ByteCode, Error> {
+    if ByteCode::is_binary(bytes) {
+        return ByteCode::from_bytes(bytes).map_err(Error::Binary);
+    }
+    flock_serde::from_slice(flock_bytecode::ARTIFACT, bytes).map_err(Error::Artifact)
+}
+
+/// Writes the bytecode in the compact binary encoding if `path` ends in `.flockc`, in an
+/// artifact container in `--artifact-format` otherwise.
+pub fn write(path: impl AsRef<Path>, bytecode: &ByteCode) -> Result<(), flock_serde::Error> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|e| e == "flockc") {
+        return Ok(std::fs::write(path, bytecode.to_bytes())?);
+    }
+    flock_serde::write(path, flock_bytecode::ARTIFACT, bytecode, artifact_format())
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::sandbox::Sandbox;
+
+gflags::define! {
+    /// TOML file describing this node and its peers. Flags given on the command line win.
+    pub --config <PATH>: &str
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub node_name: Option<String>,
+    pub listen_port: Option<u16>,
+    pub listen: Option<bool>,
+    pub peers: Vec<String>,
+    pub zone: Option<String>,
+    pub peer_zones: HashMap<String, String>,
+    pub max_local_workers: Option<usize>,
+    pub max_in_flight_per_peer: Option<usize>,
+    pub max_shipped_task_bytes: Option<usize>,
+    pub affinity_queue_depth: Option<usize>,
+    pub fork_inline_threshold: Option<usize>,
+    pub inline_fork_cost: Option<u64>,
+    pub max_fork_depth: Option<u64>,
+    pub global_alloc_base: Option<u64>,
+    pub heap_base: Option<u64>,
+    pub adaptive_offload: Option<bool>,
+    pub fair_share: Option<bool>,
+    pub steal_back_after_ms: Option<u64>,
+    pub slow_peer_p99_ms: Option<u64>,
+    pub slow_peer_failure_percent: Option<u64>,
+    pub finished_ttl_secs: Option<u64>,
+    pub prewarm_peers: Option<bool>,
+    pub relay_bytecode_over_kib: Option<u64>,
+    pub shared_memory: Option<String>,
+    pub shared_memory_base: Option<u64>,
+    pub shared_memory_cells: Option<u64>,
+    pub max_queued_tasks: Option<usize>,
+    pub retry_attempts: Option<u64>,
+    pub retry_backoff_ms: Option<u64>,
+    pub fail_lost_tasks: Option<bool>,
+    pub coverage: Option<bool>,
+    pub ordered_completion: Option<bool>,
+    pub core_dump_dir: Option<String>,
+    pub checkpoint_store: Option<String>,
+    pub checkpoint_endpoint: Option<String>,
+    pub checkpoint_interval_secs: Option<u64>,
+    pub checkpoint_chunk_bytes: Option<u64>,
+    pub sandbox: HashMap<String, Sandbox>,
+}
+
+lazy_static::lazy_static! {
+    static ref LOADED: Config = load();
+}
+
+pub fn config() -> &'static Config {
+    &LOADED
+}
+
+fn load() -> Config {
+    if !CONFIG.is_present() {
+        return Config::default();
+    }
+    let path = CONFIG.flag;
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Unable to read config {}: {}", path, e));
+    toml::from_str(&contents).unwrap_or_else(|e| panic!("Invalid config {}: {}", path, e))
+}
+
+pub fn setting<T: Clone + 'static>(flag: &gflags::Flag<T>, configured: &Option<T>) -> T {
+    match configured {
+        Some(value) if !flag.is_present() => value.clone(),
+        _ => flag.flag.clone(),
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use flock_bytecode::{flock_serde, ByteCode};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config;
+use crate::snapshot::{self, Snapshot};
+use crate::ExecutionError;
+
+gflags::define! {
+    /// Write a `.flockdump` of every task that fails on this node to DIR, in `--artifact-format`,
+    /// for `flock_core` to inspect.
+    pub --core-dump-dir <DIR>: &str
+}
+
+/// Kind of `flock_serde` artifact a `CoreDump` is written as.
+pub const ARTIFACT: &str = "flockdump";
+
+/// Instructions a `Trace` keeps, the most recent.
+const TRACE_LEN: usize = 64;
+
+/// Distinct addresses a `Trace` keeps, the most recently accessed.
+const ADDRESSES: usize = 32;
+
+/// Where core dumps go, if anywhere.
+pub(crate) fn configured() -> Option<PathBuf> {
+    if CORE_DUMP_DIR.is_present() {
+        Some(PathBuf::from(CORE_DUMP_DIR.flag))
+    } else {
+        config().core_dump_dir.as_ref().map(PathBuf::from)
+    }
+}
+
+/// What a task has recently done on this node, kept while core dumps are on.
+#[derive(Default)]
+pub(crate) struct Trace {
+    pcs: VecDeque<usize>,
+    addrs: VecDeque<u64>,
+    /// The task failed because a task it joined did, which has its own dump.
+    pub(crate) joined_failure: bool,
+}
+
+impl Trace {
+    pub(crate) fn executed(&mut self, pc: usize) {
+        if self.pcs.len() == TRACE_LEN {
+            self.pcs.pop_front();
+        }
+        self.pcs.push_back(pc);
+    }
+
+    pub(crate) fn accessed(&mut self, addr: u64) {
+        self.addrs.retain(|&a| a != addr);
+        if self.addrs.len() == ADDRESSES {
+            self.addrs.pop_front();
+        }
+        self.addrs.push_back(addr);
+    }
+}
+
+/// A task as it failed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoreDump {
+    pub node: String,
+    pub task_id: usize,
+    pub error: ExecutionError,
+    /// Just past the instruction that failed, which is the last in `trace`.
+    pub pc: usize,
+    /// Top last.
+    pub stack: Vec<i64>,
+    /// Instructions the task last ran on this node, oldest first.
+    pub trace: Vec<usize>,
+    /// Values when it failed of the addresses the task last loaded or stored.
+    pub memory: BTreeMap<u64, i64>,
+    pub bytecode: ByteCode,
+}
+
+impl CoreDump {
+    pub(crate) fn new(
+        node: String,
+        task_id: usize,
+        error: ExecutionError,
+        task: &crate::Task,
+        trace: Trace,
+        load: impl Fn(u64) -> i64,
+        bytecode: &ByteCode,
+    ) -> CoreDump {
+        CoreDump {
+            node,
+            task_id,
+            error,
+            pc: task.program_counter,
+            stack: task.stack.clone(),
+            trace: trace.pcs.into(),
+            memory: trace.addrs.into_iter().map(|a| (a, load(a))).collect(),
+            bytecode: bytecode.clone(),
+        }
+    }
+
+    /// The instructions around where the task was and its stack, as `DUMP_DEBUG` lists them.
+    pub fn snapshot(&self) -> Snapshot {
+        snapshot::around(&self.bytecode, self.pc, &self.stack)
+    }
+
+    /// Writes to `dir`, named for the task, logging rather than failing.
+    pub(crate) fn write(&self, dir: &Path) {
+        let path = dir.join(format!("{}.{}", self.task_id, ARTIFACT));
+        let format = crate::artifact_format();
+        match flock_serde::write(&path, ARTIFACT, self, format) {
+            Ok(()) => log::info!("Wrote core dump of task {} to {:?}", self.task_id, path),
+            Err(e) => log::error!("Unable to write core dump to {:?}: {}", path, e),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<CoreDump, flock_serde::Error> {
+        flock_serde::read(path, ARTIFACT)
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use flock_bytecode::ByteCode;
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// Count how often each bytecode index executes, merged across peers.
+    pub --coverage: bool = false
+}
+
+gflags::define! {
+    /// Where `run` writes the annotated coverage listing.
+    pub --coverage-output <PATH>: &str = "flock.coverage"
+}
+
+gflags::define! {
+    /// After `run`, print this many of the most executed bytecode indices with their labels,
+    /// counting as `--coverage` does. 0 prints none.
+    pub --hot-spots <N>: usize = 0
+}
+
+/// Whether `--coverage`, or `--hot-spots` needing the same counts, are on.
+pub(crate) fn configured() -> bool {
+    requested() || HOT_SPOTS.flag > 0
+}
+
+/// Whether `--coverage` itself is on, so `run` writes the listing.
+pub(crate) fn requested() -> bool {
+    setting(&COVERAGE, &config().coverage)
+}
+
+pub type Hits = Arc<Vec<AtomicU64>>;
+
+#[derive(Default)]
+pub struct Coverage {
+    hits: DashMap<u64, Hits>,
+}
+
+impl Coverage {
+    pub fn for_bytecode(&self, id: u64, bytecode: &ByteCode) -> Hits {
+        let mut hits = self.hits.entry(id).or_default();
+        if hits.len() != bytecode.len() {
+            *hits = Arc::new((0..bytecode.len()).map(|_| AtomicU64::new(0)).collect());
+        }
+        hits.clone()
+    }
+
+    pub fn snapshot(&self, id: u64) -> Vec<u64> {
+        self.hits
+            .get(&id)
+            .map(|hits| hits.iter().map(|h| h.load(Ordering::Relaxed)).collect())
+            .unwrap_or_default()
+    }
+}
+
+pub fn record(hits: &[AtomicU64], index: usize) {
+    if let Some(h) = hits.get(index) {
+        h.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn merge(into: &mut Vec<u64>, other: &[u64]) {
+    if into.len() < other.len() {
+        into.resize(other.len(), 0);
+    }
+    for (total, hits) in into.iter_mut().zip(other) {
+        *total += hits;
+    }
+}
+
+/// An instruction among the most executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub index: usize,
+    /// Nearest label at or before the instruction, as `label+offset`.
+    pub label: Option<String>,
+    pub count: u64,
+}
+
+/// The `n` most executed instructions, most first, ties broken by index.
+pub fn hot_spots(bytecode: &ByteCode, hits: &[u64], n: usize) -> Vec<HotSpot> {
+    let mut executed: Vec<(usize, u64)> = hits
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    executed.sort_by_key(|&(index, count)| (std::cmp::Reverse(count), index));
+    executed
+        .into_iter()
+        .take(n)
+        .map(|(index, count)| HotSpot {
+            index,
+            label: bytecode.symbolize(index),
+            count,
+        })
+        .collect()
+}
+
+/// One line per hot spot, with its instruction.
+pub fn hot_spot_report(bytecode: &ByteCode, hot_spots: &[HotSpot]) -> String {
+    let mut report = String::new();
+    for spot in hot_spots {
+        report += &format!(
+            "{:>10} | {:04} | {:<20} | {:?}\n",
+            spot.count,
+            spot.index,
+            spot.label.as_deref().unwrap_or(""),
+            bytecode.get(spot.index).unwrap()
+        );
+    }
+    report
+}
+
+/// One line per instruction, with never-executed instructions marked `#####` as in gcov.
+pub fn listing(bytecode: &ByteCode, hits: &[u64]) -> String {
+    let mut listing = String::new();
+    for index in 0..bytecode.len() {
+        let count = match hits.get(index).cloned().unwrap_or(0) {
+            0 => "#####".to_string(),
+            n => n.to_string(),
+        };
+        listing += &format!(
+            "{:>10} | {:04} | {:?}\n",
+            count,
+            index,
+            bytecode.get(index).unwrap()
+        );
+    }
+    listing
+}
@@ -0,0 +1,65 @@
+//! Effects of forked tasks under `--ordered-completion`, held back until they're joined.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// Apply a forked task's `EMIT`s and stores when it's joined, in the order tasks are joined,
+    /// rather than as it runs, so output doesn't depend on which child finishes first. A task
+    /// sees its own stores and those of children it joined. Children never joined have no effect.
+    pub --ordered-completion: bool = false
+}
+
+pub(crate) fn configured() -> bool {
+    setting(&ORDERED_COMPLETION, &config().ordered_completion)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Effect {
+    Store { addr: u64, value: i64 },
+    Emit { task_id: usize, value: i64 },
+}
+
+/// What a task under ordered completion has done but not yet applied.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Deferred {
+    /// In the order they happened, the effects of joined children where they were joined.
+    effects: Vec<Effect>,
+    /// Leading effects the parent held when this task forked, seen by loads here but applied
+    /// through the parent.
+    inherited: usize,
+}
+
+impl Deferred {
+    pub fn push(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    /// Effects held, inherited ones included.
+    pub fn held(&self) -> usize {
+        self.effects.len()
+    }
+
+    /// Makes everything held so far inherited, for the child of a fork.
+    pub fn forked(&mut self) {
+        self.inherited = self.effects.len();
+    }
+
+    /// The value most recently stored to `addr` without being applied, if any.
+    pub fn load(&self, addr: u64) -> Option<i64> {
+        self.effects.iter().rev().find_map(|effect| match *effect {
+            Effect::Store { addr: a, value } if a == addr => Some(value),
+            _ => None,
+        })
+    }
+
+    /// The joined task's own effects, which the joiner applies or holds in turn.
+    pub fn into_own(self) -> impl Iterator<Item = Effect> {
+        self.effects.into_iter().skip(self.inherited)
+    }
+
+    pub fn extend(&mut self, effects: impl IntoIterator<Item = Effect>) {
+        self.effects.extend(effects);
+    }
+}
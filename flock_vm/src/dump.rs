@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+gflags::define! {
+    /// Write stores, emitted values and final memory for `flock_diff`, in `--artifact-format`.
+    pub --dump <PATH>: &str
+}
+
+/// Kind of `flock_serde` artifact a `Dump` is written as.
+pub const ARTIFACT: &str = "dump";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Store {
+    pub addr: u64,
+    pub value: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dump {
+    pub stores: Vec<Store>,
+    pub emitted: Vec<i64>,
+    pub memory: BTreeMap<u64, i64>,
+}
+
+/// Stores and emitted values in the order they reached this VM.
+#[derive(Default)]
+pub struct Recorder {
+    stores: Mutex<Vec<Store>>,
+    emitted: Mutex<Vec<i64>>,
+}
+
+impl Recorder {
+    pub fn store(&self, addr: u64, value: i64) {
+        self.stores.lock().unwrap().push(Store { addr, value });
+    }
+
+    pub fn emit(&self, value: i64) {
+        self.emitted.lock().unwrap().push(value);
+    }
+
+    pub fn dump(&self, memory: BTreeMap<u64, i64>) -> Dump {
+        Dump {
+            stores: self.stores.lock().unwrap().clone(),
+            emitted: self.emitted.lock().unwrap().clone(),
+            memory,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! What executors run tasks against, so the run loop can be driven without a whole `VmHandle`.
+
+use std::sync::Arc;
+
+use flock_bytecode::{ByteCode, PackedWidth};
+
+use crate::{submission::Submission, ExecutionError, TaskKey, TaskOrder, VmHandle};
+
+/// Where finished tasks' results are kept until joined.
+pub(crate) trait Results: Send + Sync {
+    fn finish(&self, submission: Submission, id: usize, result: Result<TaskOrder, ExecutionError>);
+
+    /// The task's result, if it has finished, which is then delivered.
+    fn take(&self, key: TaskKey) -> Option<Result<TaskOrder, ExecutionError>>;
+}
+
+/// Bytecode tasks refer to by id.
+pub(crate) trait Programs: Send + Sync {
+    fn bytecode(&self, id: u64) -> Option<Arc<ByteCode>>;
+}
+
+/// Addresses tasks load and store. Unwritten ones hold 0.
+pub(crate) trait Memory: Send + Sync {
+    fn load(&self, addr: u64) -> i64;
+
+    fn store(&self, addr: u64, value: i64);
+
+    /// Replaces one element of a packed cell, returning the whole cell.
+    fn store_packed(&self, addr: u64, width: PackedWidth, shift: u32, value: i64) -> i64;
+}
+
+#[derive(Clone)]
+pub(crate) struct Environment {
+    pub results: Arc<dyn Results>,
+    pub programs: Arc<dyn Programs>,
+    pub memory: Arc<dyn Memory>,
+}
+
+impl Environment {
+    /// Everything kept by the VM itself.
+    pub fn of(vm: &Arc<VmHandle>) -> Environment {
+        Environment {
+            results: vm.clone(),
+            programs: vm.clone(),
+            memory: vm.clone(),
+        }
+    }
+}
+
+impl Results for VmHandle {
+    fn finish(&self, submission: Submission, id: usize, result: Result<TaskOrder, ExecutionError>) {
+        VmHandle::finish(self, submission, id, result)
+    }
+
+    fn take(&self, key: TaskKey) -> Option<Result<TaskOrder, ExecutionError>> {
+        let done = self.finished.remove(&key)?;
+        if let Some(j) = &self.journal {
+            j.delivered(key);
+        }
+        Some(done)
+    }
+}
+
+impl Programs for VmHandle {
+    fn bytecode(&self, id: u64) -> Option<Arc<ByteCode>> {
+        self.bytecode_registry.get(&id).map(|b| b.clone())
+    }
+}
+
+impl Memory for VmHandle {
+    fn load(&self, addr: u64) -> i64 {
+        VmHandle::load(self, addr)
+    }
+
+    fn store(&self, addr: u64, value: i64) {
+        VmHandle::store(self, addr, value)
+    }
+
+    fn store_packed(&self, addr: u64, width: PackedWidth, shift: u32, value: i64) -> i64 {
+        VmHandle::store_packed(self, addr, width, shift, value)
+    }
+}
@@ -0,0 +1,39 @@
+#[cfg(feature = "asm")]
+use crate::asm::diagnostic::Diagnostics;
+use crate::ExecutionError;
+
+#[derive(Debug)]
+pub enum Error {
+    #[cfg(feature = "asm")]
+    Assembly(Diagnostics),
+    /// The VM couldn't listen for or connect to peers.
+    Startup(std::io::Error),
+    Execution(ExecutionError),
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "asm")]
+impl From<Diagnostics> for Error {
+    fn from(diagnostics: Diagnostics) -> Self {
+        Error::Assembly(diagnostics)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Startup(error)
+    }
+}
+
+impl From<ExecutionError> for Error {
+    fn from(error: ExecutionError) -> Self {
+        Error::Execution(error)
+    }
+}
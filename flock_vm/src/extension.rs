@@ -0,0 +1,47 @@
+use crate::ExecutionError;
+
+type Handler = dyn Fn(&[i64]) -> Result<Vec<i64>, String> + Send + Sync;
+
+/// A host-provided instruction run by `EXT code`. It receives the top `pops` values, deepest
+/// first, and must return exactly `pushes` values.
+pub struct Extension {
+    pub mnemonic: String,
+    pub pops: usize,
+    pub pushes: usize,
+    handler: Box<Handler>,
+}
+
+impl Extension {
+    pub fn new(
+        mnemonic: &str,
+        pops: usize,
+        pushes: usize,
+        handler: impl Fn(&[i64]) -> Result<Vec<i64>, String> + Send + Sync + 'static,
+    ) -> Self {
+        Extension {
+            mnemonic: mnemonic.to_string(),
+            pops,
+            pushes,
+            handler: Box::new(handler),
+        }
+    }
+
+    pub(crate) fn call(&self, code: u16, stack: &mut Vec<i64>) -> Result<(), ExecutionError> {
+        let split = stack
+            .len()
+            .checked_sub(self.pops)
+            .ok_or(ExecutionError::PopFromEmptyStack)?;
+        let args = stack.split_off(split);
+        let results =
+            (self.handler)(&args).map_err(|e| ExecutionError::ExtensionFailed(code, e))?;
+        if results.len() != self.pushes {
+            return Err(ExecutionError::ExtensionStackEffect(
+                code,
+                self.pushes,
+                results.len(),
+            ));
+        }
+        stack.extend(results);
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use flock_bytecode::ByteCode;
+
+use crate::asm::expect::Expectation;
+use crate::asm::statement::Spanned;
+use crate::{Error, ExecutionError, Vm};
+
+/// Assembles and runs a program, returning the final stack of the main task.
+///
+/// ```
+/// let stack = flock_vm::run_source("PUSH 5\nPUSH 8\nADD")?;
+/// assert_eq!(stack, vec![13]);
+/// # Ok::<(), flock_vm::Error>(())
+/// ```
+pub fn run_source(asm: &str) -> Result<Vec<i64>, Error> {
+    let bytecode = crate::asm::assemble(asm)?;
+    crate::run(bytecode)
+}
+
+/// An expectation a program didn't meet, with what it left instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unmet {
+    pub expectation: Spanned<Expectation>,
+    pub found: String,
+}
+
+/// Runs a program on `vm`, returning the expectations from its `;; expect-` annotations it
+/// didn't meet.
+pub fn check(
+    vm: &mut Vm,
+    bytecode: ByteCode,
+    expectations: &[Spanned<Expectation>],
+) -> Result<Vec<Unmet>, ExecutionError> {
+    let stack = vm.execute(bytecode, &[])?;
+    Ok(expectations
+        .iter()
+        .filter_map(|expectation| {
+            let found = match &expectation.value {
+                Expectation::Stack(expected) if *expected != stack => format!("{:?}", stack),
+                Expectation::Memory(addr, value) if vm.load(*addr) != *value => {
+                    vm.load(*addr).to_string()
+                }
+                _ => return None,
+            };
+            Some(Unmet {
+                expectation: expectation.clone(),
+                found,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use crate::{task_queue::Handle, TaskOrder};
+
+gflags::define! {
+    /// Hand tasks from peers to workers round robin between programs instead of first come,
+    /// first served.
+    pub --fair-share: bool = true
+}
+
+/// A root program, as the peer that started it and its bytecode there.
+pub type Program = (Option<IpAddr>, u64);
+
+/// Tasks peers submitted, held per program and released into the shared queue a program at a
+/// time, so one program's burst doesn't starve another's.
+#[derive(Default)]
+pub struct FairQueue {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Programs with waiting tasks, in the order they're next served.
+    turns: VecDeque<Program>,
+    waiting: HashMap<Program, VecDeque<TaskOrder>>,
+    len: usize,
+}
+
+impl FairQueue {
+    pub fn push(&self, program: Program, task_order: TaskOrder) {
+        let mut inner = self.inner.lock().unwrap();
+        let waiting = inner.waiting.entry(program).or_default();
+        waiting.push_back(task_order);
+        if waiting.len() == 1 {
+            inner.turns.push_back(program);
+        }
+        inner.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len
+    }
+
+    /// Moves tasks into `queue` until it has `target` pending, one from each program in turn.
+    pub fn release(&self, queue: &Handle<TaskOrder>, target: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        while queue.pending() < target {
+            let program = match inner.turns.pop_front() {
+                Some(p) => p,
+                None => return,
+            };
+            let waiting = inner.waiting.get_mut(&program).unwrap();
+            queue.push_nonworker(waiting.pop_front().unwrap());
+            if waiting.is_empty() {
+                inner.waiting.remove(&program);
+            } else {
+                inner.turns.push_back(program);
+            }
+            inner.len -= 1;
+        }
+    }
+}
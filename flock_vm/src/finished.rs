@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::{ExecutionError, TaskKey, TaskOrder};
+
+gflags::define! {
+    /// Seconds a finished task result is kept before being evicted if nobody claims it.
+    pub --finished-ttl-secs: u64 = 600
+}
+
+pub type TaskResult = Result<TaskOrder, ExecutionError>;
+
+struct Finished {
+    result: TaskResult,
+    inserted: Instant,
+}
+
+pub struct FinishedMap {
+    results: DashMap<TaskKey, Finished>,
+    ttl: Duration,
+    evicted: AtomicUsize,
+}
+
+impl FinishedMap {
+    pub fn new(ttl: Duration) -> Self {
+        FinishedMap {
+            results: DashMap::new(),
+            ttl,
+            evicted: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn insert(&self, key: TaskKey, result: TaskResult) -> Option<TaskResult> {
+        let finished = Finished {
+            result,
+            inserted: Instant::now(),
+        };
+        self.results.insert(key, finished).map(|f| f.result)
+    }
+
+    pub fn remove(&self, key: &TaskKey) -> Option<TaskResult> {
+        self.results.remove(key).map(|(_, f)| f.result)
+    }
+
+    pub fn contains(&self, key: TaskKey) -> bool {
+        self.results.contains_key(&key)
+    }
+
+    /// Drops the results of a submission that ended without joining them.
+    pub fn purge(&self, submission: u64) {
+        self.results.retain(|(s, _), _| *s != submission);
+    }
+
+    /// Total number of results dropped because they outlived the TTL.
+    pub fn evicted_count(&self) -> usize {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    pub fn expired(&self) -> Vec<TaskKey> {
+        self.results
+            .iter()
+            .filter(|entry| entry.inserted.elapsed() > self.ttl)
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    pub fn evict(&self, key: TaskKey) -> Option<TaskResult> {
+        let removed = self
+            .results
+            .remove_if(&key, |_, f| f.inserted.elapsed() > self.ttl)
+            .map(|(_, f)| f.result);
+        if removed.is_some() {
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "Evicted unclaimed result of task {} after {:?}",
+                key.1,
+                self.ttl
+            );
+        }
+        removed
+    }
+}
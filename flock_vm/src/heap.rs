@@ -0,0 +1,90 @@
+//! Blocks `ALLOC` reserves and `FREE` returns, handed out by the node a program was submitted to
+//! so they don't overlap across peers.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use flock_bytecode::Arena;
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// First address `ALLOC` hands out, above those `ALLOC_GLOBAL` does.
+    pub --heap-base: u64 = 1 << 48
+}
+
+#[derive(Default)]
+pub(crate) struct Heap {
+    blocks: Mutex<Blocks>,
+}
+
+#[derive(Default)]
+struct Blocks {
+    /// Size and arena of each reserved block, by its first address.
+    used: HashMap<u64, (u64, Arena)>,
+    /// Sizes of freed runs below `end`, by their first address. Adjacent runs are merged.
+    free: BTreeMap<u64, u64>,
+    /// Addresses past the base ever handed out.
+    end: u64,
+}
+
+impl Heap {
+    /// The first address of a block of `size`, at least one, if any are left.
+    pub(crate) fn alloc(&self, arena: Arena, size: u64) -> Option<u64> {
+        let base = setting(&HEAP_BASE, &config().heap_base);
+        let size = size.max(1);
+        let mut blocks = self.blocks.lock().unwrap();
+
+        let fit = blocks
+            .free
+            .iter()
+            .find(|(_, free)| **free >= size)
+            .map(|(start, free)| (*start, *free));
+        let offset = match fit {
+            Some((start, free)) => {
+                blocks.free.remove(&start);
+                if free > size {
+                    blocks.free.insert(start + size, free - size);
+                }
+                start
+            }
+            None => {
+                let start = blocks.end;
+                let end = start.checked_add(size)?;
+                base.checked_add(end)?;
+                blocks.end = end;
+                start
+            }
+        };
+        blocks.used.insert(base + offset, (size, arena));
+        Some(base + offset)
+    }
+
+    /// Returns the block at `addr` to the heap, false if `arena` didn't reserve one there.
+    pub(crate) fn free(&self, arena: Arena, addr: u64) -> bool {
+        let base = setting(&HEAP_BASE, &config().heap_base);
+        let mut blocks = self.blocks.lock().unwrap();
+        let size = match blocks.used.get(&addr) {
+            Some((size, reserved)) if *reserved == arena => *size,
+            _ => return false,
+        };
+        blocks.used.remove(&addr);
+
+        let (mut start, mut end) = (addr - base, addr - base + size);
+        if let Some((&before, &len)) = blocks.free.range(..start).next_back() {
+            if before + len == start {
+                blocks.free.remove(&before);
+                start = before;
+            }
+        }
+        if let Some(len) = blocks.free.remove(&end) {
+            end += len;
+        }
+        if end == blocks.end {
+            blocks.end = start;
+        } else {
+            blocks.free.insert(start, end - start);
+        }
+        true
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+gflags::define! {
+    /// Human-readable name shown alongside this node's id in logs and peer listings.
+    pub --node-name <NAME>: &str
+}
+
+gflags::define! {
+    /// Where this node's id is persisted so it survives restarts.
+    pub --node-id-file <PATH>: &str = ".flock_node_id"
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeIdentity {
+    pub id: Uuid,
+    pub name: Option<String>,
+}
+
+impl NodeIdentity {
+    pub fn load() -> NodeIdentity {
+        NodeIdentity {
+            id: load_or_create_id(NODE_ID_FILE.flag),
+            name: if NODE_NAME.is_present() {
+                Some(NODE_NAME.flag.to_string())
+            } else {
+                crate::config::config().node_name.clone()
+            },
+        }
+    }
+
+    /// A fresh identity for the `n`th node of `--simulate-cluster`, which shares this process.
+    pub(crate) fn simulated(n: usize) -> NodeIdentity {
+        NodeIdentity {
+            id: Uuid::new_v4(),
+            name: Some(format!("simulated-{}", n)),
+        }
+    }
+}
+
+fn load_or_create_id(path: &str) -> Uuid {
+    if let Ok(contents) = std::fs::read_to_string(path) {
+        match Uuid::parse_str(contents.trim()) {
+            Ok(id) => return id,
+            Err(e) => log::warn!("Ignoring malformed node id in {}: {}", path, e),
+        }
+    }
+    let id = Uuid::new_v4();
+    if let Err(e) = std::fs::write(path, id.to_string()) {
+        log::warn!("Unable to persist node id to {}: {}", path, e);
+    }
+    id
+}
+
+impl std::fmt::Display for NodeIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({})", name, self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Host data an embedder maps into VM memory, so tasks read and write it in place rather than
+//! it being copied in first.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Consulted, in the order added, before this VM's memory on every load and store by tasks
+/// running here. Tasks sent to peers only see the peer's own.
+pub trait MemoryInterceptor: Send + Sync {
+    /// The value at `addr`, or `None` to leave the load to the next interceptor or the VM.
+    fn on_load(&self, addr: u64) -> Option<i64>;
+
+    /// Whether the store was handled, otherwise it goes to the next interceptor or the VM.
+    fn on_store(&self, addr: u64, value: i64) -> bool;
+}
+
+/// Values exposed at consecutive addresses from `base`. Stores to them are dropped.
+pub struct ReadOnly<T> {
+    base: u64,
+    values: Arc<T>,
+}
+
+impl<T: AsRef<[i64]>> ReadOnly<T> {
+    pub fn new(base: u64, values: Arc<T>) -> Self {
+        ReadOnly { base, values }
+    }
+
+    pub fn addresses(&self) -> Range<u64> {
+        self.base..self.base + self.values.as_ref().as_ref().len() as u64
+    }
+
+    fn index(&self, addr: u64) -> Option<usize> {
+        addr.checked_sub(self.base)
+            .filter(|_| self.addresses().contains(&addr))
+            .map(|i| i as usize)
+    }
+}
+
+impl<T: AsRef<[i64]> + Send + Sync> MemoryInterceptor for ReadOnly<T> {
+    fn on_load(&self, addr: u64) -> Option<i64> {
+        self.index(addr).map(|i| self.values.as_ref().as_ref()[i])
+    }
+
+    fn on_store(&self, addr: u64, value: i64) -> bool {
+        let handled = self.index(addr).is_some();
+        if handled {
+            log::warn!("Dropping store of {} to read-only 0x{:x}", value, addr);
+        }
+        handled
+    }
+}
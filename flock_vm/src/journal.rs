@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use dashmap::DashMap;
+use flock_bytecode::ByteCode;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::{self, Checkpointer, Syncing};
+use crate::seal::{SealError, Sealer};
+use crate::{finished::TaskResult, sandbox::Active, TaskKey, TaskOrder};
+
+gflags::define! {
+    /// Append-only file of task transitions, replayed on startup to recover queued tasks and
+    /// undelivered results. Encrypted when `--state-key-file` is given. Restored from
+    /// `--checkpoint-store` when missing.
+    pub --journal <PATH>: &str
+}
+
+pub(crate) fn configured() -> Option<PathBuf> {
+    if JOURNAL.is_present() {
+        Some(PathBuf::from(JOURNAL.flag))
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record<'a> {
+    Bytecode {
+        id: u64,
+        bytecode: &'a ByteCode,
+    },
+    Queued {
+        task: &'a TaskOrder,
+        origin: Option<SocketAddr>,
+        sandbox_origin: Option<IpAddr>,
+    },
+    Finished {
+        submission: u64,
+        id: usize,
+        result: &'a TaskResult,
+    },
+    Delivered {
+        submission: u64,
+        id: usize,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Entry {
+    Bytecode {
+        id: u64,
+        bytecode: ByteCode,
+    },
+    Queued {
+        task: TaskOrder,
+        origin: Option<SocketAddr>,
+        sandbox_origin: Option<IpAddr>,
+    },
+    Finished {
+        #[serde(default)]
+        submission: u64,
+        id: usize,
+        result: TaskResult,
+    },
+    Delivered {
+        #[serde(default)]
+        submission: u64,
+        id: usize,
+    },
+}
+
+/// State left behind by a previous run of this node.
+#[derive(Default)]
+pub struct Recovered {
+    pub bytecode: Vec<(u64, ByteCode)>,
+    /// Tasks that were queued or running, with the peer that requested them.
+    pub queued: Vec<(TaskOrder, Option<SocketAddr>)>,
+    pub finished: Vec<(TaskKey, TaskResult)>,
+}
+
+pub struct Journal {
+    file: Mutex<File>,
+    sealer: Option<Sealer>,
+    /// Recovered tasks not yet finished, so a repeated request waits instead of rerunning them.
+    recovering: DashMap<TaskKey, ()>,
+    /// Uploading to `--checkpoint-store`, which stops with a last upload when dropped.
+    _checkpoints: Option<Syncing>,
+}
+
+impl Journal {
+    /// Replays the journal at `path`, then rewrites it to hold only what is still live.
+    pub fn open(path: &Path) -> (Journal, Recovered) {
+        let sealer = Sealer::configured();
+        let store = checkpoint::configured();
+        if let Some(store) = &store {
+            restore(&**store, path);
+        }
+        let recovered = replay(path, sealer.as_ref());
+        let compacted = PathBuf::from(format!("{}.compacting", path.display()));
+        let mut journal = Journal {
+            file: Mutex::new(File::create(&compacted).unwrap_or_else(|e| {
+                panic!("Unable to write journal {}: {}", compacted.display(), e)
+            })),
+            sealer,
+            recovering: recovered
+                .queued
+                .iter()
+                .map(|(task, _)| (task.key(), ()))
+                .collect(),
+            _checkpoints: None,
+        };
+        for (id, bytecode) in &recovered.bytecode {
+            journal.bytecode(*id, bytecode);
+        }
+        for (task, origin) in &recovered.queued {
+            journal.queued(task, *origin);
+        }
+        for (key, result) in &recovered.finished {
+            journal.finished(*key, result);
+        }
+        std::fs::rename(&compacted, path)
+            .unwrap_or_else(|e| panic!("Unable to replace journal {}: {}", path.display(), e));
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Unable to open journal {}: {}", path.display(), e));
+        *journal.file.lock().unwrap() = file;
+        if let Some(store) = store {
+            let (chunk_bytes, interval) = checkpoint::schedule();
+            let mut checkpointer = Checkpointer::new(store, "journal", path, chunk_bytes);
+            // Right away, as compaction replaced what was uploaded before.
+            if let Err(e) = checkpointer.sync() {
+                log::error!("Unable to upload checkpoint of journal: {}", e);
+            }
+            journal._checkpoints = Some(checkpointer.spawn(interval));
+        }
+
+        log::info!(
+            "Recovered {} queued tasks and {} undelivered results from {}",
+            recovered.queued.len(),
+            recovered.finished.len(),
+            path.display()
+        );
+        (journal, recovered)
+    }
+
+    pub fn bytecode(&self, id: u64, bytecode: &ByteCode) {
+        self.write(&Record::Bytecode { id, bytecode });
+    }
+
+    pub fn queued(&self, task: &TaskOrder, origin: Option<SocketAddr>) {
+        let sandbox_origin = task.sandbox.as_ref().map(|s| s.origin);
+        self.write(&Record::Queued {
+            task,
+            origin,
+            sandbox_origin,
+        });
+    }
+
+    pub fn finished(&self, key: TaskKey, result: &TaskResult) {
+        self.recovering.remove(&key);
+        let (submission, id) = key;
+        self.write(&Record::Finished {
+            submission,
+            id,
+            result,
+        });
+    }
+
+    pub fn delivered(&self, (submission, id): TaskKey) {
+        self.write(&Record::Delivered { submission, id });
+    }
+
+    pub fn is_recovering(&self, key: TaskKey) -> bool {
+        self.recovering.contains_key(&key)
+    }
+
+    // One write per line, so a crash loses at most the entry being written.
+    fn write(&self, record: &Record) {
+        let json = serde_json::to_string(record).unwrap();
+        let mut line = match &self.sealer {
+            Some(sealer) => sealer.seal(json.as_bytes()),
+            None => json,
+        };
+        line.push('\n');
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            log::error!("Unable to write journal: {}", e);
+        }
+    }
+}
+
+/// Downloads the journal if this node lost it, such as with its disk.
+fn restore(store: &dyn checkpoint::ObjectStore, path: &Path) {
+    if path.exists() {
+        return;
+    }
+    match checkpoint::restore(store, "journal", path) {
+        Ok(true) => log::info!(
+            "Restored journal {} from checkpoint store",
+            path.display()
+        ),
+        Ok(false) => {}
+        Err(e) => panic!(
+            "Unable to restore journal {} from checkpoint store: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn replay(path: &Path, sealer: Option<&Sealer>) -> Recovered {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Recovered::default(),
+        Err(e) => panic!("Unable to read journal {}: {}", path.display(), e),
+    };
+
+    let mut bytecode = HashMap::new();
+    let mut queued = HashMap::new();
+    let mut finished = HashMap::new();
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| panic!("Unable to read journal {}: {}", path.display(), e));
+    // A torn write can fail authentication too, but only on the last line.
+    let (mut opened, mut unauthentic) = (false, false);
+    for (n, line) in lines.iter().enumerate() {
+        // Entries written before a key was set are read as is, and encrypted by compaction.
+        let json = match sealer {
+            Some(sealer) if !line.starts_with('{') => match sealer.open(line) {
+                Ok(json) => {
+                    opened = true;
+                    json
+                }
+                Err(e) => {
+                    unauthentic |= matches!(e, SealError::Unauthentic) && n + 1 < lines.len();
+                    log::warn!(
+                        "Ignoring torn journal entry {}:{}: {}",
+                        path.display(),
+                        n + 1,
+                        e
+                    );
+                    continue;
+                }
+            },
+            None if !line.is_empty() && line.bytes().all(|b| b.is_ascii_hexdigit()) => panic!(
+                "Journal {} is encrypted, set --state-key-file or FLOCK_STATE_KEY",
+                path.display()
+            ),
+            _ => line.clone().into_bytes(),
+        };
+        let entry = match serde_json::from_slice(&json) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!(
+                    "Ignoring torn journal entry {}:{}: {}",
+                    path.display(),
+                    n + 1,
+                    e
+                );
+                continue;
+            }
+        };
+        match entry {
+            Entry::Bytecode { id, bytecode: b } => {
+                bytecode.insert(id, b);
+            }
+            Entry::Queued {
+                mut task,
+                origin,
+                sandbox_origin,
+            } => {
+                task.sandbox = sandbox_origin.and_then(Active::for_origin).map(Arc::new);
+                queued.insert(task.key(), (task, origin));
+            }
+            Entry::Finished {
+                submission,
+                id,
+                result,
+            } => {
+                queued.remove(&(submission, id));
+                finished.insert((submission, id), result);
+            }
+            Entry::Delivered { submission, id } => {
+                finished.remove(&(submission, id));
+            }
+        }
+    }
+
+    // Compaction would drop everything a wrong key can't read.
+    if unauthentic && !opened {
+        panic!(
+            "Unable to decrypt journal {}, is the state key right?",
+            path.display()
+        );
+    }
+
+    let used: HashSet<_> = queued.values().map(|(task, _)| task.bytecode_id).collect();
+    bytecode.retain(|id, _| used.contains(id));
+    Recovered {
+        bytecode: bytecode.into_iter().collect(),
+        queued: queued.into_values().collect(),
+        finished: finished.into_iter().collect(),
+    }
+}
@@ -1,18 +1,136 @@
 #![feature(thread_id_value)]
 
-use flock_bytecode::ByteCode;
+use flock_bytecode::{cost::Cost, flock_serde, Arena, ByteCode};
+
+#[cfg(feature = "asm")]
+pub use flock_asm as asm;
+
+#[cfg(feature = "asm")]
+mod facade;
+#[cfg(feature = "asm")]
+pub use facade::{check, run_source, Unmet};
+
+mod error;
+pub use error::Error;
+
+mod environment;
+use environment::Environment;
+
+pub mod audit;
+
+mod builder;
+use builder::local_workers;
+pub use builder::VmBuilder;
+
+pub mod checkpoint;
+mod chunks;
+
+pub mod compiled;
 
 pub mod cluster;
 use cluster::*;
 
+pub mod config;
+use config::{config, setting};
+
+pub mod core_dump;
+pub mod coverage;
+use coverage::{Coverage, HOT_SPOTS};
+
+mod deferred;
+use deferred::{Deferred, Effect};
+
+pub mod dump;
+use core_dump::{CoreDump, Trace};
+use dump::{Dump, Recorder};
+
+pub mod extension;
+use extension::Extension;
+
+mod heap;
+use heap::Heap;
+pub use heap::HEAP_BASE;
+
+pub mod intercept;
+use intercept::MemoryInterceptor;
+
+mod native;
+
+mod offload;
+use offload::OffloadStats;
+
+mod priority;
+use priority::Priorities;
+
+pub mod peer_stats;
+use peer_stats::PeerStats;
+
+pub mod programs;
+use programs::ProgramInfo;
+
+pub mod loopback;
+
+mod fair;
+use fair::FairQueue;
+
+mod finished;
+use finished::FinishedMap;
+
+pub mod identity;
+use identity::NodeIdentity;
+
+mod journal;
+use journal::{Journal, Recovered};
+
+mod redact;
+use redact::Payload;
+
+mod reduction;
+pub use reduction::Reduction;
+
+mod reservations;
+use reservations::Reservations;
+
+pub mod scaler;
+
+mod retry;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub mod sanitize;
+use sanitize::Sanitizer;
+
+mod seal;
+
+mod simulate;
+
+pub mod snapshot;
+
+mod shared_memory;
+use shared_memory::SharedRegion;
+
+mod submission;
+pub use submission::ErrorPolicy;
+use submission::{Submission, Submissions, ERROR_POLICY};
+
+pub mod sandbox;
+
 mod task;
+pub use task::{ExecutionError, IntOverflow};
 use task::*;
 
-mod task_queue;
+pub mod task_queue;
+pub use task_queue::QueueOrder;
 use task_queue::{ControlFlow, TaskQueue};
 
 mod thread_runner;
 
+pub mod watchdog;
+use watchdog::Health;
+
+mod zone;
+
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use dashmap::DashMap;
@@ -21,38 +139,484 @@ gflags::define! {
     pub --max-local-workers: usize = usize::MAX
 }
 
-pub fn run(bytecode: ByteCode) -> Result<(), ExecutionError> {
-    let mut vm = Vm::create();
-    let bytecode = Arc::new(bytecode);
-    let bytecode_id = vm.register(&bytecode);
+gflags::define! {
+    /// Tasks submitted to one peer at a time before waiting for their results.
+    pub --max-in-flight-per-peer: usize = 64
+}
 
-    vm.block_on_task(TaskOrder {
-        id: 0,
-        task: Task::new(),
-        bytecode_id,
-    })?;
+gflags::define! {
+    /// Tasks larger than this serialized, mostly their stack, are run here instead of being sent
+    /// to a peer.
+    pub --max-shipped-task-bytes: usize = 64 << 20
+}
 
-    Ok(())
+gflags::define! {
+    /// Tasks a peer sent, and the children they fork, are only sent on to other peers while more
+    /// than this many tasks wait here, staying near the memory they wrote otherwise. 0 always
+    /// sends them on.
+    pub --affinity-queue-depth <TASKS>: usize = 8
+}
+
+gflags::define! {
+    /// Run forked children inline instead of queueing them once this many tasks are pending.
+    pub --fork-inline-threshold: usize = usize::MAX
+}
+
+gflags::define! {
+    /// Run a forked child inline when its estimated worst-case instruction count is below this.
+    pub --inline-fork-cost: u64 = 200
+}
+
+gflags::define! {
+    /// First address `ALLOC_GLOBAL` hands out, above those programs use directly.
+    pub --global-alloc-base: u64 = 1 << 40
+}
+
+gflags::define! {
+    /// Fail a task with ForkDepthExceeded when it forks this many generations below the root.
+    pub --max-fork-depth: u64 = 1000
+}
+
+gflags::define! {
+    /// Format artifacts such as `--dump` and `flock_asm --output` are written in: `json`
+    /// to read them, or the smaller `binary`.
+    pub --artifact-format <FORMAT>: &str = "json"
+}
+
+/// `--artifact-format`, JSON if it's unknown.
+pub fn artifact_format() -> flock_serde::Format {
+    ARTIFACT_FORMAT.flag.parse().unwrap_or_else(|e| {
+        log::error!("{}", e);
+        flock_serde::Format::Json
+    })
+}
+
+pub fn run(bytecode: ByteCode) -> Result<Vec<i64>, Error> {
+    let mut vm = Vm::create()?;
+    let emitted = vm.emitted();
+    std::thread::spawn(move || {
+        for e in emitted.iter() {
+            log::info!("Task {} emitted {}", e.task_id, Payload(&[e.value]));
+        }
+    });
+    let listed = bytecode.clone();
+    let result = vm.execute(bytecode, &[]);
+    // `--hot-spots` counts as well, but only `--coverage` asked for the listing.
+    if let Some(hits) = vm.coverage().filter(|_| coverage::requested()) {
+        let path = coverage::COVERAGE_OUTPUT.flag;
+        if let Err(e) = std::fs::write(path, coverage::listing(&listed, &hits)) {
+            log::error!("Unable to write coverage to {}: {}", path, e);
+        }
+    }
+    if HOT_SPOTS.flag > 0 {
+        if let Some(spots) = vm.hot_spots(HOT_SPOTS.flag) {
+            eprint!("Hot spots:\n{}", coverage::hot_spot_report(&listed, &spots));
+        }
+    }
+    if let Some(dump) = vm.dump() {
+        let path = dump::DUMP.flag;
+        if let Err(e) = flock_serde::write(path, dump::ARTIFACT, &dump, artifact_format()) {
+            log::error!("Unable to write dump to {}: {}", path, e);
+        }
+    }
+    if let Err(e) = &result {
+        log::error!("{}", e.symbolized(&listed));
+    }
+    Ok(result?)
 }
 
-type FinishedMap = DashMap<usize, Result<TaskOrder, ExecutionError>>;
 type ByteCodeMap = DashMap<u64, Arc<ByteCode>>;
 
 pub struct VmHandle {
     queue_handle: task_queue::Handle<TaskOrder>,
     finished: FinishedMap,
     bytecode_registry: ByteCodeMap,
+    /// When each bytecode in the registry was defined.
+    registered: DashMap<u64, std::time::SystemTime>,
+    chunks: chunks::Chunks,
     memory: DashMap<u64, i64>,
+    /// Addresses mapped from `--shared-memory` instead of held in `memory`.
+    shared_memory: Option<SharedRegion>,
+    /// Where the peers that sent tasks here listen, for those not yet claimed.
+    remote_origins: DashMap<TaskKey, std::net::SocketAddr>,
+    submissions: Submissions,
+    reservations: Reservations,
+    identity: NodeIdentity,
+    emitted: (flume::Sender<Emitted>, flume::Receiver<Emitted>),
+    debug_dumps: (flume::Sender<DebugDump>, flume::Receiver<DebugDump>),
+    coverage: Option<Coverage>,
+    sanitizer: Option<Sanitizer>,
+    /// Where to write a `.flockdump` of each task that fails here.
+    core_dumps: Option<std::path::PathBuf>,
+    /// Instructions bytecode defined by peers may use.
+    remote_opcodes: sandbox::OpCodePolicy,
+    /// Tasks waiting here below which those from peers aren't sent on, see
+    /// `--affinity-queue-depth`.
+    affinity_queue_depth: usize,
+    recorder: Option<Recorder>,
+    extensions: DashMap<u16, Arc<Extension>>,
+    interceptors: std::sync::RwLock<Vec<Arc<dyn MemoryInterceptor>>>,
+    fork_costs: DashMap<(u64, usize), Cost>,
+    /// Addresses `ALLOC_GLOBAL` has handed out past `--global-alloc-base`.
+    allocated: std::sync::atomic::AtomicU64,
+    heap: Heap,
+    offload: OffloadStats,
+    priorities: Priorities,
+    journal: Option<Journal>,
+    served: std::sync::atomic::AtomicUsize,
+    peer_stats: PeerStats,
+    fair: Option<FairQueue>,
+    draining: std::sync::atomic::AtomicBool,
+    /// Background tasks that have died.
+    health: Arc<Health>,
+    /// Whether programs started here run under `--ordered-completion`.
+    ordered_completion: bool,
+    /// See `--max-fork-depth`.
+    max_fork_depth: u64,
+    /// What arithmetic overflow does in programs started here, see `--int-overflow`.
+    int_overflow: IntOverflow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Emitted {
+    pub task_id: usize,
+    pub value: i64,
 }
 
+/// A `DUMP_DEBUG` listing, in the `snapshot` format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugDump {
+    pub task_id: usize,
+    pub listing: String,
+}
+
+/// `DUMP_DEBUG` listings kept for `Vm::debug_dumps` before later ones are only printed.
+const UNREAD_DEBUG_DUMPS: usize = 1024;
+
 impl VmHandle {
-    fn new(queue: &TaskQueue<TaskOrder>) -> VmHandle {
-        VmHandle {
+    fn new(
+        queue: &TaskQueue<TaskOrder>,
+        shared_memory: Option<SharedRegion>,
+        identity: NodeIdentity,
+        coverage: bool,
+        sanitize: bool,
+        remote_opcodes: sandbox::OpCodePolicy,
+        affinity_queue_depth: usize,
+        journal: Option<std::path::PathBuf>,
+    ) -> VmHandle {
+        let (journal, recovered) = match journal {
+            Some(path) => {
+                let (journal, recovered) = Journal::open(&path);
+                (Some(journal), recovered)
+            }
+            None => (None, Recovered::default()),
+        };
+        let handle = VmHandle {
             queue_handle: queue.handle(),
-            finished: DashMap::new(),
+            finished: FinishedMap::new(std::time::Duration::from_secs(setting(
+                &finished::FINISHED_TTL_SECS,
+                &config().finished_ttl_secs,
+            ))),
             bytecode_registry: DashMap::new(),
+            registered: DashMap::new(),
+            chunks: Default::default(),
             memory: DashMap::new(),
+            shared_memory,
+            remote_origins: DashMap::new(),
+            submissions: Submissions::default(),
+            reservations: Reservations::new(std::time::Duration::from_millis(setting(
+                &reservations::STEAL_BACK_AFTER_MS,
+                &config().steal_back_after_ms,
+            ))),
+            identity,
+            emitted: flume::unbounded(),
+            debug_dumps: flume::bounded(UNREAD_DEBUG_DUMPS),
+            coverage: if coverage {
+                Some(Coverage::default())
+            } else {
+                None
+            },
+            sanitizer: if sanitize {
+                Some(Sanitizer::default())
+            } else {
+                None
+            },
+            core_dumps: None,
+            remote_opcodes,
+            affinity_queue_depth,
+            recorder: if dump::DUMP.is_present() {
+                Some(Recorder::default())
+            } else {
+                None
+            },
+            extensions: DashMap::new(),
+            interceptors: Default::default(),
+            fork_costs: DashMap::new(),
+            allocated: Default::default(),
+            heap: Heap::default(),
+            offload: OffloadStats::new(setting(
+                &offload::ADAPTIVE_OFFLOAD,
+                &config().adaptive_offload,
+            )),
+            priorities: Priorities::default(),
+            journal,
+            served: Default::default(),
+            peer_stats: PeerStats::new(
+                std::time::Duration::from_millis(setting(
+                    &peer_stats::SLOW_PEER_P99_MS,
+                    &config().slow_peer_p99_ms,
+                )),
+                setting(
+                    &peer_stats::SLOW_PEER_FAILURE_PERCENT,
+                    &config().slow_peer_failure_percent,
+                ),
+            ),
+            fair: if setting(&fair::FAIR_SHARE, &config().fair_share) {
+                Some(FairQueue::default())
+            } else {
+                None
+            },
+            draining: Default::default(),
+            health: Arc::default(),
+            ordered_completion: false,
+            max_fork_depth: setting(&MAX_FORK_DEPTH, &config().max_fork_depth),
+            int_overflow: task::INT_OVERFLOW.flag,
+        };
+        handle.recover(recovered);
+        handle
+    }
+
+    fn recover(&self, recovered: Recovered) {
+        for (id, bytecode) in recovered.bytecode {
+            self.bytecode_registry.insert(id, Arc::new(bytecode));
+            self.registered.insert(id, std::time::SystemTime::now());
+        }
+        for (key, result) in recovered.finished {
+            self.finished.insert(key, result);
         }
+        for (task_order, origin) in recovered.queued {
+            if let Some(origin) = origin {
+                let reply_to = task_order.emit_to.unwrap_or(origin);
+                self.remote_origins.insert(task_order.key(), reply_to);
+            }
+            self.submissions
+                .admitted(&task_order.submission, task_order.emit_to);
+            self.queue_handle.push_nonworker(task_order);
+        }
+    }
+
+    fn define_bytecode(&self, id: u64, bytecode: Arc<ByteCode>) {
+        if let Some(j) = &self.journal {
+            j.bytecode(id, &bytecode);
+        }
+        self.bytecode_registry.insert(id, bytecode);
+        self.registered.insert(id, std::time::SystemTime::now());
+    }
+
+    fn finish(&self, submission: Submission, id: usize, result: Result<TaskOrder, ExecutionError>) {
+        let key = (submission.id, id);
+        self.priorities.finished(key);
+        let remote = self.remote_origins.contains_key(&key);
+        self.submissions.finished(&submission, id, &result, remote);
+        if let Some((origin, errors)) = self.submissions.take_report(&submission) {
+            cluster::report_errors(origin, submission.id, errors);
+        }
+        if let Some(j) = &self.journal {
+            j.finished(key, &result);
+        }
+        // Nobody joins the tasks of a cancelled submission, only peers still collect theirs.
+        if !remote && self.submissions.is_cancelled(&submission) {
+            if let Some(j) = &self.journal {
+                j.delivered(key);
+            }
+            return;
+        }
+        let already_there = self.finished.insert(key, result);
+        assert!(already_there.is_none());
+    }
+
+    fn redefine_bytecode(&self, id: u64, bytecode: Arc<ByteCode>) {
+        self.define_bytecode(id, bytecode);
+        self.fork_costs.retain(|(b, _), _| *b != id);
+    }
+
+    fn fork_cost(&self, bytecode_id: u64, bytecode: &ByteCode, pc: usize) -> Cost {
+        *self
+            .fork_costs
+            .entry((bytecode_id, pc))
+            .or_insert_with(|| bytecode.fork_child_cost(pc))
+    }
+
+    fn store(&self, addr: u64, value: i64) {
+        if self.intercept_store(addr, value) {
+            return;
+        }
+        if !self
+            .shared_memory
+            .as_ref()
+            .is_some_and(|s| s.store(addr, value))
+        {
+            self.memory.insert(addr, value);
+        }
+        if let Some(r) = &self.recorder {
+            r.store(addr, value);
+        }
+    }
+
+    fn load(&self, addr: u64) -> i64 {
+        if let Some(value) = self.intercept_load(addr) {
+            return value;
+        }
+        if let Some(value) = self.shared_memory.as_ref().and_then(|s| s.load(addr)) {
+            return value;
+        }
+        self.memory
+            .get(&addr)
+            .map(|ref_| *ref_.value())
+            .unwrap_or(0)
+    }
+
+    fn intercept_load(&self, addr: u64) -> Option<i64> {
+        let interceptors = self.interceptors.read().unwrap();
+        interceptors.iter().find_map(|i| i.on_load(addr))
+    }
+
+    fn intercept_store(&self, addr: u64, value: i64) -> bool {
+        let interceptors = self.interceptors.read().unwrap();
+        interceptors.iter().any(|i| i.on_store(addr, value))
+    }
+
+    /// The first of `count` addresses no earlier call returned, if any are left.
+    fn alloc_global(&self, count: u64) -> Option<u64> {
+        use std::sync::atomic::Ordering;
+
+        let base = setting(&GLOBAL_ALLOC_BASE, &config().global_alloc_base);
+        let offset = self
+            .allocated
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |allocated| {
+                allocated
+                    .checked_add(count)
+                    .filter(|end| base.checked_add(*end).is_some())
+            })
+            .ok()?;
+        Some(base + offset)
+    }
+
+    /// Replaces one element of a packed cell, returning the whole cell.
+    fn store_packed(
+        &self,
+        addr: u64,
+        width: flock_bytecode::PackedWidth,
+        shift: u32,
+        value: i64,
+    ) -> i64 {
+        if let Some(cell) = self.intercept_load(addr) {
+            let cell = width.insert(cell, shift, value);
+            if self.intercept_store(addr, cell) {
+                return cell;
+            }
+        }
+        let shared = self.shared_memory.as_ref();
+        let cell = match shared.and_then(|s| s.update(addr, |c| width.insert(c, shift, value))) {
+            Some(cell) => cell,
+            None => {
+                let mut cell = self.memory.entry(addr).or_insert(0);
+                *cell = width.insert(*cell, shift, value);
+                *cell
+            }
+        };
+        if let Some(r) = &self.recorder {
+            r.store(addr, cell);
+        }
+        cell
+    }
+
+    fn emit(&self, to: Option<std::net::SocketAddr>, emitted: Emitted) {
+        match to {
+            None => {
+                if let Some(r) = &self.recorder {
+                    r.emit(emitted.value);
+                }
+                self.emitted.0.send(emitted).unwrap()
+            }
+            Some(origin) => cluster::emit_remote(origin, emitted),
+        }
+    }
+
+    /// Prints a `DUMP_DEBUG` listing here, or on the node the task came from as it happens.
+    fn dump_debug(&self, to: Option<std::net::SocketAddr>, task_id: usize, listing: String) {
+        match to {
+            None => {
+                eprint!("{}", listing);
+                let _ = self.debug_dumps.0.try_send(DebugDump { task_id, listing });
+            }
+            Some(origin) => cluster::dump_debug_remote(origin, task_id, listing),
+        }
+    }
+
+    pub fn identity(&self) -> &NodeIdentity {
+        &self.identity
+    }
+
+    pub fn evicted_results(&self) -> usize {
+        self.finished.evicted_count()
+    }
+
+    /// Tasks this node has run to completion for peers.
+    pub fn served_requests(&self) -> usize {
+        self.served.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Queues a task a peer sent, taking turns with other programs when sharing fairly.
+    fn queue_remote(&self, program: fair::Program, task_order: TaskOrder) {
+        match &self.fair {
+            Some(f) => f.push(program, task_order),
+            None => self.queue_handle.push_nonworker(task_order),
+        }
+    }
+
+    /// Tasks waiting for a worker.
+    fn queued(&self) -> usize {
+        self.queue_handle.pending() + self.fair.as_ref().map_or(0, FairQueue::len)
+    }
+
+    pub fn peer_status(&self) -> Vec<peer_stats::PeerStatus> {
+        self.peer_stats.snapshot()
+    }
+
+    /// Background tasks that have died since the VM started, whether or not they were restarted.
+    pub fn task_failures(&self) -> Vec<watchdog::TaskFailure> {
+        self.health.snapshot()
+    }
+
+    /// Resolves once no task is queued or running here, or sent to a peer without its result
+    /// back yet, such as forked tasks nothing joins that outlive their program's `execute`.
+    pub async fn quiesce(&self) {
+        self.submissions.quiesce().await
+    }
+
+    /// The bytecode defined here, by id.
+    pub fn programs(&self) -> Vec<ProgramInfo> {
+        let mut programs = self
+            .bytecode_registry
+            .iter()
+            .map(|entry| {
+                let bytecode = entry.value();
+                ProgramInfo {
+                    id: *entry.key(),
+                    instructions: bytecode.len(),
+                    bytes: bytecode.to_bytes().len(),
+                    entry_points: programs::entry_points(bytecode),
+                    registered: self
+                        .registered
+                        .get(entry.key())
+                        .map_or(std::time::UNIX_EPOCH, |t| *t),
+                    references: Arc::strong_count(bytecode) - 1,
+                }
+            })
+            .collect::<Vec<_>>();
+        programs.sort_by_key(|p| p.id);
+        programs
     }
 }
 
@@ -61,64 +625,270 @@ pub struct Vm {
     shared: Arc<VmHandle>,
     cluster: Option<Arc<Cluster>>,
     workers: Vec<std::thread::JoinHandle<()>>,
+    /// Whether local workers run the tasks kept from peers, see `RemoteExecutor::hand_off`.
+    hand_off: bool,
+    /// Bytecode id of the program most recently passed to `execute`.
+    program: u64,
+    /// Leaves run in-process for `--simulate-cluster`, served until dropped.
+    _simulated: Vec<Vm>,
+}
+
+/// Id bytecode is known by on every node, from its content, so programs from different origins,
+/// or recovered from a journal, only share an id when they're the same program.
+fn bytecode_id(bytecode: &ByteCode) -> u64 {
+    let mut id = [0; 8];
+    id.copy_from_slice(&chunks::hash(&bytecode.to_bytes())[..8]);
+    u64::from_le_bytes(id)
 }
 
 impl Vm {
-    pub fn create() -> Vm {
-        let task_queue = TaskQueue::new();
-        let shared = Arc::new(VmHandle::new(&task_queue));
-        Vm {
-            cluster: Some(Arc::new(Cluster::connect(&shared))),
-            shared,
-            task_queue,
-            workers: Vec::new(),
+    /// Starts a VM configured by flags and `--config`, as the binaries do.
+    pub fn create() -> std::io::Result<Vm> {
+        let vm = VmBuilder::from_flags().build()?;
+        if scaler::SCALER_HOOK.is_present() {
+            vm.scale_with(Arc::new(scaler::CommandHook::new(scaler::SCALER_HOOK.flag)));
         }
-        .spawn_workers()
+        Ok(vm)
+    }
+
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
     }
 
     pub fn create_leaf() -> Vm {
-        let task_queue = TaskQueue::new();
+        let (order, max_age) = task_queue::configured();
+        let task_queue = TaskQueue::new(order, max_age);
+        let mut shared = VmHandle::new(
+            &task_queue,
+            None,
+            NodeIdentity::load(),
+            coverage::configured(),
+            sanitize::SANITIZE.flag,
+            sandbox::OpCodePolicy::configured(),
+            setting(&AFFINITY_QUEUE_DEPTH, &config().affinity_queue_depth),
+            journal::configured(),
+        );
+        shared.core_dumps = core_dump::configured();
+        shared.ordered_completion = deferred::configured();
         Vm {
             cluster: None,
-            shared: Arc::new(VmHandle::new(&task_queue)),
+            shared: Arc::new(shared),
             task_queue,
             workers: Vec::new(),
+            hand_off: false,
+            program: 0,
+            _simulated: Vec::new(),
         }
-        .spawn_workers()
+        .spawn_workers(local_workers())
     }
 
     pub fn handle(&self) -> Arc<VmHandle> {
         self.shared.clone()
     }
 
+    /// The address peers reach this VM at, when it listens.
+    pub fn listen_addr(&self) -> Option<std::net::SocketAddr> {
+        self.cluster.as_ref()?.listen_addr()
+    }
+
     fn register(&mut self, bytecode: &Arc<ByteCode>) -> u64 {
-        self.shared.bytecode_registry.insert(0, bytecode.clone());
-        0
+        let id = bytecode_id(bytecode);
+        self.shared.define_bytecode(id, bytecode.clone());
+        if let Some(c) = &self.cluster {
+            if setting(&PREWARM_PEERS, &config().prewarm_peers) {
+                c.prewarm(id, bytecode);
+            }
+        }
+        id
+    }
+
+    /// Runs a program to completion with `inputs` on its main task's stack, the last on top,
+    /// returning the final stack of its main task. Failed tasks are handled according to
+    /// `--error-policy`.
+    ///
+    /// ```
+    /// let bytecode = flock_vm::asm::assemble("ADD\nMULI 2")?;
+    /// let stack = flock_vm::Vm::create_leaf().execute(bytecode, &[5, 8])?;
+    /// assert_eq!(stack, vec![26]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn execute(
+        &mut self,
+        bytecode: ByteCode,
+        inputs: &[i64],
+    ) -> Result<Vec<i64>, ExecutionError> {
+        self.execute_with(bytecode, inputs, ERROR_POLICY.flag)
+    }
+
+    /// Like `execute`, handling failed tasks according to `policy`.
+    pub fn execute_with(
+        &mut self,
+        bytecode: ByteCode,
+        inputs: &[i64],
+        policy: ErrorPolicy,
+    ) -> Result<Vec<i64>, ExecutionError> {
+        use rand::Rng;
+
+        if let Some((pc, target)) = bytecode.out_of_bounds_jump() {
+            return Err(ExecutionError::JumpOutOfBounds { pc, target });
+        }
+        let bytecode_id = self.register(&Arc::new(bytecode));
+        self.program = bytecode_id;
+        if let Some(s) = &self.shared.sanitizer {
+            s.start(0);
+        }
+        let mut task = Task::new();
+        task.stack.extend_from_slice(inputs);
+        task.int_overflow = self.shared.int_overflow;
+        self.block_on_task(TaskOrder {
+            id: 0,
+            task,
+            bytecode_id,
+            emit_to: None,
+            sandbox: None,
+            submission: Submission {
+                id: rand::thread_rng().gen(),
+                policy,
+                ordered: self.shared.ordered_completion,
+            },
+            attempts: 0,
+            deferred: Deferred::default(),
+        })
+    }
+
+    /// Execution counts per bytecode index, summed over this VM and its peers.
+    pub fn coverage(&self) -> Option<Vec<u64>> {
+        let mut hits = self.shared.coverage.as_ref()?.snapshot(self.program);
+        if let Some(c) = &self.cluster {
+            for peer_hits in c.coverage(self.program) {
+                coverage::merge(&mut hits, &peer_hits);
+            }
+        }
+        Some(hits)
+    }
+
+    /// The `n` most executed instructions of the program most recently passed to `execute`,
+    /// counted across this VM and its peers that count them.
+    pub fn hot_spots(&self, n: usize) -> Option<Vec<coverage::HotSpot>> {
+        let hits = self.coverage()?;
+        let bytecode = self.shared.bytecode_registry.get(&self.program)?.clone();
+        Some(coverage::hot_spots(&bytecode, &hits, n))
+    }
+
+    /// Data races `--sanitize` found while running the program most recently passed to
+    /// `execute`.
+    pub fn races(&self) -> Option<Vec<sanitize::Race>> {
+        Some(self.shared.sanitizer.as_ref()?.races())
+    }
+
+    /// What this VM recorded for `--dump`, with the current contents of memory.
+    pub fn dump(&self) -> Option<Dump> {
+        let memory = self
+            .shared
+            .memory
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .chain(self.shared.shared_memory.iter().flat_map(|s| s.nonzero()))
+            .collect();
+        Some(self.shared.recorder.as_ref()?.dump(memory))
+    }
+
+    pub fn register_extension(&self, code: u16, extension: Extension) {
+        self.shared.extensions.insert(code, Arc::new(extension));
+    }
+
+    /// Puts `interceptor` in front of this VM's memory, after any added before it.
+    pub fn intercept_memory(&self, interceptor: impl MemoryInterceptor + 'static) {
+        let interceptor: Arc<dyn MemoryInterceptor> = Arc::new(interceptor);
+        self.shared.interceptors.write().unwrap().push(interceptor);
     }
 
-    fn block_on_task(&mut self, task_order: TaskOrder) -> Result<(), ExecutionError> {
-        self.executor().run_to_completion(task_order)?;
-        assert_eq!(self.shared.finished.len(), 0);
+    /// Mnemonics of the registered extensions, for `flock_asm::assemble_with`.
+    pub fn extension_mnemonics(&self) -> HashMap<String, u16> {
+        self.shared
+            .extensions
+            .iter()
+            .map(|e| (e.mnemonic.clone(), *e.key()))
+            .collect()
+    }
+
+    /// The value at `addr` in this VM's memory, 0 if never stored.
+    pub fn load(&self, addr: u64) -> i64 {
+        self.shared.load(addr)
+    }
+
+    /// Values sent by `EMIT`, in the order they reached this VM. Unread values are buffered.
+    pub fn emitted(&self) -> flume::Receiver<Emitted> {
+        self.shared.emitted.1.clone()
+    }
+
+    /// Listings printed by `DUMP_DEBUG`, here or for tasks sent to peers, in the order they reached
+    /// this VM. Up to 1024 unread listings are buffered.
+    pub fn debug_dumps(&self) -> flume::Receiver<DebugDump> {
+        self.shared.debug_dumps.1.clone()
+    }
+
+    /// Folds every emitted value into an accumulator, competing with other `emitted` readers.
+    pub fn reduce_emitted<T: Send + 'static>(
+        &self,
+        init: T,
+        fold: impl Fn(T, Emitted) -> T + Send + 'static,
+    ) -> Reduction<T> {
+        Reduction::spawn(self.emitted(), init, fold)
+    }
+
+    pub fn update_bytecode(&self, id: u64, bytecode: ByteCode) {
+        let bytecode = Arc::new(bytecode);
+        self.shared.redefine_bytecode(id, bytecode.clone());
+        if let Some(c) = &self.cluster {
+            c.redefine_bytecode(id, &bytecode);
+        }
+    }
 
-        Ok(())
+    fn block_on_task(&mut self, task_order: TaskOrder) -> Result<Vec<i64>, ExecutionError> {
+        let submission = task_order.submission;
+        let submissions = &self.shared.submissions;
+        submissions.started(&submission);
+        let mut executor = self.executor();
+        let result = executor
+            .run_caught(task_order)
+            .and_then(|t| t.task.failure().map_or(Ok(t), Err));
+        submissions.finished(&submission, 0, &result, false);
+        if submission.policy == ErrorPolicy::Collect {
+            executor.busy_until_settled(&submission);
+        }
+
+        let cancelled = submissions.is_cancelled(&submission);
+        let mut errors = submissions.end(&submission);
+        if cancelled {
+            if let Some(c) = &self.cluster {
+                c.cancel(submission.id);
+            }
+        }
+        self.shared.finished.purge(submission.id);
+        let finished = match (submission.policy, errors.len()) {
+            (_, 0) => result?,
+            (ErrorPolicy::FailFast, _) => return Err(errors.remove(0)),
+            (ErrorPolicy::Collect, _) => return Err(ExecutionError::Multiple(errors)),
+        };
+
+        Ok(finished.task.stack)
     }
 
-    fn spawn_workers(mut self) -> Self {
+    fn spawn_workers(mut self, local_workers: usize) -> Self {
         let mut workers = Vec::new();
+        self.hand_off = local_workers > 0;
 
-        let local_workers = std::cmp::min(num_cpus::get(), MAX_LOCAL_WORKERS.flag);
-        workers.extend(
-            (0..local_workers)
-                .map(|_| self.executor())
-                .map(|mut executor| std::thread::spawn(move || executor.run())),
-        );
+        workers.extend((0..local_workers).map(|n| {
+            let mut executor = self.executor();
+            spawn_named(format!("flock-worker-{}", n), move || executor.run())
+        }));
 
         workers.extend(
             self.cluster
                 .iter()
                 .flat_map(|cluster| cluster.peers())
-                .map(|peer| self.remote_executor(peer))
-                .map(|mut executor| std::thread::spawn(move || executor.run())),
+                .map(|peer| self.remote_executor(peer).spawn()),
         );
 
         self.workers = workers;
@@ -126,19 +896,24 @@ impl Vm {
     }
 
     fn executor(&self) -> Executor {
-        Executor {
-            handle: self.task_queue.handle(),
-            shared: self.shared.clone(),
-            cluster: self.cluster.clone(),
-        }
+        Executor::new(&self.task_queue, &self.shared, &self.cluster)
     }
 
     fn remote_executor(&self, peer: Peer) -> RemoteExecutor {
-        RemoteExecutor {
-            handle: self.task_queue.handle(),
-            shared: self.shared.clone(),
-            peer,
-        }
+        let queue = &self.task_queue;
+        RemoteExecutor::new(queue, &self.shared, &self.cluster, peer, self.hand_off)
+    }
+
+    /// Watches the queue, asking `hook` for more nodes while it stays deep and draining a peer
+    /// once it stays empty.
+    pub fn scale_with(&self, hook: Arc<dyn scaler::ClusterScalerHook>) {
+        let cluster = match &self.cluster {
+            Some(c) => Arc::downgrade(c),
+            None => return,
+        };
+        let (queue, shared) = (self.task_queue.clone(), Arc::downgrade(&self.shared));
+        let hand_off = self.hand_off;
+        std::thread::spawn(move || scaler::watch(hook, queue, shared, cluster, hand_off));
     }
 }
 
@@ -153,93 +928,447 @@ impl Drop for Vm {
     }
 }
 
+/// Spawns a thread called `name`, so panics and profilers say which it was.
+pub(crate) fn spawn_named(
+    name: String,
+    run: impl FnOnce() + Send + 'static,
+) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name(name)
+        .spawn(run)
+        .expect("Unable to spawn thread")
+}
+
 struct Executor {
     handle: task_queue::Handle<TaskOrder>,
     shared: Arc<VmHandle>,
+    /// Results, bytecode and memory, the VM's own unless replaced.
+    env: Environment,
     cluster: Option<Arc<Cluster>>,
 }
 
 impl Executor {
+    fn new(
+        queue: &TaskQueue<TaskOrder>,
+        shared: &Arc<VmHandle>,
+        cluster: &Option<Arc<Cluster>>,
+    ) -> Executor {
+        Executor {
+            handle: queue.worker_handle(),
+            shared: shared.clone(),
+            env: Environment::of(shared),
+            cluster: cluster.clone(),
+        }
+    }
+
     fn run(&mut self) {
         while self.busy_tick() {}
     }
 
     fn busy_tick(&mut self) -> bool {
-        let next = match self.handle.next() {
+        let priorities = &self.shared.priorities;
+        let next = match self
+            .handle
+            .next_preferring(|t: &TaskOrder| priorities.is_urgent(t.key()))
+        {
             ControlFlow::Continue(n) => n,
             ControlFlow::Finish => return false,
-            ControlFlow::Retry => return true,
+            ControlFlow::Retry => match self.shared.reservations.steal(|t| self.idempotent(t)) {
+                Some(stolen) => {
+                    log::info!("Stealing back task {} from remote peer", stolen.id);
+                    stolen
+                }
+                None => return true,
+            },
         };
-        let id = next.id;
+        let (id, submission) = (next.id, next.submission);
+        let class = next.class();
 
-        let result = self.run_to_completion(next);
-        let already_there = self.shared.finished.insert(id, result);
-        assert!(already_there.is_none());
+        let started = std::time::Instant::now();
+        let result = self.run_caught(next);
+        self.shared.offload.record_local(class, started.elapsed());
+        self.env.results.finish(submission, id, result);
         true
     }
 
+    /// Whether the task resumes in an `.idempotent` region, so its effects may happen twice.
+    fn idempotent(&self, task_order: &TaskOrder) -> bool {
+        self.env
+            .programs
+            .bytecode(task_order.bytecode_id)
+            .is_some_and(|b| b.is_idempotent(task_order.task.program_counter))
+    }
+
+    /// Runs the task, failing it if the worker panics rather than leaving whatever joins it
+    /// waiting forever.
+    fn run_caught(&mut self, task_order: TaskOrder) -> Result<TaskOrder, ExecutionError> {
+        let id = task_order.id;
+        let run = std::panic::AssertUnwindSafe(|| self.run_to_completion(task_order));
+        std::panic::catch_unwind(run).unwrap_or_else(|panic| {
+            let message = watchdog::panic_message(&*panic);
+            log::error!("Task {} panicked: {}", id, message);
+            Err(ExecutionError::WorkerPanicked(message))
+        })
+    }
+
     fn run_to_completion(
         &mut self,
         mut task_order: TaskOrder,
     ) -> Result<TaskOrder, ExecutionError> {
+        // Fetched once so a redefinition only affects tasks started afterwards.
+        let bytecode = self.env.programs.bytecode(task_order.bytecode_id).unwrap();
+        let result = match self.shared.core_dumps.clone() {
+            None => self.run_until_done(&mut task_order, &bytecode, None),
+            Some(dir) => {
+                let mut trace = Trace::default();
+                let result = self.run_until_done(&mut task_order, &bytecode, Some(&mut trace));
+                match result {
+                    Err(e) if !matches!(e, ExecutionError::Cancelled) && !trace.joined_failure => {
+                        let dump = CoreDump::new(
+                            self.shared.identity.to_string(),
+                            task_order.id,
+                            e.clone(),
+                            &task_order.task,
+                            trace,
+                            |addr| self.env.memory.load(addr),
+                            &bytecode,
+                        );
+                        dump.write(&dir);
+                        Err(e)
+                    }
+                    result => result,
+                }
+            }
+        };
+        let arena = std::mem::take(&mut task_order.task.arena);
+        if !arena.is_empty() {
+            if let Err(e) = self.free(&task_order, Arena::Task, arena) {
+                log::warn!("Unable to free task {}'s blocks: {}", task_order.id, e);
+            }
+        }
+        match result {
+            Ok(()) => Ok(task_order),
+            Err(e) => Err(e.in_task(task_order.id, &task_order.task, &bytecode)),
+        }
+    }
+
+    /// Returns blocks of `arena` to the heap of the node the task's program was submitted to.
+    fn free(
+        &self,
+        task_order: &TaskOrder,
+        arena: Arena,
+        addrs: Vec<u64>,
+    ) -> Result<(), ExecutionError> {
+        let invalid = match task_order.emit_to {
+            None => addrs
+                .into_iter()
+                .find(|a| !self.shared.heap.free(arena, *a)),
+            Some(origin) => cluster::free_remote(origin, arena, addrs).map_err(|e| {
+                log::error!("Unable to free on {}: {}", origin, e);
+                ExecutionError::OriginUnreachable
+            })?,
+        };
+        match invalid {
+            Some(addr) => Err(ExecutionError::InvalidFree(addr)),
+            None => Ok(()),
+        }
+    }
+
+    fn run_until_done(
+        &mut self,
+        task_order: &mut TaskOrder,
+        bytecode: &ByteCode,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<(), ExecutionError> {
+        let hits = self
+            .shared
+            .coverage
+            .as_ref()
+            .map(|c| c.for_bytecode(task_order.bytecode_id, bytecode));
         // TODO(shelbyd): Never overflow stack.
+        let sandbox = task_order.sandbox.clone();
+        let budget = sandbox.as_deref().map(sandbox::Active::budget);
         loop {
-            let bytecode = self
-                .shared
-                .bytecode_registry
-                .get(&task_order.bytecode_id)
-                .unwrap()
-                .clone();
-            match task_order.task.run(&bytecode)? {
-                Execution::Terminated => {
-                    return Ok(task_order);
+            if self.shared.submissions.is_cancelled(&task_order.submission) {
+                return Err(ExecutionError::Cancelled);
+            }
+            match task_order.task.run(
+                bytecode,
+                hits.as_deref().map(Vec::as_slice),
+                budget,
+                trace.as_deref_mut(),
+            )? {
+                Execution::Terminated(termination) => {
+                    task_order.task.termination = Some(termination);
+                    return Ok(());
                 }
                 Execution::Fork => {
-                    use rand::Rng;
-
+                    let max_depth = self.shared.max_fork_depth;
+                    if task_order.task.fork_depth >= max_depth {
+                        return Err(ExecutionError::ForkDepthExceeded(max_depth));
+                    }
                     let mut forked = task_order.clone();
-                    forked.id = rand::thread_rng().gen();
-                    // TODO(shelbyd): Never generate duplicate ids.
+                    forked.id = task::child_id(task_order.id, &mut task_order.task.forks)?;
 
                     forked.task.forked = true;
+                    forked.task.fork_depth += 1;
+                    forked.task.forks = 0;
+                    // Blocks from the parent's arena stay the parent's.
+                    forked.task.arena.clear();
+                    forked.attempts = 0;
+                    forked.deferred.forked();
                     task_order.task.forked = false;
+                    if let Some(s) = &self.shared.sanitizer {
+                        s.forked(task_order.id, forked.id);
+                    }
 
                     forked.task.stack.push(task_order.id as i64);
+                    self.shared.priorities.forked(task_order.key(), forked.key());
+                    self.shared.submissions.started(&forked.submission);
                     task_order.task.stack.push(forked.id as i64);
 
-                    self.handle.push(forked);
+                    let threshold =
+                        setting(&FORK_INLINE_THRESHOLD, &config().fork_inline_threshold);
+                    let cheap = self
+                        .shared
+                        .fork_cost(
+                            task_order.bytecode_id,
+                            bytecode,
+                            forked.task.program_counter,
+                        )
+                        .worst
+                        .is_some_and(|worst| {
+                            worst < setting(&INLINE_FORK_COST, &config().inline_fork_cost)
+                        });
+                    if cheap || self.handle.pending() >= threshold {
+                        let (id, submission) = (forked.id, forked.submission);
+                        let result = self.run_caught(forked);
+                        self.env.results.finish(submission, id, result);
+                    } else {
+                        if let Some(j) = &self.shared.journal {
+                            j.queued(&forked, None);
+                        }
+                        self.handle.push(forked);
+                    }
                 }
-                Execution::Join { task_id, count } => {
-                    let joined = self.busy_until_task_done(task_id)?;
+                Execution::Join {
+                    task_id,
+                    count,
+                    status,
+                } => {
+                    let submission = task_order.submission;
+                    let joined = match self.busy_until_task_done(task_id, &submission) {
+                        Ok(joined) => joined,
+                        Err(e) => {
+                            self.shared.submissions.propagated(&submission, task_id);
+                            if let Some(t) = &mut trace {
+                                t.joined_failure = true;
+                            }
+                            return Err(e);
+                        }
+                    };
+                    if let Some(s) = &self.shared.sanitizer {
+                        s.joined(task_order.id, task_id);
+                    }
+                    if let Some(e) = joined.task.failure().filter(|_| !status) {
+                        if let Some(t) = &mut trace {
+                            t.joined_failure = true;
+                        }
+                        return Err(e);
+                    }
                     let other_stack = &joined.task.stack;
                     let to_push = other_stack.split_at(other_stack.len() - count).1;
                     task_order.task.stack.extend(to_push.iter().cloned());
+                    if status {
+                        let (code, halted) = match joined.task.termination {
+                            Some(Termination::Halted(code)) => (code, 1),
+                            _ => (0, 0),
+                        };
+                        task_order.task.stack.extend([code, halted]);
+                    }
+                    let effects = joined.deferred.into_own();
+                    if task_order.defers() {
+                        task_order.deferred.extend(effects);
+                    } else {
+                        for effect in effects {
+                            self.apply(task_order, effect);
+                        }
+                    }
                 }
                 Execution::Store { addr, value } => {
-                    self.shared.memory.insert(addr, value);
-                    if let Some(c) = &self.cluster {
-                        c.store(addr, value);
+                    if let Some(t) = &mut trace {
+                        t.accessed(addr);
+                    }
+                    if let Some(s) = &sandbox {
+                        s.check(addr)?;
+                    }
+                    self.sanitize(task_order, addr, sanitize::Kind::Write);
+                    self.effect(task_order, Effect::Store { addr, value });
+                }
+                Execution::Emit { value } => {
+                    let task_id = task_order.id;
+                    self.effect(task_order, Effect::Emit { task_id, value });
+                }
+                Execution::AllocGlobal { count } => {
+                    let base = match task_order.emit_to {
+                        None => self.shared.alloc_global(count),
+                        Some(origin) => {
+                            cluster::alloc_global_remote(origin, count).map_err(|e| {
+                                log::error!("Unable to allocate from {}: {}", origin, e);
+                                ExecutionError::OriginUnreachable
+                            })?
+                        }
+                    };
+                    let base = base.ok_or(ExecutionError::GlobalAddressesExhausted)?;
+                    task_order.task.stack.push(base as i64);
+                }
+                Execution::Alloc { arena, size } => {
+                    let base = match task_order.emit_to {
+                        None => self.shared.heap.alloc(arena, size),
+                        Some(origin) => {
+                            cluster::alloc_remote(origin, arena, size).map_err(|e| {
+                                log::error!("Unable to allocate from {}: {}", origin, e);
+                                ExecutionError::OriginUnreachable
+                            })?
+                        }
+                    };
+                    let base = base.ok_or(ExecutionError::HeapExhausted)?;
+                    if arena == Arena::Task {
+                        task_order.task.arena.push(base);
+                    }
+                    task_order.task.stack.push(base as i64);
+                }
+                Execution::Free { arena, addr } => self.free(task_order, arena, vec![addr])?,
+                Execution::DumpDebug => {
+                    let listing = task_order.task.debug_listing(bytecode);
+                    self.shared
+                        .dump_debug(task_order.emit_to, task_order.id, listing);
+                }
+                Execution::Extension { code } => {
+                    let extension = self
+                        .shared
+                        .extensions
+                        .get(&code)
+                        .map(|e| e.clone())
+                        .ok_or(ExecutionError::UnknownExtension(code))?;
+                    extension.call(code, &mut task_order.task.stack)?;
+                }
+                Execution::CallNative { id } => {
+                    if task_order.emit_to.is_some() {
+                        return Err(ExecutionError::NativeCallRemote(id));
                     }
+                    native::call(id, &mut task_order.task.stack)?;
                 }
                 Execution::Load { addr } => {
-                    task_order.task.stack.push(
-                        self.shared
-                            .memory
-                            .get(&addr)
-                            .map(|ref_| *ref_.value())
-                            .unwrap_or(0),
-                    );
+                    if let Some(t) = &mut trace {
+                        t.accessed(addr);
+                    }
+                    if let Some(s) = &sandbox {
+                        s.check(addr)?;
+                    }
+                    self.sanitize(task_order, addr, sanitize::Kind::Read);
+                    let value = self.load(task_order, addr);
+                    task_order.task.stack.push(value);
+                }
+                Execution::LoadPacked { addr, width, shift } => {
+                    if let Some(t) = &mut trace {
+                        t.accessed(addr);
+                    }
+                    if let Some(s) = &sandbox {
+                        s.check(addr)?;
+                    }
+                    self.sanitize(task_order, addr, sanitize::Kind::Read);
+                    let cell = self.load(task_order, addr);
+                    task_order.task.stack.push(width.extract(cell, shift));
+                }
+                Execution::StorePacked {
+                    addr,
+                    width,
+                    shift,
+                    value,
+                } => {
+                    if let Some(t) = &mut trace {
+                        t.accessed(addr);
+                    }
+                    if let Some(s) = &sandbox {
+                        s.check(addr)?;
+                    }
+                    // The whole cell is sent to peers, so other elements of it count as well.
+                    self.sanitize(task_order, addr, sanitize::Kind::Write);
+                    if task_order.defers() {
+                        // Held as the whole cell, which replaces the others' elements when
+                        // applied. Those are only applied in join order, so that's still
+                        // the same from run to run.
+                        let cell = width.insert(self.load(task_order, addr), shift, value);
+                        task_order
+                            .deferred
+                            .push(Effect::Store { addr, value: cell });
+                    } else {
+                        let cell = self.env.memory.store_packed(addr, width, shift, value);
+                        if let Some(c) = &self.cluster {
+                            c.store(addr, cell);
+                        }
+                    }
                 }
             }
         }
     }
 
-    fn busy_until_task_done(&mut self, task_id: usize) -> Result<TaskOrder, ExecutionError> {
+    /// Applies the effect, or holds it if the task defers its effects.
+    fn effect(&self, task_order: &mut TaskOrder, effect: Effect) {
+        if task_order.defers() {
+            task_order.deferred.push(effect);
+        } else {
+            self.apply(task_order, effect);
+        }
+    }
+
+    fn apply(&self, task_order: &TaskOrder, effect: Effect) {
+        match effect {
+            Effect::Store { addr, value } => {
+                self.env.memory.store(addr, value);
+                if let Some(c) = &self.cluster {
+                    c.store(addr, value);
+                }
+            }
+            Effect::Emit { task_id, value } => {
+                let emitted = Emitted { task_id, value };
+                self.shared.emit(task_order.emit_to, emitted);
+            }
+        }
+    }
+
+    /// What the task sees at `addr`, counting stores it holds back.
+    fn load(&self, task_order: &TaskOrder, addr: u64) -> i64 {
+        task_order
+            .deferred
+            .load(addr)
+            .unwrap_or_else(|| self.env.memory.load(addr))
+    }
+
+    fn sanitize(&self, task_order: &TaskOrder, addr: u64, kind: sanitize::Kind) {
+        if let Some(s) = &self.shared.sanitizer {
+            let pc = task_order.task.program_counter - 1;
+            s.access(task_order.id, addr, kind, pc);
+        }
+    }
+
+    fn busy_until_task_done(
+        &mut self,
+        task_id: usize,
+        submission: &Submission,
+    ) -> Result<TaskOrder, ExecutionError> {
+        let shared = self.shared.clone();
+        let key = (submission.id, task_id);
+        let _waiting = shared.priorities.wait(key);
         let mut last_failed = false;
         loop {
             // TODO(shelbyd): Error with unrecognized task id.
-            if let Some(done) = self.shared.finished.remove(&task_id) {
-                return done.1;
+            if let Some(done) = self.env.results.take(key) {
+                return done;
+            }
+            if self.shared.submissions.is_cancelled(submission) {
+                return Err(ExecutionError::Cancelled);
             }
             if !self.busy_tick() {
                 if last_failed {
@@ -249,33 +1378,381 @@ impl Executor {
             }
         }
     }
+
+    /// Helps run tasks until none of the submission's are left on this node.
+    fn busy_until_settled(&mut self, submission: &Submission) {
+        while !self.shared.submissions.is_settled(submission) {
+            if !self.busy_tick() {
+                return;
+            }
+        }
+    }
 }
 
 struct RemoteExecutor {
     handle: task_queue::Handle<TaskOrder>,
     shared: Arc<VmHandle>,
     peer: Peer,
+    /// Runs tasks kept on this node when there are no local workers to leave them to.
+    local: Executor,
+    /// Whether local workers take the tasks kept on this node, so they don't hold up the ones
+    /// in flight to the peer.
+    hand_off: bool,
+    /// How long to wait before shipping again after the peer refused work as busy.
+    backoff: std::time::Duration,
+    /// Tasks submitted to the peer whose results haven't been collected, with when they were sent.
+    in_flight: HashMap<TaskKey, (TaskOrder, std::time::Instant)>,
+    max_in_flight: usize,
+    /// See `--max-shipped-task-bytes`.
+    max_task_bytes: usize,
+    last_poll: std::time::Instant,
+    /// Set once the peer stopped taking tasks, so only the ones in flight are collected.
+    draining: bool,
+    /// Bytecode the peer refused for using an instruction it forbids, whose tasks run here.
+    refused: std::collections::HashSet<u64>,
+    /// Held back while peers nearer to this node have room.
+    zone: zone::Slot,
 }
 
+/// Most bytes a value of the task's stack, locals or arena takes serialized, with a separator.
+const SERIALIZED_VALUE_BYTES: usize = 21;
+
+/// Most bytes a call frame or deferred effect takes serialized, besides its locals.
+const SERIALIZED_ENTRY_BYTES: usize = 96;
+
+/// Most bytes the rest of the task takes serialized, field names and all.
+const SERIALIZED_FIXED_BYTES: usize = 1024;
+
+/// At least as many bytes as the task takes sent to peers, from how much it holds rather than
+/// serializing it, so only tasks that may be too large to ship are measured.
+fn serialized_len_bound(task_order: &TaskOrder) -> usize {
+    let task = &task_order.task;
+    let locals: usize = task.frames.iter().map(|f| f.locals.len()).sum();
+    let values = task.stack.len() + task.locals.len() + task.arena.len() + locals;
+    let entries = task.frames.len() + task_order.deferred.held();
+    values * SERIALIZED_VALUE_BYTES + entries * SERIALIZED_ENTRY_BYTES + SERIALIZED_FIXED_BYTES
+}
+
+/// Bytes the task takes as sent to peers, counted without holding them.
+fn serialized_len(task_order: &TaskOrder) -> usize {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, task_order).unwrap();
+    counter.0
+}
+
+const MAX_BUSY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often finished results are collected from a peer while tasks are in flight there.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(2);
+
 impl RemoteExecutor {
+    fn new(
+        queue: &TaskQueue<TaskOrder>,
+        shared: &Arc<VmHandle>,
+        cluster: &Option<Arc<Cluster>>,
+        peer: Peer,
+        hand_off: bool,
+    ) -> RemoteExecutor {
+        RemoteExecutor {
+            handle: queue.handle(),
+            shared: shared.clone(),
+            zone: peer.zone_slot(),
+            peer,
+            local: Executor::new(queue, shared, cluster),
+            hand_off,
+            backoff: std::time::Duration::from_secs(0),
+            in_flight: HashMap::new(),
+            max_in_flight: setting(&MAX_IN_FLIGHT_PER_PEER, &config().max_in_flight_per_peer),
+            max_task_bytes: setting(&MAX_SHIPPED_TASK_BYTES, &config().max_shipped_task_bytes),
+            last_poll: std::time::Instant::now(),
+            draining: false,
+            refused: Default::default(),
+        }
+    }
+
+    /// Runs on its own thread named for the peer.
+    fn spawn(mut self) -> std::thread::JoinHandle<()> {
+        let name = format!("flock-remote-{}", self.peer.identity());
+        spawn_named(name.clone(), move || self.supervise(&name))
+    }
+
+    /// Runs until the peer is gone or drained. If the loop panics, the tasks in flight are
+    /// handed back as if the peer were lost, which leaves nothing else behind, so it's restarted.
+    fn supervise(&mut self, name: &str) {
+        loop {
+            let run = std::panic::AssertUnwindSafe(|| self.run());
+            let panic = match std::panic::catch_unwind(run) {
+                Ok(()) => return,
+                Err(panic) => panic,
+            };
+            self.shared
+                .health
+                .died(name, watchdog::panic_message(&*panic));
+            self.abandon();
+            std::thread::sleep(watchdog::RESTART_DELAY);
+            self.shared.health.restarted(name);
+        }
+    }
+
     fn run(&mut self) {
-        while let Some(task_order) = self.handle.wait_next() {
-            let to_insert = match self.peer.try_run(&task_order) {
-                Ok(finished) => Ok(finished),
-                Err(RunError::Execution(e)) => Err(e),
-                Err(RunError::ConnectionReset) => {
-                    self.handle.push_nonworker(task_order);
-                    log::warn!("Connection to peer {:?} lost", self.peer);
-                    return;
+        loop {
+            if self.draining && self.in_flight.is_empty() {
+                log::info!("Peer {:?} drained", self.peer);
+                return;
+            }
+            let saturated = self.draining || self.in_flight.len() >= self.max_in_flight;
+            self.zone.set_saturated(saturated);
+            if !saturated && !self.zone.may_take() {
+                // Other zones only get what this one has no room for.
+                std::thread::sleep(POLL_INTERVAL);
+            } else if !saturated {
+                let next = if self.in_flight.is_empty() {
+                    match self.handle.wait_next() {
+                        Some(task_order) => Some(task_order),
+                        None => return,
+                    }
+                } else {
+                    match self.handle.next() {
+                        ControlFlow::Continue(task_order) => Some(task_order),
+                        ControlFlow::Retry => None,
+                        ControlFlow::Finish => return,
+                    }
+                };
+                if let Some(task_order) = next {
+                    if !self.ship(task_order) {
+                        return;
+                    }
                 }
-                Err(RunError::Unknown) => {
-                    self.handle.push_nonworker(task_order);
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+                if self.last_poll.elapsed() < POLL_INTERVAL {
                     continue;
                 }
+            } else if let Some(wait) = POLL_INTERVAL.checked_sub(self.last_poll.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            if !self.in_flight.is_empty() && !self.collect() {
+                return;
+            }
+        }
+    }
+
+    /// Submits the task to the peer, or keeps it on this node if it's too cheap to ship, too
+    /// large, the peer refused its bytecode or it came from another peer while this node has
+    /// room. Returns false once the peer is gone.
+    fn ship(&mut self, task_order: TaskOrder) -> bool {
+        let class = task_order.class();
+        // Joined tasks are still shipped. Local workers already take them from their own queues
+        // first, and in fork/join code nearly every task that reaches the shared pool is joined.
+        let (id, submission) = (task_order.id, task_order.submission);
+        if self.shared.submissions.is_cancelled(&submission) {
+            let cancelled = Err(ExecutionError::Cancelled);
+            self.local.env.results.finish(submission, id, cancelled);
+            return true;
+        }
+        // Sent by a peer, or forked by a task that was.
+        let sticky = task_order.emit_to.is_some()
+            && self.handle.pending() < self.shared.affinity_queue_depth;
+        if sticky || self.refused.contains(&task_order.bytecode_id) {
+            self.keep(task_order);
+            return true;
+        }
+        let bytes = if serialized_len_bound(&task_order) > self.max_task_bytes {
+            serialized_len(&task_order)
+        } else {
+            0
+        };
+        if bytes > self.max_task_bytes {
+            log::warn!(
+                "Task {} is {} bytes serialized, over --max-shipped-task-bytes, keeping it here",
+                id,
+                bytes
+            );
+            self.keep(task_order);
+            return true;
+        }
+        if !self.shared.offload.worth_shipping(class) {
+            self.keep(task_order);
+            return true;
+        }
+
+        self.shared.reservations.reserve(&task_order);
+        match self.peer.submit(&task_order) {
+            Ok(()) => {
+                self.backoff = std::time::Duration::from_secs(0);
+                self.in_flight
+                    .insert(task_order.key(), (task_order, std::time::Instant::now()));
+                true
+            }
+            Err(RunError::Busy(retry_after)) => {
+                // Leave the task to local workers and other peers meanwhile. The peer never took
+                // it, so this doesn't count against its attempts.
+                self.give_back(task_order);
+                self.backoff = (self.backoff * 2).min(MAX_BUSY_BACKOFF).max(retry_after);
+                log::debug!("Peer {:?} busy, backing off {:?}", self.peer, self.backoff);
+                self.zone.set_saturated(true);
+                std::thread::sleep(self.backoff);
+                true
+            }
+            Err(RunError::Draining) => {
+                self.give_back(task_order);
+                self.draining = true;
+                true
+            }
+            Err(RunError::ForbiddenOpCode(mnemonic)) => {
+                log::warn!(
+                    "Peer {:?} forbids {}, running bytecode {} here",
+                    self.peer,
+                    mnemonic,
+                    task_order.bytecode_id
+                );
+                self.refused.insert(task_order.bytecode_id);
+                if self.shared.reservations.release(task_order.key()) {
+                    self.keep(task_order);
+                }
+                true
+            }
+            Err(RunError::ConnectionReset) => {
+                // The peer may have started the task before the connection went.
+                self.lost(task_order);
+                self.abandon();
+                false
+            }
+            Err(RunError::Unknown) => {
+                self.give_back(task_order);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                true
+            }
+        }
+    }
+
+    /// Finishes tasks whose results the peer has. Returns false once the peer is gone.
+    fn collect(&mut self) -> bool {
+        self.last_poll = std::time::Instant::now();
+        let results = match self.peer.poll(self.in_flight.keys().cloned().collect()) {
+            Ok(results) => results,
+            Err(RunError::ConnectionReset) => {
+                self.abandon();
+                return false;
+            }
+            Err(_) => return true,
+        };
+        for (key, result) in results {
+            let (task_order, sent) = match self.in_flight.remove(&key) {
+                Some(f) => f,
+                None => continue,
             };
-            let already_there = self.shared.finished.insert(task_order.id, to_insert);
-            assert!(already_there.is_none());
+            let elapsed = sent.elapsed();
+            self.shared
+                .offload
+                .record_remote(task_order.class(), elapsed);
+            self.shared
+                .peer_stats
+                .record(self.peer.identity(), elapsed, true);
+            if !self.shared.reservations.release(key) {
+                log::debug!(
+                    "Task {} was stolen back while on {:?}",
+                    task_order.id,
+                    self.peer
+                );
+                continue;
+            }
+            self.local
+                .env
+                .results
+                .finish(task_order.submission, task_order.id, result);
+        }
+        true
+    }
+
+    /// Leaves the task to local workers, or runs it here if there are none.
+    fn keep(&mut self, task_order: TaskOrder) {
+        if self.hand_off {
+            return self.handle.push_for_workers(task_order);
+        }
+        let (id, submission, class) = (task_order.id, task_order.submission, task_order.class());
+        let started = std::time::Instant::now();
+        let result = self.local.run_caught(task_order);
+        self.shared.offload.record_local(class, started.elapsed());
+        self.local.env.results.finish(submission, id, result);
+    }
+
+    fn give_back(&mut self, task_order: TaskOrder) {
+        if self.shared.reservations.release(task_order.key()) {
+            self.handle.push_nonworker(task_order);
+        }
+    }
+
+    /// Gives the task back once the program's backoff has passed, or fails it with `error` if
+    /// it has no attempts left.
+    fn retry(&mut self, mut task_order: TaskOrder, error: ExecutionError) {
+        if !self.shared.reservations.release(task_order.key()) {
+            return;
+        }
+        let retry = match self.local.env.programs.bytecode(task_order.bytecode_id) {
+            Some(bytecode) => retry::policy(&bytecode),
+            None => return self.handle.push_nonworker(task_order),
+        };
+        task_order.attempts += 1;
+        if task_order.attempts >= retry.attempts {
+            log::warn!(
+                "Task {} failed {} attempts, last with {:?}",
+                task_order.id,
+                task_order.attempts,
+                error
+            );
+            self.local
+                .env
+                .results
+                .finish(task_order.submission, task_order.id, Err(error));
+            return;
+        }
+        let backoff = retry::backoff(&retry, task_order.attempts);
+        log::debug!(
+            "Retrying task {} after {:?}, attempt {} failed with {:?}",
+            task_order.id,
+            backoff,
+            task_order.attempts,
+            error
+        );
+        self.handle.push_nonworker_after(task_order, backoff);
+    }
+
+    /// Handles a task that may have run on the peer before it was lost. Tasks resuming in an
+    /// `.idempotent` region are retried, others are run again unless `--fail-lost-tasks`.
+    fn lost(&mut self, task_order: TaskOrder) {
+        if self.local.idempotent(&task_order) {
+            self.retry(task_order, ExecutionError::PeerLost);
+        } else if !setting(&retry::FAIL_LOST_TASKS, &config().fail_lost_tasks) {
+            self.give_back(task_order);
+        } else if self.shared.reservations.release(task_order.key()) {
+            log::warn!(
+                "Task {} was lost with peer {:?} and isn't idempotent",
+                task_order.id,
+                self.peer
+            );
+            self.local.env.results.finish(
+                task_order.submission,
+                task_order.id,
+                Err(ExecutionError::PeerLost),
+            );
+        }
+    }
+
+    fn abandon(&mut self) {
+        log::warn!("Connection to peer {:?} lost", self.peer);
+        for (_, (task_order, _)) in std::mem::take(&mut self.in_flight) {
+            self.lost(task_order);
         }
     }
 }
@@ -285,4 +1762,38 @@ struct TaskOrder {
     id: usize,
     task: Task,
     bytecode_id: u64,
+    /// Where `EMIT` values go, `None` when this VM started the program.
+    emit_to: Option<std::net::SocketAddr>,
+    /// Limits from the peer that sent this task, never sent onwards.
+    #[serde(skip)]
+    sandbox: Option<Arc<sandbox::Active>>,
+    #[serde(default)]
+    submission: Submission,
+    /// Attempts that failed transiently, counted against the program's `.retry` policy.
+    #[serde(default)]
+    attempts: u32,
+    /// Effects held back until the task is joined, under `Submission::ordered`.
+    #[serde(default)]
+    deferred: Deferred,
+}
+
+/// A task's submission id and its own. Every program's main task is 0 and a forked task's id
+/// follows from its parent's, so only the submission tells apart tasks of different programs,
+/// whichever node started them.
+type TaskKey = (u64, usize);
+
+impl TaskOrder {
+    fn class(&self) -> offload::TaskClass {
+        (self.bytecode_id, self.task.program_counter)
+    }
+
+    fn key(&self) -> TaskKey {
+        (self.submission.id, self.id)
+    }
+
+    /// Whether effects are held back rather than applied. The root task applies them, its own
+    /// and those of the children it joins.
+    fn defers(&self) -> bool {
+        self.submission.ordered && self.task.fork_depth > 0
+    }
 }
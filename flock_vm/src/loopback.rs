@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+
+use tokio::runtime::Runtime;
+
+use crate::{cluster::ClusterServer, Vm};
+
+/// A scheduler VM connected to a leaf VM served from this process on an ephemeral localhost
+/// port, for exercising the cluster path in tests.
+pub struct Loopback {
+    pub scheduler: Vm,
+    pub leaf: Vm,
+    // Serves the leaf until dropped.
+    _runtime: Runtime,
+}
+
+impl Loopback {
+    pub fn start() -> std::io::Result<Loopback> {
+        let runtime = Runtime::new()?;
+        let leaf = Vm::create_leaf();
+        let local = SocketAddr::from(([127, 0, 0, 1], 0));
+        let (addr, serve) = runtime.block_on(ClusterServer::new(&leaf.handle()).bind(local))?;
+        runtime.spawn(serve);
+
+        let scheduler = Vm::builder().peers(vec![addr.to_string()]).build()?;
+        Ok(Loopback {
+            scheduler,
+            leaf,
+            _runtime: runtime,
+        })
+    }
+}
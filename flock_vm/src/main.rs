@@ -1,9 +1,29 @@
 use flock_vm::{cluster::ClusterServer, Vm};
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
+gflags::define! {
+    /// Run bytecode written by `flock_asm --output`, in either encoding, instead of
+    /// serving as a leaf. Lets programs be assembled on one machine and run on another.
+    --run <PATH>: &str
+}
+
+type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> DynResult<()> {
     pretty_env_logger::init_timed();
+    gflags::parse();
 
+    if RUN.is_present() {
+        let bytecode = flock_vm::compiled::decode(&std::fs::read(RUN.flag)?)?;
+        let stack = flock_vm::run(bytecode)?;
+        println!("{:?}", stack);
+        return Ok(());
+    }
+
+    Ok(serve()?)
+}
+
+#[tokio::main]
+async fn serve() -> std::io::Result<()> {
     let vm = Vm::create_leaf();
     ClusterServer::new(&vm.handle()).listen().await?;
 
@@ -0,0 +1,93 @@
+use crate::ExecutionError;
+
+gflags::define! {
+    /// Shared library providing the functions `CALL_NATIVE` may call. Needs the `native` feature.
+    pub --native-library <PATH>: &str
+}
+
+gflags::define! {
+    /// Comma-separated `ID=SYMBOL` pairs exposing library functions to `CALL_NATIVE ID`.
+    pub --native-symbols <SYMBOLS>: &str
+}
+
+#[cfg(feature = "native")]
+const MAX_RESULTS: usize = 16;
+
+/// Receives the arguments deepest first and writes its results, returning how many it wrote or
+/// a negative error code.
+#[cfg(feature = "native")]
+type NativeFn = unsafe extern "C" fn(*const i64, usize, *mut i64, usize) -> isize;
+
+#[cfg(feature = "native")]
+struct Natives {
+    _library: libloading::Library,
+    symbols: std::collections::HashMap<u16, NativeFn>,
+}
+
+#[cfg(feature = "native")]
+lazy_static::lazy_static! {
+    static ref NATIVES: Option<Natives> = load();
+}
+
+#[cfg(feature = "native")]
+fn load() -> Option<Natives> {
+    if !NATIVE_LIBRARY.is_present() {
+        return None;
+    }
+    let library = match unsafe { libloading::Library::new(NATIVE_LIBRARY.flag) } {
+        Ok(l) => l,
+        Err(e) => {
+            log::error!("Unable to load {}: {}", NATIVE_LIBRARY.flag, e);
+            return None;
+        }
+    };
+    let mut symbols = std::collections::HashMap::new();
+    if NATIVE_SYMBOLS.is_present() {
+        for pair in NATIVE_SYMBOLS.flag.split(',') {
+            let (id, name) = match pair.split_once('=').map(|(i, n)| (i.parse::<u16>(), n)) {
+                Some((Ok(id), name)) => (id, name),
+                _ => {
+                    log::error!("Invalid native symbol {}, expected ID=SYMBOL", pair);
+                    continue;
+                }
+            };
+            match unsafe { library.get::<NativeFn>(name.as_bytes()) } {
+                Ok(symbol) => {
+                    symbols.insert(id, *symbol);
+                }
+                Err(e) => log::error!("Unable to find native symbol {}: {}", name, e),
+            }
+        }
+    }
+    Some(Natives {
+        _library: library,
+        symbols,
+    })
+}
+
+/// Pops an argument count and that many arguments, then pushes the function's results.
+#[cfg(feature = "native")]
+pub fn call(id: u16, stack: &mut Vec<i64>) -> Result<(), ExecutionError> {
+    let function = NATIVES
+        .as_ref()
+        .and_then(|n| n.symbols.get(&id))
+        .ok_or(ExecutionError::NativeUnavailable(id))?;
+    let count = stack.pop().ok_or(ExecutionError::PopFromEmptyStack)?;
+    let split = stack
+        .len()
+        .checked_sub(count as usize)
+        .ok_or(ExecutionError::PopFromEmptyStack)?;
+    let args = stack.split_off(split);
+    let mut results = [0; MAX_RESULTS];
+    let written = unsafe { function(args.as_ptr(), args.len(), results.as_mut_ptr(), MAX_RESULTS) };
+    if written < 0 || written as usize > MAX_RESULTS {
+        return Err(ExecutionError::NativeCallFailed(id, written as i64));
+    }
+    stack.extend_from_slice(&results[..written as usize]);
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+pub fn call(id: u16, _: &mut Vec<i64>) -> Result<(), ExecutionError> {
+    Err(ExecutionError::NativeUnavailable(id))
+}
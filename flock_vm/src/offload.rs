@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use dashmap::DashMap;
+
+gflags::define! {
+    /// Stop shipping task classes to peers once they run locally faster than a round trip costs.
+    pub --adaptive-offload: bool = true
+}
+
+/// Tasks are classed by bytecode and starting instruction, so the children of one `FORK` share
+/// a class.
+pub type TaskClass = (u64, usize);
+
+/// A class judged too cheap to ship is still shipped this often, so a faster network or peer
+/// gets noticed.
+const REMEASURE_EVERY: u32 = 32;
+
+const SMOOTHING: f64 = 0.2;
+
+#[derive(Default)]
+struct Timings {
+    local: Option<Duration>,
+    remote: Option<Duration>,
+    kept_local: u32,
+}
+
+pub struct OffloadStats {
+    classes: DashMap<TaskClass, Timings>,
+    enabled: bool,
+}
+
+impl OffloadStats {
+    pub fn new(enabled: bool) -> Self {
+        OffloadStats {
+            classes: DashMap::new(),
+            enabled,
+        }
+    }
+
+    pub fn record_local(&self, class: TaskClass, elapsed: Duration) {
+        let mut timings = self.classes.entry(class).or_default();
+        timings.local = Some(smooth(timings.local, elapsed));
+    }
+
+    pub fn record_remote(&self, class: TaskClass, elapsed: Duration) {
+        let mut timings = self.classes.entry(class).or_default();
+        timings.remote = Some(smooth(timings.remote, elapsed));
+    }
+
+    /// Whether the time a task of this class takes covers the overhead of a remote round trip.
+    pub fn worth_shipping(&self, class: TaskClass) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        let mut timings = match self.classes.get_mut(&class) {
+            Some(t) => t,
+            None => return true,
+        };
+        let (local, remote) = match (timings.local, timings.remote) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return true,
+        };
+        let overhead = remote.saturating_sub(local);
+        if local >= overhead {
+            return true;
+        }
+        timings.kept_local += 1;
+        if timings.kept_local % REMEASURE_EVERY == 0 {
+            return true;
+        }
+        log::debug!(
+            "Keeping task class {:?} local, {:?} to run vs {:?} to ship",
+            class,
+            local,
+            overhead
+        );
+        false
+    }
+}
+
+fn smooth(average: Option<Duration>, sample: Duration) -> Duration {
+    match average {
+        None => sample,
+        Some(a) => a.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::identity::NodeIdentity;
+
+gflags::define! {
+    /// Warn when a peer's p99 round trip, task execution included, exceeds this.
+    pub --slow-peer-p99-ms: u64 = 5000
+}
+
+gflags::define! {
+    /// Warn when more than this percentage of recent requests to a peer fail.
+    pub --slow-peer-failure-percent: u64 = 10
+}
+
+/// Bucket `i` counts round trips shorter than 2^i microseconds, the last one everything longer.
+const BUCKETS: usize = 40;
+
+/// Requests per window. Statistics cover the current and previous window, so an old burst of
+/// slow requests ages out.
+const WINDOW: u64 = 256;
+
+/// A peer isn't judged degraded on fewer requests than this.
+const MIN_SAMPLES: u64 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub peer: NodeIdentity,
+    pub requests: u64,
+    pub failures: u64,
+    pub p50_micros: u64,
+    pub p99_micros: u64,
+    pub degraded: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Window {
+    buckets: [u64; BUCKETS],
+    requests: u64,
+    failures: u64,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window {
+            buckets: [0; BUCKETS],
+            requests: 0,
+            failures: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    current: Window,
+    previous: Window,
+    requests: u64,
+    failures: u64,
+    degraded: bool,
+}
+
+impl Stats {
+    fn record(&mut self, elapsed: Duration, ok: bool) {
+        if self.current.requests == WINDOW {
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.requests += 1;
+        self.requests += 1;
+        if ok {
+            let micros = elapsed.as_micros() as u64;
+            let bucket = (64 - micros.leading_zeros() as usize).min(BUCKETS - 1);
+            self.current.buckets[bucket] += 1;
+        } else {
+            self.current.failures += 1;
+            self.failures += 1;
+        }
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile of recent successful round trips.
+    fn quantile(&self, q: f64) -> Duration {
+        let counts: Vec<u64> = (0..BUCKETS)
+            .map(|i| self.current.buckets[i] + self.previous.buckets[i])
+            .collect();
+        let total: u64 = counts.iter().sum();
+        let rank = (total as f64 * q).ceil() as u64;
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return Duration::from_micros(1 << i);
+            }
+        }
+        Duration::from_micros(0)
+    }
+
+    fn recent(&self) -> (u64, u64) {
+        (
+            self.current.requests + self.previous.requests,
+            self.current.failures + self.previous.failures,
+        )
+    }
+
+    fn is_degraded(&self, p99_limit: Duration, failure_percent: u64) -> bool {
+        let (requests, failures) = self.recent();
+        requests >= MIN_SAMPLES
+            && (self.quantile(0.99) > p99_limit || failures * 100 > requests * failure_percent)
+    }
+}
+
+/// Round-trip latency and failures of requests to each peer.
+pub struct PeerStats {
+    peers: DashMap<Uuid, (NodeIdentity, Stats)>,
+    p99_limit: Duration,
+    failure_percent: u64,
+}
+
+impl PeerStats {
+    pub fn new(p99_limit: Duration, failure_percent: u64) -> Self {
+        PeerStats {
+            peers: DashMap::new(),
+            p99_limit,
+            failure_percent,
+        }
+    }
+
+    pub fn record(&self, peer: &NodeIdentity, elapsed: Duration, ok: bool) {
+        let mut entry = self
+            .peers
+            .entry(peer.id)
+            .or_insert_with(|| (peer.clone(), Stats::default()));
+        let stats = &mut entry.1;
+        stats.record(elapsed, ok);
+
+        let degraded = stats.is_degraded(self.p99_limit, self.failure_percent);
+        if degraded != stats.degraded {
+            stats.degraded = degraded;
+            let (requests, failures) = stats.recent();
+            if degraded {
+                log::warn!(
+                    "Peer {} degraded: p50 {:?}, p99 {:?}, {}/{} recent requests failed",
+                    peer,
+                    stats.quantile(0.5),
+                    stats.quantile(0.99),
+                    failures,
+                    requests
+                );
+            } else {
+                log::info!("Peer {} recovered", peer);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<PeerStatus> {
+        self.peers
+            .iter()
+            .map(|entry| {
+                let (peer, stats) = entry.value();
+                PeerStatus {
+                    peer: peer.clone(),
+                    requests: stats.requests,
+                    failures: stats.failures,
+                    p50_micros: stats.quantile(0.5).as_micros() as u64,
+                    p99_micros: stats.quantile(0.99).as_micros() as u64,
+                    degraded: stats.degraded,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,64 @@
+use dashmap::DashMap;
+
+use crate::TaskKey;
+
+/// Tracks which tasks are being joined, so they and everything they fork can jump ahead of other
+/// queued work.
+#[derive(Default)]
+pub struct Priorities {
+    /// Task to the number of executors blocked joining it.
+    waiters: DashMap<TaskKey, usize>,
+    /// Forked task to the task that forked it.
+    parents: DashMap<TaskKey, TaskKey>,
+}
+
+impl Priorities {
+    pub fn forked(&self, parent: TaskKey, child: TaskKey) {
+        self.parents.insert(child, parent);
+    }
+
+    pub fn finished(&self, key: TaskKey) {
+        self.parents.remove(&key);
+    }
+
+    pub fn wait(&self, key: TaskKey) -> Waiting<'_> {
+        *self.waiters.entry(key).or_insert(0) += 1;
+        Waiting {
+            priorities: self,
+            key,
+        }
+    }
+
+    /// Whether the task, or any task it descends from, is being joined.
+    pub fn is_urgent(&self, key: TaskKey) -> bool {
+        if self.waiters.is_empty() {
+            return false;
+        }
+        let mut current = key;
+        loop {
+            if self.waiters.contains_key(&current) {
+                return true;
+            }
+            match self.parents.get(&current) {
+                Some(parent) => current = *parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+pub struct Waiting<'p> {
+    priorities: &'p Priorities,
+    key: TaskKey,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.priorities.waiters.get_mut(&self.key) {
+            *count -= 1;
+        }
+        self.priorities
+            .waiters
+            .remove_if(&self.key, |_, count| *count == 0);
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::SystemTime;
+
+use flock_bytecode::ByteCode;
+use serde::{Deserialize, Serialize};
+
+/// Bytecode a node holds, as listed by `VmHandle::programs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramInfo {
+    pub id: u64,
+    pub instructions: usize,
+    /// Size of the binary encoding, as sent to peers.
+    pub bytes: usize,
+    pub entry_points: Vec<EntryPoint>,
+    /// When this node was given the current definition, or recovered it from its journal.
+    pub registered: SystemTime,
+    /// Holders besides the registry, such as tasks running it now.
+    pub references: usize,
+}
+
+/// The program entry or a statically called subroutine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryPoint {
+    pub pc: usize,
+    pub label: Option<String>,
+}
+
+pub(crate) fn entry_points(bytecode: &ByteCode) -> Vec<EntryPoint> {
+    bytecode
+        .entry_points()
+        .into_iter()
+        .map(|pc| EntryPoint {
+            pc,
+            label: bytecode.label(pc).map(String::from),
+        })
+        .collect()
+}
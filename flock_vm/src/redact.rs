@@ -0,0 +1,40 @@
+use std::fmt::{Display, Formatter, Result};
+
+gflags::define! {
+    /// How much task data, stacks and emitted values, logs show: `off` logs it all, `sizes-only`
+    /// only how many values there are, `full` not even that.
+    pub --log-redaction <POLICY>: Redaction = Redaction::SizesOnly
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    Full,
+    SizesOnly,
+    Off,
+}
+
+impl gflags::custom::Value for Redaction {
+    fn parse(arg: gflags::custom::Arg) -> gflags::custom::Result<Self> {
+        match arg.get_str() {
+            "full" => Ok(Redaction::Full),
+            "sizes-only" => Ok(Redaction::SizesOnly),
+            "off" => Ok(Redaction::Off),
+            _ => Err(gflags::custom::Error::new(
+                "expected one of: full, sizes-only, off",
+            )),
+        }
+    }
+}
+
+/// Values from a task, displayed as `--log-redaction` allows.
+pub struct Payload<'a>(pub &'a [i64]);
+
+impl Display for Payload<'_> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match LOG_REDACTION.flag {
+            Redaction::Off => write!(f, "{:?}", self.0),
+            Redaction::SizesOnly => write!(f, "<len {}>", self.0.len()),
+            Redaction::Full => write!(f, "<redacted>"),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::{Arc, Mutex};
+
+use crate::Emitted;
+
+/// A fold over emitted values running on its own thread as they arrive.
+pub struct Reduction<T> {
+    value: Arc<Mutex<Option<T>>>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl<T: Send + 'static> Reduction<T> {
+    pub(crate) fn spawn(
+        emitted: flume::Receiver<Emitted>,
+        init: T,
+        fold: impl Fn(T, Emitted) -> T + Send + 'static,
+    ) -> Self {
+        let value = Arc::new(Mutex::new(Some(init)));
+        let thread = {
+            let value = value.clone();
+            std::thread::spawn(move || {
+                for e in emitted.iter() {
+                    let mut value = value.lock().unwrap();
+                    let acc = value.take().unwrap();
+                    *value = Some(fold(acc, e));
+                }
+            })
+        };
+        Reduction { value, thread }
+    }
+
+    /// The value folded so far.
+    pub fn current(&self) -> T
+    where
+        T: Clone,
+    {
+        self.value.lock().unwrap().clone().unwrap()
+    }
+
+    /// Waits for the Vm to shut down and returns the final value.
+    pub fn finish(self) -> T {
+        self.thread.join().unwrap();
+        self.value.lock().unwrap().take().unwrap()
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::{TaskKey, TaskOrder};
+
+gflags::define! {
+    /// Milliseconds a task may wait on a remote peer before idle local workers steal it back.
+    /// Only tasks resuming in an `.idempotent` region are, as the peer may still finish them.
+    pub --steal-back-after-ms: u64 = 1000
+}
+
+struct Reservation {
+    task_order: TaskOrder,
+    since: Instant,
+}
+
+/// Tasks currently committed to a remote peer. Whoever removes a reservation owns the task's
+/// result: either the remote executor when the peer answers, or a local executor stealing it.
+pub struct Reservations {
+    tasks: DashMap<TaskKey, Reservation>,
+    steal_after: Duration,
+}
+
+impl Reservations {
+    pub fn new(steal_after: Duration) -> Self {
+        Reservations {
+            tasks: DashMap::new(),
+            steal_after,
+        }
+    }
+
+    pub fn reserve(&self, task_order: &TaskOrder) {
+        let reservation = Reservation {
+            task_order: task_order.clone(),
+            since: Instant::now(),
+        };
+        self.tasks.insert(task_order.key(), reservation);
+    }
+
+    pub fn release(&self, key: TaskKey) -> bool {
+        self.tasks.remove(&key).is_some()
+    }
+
+    /// Takes the longest waiting task past the steal-back delay that `may_rerun` allows to run
+    /// again while the peer could still be running it.
+    pub fn steal(&self, may_rerun: impl Fn(&TaskOrder) -> bool) -> Option<TaskOrder> {
+        let key = self
+            .tasks
+            .iter()
+            .filter(|r| r.since.elapsed() > self.steal_after && may_rerun(&r.task_order))
+            .min_by_key(|r| r.since)
+            .map(|r| *r.key())?;
+        self.tasks.remove(&key).map(|(_, r)| r.task_order)
+    }
+}
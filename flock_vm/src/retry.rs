@@ -0,0 +1,40 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use flock_bytecode::{ByteCode, Retry};
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// Attempts a task in an `.idempotent` region gets when a peer is lost while running it, for
+    /// programs without a `.retry` directive.
+    pub --retry-attempts: u64 = 3
+}
+
+gflags::define! {
+    /// Fail tasks lost with a peer outside an `.idempotent` region with PeerLost, rather than
+    /// running them again, for programs whose effects mustn't happen twice.
+    pub --fail-lost-tasks: bool = false
+}
+
+gflags::define! {
+    /// Wait before a task's first retry, doubling with each further one, for programs without
+    /// a `.retry` directive.
+    pub --retry-backoff-ms: u64 = 100
+}
+
+/// The program's `.retry` policy, or the configured one.
+pub fn policy(bytecode: &ByteCode) -> Retry {
+    bytecode.retry().unwrap_or_else(|| Retry {
+        attempts: u32::try_from(setting(&RETRY_ATTEMPTS, &config().retry_attempts))
+            .unwrap_or(u32::MAX)
+            .max(1),
+        backoff_ms: setting(&RETRY_BACKOFF_MS, &config().retry_backoff_ms),
+    })
+}
+
+/// Wait before the retry following `failures` failed attempts.
+pub fn backoff(retry: &Retry, failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    Duration::from_millis(retry.backoff_ms.saturating_mul(1 << doublings))
+}
@@ -0,0 +1,201 @@
+use std::io::Read;
+use std::time::SystemTime;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::checkpoint::ObjectStore;
+
+/// An S3-compatible bucket, addressed path-style so it works with other implementations too.
+pub struct S3 {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3 {
+    /// `location` is `BUCKET/PREFIX`. Credentials and region come from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN` and `AWS_REGION`.
+    pub fn from_env(location: &str, endpoint: Option<String>) -> std::io::Result<S3> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not set", name))
+            })
+        };
+        let region = var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = endpoint
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region))
+            .trim_end_matches('/')
+            .to_string();
+        let host = endpoint
+            .split_once("://")
+            .map_or(&endpoint[..], |(_, rest)| rest)
+            .to_string();
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        Ok(S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            region,
+            access_key: var("AWS_ACCESS_KEY_ID")?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: var("AWS_SESSION_TOKEN").ok(),
+            endpoint,
+            host,
+        })
+    }
+
+    /// `None` if there is no such object.
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        body: &[u8],
+    ) -> std::io::Result<Option<ureq::Response>> {
+        let object = if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        };
+        let path = format!("/{}/{}", encode(&self.bucket), encode(&object));
+        let (date_time, date) = timestamp(SystemTime::now());
+        let payload = hex(&Sha256::digest(body));
+
+        let mut headers = vec![
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", payload.clone()),
+            ("x-amz-date", date_time.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method,
+            path,
+            headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect::<String>(),
+            signed,
+            payload
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date_time,
+            scope,
+            hex(&Sha256::digest(canonical.as_bytes()))
+        );
+        let key = [&date[..], &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_key).into_bytes(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            signed,
+            hex(&hmac(&key, to_sign.as_bytes()))
+        );
+
+        let mut request = ureq::request(method, &format!("{}{}", self.endpoint, path))
+            .set("authorization", &authorization);
+        for (name, value) in &headers[1..] {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(std::io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl ObjectStore for S3 {
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        match self.request("PUT", key, bytes)? {
+            Some(_) => Ok(()),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No bucket {}", self.bucket),
+            )),
+        }
+    }
+
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match self.request("GET", key, &[])? {
+            Some(response) => {
+                let mut bytes = Vec::new();
+                response.into_reader().read_to_end(&mut bytes)?;
+                Ok(Some(bytes))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.request("DELETE", key, &[])?;
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes all but unreserved characters and `/`, as SigV4 canonical paths are.
+fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `YYYYMMDDTHHMMSSZ` and `YYYYMMDD` in UTC.
+fn timestamp(now: SystemTime) -> (String, String) {
+    let secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let date_time = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+    (date_time, date)
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use flock_bytecode::ByteCode;
+use serde::Deserialize;
+
+use crate::{config::config, ExecutionError};
+
+gflags::define! {
+    /// Comma-separated mnemonics, such as `PUSH,ADD,HALT`, that bytecode from peers may only
+    /// use. Peers are refused bytecode using any other.
+    pub --remote-allowed-opcodes <MNEMONICS>: &str
+}
+
+gflags::define! {
+    /// Comma-separated mnemonics, such as `CALL_NATIVE,EXT`, peers are refused bytecode using.
+    pub --remote-denied-opcodes <MNEMONICS>: &str
+}
+
+/// Limits on tasks received from a peer, configured per origin IP under `[sandbox."<ip>"]`
+/// with `[sandbox.default]` as the fallback.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sandbox {
+    pub instruction_budget: Option<u64>,
+    pub memory: Option<Range<u64>>,
+    pub refuse_foreign_stores: bool,
+}
+
+impl Sandbox {
+    pub fn for_origin(origin: IpAddr) -> Option<&'static Sandbox> {
+        let sandboxes = &config().sandbox;
+        sandboxes
+            .get(&origin.to_string())
+            .or_else(|| sandboxes.get("default"))
+    }
+
+    pub fn allows(&self, addr: u64) -> bool {
+        self.memory.as_ref().is_none_or(|m| m.contains(&addr))
+    }
+}
+
+/// Which instructions bytecode defined by peers may use, by mnemonic.
+#[derive(Debug, Clone, Default)]
+pub struct OpCodePolicy {
+    /// The only mnemonics allowed, if restricted.
+    pub allowed: Option<HashSet<String>>,
+    pub denied: HashSet<String>,
+}
+
+impl OpCodePolicy {
+    /// From `--remote-allowed-opcodes` and `--remote-denied-opcodes`.
+    pub fn configured() -> OpCodePolicy {
+        OpCodePolicy {
+            allowed: if REMOTE_ALLOWED_OPCODES.is_present() {
+                Some(mnemonics(REMOTE_ALLOWED_OPCODES.flag))
+            } else {
+                None
+            },
+            denied: if REMOTE_DENIED_OPCODES.is_present() {
+                mnemonics(REMOTE_DENIED_OPCODES.flag)
+            } else {
+                HashSet::new()
+            },
+        }
+    }
+
+    /// The mnemonic of the first instruction in `bytecode` the policy refuses.
+    pub fn refuses(&self, bytecode: &ByteCode) -> Option<&'static str> {
+        (0..bytecode.len())
+            .filter_map(|i| bytecode.get(i))
+            .map(|op| op.instruction().mnemonic)
+            .find(|m| {
+                self.denied.contains(*m) || self.allowed.as_ref().is_some_and(|a| !a.contains(*m))
+            })
+    }
+}
+
+fn mnemonics(list: &str) -> HashSet<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .inspect(|m| {
+            if flock_bytecode::spec::by_mnemonic(m).is_none() {
+                log::warn!("Unknown mnemonic {} in opcode policy", m);
+            }
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// A sandbox applied to one remote request, shared by every task it forks.
+#[derive(Debug)]
+pub struct Active {
+    pub origin: IpAddr,
+    pub sandbox: &'static Sandbox,
+    budget: AtomicI64,
+}
+
+impl Active {
+    pub fn for_origin(origin: IpAddr) -> Option<Self> {
+        let sandbox = Sandbox::for_origin(origin)?;
+        let budget = sandbox.instruction_budget.map_or(i64::MAX, |b| b as i64);
+        Some(Active {
+            origin,
+            sandbox,
+            budget: AtomicI64::new(budget),
+        })
+    }
+
+    pub fn budget(&self) -> &AtomicI64 {
+        &self.budget
+    }
+
+    pub fn check(&self, addr: u64) -> Result<(), ExecutionError> {
+        if self.sandbox.allows(addr) {
+            Ok(())
+        } else {
+            Err(ExecutionError::SandboxViolation(addr))
+        }
+    }
+}
+
+pub fn spend(budget: &AtomicI64) -> Result<(), ExecutionError> {
+    if budget.fetch_sub(1, Ordering::Relaxed) <= 0 {
+        return Err(ExecutionError::InstructionBudgetExhausted);
+    }
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use dashmap::{DashMap, DashSet};
+
+gflags::define! {
+    /// Track which tasks read and write each address, reporting accesses by tasks that neither
+    /// fork nor join each other in between. Only tasks run on this node are checked.
+    pub --sanitize: bool = false
+}
+
+/// Forks and joins each task has seen, by task id.
+type Clock = HashMap<usize, u64>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Kind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Access {
+    pub kind: Kind,
+    /// Bytecode index of the instruction.
+    pub pc: usize,
+}
+
+/// Two accesses to an address, at least one a write, that no fork or join orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Race {
+    pub addr: u64,
+    pub first: Access,
+    pub second: Access,
+}
+
+impl std::fmt::Display for Race {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Data race on 0x{:x}: {:?} at {} and {:?} at {} by concurrent tasks",
+            self.addr, self.first.kind, self.first.pc, self.second.kind, self.second.pc
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Seen {
+    task: usize,
+    /// The task's own entry in its clock at the time.
+    epoch: u64,
+    pc: usize,
+}
+
+#[derive(Default)]
+struct Shadow {
+    write: Option<Seen>,
+    /// Reads since the last write, the latest of each task.
+    reads: Vec<Seen>,
+}
+
+#[derive(Default)]
+pub struct Sanitizer {
+    clocks: DashMap<usize, Clock>,
+    shadow: DashMap<u64, Shadow>,
+    races: DashSet<Race>,
+}
+
+impl Sanitizer {
+    /// Forgets earlier programs, starting over from the root task.
+    pub fn start(&self, root: usize) {
+        self.clocks.clear();
+        self.shadow.clear();
+        self.races.clear();
+        self.clocks
+            .insert(root, std::iter::once((root, 1)).collect());
+    }
+
+    /// The child starts with everything the parent has seen, which it then moves past.
+    pub fn forked(&self, parent: usize, child: usize) {
+        let mut clock = match self.clocks.get_mut(&parent) {
+            Some(mut parent_clock) => {
+                let clock = parent_clock.clone();
+                *parent_clock.entry(parent).or_default() += 1;
+                clock
+            }
+            // Forked from a task a peer sent, which isn't tracked.
+            None => return,
+        };
+        clock.insert(child, 1);
+        self.clocks.insert(child, clock);
+    }
+
+    /// The parent has now seen everything the child did.
+    pub fn joined(&self, parent: usize, child: usize) {
+        let child_clock = match self.clocks.remove(&child) {
+            Some((_, c)) => c,
+            None => return,
+        };
+        if let Some(mut clock) = self.clocks.get_mut(&parent) {
+            for (task, epoch) in child_clock {
+                let seen = clock.entry(task).or_default();
+                *seen = std::cmp::max(*seen, epoch);
+            }
+            *clock.entry(parent).or_default() += 1;
+        }
+    }
+
+    pub fn access(&self, task: usize, addr: u64, kind: Kind, pc: usize) {
+        // Cloned so the clock isn't locked alongside the shadow.
+        let clock = match self.clocks.get(&task) {
+            Some(c) => c.clone(),
+            None => return,
+        };
+        let before = |seen: &Seen| seen.task == task || clock.get(&seen.task) >= Some(&seen.epoch);
+        let current = Seen {
+            task,
+            epoch: clock[&task],
+            pc,
+        };
+
+        let mut shadow = self.shadow.entry(addr).or_default();
+        let mut conflicting: Vec<(Kind, Seen)> = shadow
+            .write
+            .iter()
+            .filter(|w| !before(w))
+            .map(|w| (Kind::Write, *w))
+            .collect();
+        match kind {
+            Kind::Write => {
+                conflicting.extend(
+                    shadow
+                        .reads
+                        .iter()
+                        .filter(|r| !before(r))
+                        .map(|r| (Kind::Read, *r)),
+                );
+                shadow.write = Some(current);
+                shadow.reads.clear();
+            }
+            Kind::Read => {
+                shadow.reads.retain(|r| r.task != task);
+                shadow.reads.push(current);
+            }
+        }
+        drop(shadow);
+
+        for (first_kind, first) in conflicting {
+            let race = Race {
+                addr,
+                first: Access {
+                    kind: first_kind,
+                    pc: first.pc,
+                },
+                second: Access { kind, pc },
+            };
+            if self.races.insert(race) {
+                log::error!("{}", race);
+            }
+        }
+    }
+
+    /// Every distinct race found since `start`.
+    pub fn races(&self) -> Vec<Race> {
+        let mut races: Vec<_> = self.races.iter().map(|r| *r).collect();
+        races.sort();
+        races
+    }
+}
@@ -0,0 +1,139 @@
+use std::process::Command;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use crate::{cluster::Cluster, identity::NodeIdentity, task_queue::TaskQueue};
+use crate::{RemoteExecutor, TaskOrder, VmHandle};
+
+gflags::define! {
+    /// Program run as `CMD up QUEUED` when the queue stays deep, printing the addresses of the
+    /// nodes it started one per line once they accept connections, and as `CMD down ADDR ID`
+    /// once a peer drained after the queue stayed empty.
+    pub --scaler-hook <CMD>: &str
+}
+
+gflags::define! {
+    /// Queue depth that asks the scaler hook for more nodes once sustained.
+    pub --scale-up-queued: usize = 1000
+}
+
+gflags::define! {
+    /// Seconds the queue must stay deep, or empty, before scaling up or down.
+    pub --scale-after-secs: u64 = 60
+}
+
+/// Starts and stops cluster nodes as the backlog grows and shrinks, see `Vm::scale_with`.
+pub trait ClusterScalerHook: Send + Sync {
+    /// The queue stayed at least `--scale-up-queued` deep. Returns the addresses of any nodes
+    /// started, once they accept connections.
+    fn scale_up(&self, queued: usize) -> Vec<String>;
+
+    /// `peer` at `addr` finished its work after being drained and has shut down.
+    fn scale_down(&self, peer: &NodeIdentity, addr: &str);
+}
+
+pub struct CommandHook {
+    command: String,
+}
+
+impl CommandHook {
+    pub fn new(command: impl Into<String>) -> Self {
+        CommandHook {
+            command: command.into(),
+        }
+    }
+}
+
+impl ClusterScalerHook for CommandHook {
+    fn scale_up(&self, queued: usize) -> Vec<String> {
+        let output = Command::new(&self.command)
+            .arg("up")
+            .arg(queued.to_string())
+            .output();
+        match output {
+            Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect(),
+            Ok(out) => {
+                log::error!("Scaler hook {} up exited with {}", self.command, out.status);
+                Vec::new()
+            }
+            Err(e) => {
+                log::error!("Unable to run scaler hook {}: {}", self.command, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn scale_down(&self, peer: &NodeIdentity, addr: &str) {
+        let status = Command::new(&self.command)
+            .arg("down")
+            .arg(addr)
+            .arg(peer.id.to_string())
+            .status();
+        match status {
+            Ok(s) if s.success() => {}
+            Ok(s) => log::error!("Scaler hook {} down exited with {}", self.command, s),
+            Err(e) => log::error!("Unable to run scaler hook {}: {}", self.command, e),
+        }
+    }
+}
+
+/// Samples the queue every second until the VM is dropped.
+pub(crate) fn watch(
+    hook: Arc<dyn ClusterScalerHook>,
+    queue: TaskQueue<TaskOrder>,
+    shared: Weak<VmHandle>,
+    cluster: Weak<Cluster>,
+    hand_off: bool,
+) {
+    let (up_queued, after) = (
+        SCALE_UP_QUEUED.flag,
+        Duration::from_secs(SCALE_AFTER_SECS.flag),
+    );
+    let (mut deep_since, mut empty_since) = (None, None);
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let (shared, cluster) = match (shared.upgrade(), cluster.upgrade()) {
+            (Some(s), Some(c)) => (s, c),
+            _ => return,
+        };
+        let queued = shared.queued();
+        let now = Instant::now();
+        let deep = *deep_since.get_or_insert(now);
+        let empty = *empty_since.get_or_insert(now);
+        if queued < up_queued {
+            deep_since = None;
+        }
+        if queued > 0 {
+            empty_since = None;
+        }
+
+        if queued >= up_queued && now - deep >= after {
+            deep_since = None;
+            log::info!("{} tasks queued for {:?}, scaling up", queued, after);
+            for addr in hook.scale_up(queued) {
+                match cluster.add_peer(addr.clone()) {
+                    Ok(peer) => {
+                        let cluster = Some(cluster.clone());
+                        RemoteExecutor::new(&queue, &shared, &cluster, peer, hand_off).spawn();
+                    }
+                    Err(e) => log::error!("Unable to connect to new peer {}: {}", addr, e),
+                }
+            }
+        } else if queued == 0 && now - empty >= after {
+            empty_since = None;
+            if let Some(draining) = cluster.drain_peer() {
+                let hook = hook.clone();
+                std::thread::spawn(move || {
+                    let (peer, addr) = (draining.identity.clone(), draining.addr.clone());
+                    draining.wait();
+                    hook.scale_down(&peer, &addr);
+                });
+            }
+        }
+    }
+}
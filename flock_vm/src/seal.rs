@@ -0,0 +1,88 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+
+gflags::define! {
+    /// File holding a 256-bit key as 64 hex digits. When given, or set in `FLOCK_STATE_KEY`,
+    /// state written to disk such as the journal is encrypted with it.
+    pub --state-key-file <PATH>: &str
+}
+
+const KEY_VAR: &str = "FLOCK_STATE_KEY";
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts and authenticates records persisted to disk.
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+}
+
+#[derive(Debug)]
+pub enum SealError {
+    NotHex,
+    Truncated,
+    /// Corrupted, or sealed with a different key.
+    Unauthentic,
+}
+
+impl std::fmt::Display for SealError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Sealer {
+    /// The sealer for the configured key, `None` if state is stored in the clear.
+    pub fn configured() -> Option<Sealer> {
+        let (source, key) = if STATE_KEY_FILE.is_present() {
+            let path = STATE_KEY_FILE.flag;
+            let key = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Unable to read state key {}: {}", path, e));
+            (path.to_string(), key)
+        } else {
+            (KEY_VAR.to_string(), std::env::var(KEY_VAR).ok()?)
+        };
+        match from_hex(key.trim()) {
+            Some(key) if key.len() == 32 => Some(Sealer {
+                cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            }),
+            _ => panic!("State key in {} must be 64 hex digits", source),
+        }
+    }
+
+    /// Hex of a random nonce followed by the ciphertext.
+    pub fn seal(&self, plaintext: &[u8]) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("sealing never fails for in-memory buffers");
+        to_hex(nonce.iter().chain(&ciphertext))
+    }
+
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>, SealError> {
+        let bytes = from_hex(sealed).ok_or(SealError::NotHex)?;
+        if bytes.len() < NONCE_LEN {
+            return Err(SealError::Truncated);
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SealError::Unauthentic)
+    }
+}
+
+fn to_hex<'b>(bytes: impl Iterator<Item = &'b u8>) -> String {
+    bytes.map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,152 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use memmap2::MmapMut;
+use uuid::Uuid;
+
+use crate::config::{config, setting};
+
+gflags::define! {
+    /// File, such as one under /dev/shm, backing memory addresses from `--shared-memory-base`
+    /// for every flock_vm on this host that maps it. Stores to those addresses aren't sent to
+    /// peers sharing it.
+    pub --shared-memory <PATH>: &str
+}
+
+gflags::define! {
+    pub --shared-memory-base: u64 = 0
+}
+
+gflags::define! {
+    /// Size of the `--shared-memory` region, which every VM mapping it must agree on.
+    pub --shared-memory-cells: u64 = 1 << 20
+}
+
+/// The region's id fills the first two cells of the file, so VMs can tell they map the same one.
+const HEADER_BYTES: usize = 16;
+
+/// Path, base address and cells of the configured region, if any.
+pub(crate) fn configured() -> Option<(PathBuf, u64, u64)> {
+    let path = if SHARED_MEMORY.is_present() {
+        PathBuf::from(SHARED_MEMORY.flag)
+    } else {
+        PathBuf::from(config().shared_memory.as_ref()?)
+    };
+    Some((
+        path,
+        setting(&SHARED_MEMORY_BASE, &config().shared_memory_base),
+        setting(&SHARED_MEMORY_CELLS, &config().shared_memory_cells),
+    ))
+}
+
+/// Memory cells mapped from a file, shared by the processes mapping it.
+pub struct SharedRegion {
+    /// Kept so the cells stay mapped.
+    _map: MmapMut,
+    cells: *const AtomicI64,
+    id: Uuid,
+    base: u64,
+    len: u64,
+}
+
+// SAFETY: The cells are only accessed through atomics, and the mapping lives as long as the
+// region.
+unsafe impl Send for SharedRegion {}
+unsafe impl Sync for SharedRegion {}
+
+impl SharedRegion {
+    /// Maps the file at `path`, creating it if this is the first VM to.
+    pub fn open(path: &Path, base: u64, cells: u64) -> std::io::Result<SharedRegion> {
+        let size = HEADER_BYTES as u64 + cells * 8;
+        create(path, size)?;
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let actual = file.metadata()?.len();
+        if actual != size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} holds {} cells, not {}",
+                    path.display(),
+                    actual.saturating_sub(HEADER_BYTES as u64) / 8,
+                    cells
+                ),
+            ));
+        }
+        // SAFETY: Other processes may write the file concurrently, which is why it's only read
+        // through atomics.
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let id = Uuid::from_slice(&map[..HEADER_BYTES]).expect("header holds a uuid");
+        // The mapping is page aligned, so cells after the 16 byte header are 8 byte aligned.
+        let cells_ptr = map[HEADER_BYTES..].as_mut_ptr() as *const AtomicI64;
+        log::info!("Mapped shared memory {} ({})", path.display(), id);
+        Ok(SharedRegion {
+            _map: map,
+            cells: cells_ptr,
+            id,
+            base,
+            len: cells,
+        })
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn cell(&self, addr: u64) -> Option<&AtomicI64> {
+        let index = addr.checked_sub(self.base).filter(|i| *i < self.len)?;
+        // SAFETY: `index` is within the mapping, which outlives the borrow.
+        Some(unsafe { &*self.cells.add(index as usize) })
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.cell(addr).is_some()
+    }
+
+    pub fn load(&self, addr: u64) -> Option<i64> {
+        Some(self.cell(addr)?.load(Ordering::SeqCst))
+    }
+
+    /// Returns false if `addr` is outside the region.
+    pub fn store(&self, addr: u64, value: i64) -> bool {
+        self.cell(addr)
+            .map(|c| c.store(value, Ordering::SeqCst))
+            .is_some()
+    }
+
+    /// Atomically replaces the cell at `addr` with `f` of it, returning the new value.
+    pub fn update(&self, addr: u64, f: impl Fn(i64) -> i64) -> Option<i64> {
+        let old = self
+            .cell(addr)?
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| Some(f(v)))
+            .unwrap();
+        Some(f(old))
+    }
+
+    /// Cells that have been written, for `--dump`.
+    pub fn nonzero(&self) -> impl Iterator<Item = (u64, i64)> + '_ {
+        (0..self.len).filter_map(move |i| {
+            let addr = self.base + i;
+            self.load(addr).filter(|v| *v != 0).map(|v| (addr, v))
+        })
+    }
+}
+
+/// Creates the file with a fresh id unless it exists. Other VMs may be starting too, so it's
+/// prepared under a temporary name and linked into place, which only one of them can do.
+fn create(path: &Path, size: u64) -> std::io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(Uuid::new_v4().as_bytes())?;
+    file.set_len(size)?;
+    let linked = std::fs::hard_link(&tmp, path);
+    std::fs::remove_file(&tmp)?;
+    match linked {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,66 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::{identity::NodeIdentity, QueueOrder, Vm};
+
+gflags::define! {
+    /// Run this many nodes in-process, this VM and leaves it sends tasks to, to estimate how a
+    /// program behaves distributed. Workers are split evenly between them. 0 runs only this VM.
+    pub --simulate-cluster <NODES>: usize = 0
+}
+
+gflags::define! {
+    /// One-way delay added to every message between `--simulate-cluster` nodes.
+    pub --simulated-latency-ms: u64 = 0
+}
+
+/// Nodes and latency from `--simulate-cluster` and `--simulated-latency-ms`, if simulating.
+pub(crate) fn configured() -> Option<(usize, Duration)> {
+    if SIMULATE_CLUSTER.flag == 0 {
+        return None;
+    }
+    Some((
+        SIMULATE_CLUSTER.flag,
+        Duration::from_millis(SIMULATED_LATENCY_MS.flag),
+    ))
+}
+
+/// Starts `count` leaves with `workers` each, served on ephemeral localhost ports with `latency`
+/// added to every message, counting coverage if `coverage`.
+pub(crate) fn start_leaves(
+    count: usize,
+    workers: usize,
+    latency: Duration,
+    (order, max_age): (QueueOrder, Duration),
+    coverage: bool,
+) -> std::io::Result<Vec<Vm>> {
+    log::info!(
+        "Simulating {} leaves with {} workers and {:?} latency",
+        count,
+        workers,
+        latency
+    );
+    (0..count)
+        .map(|n| {
+            Vm::builder()
+                .workers(workers)
+                .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .latency(latency)
+                .queue_order(order, max_age)
+                .coverage(coverage)
+                .identity(NodeIdentity::simulated(n + 1))
+                .build()
+        })
+        .collect()
+}
+
+/// Waits `latency` on either side of handling a message, as if it crossed the network each way.
+pub(crate) async fn delayed<F: std::future::Future>(latency: Duration, handle: F) -> F::Output {
+    if latency.is_zero() {
+        return handle.await;
+    }
+    tokio::time::sleep(latency).await;
+    let output = handle.await;
+    tokio::time::sleep(latency).await;
+    output
+}
@@ -0,0 +1,108 @@
+//! The stable text `DUMP_DEBUG` prints for a task. The first line names the format's version,
+//! bumped whenever a line changes meaning, so golden files and external tools can rely on it:
+//!
+//! ```text
+//! flock-debug 1
+//! pc 4
+//! op -4 PUSH 5
+//! op -1 DUMP_DEBUG
+//! stack 13
+//! ```
+//!
+//! `op` lines give an instruction's offset from `pc`, its mnemonic, then its operands as encoded
+//! on the wire. `stack` lines list values from the top of the stack down.
+
+use std::str::FromStr;
+
+use flock_bytecode::{wire, ByteCode, OpCode};
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: u32 = 1;
+
+const HEADER: &str = "flock-debug";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub pc: usize,
+    /// Instructions around `pc` by their offset from it, as written in `op` lines.
+    pub ops: Vec<(isize, String)>,
+    /// Top first.
+    pub stack: Vec<i64>,
+}
+
+/// The instructions within a few of `pc` and the stack, given top last.
+pub(crate) fn around(bytecode: &ByteCode, pc: usize, stack: &[i64]) -> Snapshot {
+    let bounds: usize = 5;
+    let ops = bytecode
+        .surrounding(pc, bounds)
+        .map(|(i, op)| ((i as isize) - (pc as isize), render(op)))
+        .collect();
+    Snapshot {
+        pc,
+        ops,
+        stack: stack.iter().rev().copied().collect(),
+    }
+}
+
+/// An instruction as its mnemonic followed by its wire operands.
+pub(crate) fn render(op: &OpCode) -> String {
+    let encoded = wire::encode(op);
+    std::iter::once(op.instruction().mnemonic.to_string())
+        .chain(encoded[1..].iter().map(|o| o.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{} {}", HEADER, VERSION)?;
+        writeln!(f, "pc {}", self.pc)?;
+        for (offset, op) in &self.ops {
+            writeln!(f, "op {} {}", offset, op)?;
+        }
+        for value in &self.stack {
+            writeln!(f, "stack {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Snapshot, String> {
+        let mut lines = s.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+        match lines
+            .next()
+            .map(|(_, l)| l.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(header) if header == [HEADER, &VERSION.to_string()] => {}
+            Some(header) if header.first() == Some(&HEADER) => {
+                return Err(format!("Unsupported debug format {}", header.join(" ")));
+            }
+            _ => return Err(format!("Missing {} header", HEADER)),
+        }
+
+        let mut pc = None;
+        let mut ops = Vec::new();
+        let mut stack = Vec::new();
+        for (n, line) in lines {
+            let invalid = || format!("Invalid line {}: {}", n + 1, line);
+            let (key, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            match key {
+                "pc" => pc = Some(rest.parse().map_err(|_| invalid())?),
+                "op" => {
+                    let (offset, op) = rest.split_once(' ').ok_or_else(invalid)?;
+                    ops.push((offset.parse().map_err(|_| invalid())?, op.to_string()));
+                }
+                "stack" => stack.push(rest.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Snapshot {
+            pc: pc.ok_or("Missing pc line")?,
+            ops,
+            stack,
+        })
+    }
+}
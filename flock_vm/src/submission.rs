@@ -0,0 +1,215 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::finished::TaskResult;
+use crate::ExecutionError;
+
+gflags::define! {
+    /// What happens to the rest of a program once one of its tasks fails: `fail-fast` cancels
+    /// them and returns the first error, `collect` lets them finish and returns every error.
+    pub --error-policy <POLICY>: ErrorPolicy = ErrorPolicy::FailFast
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    #[default]
+    FailFast,
+    /// Errors of tasks a peer forked reach the origin once that peer has finished all of the
+    /// program's tasks it was sent.
+    Collect,
+}
+
+impl gflags::custom::Value for ErrorPolicy {
+    fn parse(arg: gflags::custom::Arg) -> gflags::custom::Result<Self> {
+        match arg.get_str() {
+            "fail-fast" => Ok(ErrorPolicy::FailFast),
+            "collect" => Ok(ErrorPolicy::Collect),
+            _ => Err(gflags::custom::Error::new(
+                "expected one of: fail-fast, collect",
+            )),
+        }
+    }
+}
+
+/// The program run a task belongs to, shared by every task it forks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: u64,
+    pub policy: ErrorPolicy,
+    /// Whether forked tasks' effects wait until they're joined, see `--ordered-completion`.
+    #[serde(default)]
+    pub ordered: bool,
+}
+
+#[derive(Default)]
+struct State {
+    /// Tasks started on this node that haven't finished.
+    live: usize,
+    /// First error under `FailFast`, after which the remaining tasks are cancelled.
+    failure: Option<ExecutionError>,
+    /// Errors by task under `Collect`, except those a joining task failed with too.
+    errors: Vec<(usize, ExecutionError)>,
+    /// Where errors collected on a peer are reported.
+    origin: Option<SocketAddr>,
+    /// When the submission ended after failing, kept until `expire` so its stragglers are
+    /// dropped.
+    ended: Option<Instant>,
+}
+
+/// Tracks the tasks and errors of each submission running on this node.
+#[derive(Default)]
+pub struct Submissions {
+    states: DashMap<u64, State>,
+    /// Tasks started or admitted here that haven't finished, of every submission. Those sent
+    /// on to peers count until their results are back.
+    live: AtomicUsize,
+    quiet: Notify,
+}
+
+impl Submissions {
+    pub fn started(&self, submission: &Submission) {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        self.states.entry(submission.id).or_default().live += 1;
+    }
+
+    /// A task sent by a peer, errors of which go back to `origin`.
+    pub fn admitted(&self, submission: &Submission, origin: Option<SocketAddr>) {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        let mut state = self.states.entry(submission.id).or_default();
+        state.live += 1;
+        if state.origin.is_none() {
+            state.origin = origin;
+        }
+    }
+
+    /// Records how a task finished. `reported` errors reach the origin some other way, with the
+    /// task's result.
+    pub fn finished(
+        &self,
+        submission: &Submission,
+        id: usize,
+        result: &TaskResult,
+        reported: bool,
+    ) {
+        let mut state = self.states.entry(submission.id).or_default();
+        state.live = state.live.saturating_sub(1);
+        let was = self
+            .live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if was == Ok(1) {
+            self.quiet.notify_waiters();
+        }
+        match (result, submission.policy) {
+            (Ok(_), _) | (Err(ExecutionError::Cancelled), _) => {}
+            (Err(e), ErrorPolicy::FailFast) => {
+                if state.failure.is_none() {
+                    log::info!(
+                        "Task {} failed, cancelling submission {}",
+                        id,
+                        submission.id
+                    );
+                    state.failure = Some(e.clone());
+                }
+            }
+            (Err(_), ErrorPolicy::Collect) if reported => {}
+            (Err(e), ErrorPolicy::Collect) => state.errors.push((id, e.clone())),
+        }
+    }
+
+    /// The joining task failed with `task`'s error, so it's only counted once.
+    pub fn propagated(&self, submission: &Submission, task: usize) {
+        if let Some(mut state) = self.states.get_mut(&submission.id) {
+            state.errors.retain(|(id, _)| *id != task);
+        }
+    }
+
+    pub fn is_cancelled(&self, submission: &Submission) -> bool {
+        self.states
+            .get(&submission.id)
+            .is_some_and(|s| s.failure.is_some())
+    }
+
+    /// Cancels a submission another node saw fail.
+    pub fn cancel(&self, id: u64) {
+        if let Some(mut state) = self.states.get_mut(&id) {
+            state.failure.get_or_insert(ExecutionError::Cancelled);
+        }
+    }
+
+    /// Resolves once every task started or admitted here has finished.
+    pub async fn quiesce(&self) {
+        loop {
+            let quiet = self.quiet.notified();
+            if self.live.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            quiet.await;
+        }
+    }
+
+    /// Whether tasks of the submission may still run or be joined here.
+    pub fn is_running(&self, id: u64) -> bool {
+        self.states.contains_key(&id)
+    }
+
+    pub fn is_settled(&self, submission: &Submission) -> bool {
+        self.states.get(&submission.id).is_none_or(|s| s.live == 0)
+    }
+
+    /// Errors collected on this peer once it holds no more of the submission's tasks, with
+    /// where to report them.
+    pub fn take_report(
+        &self,
+        submission: &Submission,
+    ) -> Option<(SocketAddr, Vec<ExecutionError>)> {
+        let (_, state) = self
+            .states
+            .remove_if(&submission.id, |_, s| s.live == 0 && s.origin.is_some())?;
+        let errors: Vec<_> = state.errors.into_iter().map(|(_, e)| e).collect();
+        if errors.is_empty() {
+            return None;
+        }
+        Some((state.origin?, errors))
+    }
+
+    /// Adds errors a peer reported.
+    pub fn reported(&self, id: u64, errors: Vec<ExecutionError>) {
+        if let Some(mut state) = self.states.get_mut(&id) {
+            state.errors.extend(errors.into_iter().map(|e| (0, e)));
+        }
+    }
+
+    /// Ends a submission started here, returning its first error under `FailFast` or every
+    /// error under `Collect`. A failed submission is remembered so its stragglers are dropped.
+    pub fn end(&self, submission: &Submission) -> Vec<ExecutionError> {
+        let (_, state) = match self.states.remove(&submission.id) {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        match state.failure {
+            Some(failure) => {
+                self.states.insert(
+                    submission.id,
+                    State {
+                        failure: Some(ExecutionError::Cancelled),
+                        ended: Some(Instant::now()),
+                        ..State::default()
+                    },
+                );
+                vec![failure]
+            }
+            None => state.errors.into_iter().map(|(_, e)| e).collect(),
+        }
+    }
+
+    /// Forgets failed submissions that ended more than `ttl` ago.
+    pub fn expire(&self, ttl: Duration) {
+        self.states
+            .retain(|_, s| s.ended.is_none_or(|ended| ended.elapsed() <= ttl));
+    }
+}
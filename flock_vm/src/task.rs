@@ -1,10 +1,82 @@
-use flock_bytecode::{ByteCode, ConditionFlags, OpCode};
+use std::sync::atomic::{AtomicI64, AtomicU64};
+
+use flock_bytecode::{Arena, ByteCode, ConditionFlags, OpCode, PackedWidth};
+
+use crate::core_dump::Trace;
+use crate::snapshot::Snapshot;
+
+gflags::define! {
+    /// What arithmetic overflow does in programs started here, on every node running them:
+    /// `wrap` around or `trap` with an ExecutionError.
+    pub --int-overflow <MODE>: IntOverflow = IntOverflow::Wrap
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum IntOverflow {
+    #[default]
+    Wrap,
+    Trap,
+}
+
+impl gflags::custom::Value for IntOverflow {
+    fn parse(arg: gflags::custom::Arg) -> gflags::custom::Result<Self> {
+        match arg.get_str() {
+            "wrap" => Ok(IntOverflow::Wrap),
+            "trap" => Ok(IntOverflow::Trap),
+            _ => Err(gflags::custom::Error::new("expected one of: wrap, trap")),
+        }
+    }
+}
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Task {
     pub(crate) program_counter: usize,
     pub(crate) stack: Vec<i64>,
     pub(crate) forked: bool,
+    /// Forks between the root task and this one.
+    #[serde(default)]
+    pub(crate) fork_depth: u64,
+    /// The `CARRY` and `OVERFLOW` flags, as the last `CMP` or `TEST` left them.
+    #[serde(default)]
+    pub(crate) carry: bool,
+    #[serde(default)]
+    pub(crate) overflow: bool,
+    /// Locals of the current call, see `OpCode::LoadLocal`.
+    #[serde(default)]
+    pub(crate) locals: Vec<i64>,
+    /// Frames of the calls `CALL` made that haven't returned, innermost last.
+    #[serde(default)]
+    pub(crate) frames: Vec<Frame>,
+    /// How the task ended, once it has.
+    #[serde(default)]
+    pub(crate) termination: Option<Termination>,
+    /// Blocks it reserved from its own arena and hasn't freed.
+    #[serde(default)]
+    pub(crate) arena: Vec<u64>,
+    /// Tasks it has forked, numbering the next one's id.
+    #[serde(default)]
+    pub(crate) forks: u64,
+    /// What arithmetic overflow does, as the node the program started on has it.
+    #[serde(default)]
+    pub(crate) int_overflow: IntOverflow,
+}
+
+/// What `CALL` saves of the caller.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Frame {
+    pub(crate) return_to: usize,
+    pub(crate) locals: Vec<i64>,
+}
+
+/// Locals a call frame may have, so a bad index fails rather than allocating.
+const MAX_LOCALS: i64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Termination {
+    /// Ran `HALT`, with its code.
+    Halted(i64),
+    /// Ran off the end of the bytecode.
+    EndOfProgram,
 }
 
 impl Task {
@@ -13,11 +85,50 @@ impl Task {
             program_counter: 0,
             stack: Vec::new(),
             forked: false,
+            fork_depth: 0,
+            carry: false,
+            overflow: false,
+            locals: Vec::new(),
+            frames: Vec::new(),
+            termination: None,
+            arena: Vec::new(),
+            forks: 0,
+            int_overflow: IntOverflow::Wrap,
+        }
+    }
+
+    /// The error a nonzero halt code fails the joining task or the program with.
+    pub(crate) fn failure(&self) -> Option<ExecutionError> {
+        match self.termination {
+            Some(Termination::Halted(code)) if code != 0 => Some(ExecutionError::Halted(code)),
+            _ => None,
+        }
+    }
+
+    fn wrap_or_trap(&self, (value, overflowed): (i64, bool)) -> Result<i64, ExecutionError> {
+        if overflowed && self.int_overflow == IntOverflow::Trap {
+            return Err(ExecutionError::IntegerOverflow);
         }
+        Ok(value)
     }
 
-    pub fn run(&mut self, bytecode: &ByteCode) -> Result<Execution, ExecutionError> {
+    pub fn run(
+        &mut self,
+        bytecode: &ByteCode,
+        coverage: Option<&[AtomicU64]>,
+        budget: Option<&AtomicI64>,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<Execution, ExecutionError> {
         loop {
+            if let Some(budget) = budget {
+                crate::sandbox::spend(budget)?;
+            }
+            if let Some(t) = &mut trace {
+                t.executed(self.program_counter);
+            }
+            if let Some(hits) = coverage {
+                crate::coverage::record(hits, self.program_counter);
+            }
             if let ControlFlow::Return(execution) = self.tick(bytecode)? {
                 return Ok(execution);
             }
@@ -27,7 +138,11 @@ impl Task {
     fn tick(&mut self, bytecode: &ByteCode) -> Result<ControlFlow, ExecutionError> {
         let op = match bytecode.get(self.program_counter) {
             Some(op) => op,
-            None => return Ok(ControlFlow::Return(Execution::Terminated)),
+            None => {
+                return Ok(ControlFlow::Return(Execution::Terminated(
+                    Termination::EndOfProgram,
+                )))
+            }
         };
         self.program_counter += 1;
 
@@ -35,13 +150,125 @@ impl Task {
             OpCode::Push(value) => {
                 self.stack.push(*value);
             }
+            OpCode::PushN(values) => {
+                self.stack.extend_from_slice(values);
+            }
             OpCode::Add => {
                 let a = self.pop()?;
                 let b = self.pop()?;
-                self.stack.push(a.overflowing_add(b).0);
+                self.stack.push(self.wrap_or_trap(b.overflowing_add(a))?);
+            }
+            OpCode::Mul => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(self.wrap_or_trap(b.overflowing_mul(a))?);
+            }
+            OpCode::AddImm(a) => {
+                let b = self.pop()?;
+                self.stack.push(self.wrap_or_trap(b.overflowing_add(*a))?);
+            }
+            OpCode::MulImm(a) => {
+                let b = self.pop()?;
+                self.stack.push(self.wrap_or_trap(b.overflowing_mul(*a))?);
+            }
+            OpCode::Div => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a == 0 {
+                    return Err(ExecutionError::DivideByZero);
+                }
+                self.stack.push(self.wrap_or_trap(b.overflowing_div(a))?);
+            }
+            OpCode::AddChecked => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let (value, overflowed) = b.overflowing_add(a);
+                self.stack.push(value);
+                self.stack.push(overflowed as i64);
+            }
+            OpCode::AddSaturating => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b.saturating_add(a));
+            }
+            OpCode::MulChecked => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let (value, overflowed) = b.overflowing_mul(a);
+                self.stack.push(value);
+                self.stack.push(overflowed as i64);
+            }
+            OpCode::MulSaturating => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b.saturating_mul(a));
+            }
+            OpCode::MulWide => {
+                let a = self.pop()? as i128;
+                let b = self.pop()? as i128;
+                let product = b * a;
+                self.stack.push(product as i64);
+                self.stack.push((product >> 64) as i64);
+            }
+            OpCode::DivWide => {
+                let a = self.pop()? as i128;
+                let hi = self.pop()? as i128;
+                let lo = self.pop()? as u64 as i128;
+                if a == 0 {
+                    return Err(ExecutionError::DivideByZero);
+                }
+                let dividend = (hi << 64) | lo;
+                let (quotient, overflowed) = dividend.overflowing_div(a);
+                let remainder = dividend.wrapping_rem(a);
+                let fits = !overflowed && quotient == quotient as i64 as i128;
+                self.stack.push(self.wrap_or_trap((quotient as i64, !fits))?);
+                self.stack.push(remainder as i64);
+            }
+            OpCode::And => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b & a);
+            }
+            OpCode::Or => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b | a);
+            }
+            OpCode::Xor => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b ^ a);
+            }
+            OpCode::Not => {
+                let a = self.pop()?;
+                self.stack.push(!a);
+            }
+            OpCode::Shl => {
+                let a = shift(self.pop()?)?;
+                let b = self.pop()?;
+                self.stack.push(b << a);
+            }
+            OpCode::Shr => {
+                let a = shift(self.pop()?)?;
+                let b = self.pop()?;
+                self.stack.push(((b as u64) >> a) as i64);
+            }
+            OpCode::Compare => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.carry = (b as u64) < (a as u64);
+                self.overflow = b.checked_sub(a).is_none();
+                self.stack.push(b.cmp(&a) as i64);
+            }
+            OpCode::Test => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.carry = false;
+                self.overflow = false;
+                self.stack.push(b & a);
             }
             OpCode::DumpDebug => {
-                self.print_debug(bytecode);
+                return Ok(ControlFlow::Return(Execution::DumpDebug));
             }
             OpCode::Jump(flags, target) => {
                 let target = match target {
@@ -50,14 +277,39 @@ impl Task {
                 };
 
                 let should_jump = {
-                    let zero = flags
-                        .contains(ConditionFlags::ZERO)
-                        .implies(*self.peek()? == 0);
-                    let forked = flags.contains(ConditionFlags::FORK).implies(self.forked);
-                    zero && forked
+                    let on_top = ConditionFlags::ZERO
+                        | ConditionFlags::NOT_ZERO
+                        | ConditionFlags::NEGATIVE
+                        | ConditionFlags::NOT_NEGATIVE;
+                    let top = if flags.intersects(on_top) {
+                        *self.peek()?
+                    } else {
+                        0
+                    };
+                    let (zero, negative) = (top == 0, top < 0);
+                    flags.contains(ConditionFlags::ZERO).implies(zero)
+                        && flags.contains(ConditionFlags::NOT_ZERO).implies(!zero)
+                        && flags.contains(ConditionFlags::NEGATIVE).implies(negative)
+                        && flags
+                            .contains(ConditionFlags::NOT_NEGATIVE)
+                            .implies(!negative)
+                        && flags.contains(ConditionFlags::CARRY).implies(self.carry)
+                        && flags
+                            .contains(ConditionFlags::NOT_CARRY)
+                            .implies(!self.carry)
+                        && flags
+                            .contains(ConditionFlags::OVERFLOW)
+                            .implies(self.overflow)
+                        && flags
+                            .contains(ConditionFlags::NOT_OVERFLOW)
+                            .implies(!self.overflow)
+                        && flags.contains(ConditionFlags::FORK).implies(self.forked)
+                        && flags
+                            .contains(ConditionFlags::NOT_FORK)
+                            .implies(!self.forked)
                 };
                 if should_jump {
-                    self.program_counter = target as usize;
+                    self.jump(target, bytecode)?;
                 }
             }
             OpCode::JumpToSubroutine(target) => {
@@ -67,7 +319,46 @@ impl Task {
                 };
 
                 self.stack.push(self.program_counter as i64);
-                self.program_counter = target as usize;
+                self.jump(target, bytecode)?;
+            }
+            OpCode::Call(target) => {
+                let target = match target {
+                    None => self.pop()?,
+                    Some(t) => *t,
+                };
+
+                self.frames.push(Frame {
+                    return_to: self.program_counter,
+                    locals: std::mem::take(&mut self.locals),
+                });
+                self.jump(target, bytecode)?;
+            }
+            OpCode::CallReturn => {
+                let frame = self.frames.pop().ok_or(ExecutionError::ReturnWithoutCall)?;
+                self.locals = frame.locals;
+                self.program_counter = frame.return_to;
+            }
+            OpCode::LoadLocal(index) => {
+                let index = local(*index)?;
+                self.stack
+                    .push(self.locals.get(index).cloned().unwrap_or(0));
+            }
+            OpCode::StoreLocal(index) => {
+                let index = local(*index)?;
+                let value = self.pop()?;
+                if index >= self.locals.len() {
+                    self.locals.resize(index + 1, 0);
+                }
+                self.locals[index] = value;
+            }
+            OpCode::TailCall(depth, target) => {
+                let target = match target {
+                    None => self.pop()?,
+                    Some(t) => *t,
+                };
+
+                self.dredge(*depth)?;
+                self.jump(target, bytecode)?;
             }
             OpCode::Bury(index) => {
                 let value = self.pop()?;
@@ -81,11 +372,7 @@ impl Task {
                 self.stack.insert(insert_index, value);
             }
             OpCode::Dredge(index) => {
-                let remove_index = (self.stack.len() - 1)
-                    .checked_sub(*index as usize)
-                    .ok_or(ExecutionError::DredgeOutOfRange(*index))?;
-                let value = self.stack.remove(remove_index);
-                self.stack.push(value);
+                self.dredge(*index)?;
             }
             OpCode::Duplicate => {
                 let value = self.pop()?;
@@ -97,20 +384,39 @@ impl Task {
             }
             OpCode::Return => {
                 let target = self.pop()?;
-                self.program_counter = target as usize;
+                self.jump(target, bytecode)?;
             }
             OpCode::Fork => {
                 return Ok(ControlFlow::Return(Execution::Fork));
             }
+            OpCode::IsChild => {
+                self.stack.push(self.forked as i64);
+            }
             OpCode::Join(count) => {
                 let task_id = self.pop()? as usize;
                 return Ok(ControlFlow::Return(Execution::Join {
                     task_id,
                     count: *count as usize,
+                    status: false,
+                }));
+            }
+            OpCode::JoinStatus(count) => {
+                let task_id = self.pop()? as usize;
+                return Ok(ControlFlow::Return(Execution::Join {
+                    task_id,
+                    count: *count as usize,
+                    status: true,
                 }));
             }
             OpCode::Halt => {
-                return Ok(ControlFlow::Return(Execution::Terminated));
+                return Ok(ControlFlow::Return(Execution::Terminated(
+                    Termination::Halted(0),
+                )));
+            }
+            OpCode::HaltWith(code) => {
+                return Ok(ControlFlow::Return(Execution::Terminated(
+                    Termination::Halted(*code),
+                )));
             }
             OpCode::Store(addr) => {
                 let value = self.pop()?;
@@ -130,6 +436,78 @@ impl Task {
                 let addr = base.wrapping_add(offset as u64);
                 return Ok(ControlFlow::Return(Execution::Load { addr }));
             }
+            OpCode::LoadPacked(width, base) => {
+                let index = self.pop()?;
+                let (addr, shift) = width.locate(*base, index);
+                return Ok(ControlFlow::Return(Execution::LoadPacked {
+                    addr,
+                    width: *width,
+                    shift,
+                }));
+            }
+            OpCode::StorePacked(width, base) => {
+                let index = self.pop()?;
+                let (addr, shift) = width.locate(*base, index);
+                let value = self.pop()?;
+                return Ok(ControlFlow::Return(Execution::StorePacked {
+                    addr,
+                    width: *width,
+                    shift,
+                    value,
+                }));
+            }
+            OpCode::AllocGlobal(count) => {
+                return Ok(ControlFlow::Return(Execution::AllocGlobal {
+                    count: *count,
+                }));
+            }
+            OpCode::Alloc(arena, size) => {
+                return Ok(ControlFlow::Return(Execution::Alloc {
+                    arena: *arena,
+                    size: *size,
+                }));
+            }
+            OpCode::Free => {
+                let addr = self.pop()? as u64;
+                let arena = match self.arena.iter().position(|a| *a == addr) {
+                    Some(i) => {
+                        self.arena.swap_remove(i);
+                        Arena::Task
+                    }
+                    None => Arena::Shared,
+                };
+                return Ok(ControlFlow::Return(Execution::Free { arena, addr }));
+            }
+            OpCode::Emit => {
+                let value = self.pop()?;
+                return Ok(ControlFlow::Return(Execution::Emit { value }));
+            }
+            OpCode::AssertEq => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a != b {
+                    return Err(ExecutionError::AssertEqFailed(
+                        b,
+                        a,
+                        self.program_counter - 1,
+                    ));
+                }
+            }
+            OpCode::CallNative(id) => {
+                return Ok(ControlFlow::Return(Execution::CallNative { id: *id }));
+            }
+            OpCode::Extension(code) => {
+                return Ok(ControlFlow::Return(Execution::Extension { code: *code }));
+            }
+            OpCode::AssertStackDepth(depth) => {
+                if self.stack.len() as i64 != *depth {
+                    return Err(ExecutionError::AssertStackDepthFailed(
+                        *depth,
+                        self.stack.len(),
+                        self.program_counter - 1,
+                    ));
+                }
+            }
             OpCode::Panic => {
                 return Err(ExecutionError::ExplicitPanic);
             }
@@ -141,39 +519,44 @@ impl Task {
         Ok(ControlFlow::Continue)
     }
 
+    /// Continues at `target`, from the instruction just run.
+    fn jump(&mut self, target: i64, bytecode: &ByteCode) -> Result<(), ExecutionError> {
+        if !bytecode.in_bounds(target) {
+            return Err(ExecutionError::JumpOutOfBounds {
+                pc: self.program_counter - 1,
+                target,
+            });
+        }
+        self.program_counter = target as usize;
+        Ok(())
+    }
+
     fn pop(&mut self) -> Result<i64, ExecutionError> {
         self.stack.pop().ok_or(ExecutionError::PopFromEmptyStack)
     }
 
+    fn dredge(&mut self, index: i64) -> Result<(), ExecutionError> {
+        let remove_index = (self.stack.len() - 1)
+            .checked_sub(index as usize)
+            .ok_or(ExecutionError::DredgeOutOfRange(index))?;
+        let value = self.stack.remove(remove_index);
+        self.stack.push(value);
+        Ok(())
+    }
+
     fn peek(&mut self) -> Result<&i64, ExecutionError> {
         self.stack
             .get(self.stack.len() - 1)
             .ok_or(ExecutionError::PeekFromEmptyStack)
     }
 
-    fn print_debug(&self, bytecode: &ByteCode) {
-        eprintln!("Flock VM Debug");
-        eprintln!("PC: {}", self.program_counter);
-
-        eprintln!("");
-
-        eprintln!("OpCodes:");
-        let bounds: usize = 5;
-        for (i, op) in bytecode.surrounding(self.program_counter, bounds) {
-            let delta = (i as isize) - (self.program_counter as isize);
-            eprintln!("  {:#2}: {:?}", delta, op);
-        }
-
-        eprintln!("");
-
-        eprintln!("Stack:");
-        for (i, value) in self.stack.iter().rev().enumerate() {
-            eprintln!("  {:#03} {:#018x} ({})", i, value, value)
-        }
+    /// What `DUMP_DEBUG` prints: the instructions around the task's position and its stack.
+    pub(crate) fn debug_listing(&self, bytecode: &ByteCode) -> String {
+        crate::snapshot::around(bytecode, self.program_counter, &self.stack).to_string()
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub enum ExecutionError {
     PopFromEmptyStack,
     PeekFromEmptyStack,
@@ -182,14 +565,178 @@ pub enum ExecutionError {
     UnknownTaskId(usize),
     UnableToProgress,
     ExplicitPanic,
+    AssertEqFailed(i64, i64, usize),
+    AssertStackDepthFailed(i64, usize, usize),
+    /// The worker running the task panicked with this message.
+    WorkerPanicked(String),
+    /// The jump, call, tail call or return at `pc` went to `target`, outside the program.
+    JumpOutOfBounds {
+        pc: usize,
+        target: i64,
+    },
+    InstructionBudgetExhausted,
+    SandboxViolation(u64),
+    UnknownExtension(u16),
+    NativeUnavailable(u16),
+    NativeCallRemote(u16),
+    NativeCallFailed(u16, i64),
+    ExtensionFailed(u16, String),
+    ExtensionStackEffect(u16, usize, usize),
+    IntegerOverflow,
+    DivideByZero,
+    /// `SHL` or `SHR` by this, outside `0..64`.
+    ShiftOutOfRange(i64),
+    /// `LOAD_LOCAL` or `STORE_LOCAL` of this, outside `0..MAX_LOCALS`.
+    LocalOutOfRange(i64),
+    /// `RETURN` outside any `CALL`.
+    ReturnWithoutCall,
+    ForkDepthExceeded(u64),
+    /// The task forked too deep or too often for its children's ids to fit, see `child_id`.
+    ForkIdsExhausted,
+    /// The task ran `HALT` with this nonzero code, and was joined without `JOIN_STATUS`.
+    Halted(i64),
+    /// `ALLOC_GLOBAL` ran past the last address.
+    GlobalAddressesExhausted,
+    /// `ALLOC` ran past the last address.
+    HeapExhausted,
+    /// `FREE` of an address that isn't the first of a block, or of another task's block from
+    /// its arena.
+    InvalidFree(u64),
+    /// `ALLOC_GLOBAL`, `ALLOC` or `FREE` couldn't reach the node the program was submitted to.
+    OriginUnreachable,
+    /// A peer was lost while running the task, which was `.idempotent` and ran out of attempts,
+    /// or wasn't and `--fail-lost-tasks` is set.
+    PeerLost,
+    /// Another task of the program failed first under `--error-policy=fail-fast`.
+    Cancelled,
+    /// Every task that failed under `--error-policy=collect`.
+    Multiple(Vec<ExecutionError>),
+    /// `error`, with where the task that failed with it was, from the node it ran on. The
+    /// snapshot's stack is only the top `STACK_CONTEXT` values.
+    InTask {
+        task_id: usize,
+        snapshot: Snapshot,
+        error: Box<ExecutionError>,
+    },
 }
 
+/// Values from the top of a failed task's stack kept in its error.
+pub const STACK_CONTEXT: usize = 8;
+
 impl std::error::Error for ExecutionError {}
 
+/// The errors alone, or with where each task was in the alternate form.
 impl std::fmt::Display for ExecutionError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ExecutionError::InTask {
+                task_id,
+                snapshot,
+                error,
+            } if f.alternate() => write!(f, "{} in task {}\n{}", error, task_id, snapshot),
+            ExecutionError::Multiple(errors) if f.alternate() => {
+                errors.iter().try_for_each(|e| writeln!(f, "{:#}", e))
+            }
+            e => write!(f, "{:?}", e.without_context()),
+        }
+    }
+}
+
+impl ExecutionError {
+    /// Adds where `task` is to an error it failed with, unless it's from a task it joined or
+    /// its program being cancelled.
+    pub(crate) fn in_task(self, task_id: usize, task: &Task, bytecode: &ByteCode) -> Self {
+        match self {
+            ExecutionError::InTask { .. } | ExecutionError::Cancelled => self,
+            error => {
+                let mut snapshot =
+                    crate::snapshot::around(bytecode, task.program_counter, &task.stack);
+                snapshot.stack.truncate(STACK_CONTEXT);
+                ExecutionError::InTask {
+                    task_id,
+                    snapshot,
+                    error: Box::new(error),
+                }
+            }
+        }
+    }
+
+    /// The error without where it happened.
+    pub fn cause(&self) -> &ExecutionError {
+        match self {
+            ExecutionError::InTask { error, .. } => error.cause(),
+            e => e,
+        }
+    }
+
+    fn without_context(&self) -> ExecutionError {
+        match self {
+            ExecutionError::Multiple(errors) => {
+                ExecutionError::Multiple(errors.iter().map(Self::without_context).collect())
+            }
+            e => e.cause().clone(),
+        }
+    }
+
+    /// Like the alternate `Display`, naming instructions by their label in `bytecode` where it
+    /// has them.
+    pub fn symbolized(&self, bytecode: &ByteCode) -> String {
+        let at = |pc: usize| match bytecode.symbolize(pc) {
+            Some(label) => format!("{} ({})", pc, label),
+            None => pc.to_string(),
+        };
+        match self {
+            ExecutionError::InTask {
+                task_id,
+                snapshot,
+                error,
+            } => format!(
+                "{} in task {} at {}\n{}",
+                error.symbolized(bytecode),
+                task_id,
+                at(snapshot.pc.saturating_sub(1)),
+                snapshot
+            ),
+            ExecutionError::JumpOutOfBounds { pc, target } => format!(
+                "Jump at {} to {}, outside the program's {} instructions",
+                at(*pc),
+                target,
+                bytecode.len()
+            ),
+            e => e.to_string(),
+        }
+    }
+}
+
+/// A `SHL` or `SHR` amount, which must leave some bits in place.
+fn shift(amount: i64) -> Result<u32, ExecutionError> {
+    if !(0..64).contains(&amount) {
+        return Err(ExecutionError::ShiftOutOfRange(amount));
+    }
+    Ok(amount as u32)
+}
+
+fn local(index: i64) -> Result<usize, ExecutionError> {
+    if !(0..MAX_LOCALS).contains(&index) {
+        return Err(ExecutionError::LocalOutOfRange(index));
+    }
+    Ok(index as usize)
+}
+
+/// Id of the next task `parent` forks, from its id and `forks`, how many it forked before, so a
+/// program's tasks get the same ids every run. The id encodes the task's path from the main
+/// task, 0: each fork appends the Elias gamma code of its child number to the parent's, after a
+/// leading 1. The codes are prefix-free, so no two tasks of a program get the same id wherever
+/// they were forked. Tasks of other programs are told apart by their submission, see `TaskKey`.
+pub(crate) fn child_id(parent: usize, forks: &mut u64) -> Result<usize, ExecutionError> {
+    *forks += 1;
+    let child = *forks;
+    let code_len = 2 * (63 - child.leading_zeros()) + 1;
+    let path = (parent as u64).wrapping_add(1);
+    if path == 0 || 64 - path.leading_zeros() + code_len > 64 {
+        return Err(ExecutionError::ForkIdsExhausted);
     }
+    Ok(((path << code_len | child) - 1) as usize)
 }
 
 pub enum ControlFlow {
@@ -199,11 +746,53 @@ pub enum ControlFlow {
 
 #[derive(Debug)]
 pub enum Execution {
-    Terminated,
+    Terminated(Termination),
     Fork,
-    Join { task_id: usize, count: usize },
-    Store { addr: u64, value: i64 },
-    Load { addr: u64 },
+    Join {
+        task_id: usize,
+        count: usize,
+        /// Whether to push how the task ended after its values.
+        status: bool,
+    },
+    Store {
+        addr: u64,
+        value: i64,
+    },
+    Load {
+        addr: u64,
+    },
+    LoadPacked {
+        addr: u64,
+        width: PackedWidth,
+        shift: u32,
+    },
+    StorePacked {
+        addr: u64,
+        width: PackedWidth,
+        shift: u32,
+        value: i64,
+    },
+    AllocGlobal {
+        count: u64,
+    },
+    Alloc {
+        arena: Arena,
+        size: u64,
+    },
+    Free {
+        arena: Arena,
+        addr: u64,
+    },
+    Emit {
+        value: i64,
+    },
+    DumpDebug,
+    Extension {
+        code: u16,
+    },
+    CallNative {
+        id: u16,
+    },
 }
 
 trait BoolImplies {
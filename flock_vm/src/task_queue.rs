@@ -1,26 +1,118 @@
 use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use flume::*;
 
+gflags::define! {
+    /// Which of its own queued tasks a worker runs next: the newest (`lifo`) for locality, the
+    /// oldest (`fifo`) for fairness, or the newest unless the oldest has waited longer than
+    /// `--max-task-age-ms` (`hybrid`).
+    pub --queue-order <ORDER>: QueueOrder = QueueOrder::Lifo
+}
+
+gflags::define! {
+    /// How long a task may wait behind newer ones under `--queue-order=hybrid`.
+    pub --max-task-age-ms: u64 = 100
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOrder {
+    Lifo,
+    Fifo,
+    Hybrid,
+}
+
+impl gflags::custom::Value for QueueOrder {
+    fn parse(arg: gflags::custom::Arg) -> gflags::custom::Result<Self> {
+        match arg.get_str() {
+            "lifo" => Ok(QueueOrder::Lifo),
+            "fifo" => Ok(QueueOrder::Fifo),
+            "hybrid" => Ok(QueueOrder::Hybrid),
+            _ => Err(gflags::custom::Error::new(
+                "expected one of: lifo, fifo, hybrid",
+            )),
+        }
+    }
+}
+
+/// Order and age cap from `--queue-order` and `--max-task-age-ms`.
+pub(crate) fn configured() -> (QueueOrder, Duration) {
+    (
+        QUEUE_ORDER.flag,
+        Duration::from_millis(MAX_TASK_AGE_MS.flag),
+    )
+}
+
+/// Items a handle pushed, oldest first, with when they were pushed.
+type Deque<T> = Mutex<VecDeque<(Instant, T)>>;
+
 pub struct TaskQueue<T> {
     sender: Sender<ControlFlow<T>>,
     receiver: Receiver<ControlFlow<T>>,
+    /// Items only worker handles take.
+    workers_only: (Sender<T>, Receiver<T>),
+    order: QueueOrder,
+    max_age: Duration,
+    /// Every live worker handle's own items, for idle workers to steal from.
+    deques: Arc<Mutex<Vec<Weak<Deque<T>>>>>,
+}
+
+impl<T> Clone for TaskQueue<T> {
+    fn clone(&self) -> Self {
+        TaskQueue {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            workers_only: self.workers_only.clone(),
+            order: self.order,
+            max_age: self.max_age,
+            deques: self.deques.clone(),
+        }
+    }
 }
 
 impl<T> TaskQueue<T> {
-    pub fn new() -> Self {
+    /// Handles take their own items in `order`, `max_age` capping how long one waits under
+    /// `Hybrid`.
+    pub fn new(order: QueueOrder, max_age: Duration) -> Self {
         let (sender, receiver) = flume::unbounded();
-        TaskQueue { sender, receiver }
+        TaskQueue {
+            sender,
+            receiver,
+            workers_only: flume::unbounded(),
+            order,
+            max_age,
+            deques: Default::default(),
+        }
     }
 
+    /// A handle that neither steals nor is stolen from, for threads that aren't workers. Items
+    /// it pushes and keeps are only taken by itself.
     pub fn handle(&self) -> Handle<T> {
         Handle {
-            local_work: VecDeque::new(),
+            local_work: Default::default(),
+            deques: self.deques.clone(),
+            steals: false,
             sender: self.sender.clone(),
             receiver: self.receiver.clone(),
+            workers_only: self.workers_only.clone(),
+            order: self.order,
+            max_age: self.max_age,
         }
     }
 
+    /// Like `handle`, for a worker. When it has nothing of its own and the shared pool is empty,
+    /// it takes the oldest half of whichever other worker holds the most items.
+    pub fn worker_handle(&self) -> Handle<T> {
+        let mut handle = self.handle();
+        self.deques
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&handle.local_work));
+        handle.steals = true;
+        handle
+    }
+
     pub fn finish<F: FnOnce() -> R, R>(&self, task_closer: F) -> R {
         self.sender.send(ControlFlow::Finish).unwrap();
         let result = task_closer();
@@ -38,6 +130,17 @@ impl<T> TaskQueue<T> {
     }
 }
 
+/// Leaves the handle's own items to the other workers, such as forked tasks the program that
+/// pushed them never joined.
+impl<T> Drop for Handle<T> {
+    fn drop(&mut self) {
+        let local_work = std::mem::take(&mut *self.local_work.lock().unwrap());
+        for (_, work) in local_work {
+            let _ = self.sender.send(ControlFlow::Continue(work));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ControlFlow<T> {
     Continue(T),
@@ -46,29 +149,58 @@ pub enum ControlFlow<T> {
 }
 
 pub struct Handle<T> {
-    local_work: VecDeque<T>,
+    /// Items pushed by this handle or stolen by it, which other workers may steal if this is
+    /// one.
+    local_work: Arc<Deque<T>>,
+    deques: Arc<Mutex<Vec<Weak<Deque<T>>>>>,
+    steals: bool,
     sender: Sender<ControlFlow<T>>,
     receiver: Receiver<ControlFlow<T>>,
+    workers_only: (Sender<T>, Receiver<T>),
+    order: QueueOrder,
+    max_age: Duration,
 }
 
 impl<T> Handle<T> {
     pub fn push(&mut self, item: T) {
-        self.local_work.push_back(item);
-        if let Some(amount) = self.push_to_shared() {
+        let mut local_work = self.local_work.lock().unwrap();
+        local_work.push_back((Instant::now(), item));
+        if let Some(amount) = self.push_to_shared(local_work.len()) {
             log::debug!("Sending {} items to machine shared work pool", amount);
-            for work in self.local_work.drain(..amount) {
+            for (_, work) in local_work.drain(..amount) {
                 self.sender.send(ControlFlow::Continue(work)).unwrap();
             }
         }
     }
 
+    pub fn pending(&self) -> usize {
+        self.local_work.lock().unwrap().len() + self.sender.len() + self.workers_only.0.len()
+    }
+
     pub fn push_nonworker(&self, item: T) {
         self.sender.send(ControlFlow::Continue(item)).unwrap();
     }
 
-    fn push_to_shared(&mut self) -> Option<usize> {
-        if self.local_work.len() > self.sender.len() * 2 {
-            let amount = std::cmp::max(1, self.local_work.len() / 2);
+    /// Like `push_nonworker`, but only worker handles take the item, ahead of the shared pool.
+    pub fn push_for_workers(&self, item: T) {
+        self.workers_only.0.send(item).unwrap();
+    }
+
+    /// Like `push_nonworker`, once `delay` has passed. Dropped if the queue is gone by then.
+    pub fn push_nonworker_after(&self, item: T, delay: std::time::Duration)
+    where
+        T: Send + 'static,
+    {
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            let _ = sender.send(ControlFlow::Continue(item));
+        });
+    }
+
+    fn push_to_shared(&self, local: usize) -> Option<usize> {
+        if local > self.sender.len() * 2 {
+            let amount = std::cmp::max(1, local / 2);
             Some(amount)
         } else {
             None
@@ -76,14 +208,34 @@ impl<T> Handle<T> {
     }
 
     pub fn next(&mut self) -> ControlFlow<T> {
-        if let Some(local) = self.local_work.pop_back() {
+        self.next_preferring(|_| false)
+    }
+
+    /// Like `next`, but takes the newest local item matching `prefer` ahead of the rest.
+    pub fn next_preferring(&mut self, prefer: impl Fn(&T) -> bool) -> ControlFlow<T> {
+        if let Some(local) = self.pop_local(&prefer) {
             return ControlFlow::Continue(local);
         }
+        if self.steals {
+            if let Ok(item) = self.workers_only.1.try_recv() {
+                return ControlFlow::Continue(item);
+            }
+        }
 
-        match self
-            .receiver
-            .recv_timeout(std::time::Duration::from_millis(1))
-        {
+        let received = match self.receiver.try_recv() {
+            Err(TryRecvError::Empty) if self.steals && self.steal() => {
+                if let Some(stolen) = self.pop_local(&prefer) {
+                    return ControlFlow::Continue(stolen);
+                }
+                return ControlFlow::Retry;
+            }
+            Err(TryRecvError::Empty) => self
+                .receiver
+                .recv_timeout(std::time::Duration::from_millis(1)),
+            Ok(received) => Ok(received),
+            Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+        };
+        match received {
             Ok(ControlFlow::Continue(t)) => ControlFlow::Continue(t),
             Ok(ControlFlow::Finish) => {
                 self.sender.send(ControlFlow::Finish).unwrap();
@@ -95,6 +247,53 @@ impl<T> Handle<T> {
         }
     }
 
+    /// The newest local item matching `prefer`, otherwise the next in `order`.
+    fn pop_local(&self, prefer: impl Fn(&T) -> bool) -> Option<T> {
+        let mut local_work = self.local_work.lock().unwrap();
+        if let Some(i) = local_work.iter().rposition(|(_, t)| prefer(t)) {
+            return local_work.remove(i).map(|(_, t)| t);
+        }
+        let oldest_expired = local_work
+            .front()
+            .is_some_and(|(pushed, _)| pushed.elapsed() > self.max_age);
+        let popped = match self.order {
+            QueueOrder::Lifo => local_work.pop_back(),
+            QueueOrder::Fifo => local_work.pop_front(),
+            QueueOrder::Hybrid if oldest_expired => local_work.pop_front(),
+            QueueOrder::Hybrid => local_work.pop_back(),
+        };
+        popped.map(|(_, t)| t)
+    }
+
+    /// Moves the oldest half of the fullest other worker's items here. Returns whether any were.
+    fn steal(&self) -> bool {
+        let others: Vec<Arc<Deque<T>>> = {
+            let mut deques = self.deques.lock().unwrap();
+            deques.retain(|d| d.strong_count() > 0);
+            deques
+                .iter()
+                .filter_map(Weak::upgrade)
+                .filter(|d| !Arc::ptr_eq(d, &self.local_work))
+                .collect()
+        };
+        // Only one deque is locked at a time, so handles stealing from each other can't deadlock.
+        let victim = match others.iter().max_by_key(|d| d.lock().unwrap().len()) {
+            Some(v) => v,
+            None => return false,
+        };
+        let stolen: Vec<_> = {
+            let mut victim = victim.lock().unwrap();
+            let amount = victim.len().div_ceil(2);
+            victim.drain(..amount).collect()
+        };
+        if stolen.is_empty() {
+            return false;
+        }
+        log::debug!("Stole {} items from another worker", stolen.len());
+        self.local_work.lock().unwrap().extend(stolen);
+        true
+    }
+
     pub fn wait_next(&mut self) -> Option<T> {
         loop {
             match self.next() {
@@ -0,0 +1,93 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+
+/// How long a dead task is left down before it's started again, so one that dies immediately
+/// doesn't spin.
+pub(crate) const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// A background task that stopped without being asked to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskFailure {
+    pub task: String,
+    /// Why it last stopped, the panic message if it panicked.
+    pub error: String,
+    pub failures: u64,
+    /// Whether it's running again. Tasks that aren't leave the node degraded.
+    pub restarted: bool,
+}
+
+/// Background tasks that have died, by name.
+#[derive(Default)]
+pub(crate) struct Health {
+    failures: DashMap<String, TaskFailure>,
+}
+
+impl Health {
+    pub(crate) fn died(&self, task: &str, error: String) {
+        log::error!("Background task {} died: {}", task, error);
+        let mut failure = self
+            .failures
+            .entry(task.to_string())
+            .or_insert_with(|| TaskFailure {
+                task: task.to_string(),
+                error: String::new(),
+                failures: 0,
+                restarted: false,
+            });
+        failure.error = error;
+        failure.failures += 1;
+        failure.restarted = false;
+    }
+
+    pub(crate) fn restarted(&self, task: &str) {
+        log::warn!("Restarted background task {}", task);
+        if let Some(mut failure) = self.failures.get_mut(task) {
+            failure.restarted = true;
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<TaskFailure> {
+        let mut failures: Vec<_> = self.failures.iter().map(|f| f.clone()).collect();
+        failures.sort_by(|a, b| a.task.cmp(&b.task));
+        failures
+    }
+}
+
+/// Runs `task`, starting it again with `restart` whenever it panics or fails, until it finishes
+/// cleanly or this future is dropped. Polled in place rather than spawned, so dropping this
+/// drops the task too.
+pub(crate) async fn supervise<F, R>(
+    name: String,
+    health: std::sync::Arc<Health>,
+    task: F,
+    mut restart: impl FnMut() -> R,
+) where
+    F: Future<Output = std::io::Result<()>> + Send + 'static,
+    R: Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let mut running: BoxFuture<'static, std::io::Result<()>> = Box::pin(task);
+    loop {
+        let error = match AssertUnwindSafe(running).catch_unwind().await {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(panic) => panic_message(&*panic),
+        };
+        health.died(&name, error);
+        tokio::time::sleep(RESTART_DELAY).await;
+        running = Box::pin(restart());
+        health.restarted(&name);
+    }
+}
+
+pub(crate) fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::config::config;
+
+gflags::define! {
+    /// Zone, such as a rack or region, this node runs in. Peers that `peer_zones` puts in
+    /// another zone only get tasks once same-zone peers are saturated, and one copy of each
+    /// store per zone.
+    pub --zone <NAME>: &str
+}
+
+/// The zone of this node and of its peers, by address.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    pub zone: Option<String>,
+    pub peer_zones: HashMap<String, String>,
+}
+
+impl Topology {
+    pub(crate) fn configured() -> Topology {
+        Topology {
+            zone: if ZONE.is_present() {
+                Some(ZONE.flag.to_string())
+            } else {
+                config().zone.clone()
+            },
+            peer_zones: config().peer_zones.clone(),
+        }
+    }
+
+    /// The zone of the peer at `addr`, if it and this node are tagged with different zones.
+    pub fn remote_zone(&self, addr: &str) -> Option<&str> {
+        let zone = self.peer_zones.get(addr)?;
+        match &self.zone {
+            Some(own) if own != zone => Some(zone),
+            _ => None,
+        }
+    }
+}
+
+/// Counts the same-zone peers that can take more tasks, so other zones only get the overflow.
+#[derive(Default)]
+pub struct Capacity {
+    near: AtomicUsize,
+    saturated: AtomicUsize,
+}
+
+impl Capacity {
+    pub fn join(self: &Arc<Self>, near: bool) -> Slot {
+        if near {
+            self.near.fetch_add(1, Ordering::Relaxed);
+        }
+        Slot {
+            capacity: self.clone(),
+            near,
+            saturated: false,
+        }
+    }
+}
+
+/// A remote executor's share of its zone's capacity, given back when dropped.
+pub struct Slot {
+    capacity: Arc<Capacity>,
+    near: bool,
+    saturated: bool,
+}
+
+impl Slot {
+    /// Whether the executor may take a task: always in this zone, elsewhere only once every
+    /// same-zone peer is saturated.
+    pub fn may_take(&self) -> bool {
+        self.near
+            || self.capacity.saturated.load(Ordering::Relaxed)
+                >= self.capacity.near.load(Ordering::Relaxed)
+    }
+
+    pub fn set_saturated(&mut self, saturated: bool) {
+        if !self.near || saturated == self.saturated {
+            return;
+        }
+        self.saturated = saturated;
+        if saturated {
+            self.capacity.saturated.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.capacity.saturated.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.set_saturated(false);
+        if self.near {
+            self.capacity.near.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
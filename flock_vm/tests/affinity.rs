@@ -0,0 +1,66 @@
+use flock_vm::Vm;
+
+// Forks a child that forks three more, none cheap enough to run inline. The main task keeps busy
+// meanwhile, leaving the child to the peer.
+const NESTED_FORKS: &str = "
+  FORK
+  JMP f, $parent
+  PUSH 20000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+parent:
+  POP
+  FORK
+  JMP f, $leaf
+  FORK
+  JMP f, $leaf
+  FORK
+  JMP f, $leaf
+  JOIN 0
+  JOIN 0
+  JOIN 0
+  PUSH 3
+  HALT
+
+leaf:
+  POP
+  PUSH 10
+countdown:
+  PUSH -1
+  ADD
+  JMP !z, $countdown
+  POP
+  HALT
+";
+
+fn listening(peers: Vec<String>) -> Vm {
+    Vm::builder()
+        .workers(1)
+        .listen(([127, 0, 0, 1], 0))
+        .peers(peers)
+        .affinity_queue_depth(usize::MAX)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn children_of_a_remote_task_stay_on_its_peer() {
+    let far = listening(Vec::new());
+    let near = listening(vec![far.listen_addr().unwrap().to_string()]);
+    let mut origin = Vm::builder()
+        .workers(0)
+        .peers(vec![near.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+
+    let bytecode = flock_vm::asm::assemble(NESTED_FORKS).unwrap();
+    assert_eq!(origin.execute(bytecode, &[]).unwrap(), vec![3]);
+    assert!(near.handle().served_requests() > 0);
+    assert_eq!(far.handle().served_requests(), 0);
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use flock_vm::{Vm, GLOBAL_ALLOC_BASE};
+
+#[test]
+fn allocations_follow_each_other() {
+    let bytecode =
+        flock_vm::asm::assemble("ALLOC_GLOBAL 4\nALLOC_GLOBAL 2\nALLOC_GLOBAL 1").unwrap();
+    let base = GLOBAL_ALLOC_BASE.flag as i64;
+    assert_eq!(
+        Vm::create_leaf().execute(bytecode, &[]).unwrap(),
+        vec![base, base + 4, base + 6]
+    );
+}
+
+// Emits an address allocated by every call reaching `fibonacci_0`.
+const FIBONACCI_ALLOCATING: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  ALLOC_GLOBAL 1
+  EMIT
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+#[test]
+fn peers_allocate_from_the_origin() {
+    let mut vm = Vm::builder()
+        .workers(2)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .simulate_cluster(2, Duration::ZERO)
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(FIBONACCI_ALLOCATING).unwrap();
+
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![4181]);
+
+    // Tasks stolen back from a peer run twice, allocating again.
+    let addresses: Vec<u64> = vm.emitted().try_iter().map(|e| e.value as u64).collect();
+    assert!(addresses.len() >= 4181);
+    assert!(addresses.iter().all(|&a| a >= GLOBAL_ALLOC_BASE.flag));
+    let distinct: HashSet<_> = addresses.iter().collect();
+    assert_eq!(distinct.len(), addresses.len());
+}
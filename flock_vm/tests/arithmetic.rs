@@ -0,0 +1,137 @@
+use std::net::SocketAddr;
+
+use flock_vm::{ExecutionError, IntOverflow, Vm};
+
+/// Runs `op` on `inputs`, the last on top.
+fn run(op: &str, inputs: &[i64]) -> Result<Vec<i64>, ExecutionError> {
+    let bytecode = flock_vm::asm::assemble(&format!("{}\nHALT", op)).unwrap();
+    Vm::create_leaf().execute(bytecode, inputs)
+}
+
+#[test]
+fn division_rounds_toward_zero() {
+    assert_eq!(run("DIV", &[7, 2]).unwrap(), vec![3]);
+    assert_eq!(run("DIV", &[-7, 2]).unwrap(), vec![-3]);
+    assert_eq!(run("DIV", &[7, -2]).unwrap(), vec![-3]);
+}
+
+#[test]
+fn overflow_wraps_by_default() {
+    assert_eq!(run("ADD", &[i64::MAX, 1]).unwrap(), vec![i64::MIN]);
+    assert_eq!(run("MUL", &[i64::MAX, 2]).unwrap(), vec![-2]);
+    assert_eq!(run("ADDI 1", &[i64::MAX]).unwrap(), vec![i64::MIN]);
+    assert_eq!(run("DIV", &[i64::MIN, -1]).unwrap(), vec![i64::MIN]);
+}
+
+#[test]
+fn overflow_traps_in_programs_started_with_trap() {
+    let mut vm = Vm::builder()
+        .int_overflow(IntOverflow::Trap)
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble("ADD\nHALT").unwrap();
+    let error = vm.execute(bytecode, &[i64::MAX, 1]).unwrap_err();
+    assert!(matches!(error.cause(), ExecutionError::IntegerOverflow), "{:?}", error);
+}
+
+#[test]
+fn peers_overflow_as_the_node_the_program_started_on() {
+    // The leaf wraps, but runs the child of a program started to trap.
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .int_overflow(IntOverflow::Trap)
+        .build()
+        .unwrap();
+    let source = "
+  FORK
+  JMP f, $child
+
+  ; Gives the child time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  POP
+  PUSH 100000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  ADD
+  HALT
+";
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let error = scheduler.execute(bytecode, &[i64::MAX, 1]).unwrap_err();
+    assert!(matches!(error.cause(), ExecutionError::IntegerOverflow), "{:?}", error);
+}
+
+#[test]
+fn dividing_by_zero_fails() {
+    for dividend in [0, 1, i64::MIN] {
+        let error = run("DIV", &[dividend, 0]).unwrap_err();
+        assert!(matches!(error, ExecutionError::DivideByZero), "{:?}", error);
+    }
+}
+
+#[test]
+fn checked_ops_push_whether_they_overflowed() {
+    assert_eq!(run("ADD_CHECKED", &[2, 3]).unwrap(), vec![5, 0]);
+    assert_eq!(run("ADD_CHECKED", &[i64::MAX, 1]).unwrap(), vec![i64::MIN, 1]);
+    assert_eq!(run("MUL_CHECKED", &[-4, 3]).unwrap(), vec![-12, 0]);
+    assert_eq!(run("MUL_CHECKED", &[i64::MAX, 2]).unwrap(), vec![-2, 1]);
+}
+
+#[test]
+fn saturating_ops_clamp() {
+    assert_eq!(run("ADD_SAT", &[2, 3]).unwrap(), vec![5]);
+    assert_eq!(run("ADD_SAT", &[i64::MAX, 1]).unwrap(), vec![i64::MAX]);
+    assert_eq!(run("ADD_SAT", &[i64::MIN, -1]).unwrap(), vec![i64::MIN]);
+    assert_eq!(run("MUL_SAT", &[i64::MAX, 2]).unwrap(), vec![i64::MAX]);
+    assert_eq!(run("MUL_SAT", &[i64::MAX, -2]).unwrap(), vec![i64::MIN]);
+}
+
+#[test]
+fn wide_multiplication_pushes_the_low_then_the_high_half() {
+    assert_eq!(run("MUL_WIDE", &[6, 7]).unwrap(), vec![42, 0]);
+    assert_eq!(run("MUL_WIDE", &[-3, 5]).unwrap(), vec![-15, -1]);
+    assert_eq!(run("MUL_WIDE", &[i64::MAX, 2]).unwrap(), vec![-2, 0]);
+    assert_eq!(
+        run("MUL_WIDE", &[i64::MIN, i64::MIN]).unwrap(),
+        vec![0, 1 << 62]
+    );
+}
+
+#[test]
+fn wide_division_undoes_wide_multiplication() {
+    for (b, a) in [
+        (i64::MAX, 2),
+        (-3, 5),
+        (i64::MIN, 3),
+        (123_456_789, -987_654_321),
+    ] {
+        let source = format!("PUSH {}\nMUL_WIDE\nPUSH {}\nDIV_WIDE", a, a);
+        assert_eq!(run(&source, &[b]).unwrap(), vec![b, 0], "{} * {}", b, a);
+    }
+    assert_eq!(run("DIV_WIDE", &[-15, -1, 4]).unwrap(), vec![-3, -3]);
+}
+
+#[test]
+fn wide_division_wraps_quotients_outside_i64_and_fails_on_zero() {
+    // 2^64 / 1
+    assert_eq!(run("DIV_WIDE", &[0, 1, 1]).unwrap(), vec![0, 0]);
+    let error = run("DIV_WIDE", &[1, 0, 0]).unwrap_err();
+    assert!(matches!(error, ExecutionError::DivideByZero), "{:?}", error);
+}
@@ -0,0 +1,57 @@
+use flock_vm::Vm;
+
+fn run(source: &str) -> Result<Vec<i64>, String> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf()
+        .execute(bytecode, &[])
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn bitwise_operations() {
+    let source = "
+  PUSH 0xC
+  PUSH 0xA
+  AND
+  PUSH 0xC
+  PUSH 0xA
+  OR
+  PUSH 0xC
+  PUSH 0xA
+  XOR
+  PUSH 0
+  NOT
+  HALT
+";
+    assert_eq!(run(source), Ok(vec![8, 14, 6, -1]));
+}
+
+#[test]
+fn shifts_fill_with_zeroes() {
+    let source = "
+  PUSH 3
+  PUSH 4
+  SHL
+  PUSH -1
+  PUSH 60
+  SHR
+  PUSH -1
+  PUSH 63
+  SHL
+  HALT
+";
+    assert_eq!(run(source), Ok(vec![48, 15, i64::MIN]));
+}
+
+#[test]
+fn shifts_outside_the_width_fail() {
+    for amount in [-1, 64] {
+        let source = format!("PUSH 1\nPUSH {}\nSHL\nHALT", amount);
+        assert_eq!(
+            run(&source),
+            Err(format!("ShiftOutOfRange({})", amount)),
+            "{}",
+            amount
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use flock_vm::Vm;
+
+#[test]
+fn differently_configured_vms_share_a_process() {
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(([127, 0, 0, 1], 0))
+        .build()
+        .unwrap();
+    let addr = leaf.listen_addr().unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(2)
+        .peers(vec![addr.to_string()])
+        .build()
+        .unwrap();
+    assert_eq!(scheduler.listen_addr(), None);
+
+    let bytecode = flock_vm::asm::assemble(
+        "
+  FORK
+  JMP f, $child
+  JOIN 1
+  PUSH 2
+  ADD
+  HALT
+
+child:
+  POP
+  PUSH 40
+  HALT
+",
+    )
+    .unwrap();
+    assert_eq!(scheduler.execute(bytecode, &[]).unwrap(), vec![42]);
+
+    let peers = scheduler.handle().peer_status();
+    assert!(peers.iter().all(|p| &p.peer == leaf.handle().identity()));
+}
+
+#[test]
+fn stores_reach_every_peer_of_another_zone() {
+    let leaves: Vec<Vm> = (0..2)
+        .map(|_| {
+            Vm::builder()
+                .workers(1)
+                .listen(([127, 0, 0, 1], 0))
+                .zone("b")
+                .build()
+                .unwrap()
+        })
+        .collect();
+    let addrs: Vec<String> = leaves
+        .iter()
+        .map(|l| l.listen_addr().unwrap().to_string())
+        .collect();
+    let mut scheduler = Vm::builder()
+        .workers(1)
+        .peers(addrs.clone())
+        .zone("a")
+        .peer_zone(addrs[0].clone(), "b")
+        .peer_zone(addrs[1].clone(), "b")
+        .build()
+        .unwrap();
+
+    let store = flock_vm::asm::assemble("PUSH 7\nSTORE 16\nHALT").unwrap();
+    assert_eq!(scheduler.execute(store, &[]).unwrap(), Vec::<i64>::new());
+
+    for mut leaf in leaves {
+        let load = flock_vm::asm::assemble("LOAD 16\nHALT").unwrap();
+        assert_eq!(leaf.execute(load, &[]).unwrap(), vec![7]);
+    }
+}
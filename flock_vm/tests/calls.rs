@@ -0,0 +1,95 @@
+use flock_vm::Vm;
+
+fn run(source: &str) -> Result<Vec<i64>, String> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf()
+        .execute(bytecode, &[])
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn recursive_calls_keep_their_own_locals() {
+    let source = "
+  PUSH 5
+  CALL $factorial
+  HALT
+
+factorial:
+  .locals n
+  STORE_LOCAL n
+  LOAD_LOCAL n
+  JMP z, $factorial_0
+  POP
+  LOAD_LOCAL n
+  PUSH -1
+  ADD
+  CALL $factorial
+  LOAD_LOCAL n
+  MUL
+  RETURN
+
+factorial_0:
+  POP
+  PUSH 1
+  RETURN
+  .endlocals
+";
+    assert_eq!(run(source), Ok(vec![120]));
+}
+
+#[test]
+fn callees_cannot_overwrite_callers() {
+    let source = "
+  PUSH 7
+  STORE_LOCAL 0
+  PUSH 8
+  CALL $clobber
+  LOAD_LOCAL 0
+  HALT
+
+clobber:
+  POP
+  PUSH 99
+  STORE_LOCAL 0
+  LOAD_LOCAL 0
+  LOAD_LOCAL 1
+  RETURN
+";
+    // The callee pops the caller's value, but the return address isn't on the stack to lose.
+    assert_eq!(run(source), Ok(vec![99, 0, 7]));
+}
+
+#[test]
+fn locals_are_named_by_index() {
+    let source = "
+  .locals a, b
+  PUSH $b
+  PUSH 3
+  STORE_LOCAL b
+  LOAD_LOCAL 1
+  .endlocals
+  HALT
+";
+    assert_eq!(run(source), Ok(vec![1, 3]));
+}
+
+#[test]
+fn unknown_locals_fail_to_assemble() {
+    assert!(flock_vm::asm::assemble("LOAD_LOCAL missing").is_err());
+    assert!(flock_vm::asm::assemble(".locals a, a").is_err());
+    assert!(flock_vm::asm::assemble(".locals a\n.endlocals\nLOAD_LOCAL a").is_err());
+}
+
+#[test]
+fn return_outside_a_call_fails() {
+    assert_eq!(run("RETURN"), Err("ReturnWithoutCall".to_string()));
+}
+
+#[test]
+fn locals_outside_the_frame_fail() {
+    assert_eq!(run("LOAD_LOCAL -1"), Err("LocalOutOfRange(-1)".to_string()));
+    assert_eq!(
+        run("PUSH 1\nSTORE_LOCAL 65536"),
+        Err("LocalOutOfRange(65536)".to_string())
+    );
+}
@@ -0,0 +1,103 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use flock_vm::checkpoint::{restore, Checkpointer, Directory, ObjectStore};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Keys put, in order, on top of a `Directory`.
+struct Recording {
+    inner: Directory,
+    puts: Mutex<Vec<String>>,
+}
+
+impl ObjectStore for Recording {
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        self.puts.lock().unwrap().push(key.to_string());
+        self.inner.put(key, bytes)
+    }
+
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.inner.delete(key)
+    }
+}
+
+fn append(path: &PathBuf, bytes: &[u8]) {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[test]
+fn appends_upload_only_the_chunks_they_touch() {
+    let dir = scratch("appends_upload_only_the_chunks_they_touch");
+    let store = Arc::new(Recording {
+        inner: Directory::new(dir.join("store")).unwrap(),
+        puts: Mutex::new(Vec::new()),
+    });
+    let path = dir.join("journal");
+    append(&path, &[1; 25]);
+
+    let mut checkpointer = Checkpointer::new(store.clone(), "journal", &path, 10);
+    checkpointer.sync().unwrap();
+    let first: Vec<_> = store.puts.lock().unwrap().drain(..).collect();
+    assert_eq!(first.len(), 4, "{:?}", first);
+
+    append(&path, &[2; 10]);
+    checkpointer.sync().unwrap();
+    let second: Vec<_> = store.puts.lock().unwrap().drain(..).collect();
+    assert_eq!(second.len(), 3, "{:?}", second);
+    assert!(second[0].ends_with("/00000002.zst"), "{:?}", second);
+    assert!(second[1].ends_with("/00000003.zst"), "{:?}", second);
+    assert_eq!(second[2], "journal/manifest.json");
+
+    checkpointer.sync().unwrap();
+    assert!(store.puts.lock().unwrap().is_empty());
+}
+
+#[test]
+fn restores_the_latest_upload() {
+    let dir = scratch("restores_the_latest_upload");
+    let store = Arc::new(Directory::new(dir.join("store")).unwrap());
+    let path = dir.join("journal");
+    let restored = dir.join("restored");
+    assert!(!restore(&*store, "journal", &restored).unwrap());
+
+    append(&path, b"stale\n");
+    Checkpointer::new(store.clone(), "journal", &path, 4)
+        .sync()
+        .unwrap();
+
+    // Rewritten from scratch, as compaction does, then appended to in the background.
+    std::fs::write(&path, b"{\"event\":\"delivered\",\"id\":1}\n").unwrap();
+    let syncing = Checkpointer::new(store.clone(), "journal", &path, 4)
+        .spawn(std::time::Duration::from_secs(3600));
+    append(&path, b"{\"event\":\"delivered\",\"id\":2}\n");
+    drop(syncing);
+
+    assert!(restore(&*store, "journal", &restored).unwrap());
+    assert_eq!(
+        std::fs::read(&restored).unwrap(),
+        std::fs::read(&path).unwrap()
+    );
+    // Only the current upload is kept.
+    let generations = std::fs::read_dir(dir.join("store/journal"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().is_dir())
+        .count();
+    assert_eq!(generations, 1);
+}
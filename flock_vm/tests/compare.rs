@@ -0,0 +1,93 @@
+use flock_vm::Vm;
+
+fn run(source: &str) -> Vec<i64> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf().execute(bytecode, &[]).unwrap()
+}
+
+/// Pushes 1 if `JMP flags` after `b a CMP` jumps, 0 otherwise.
+fn jumps(b: i64, a: i64, flags: &str) -> bool {
+    let source = format!(
+        "
+  PUSH {}
+  PUSH {}
+  CMP
+  JMP {}, $taken
+  PUSH 0
+  HALT
+
+taken:
+  PUSH 1
+  HALT
+",
+        b, a, flags
+    );
+    match run(&source)[..] {
+        [_, taken] => taken == 1,
+        ref stack => panic!("{:?}", stack),
+    }
+}
+
+#[test]
+fn compare_pushes_the_sign() {
+    let source = "
+  PUSH 1
+  PUSH 2
+  CMP
+  PUSH 2
+  PUSH 2
+  CMP
+  PUSH 3
+  PUSH -9223372036854775808
+  CMP
+  HALT
+";
+    assert_eq!(run(source), vec![-1, 0, 1]);
+}
+
+#[test]
+fn signed_branches() {
+    assert!(jumps(1, 2, "n"));
+    assert!(!jumps(2, 2, "n"));
+    assert!(jumps(2, 2, "!n"));
+    assert!(jumps(3, 2, "!n!z"));
+    assert!(!jumps(2, 2, "!n!z"));
+    assert!(jumps(-5, 3, "n"));
+}
+
+#[test]
+fn unsigned_branches() {
+    assert!(jumps(1, 2, "c"));
+    // -1 is the largest unsigned value.
+    assert!(!jumps(-1, 2, "c"));
+    assert!(jumps(-1, 2, "!c!z"));
+    assert!(jumps(2, -1, "c"));
+}
+
+#[test]
+fn overflow_is_set_when_the_difference_does_not_fit() {
+    assert!(jumps(i64::MIN, 1, "o"));
+    assert!(jumps(i64::MAX, -1, "o"));
+    assert!(jumps(0, 1, "!o"));
+}
+
+#[test]
+fn test_clears_carry_and_overflow() {
+    let source = "
+  PUSH -9223372036854775808
+  PUSH 1
+  CMP
+  POP
+  PUSH 6
+  PUSH 3
+  TEST
+  JMP c, $wrong
+  JMP o, $wrong
+  HALT
+
+wrong:
+  PUSH -1
+  HALT
+";
+    assert_eq!(run(source), vec![2]);
+}
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use flock_vm::core_dump::CoreDump;
+use flock_vm::Vm;
+
+fn dump_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn dumps(dir: &PathBuf) -> Vec<CoreDump> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| CoreDump::read(entry.unwrap().path()).unwrap())
+        .collect()
+}
+
+#[test]
+fn failed_task_leaves_a_core_dump() {
+    let dir = dump_dir("failed_task_leaves_a_core_dump");
+    let mut vm = Vm::builder().workers(1).core_dumps(&dir).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(
+        "
+  PUSH 7
+  STORE 16
+  PUSH 3
+  LOAD 16
+  PUSH 0
+  DIV
+  HALT
+",
+    )
+    .unwrap();
+
+    assert!(vm.execute(bytecode, &[]).is_err());
+
+    let dumps = dumps(&dir);
+    assert_eq!(dumps.len(), 1);
+    let dump = &dumps[0];
+    assert_eq!(dump.error.to_string(), "DivideByZero");
+    assert_eq!(dump.pc, 6);
+    assert_eq!(dump.trace, vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(dump.memory.get(&16), Some(&7));
+    assert_eq!(dump.snapshot().pc, 6);
+}
+
+#[test]
+fn only_the_task_that_failed_is_dumped() {
+    let dir = dump_dir("only_the_task_that_failed_is_dumped");
+    let mut vm = Vm::builder().workers(1).core_dumps(&dir).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(
+        "
+  FORK
+  JMP f, $child
+  JOIN 0
+  HALT
+
+child:
+  PANIC
+",
+    )
+    .unwrap();
+
+    assert!(vm.execute(bytecode, &[]).is_err());
+
+    let dumps = dumps(&dir);
+    assert_eq!(dumps.len(), 1);
+    assert_eq!(dumps[0].error.to_string(), "ExplicitPanic");
+}
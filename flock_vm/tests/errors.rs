@@ -0,0 +1,200 @@
+use flock_vm::{ErrorPolicy, ExecutionError, Vm};
+
+// Forks two children that both panic, then joins them.
+const TWO_PANICS: &str = "
+  FORK
+  JMP !f, $first
+  PANIC
+
+first:
+  FORK
+  JMP !f, $second
+  PANIC
+
+second:
+  JOIN 1
+  JOIN 1
+  HALT
+";
+
+fn run(vm: &mut Vm, source: &str, policy: ErrorPolicy) -> Result<Vec<i64>, String> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    vm.execute_with(bytecode, &[], policy)
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn fail_fast_returns_first_error() {
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, TWO_PANICS, ErrorPolicy::FailFast),
+        Err("ExplicitPanic".to_string())
+    );
+}
+
+#[test]
+fn collect_returns_every_error() {
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, TWO_PANICS, ErrorPolicy::Collect),
+        Err("Multiple([ExplicitPanic, ExplicitPanic])".to_string())
+    );
+}
+
+#[test]
+fn collect_waits_for_tasks_nothing_joins() {
+    let source = "
+  FORK
+  JMP !f, $parent
+  PANIC
+
+parent:
+  PUSH 1
+  HALT
+";
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, source, ErrorPolicy::Collect),
+        Err("Multiple([ExplicitPanic])".to_string())
+    );
+}
+
+#[test]
+fn vm_runs_again_after_failure() {
+    let mut vm = Vm::create_leaf();
+    for policy in [ErrorPolicy::FailFast, ErrorPolicy::Collect] {
+        assert!(run(&mut vm, TWO_PANICS, policy).is_err());
+        assert_eq!(run(&mut vm, "PUSH 1\nHALT", policy), Ok(vec![1]));
+    }
+}
+
+#[test]
+fn jumps_outside_the_program_fail_where_they_jump() {
+    let source = "
+main:
+  PUSH 100
+  JMP
+";
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let error = Vm::create_leaf()
+        .execute(bytecode.clone(), &[])
+        .unwrap_err();
+    assert_eq!(error.to_string(), "JumpOutOfBounds { pc: 1, target: 100 }");
+    assert_eq!(
+        error.symbolized(&bytecode),
+        "Jump at 1 (main+1) to 100, outside the program's 2 instructions in task 0 at 1 (main+1)
+flock-debug 1
+pc 2
+op -2 PUSH 100
+op -1 JMP 0
+"
+    );
+
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, "PUSH -1\nRET", ErrorPolicy::FailFast),
+        Err("JumpOutOfBounds { pc: 1, target: -1 }".to_string())
+    );
+    // Just past the last instruction ends the program.
+    assert_eq!(
+        run(
+            &mut vm,
+            "main:\nPUSH 1\nJMP $main + 2",
+            ErrorPolicy::FailFast
+        ),
+        Ok(vec![1])
+    );
+}
+
+#[test]
+fn static_jumps_outside_the_program_are_refused_before_running() {
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, "main:\nHALT\nJSR $main + 7", ErrorPolicy::FailFast),
+        Err("JumpOutOfBounds { pc: 1, target: 7 }".to_string())
+    );
+}
+
+#[test]
+fn panicking_worker_fails_its_task() {
+    let source = "
+  FORK
+  JMP !f, $parent
+  EXT 9
+  HALT
+
+parent:
+  JOIN 1
+  HALT
+";
+    let mut vm = Vm::builder().workers(2).build().unwrap();
+    vm.register_extension(
+        9,
+        flock_vm::extension::Extension::new("BOOM", 0, 0, |_| panic!("boom")),
+    );
+    assert_eq!(
+        run(&mut vm, source, ErrorPolicy::FailFast),
+        Err("WorkerPanicked(\"boom\")".to_string())
+    );
+    // The workers are still there to run the next program.
+    assert_eq!(
+        run(&mut vm, "PUSH 1\nHALT", ErrorPolicy::FailFast),
+        Ok(vec![1])
+    );
+}
+
+#[test]
+fn errors_carry_where_the_task_failed() {
+    let source = "
+  PUSH 7
+  PUSH 1
+  PUSH 2
+  PUSH 3
+  PUSH 4
+  PUSH 5
+  PUSH 6
+  PUSH 7
+  PUSH 8
+  PUSH 0
+  DIV
+";
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let error = Vm::create_leaf().execute(bytecode, &[]).unwrap_err();
+    assert_eq!(error.cause().to_string(), "DivideByZero");
+    match &error {
+        ExecutionError::InTask { snapshot, .. } => {
+            assert_eq!(snapshot.pc, 11);
+            assert_eq!(snapshot.ops.last(), Some(&(-1, "DIV".to_string())));
+            // The operands DIV popped are gone, and only the top of the rest is kept.
+            assert_eq!(snapshot.stack, vec![7, 6, 5, 4, 3, 2, 1, 7]);
+        }
+        e => panic!("{:?}", e),
+    }
+    assert!(format!("{:#}", error).starts_with("DivideByZero in task 0\nflock-debug 1\npc 11\n"));
+}
+
+// Every child forks another, and nothing joins them.
+const FORK_CHAIN: &str = "
+start:
+  FORK
+  JMP f, $start
+  HALT
+";
+
+#[test]
+fn forking_past_the_max_depth_fails() {
+    let mut vm = Vm::builder().max_fork_depth(10).build().unwrap();
+    assert_eq!(
+        run(&mut vm, FORK_CHAIN, ErrorPolicy::Collect),
+        Err("Multiple([ForkDepthExceeded(10)])".to_string())
+    );
+}
+
+#[test]
+fn forking_past_the_last_task_id_fails() {
+    let mut vm = Vm::create_leaf();
+    assert_eq!(
+        run(&mut vm, FORK_CHAIN, ErrorPolicy::Collect),
+        Err("Multiple([ForkIdsExhausted])".to_string())
+    );
+}
@@ -0,0 +1,61 @@
+use flock_vm::asm::expect::Expectation;
+use flock_vm::Vm;
+
+fn unmet(file: &str, source: &str) -> Vec<String> {
+    let expectations = flock_vm::asm::expectations(file, source).unwrap();
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let mut vm = Vm::builder().workers(2).build().unwrap();
+    flock_vm::check(&mut vm, bytecode, &expectations)
+        .unwrap()
+        .into_iter()
+        .map(|u| format!("{}: {}", u.expectation.value, u.found))
+        .collect()
+}
+
+#[test]
+fn examples_meet_their_expectations() {
+    // The parallel fibonacci example takes too long unoptimized.
+    let examples = [
+        "01_add_numbers",
+        "02_count_down_from_3",
+        "03_fibonacci",
+        "05_shared_memory",
+    ];
+    for example in examples {
+        let path = format!(
+            "{}/../flock_asm/examples/{}.asm",
+            env!("CARGO_MANIFEST_DIR"),
+            example
+        );
+        let source = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(unmet(&path, &source), Vec::<String>::new(), "{}", example);
+    }
+}
+
+#[test]
+fn reports_what_the_program_left_instead() {
+    let source = "
+;; expect-stack: 2
+;; expect-memory: 0x10 = 7
+  PUSH 3
+  STORE 0x10
+  PUSH 1
+";
+    assert_eq!(
+        unmet("<source>", source),
+        vec!["stack [2]: [1]", "0x10 = 7: 3"]
+    );
+}
+
+#[test]
+fn rejects_malformed_expectations() {
+    let diagnostics =
+        flock_vm::asm::expectations("<source>", ";; expect-memory: 16\n").unwrap_err();
+    assert_eq!(diagnostics.0[0].code, "E0011");
+    assert_eq!(
+        flock_vm::asm::expectations("<source>", "; expect-stack: 1\n;; unrelated: 2\n").unwrap(),
+        Vec::new()
+    );
+    let parsed = flock_vm::asm::expectations("<source>", "  ;;   expect-stack:  -1 0x2\n").unwrap();
+    assert_eq!(parsed[0].value, Expectation::Stack(vec![-1, 2]));
+}
@@ -0,0 +1,84 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use flock_vm::Vm;
+
+// Forks a child that counts down for a while, which nothing joins.
+const UNJOINED_CHILD: &str = "
+  FORK
+  JMP !f, $parent
+  PUSH 1000000
+
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  HALT
+
+parent:
+  HALT
+";
+
+#[test]
+fn unclaimed_results_are_evicted_after_the_ttl() {
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .finished_ttl(Duration::ZERO)
+        .build()
+        .unwrap();
+    let leaf_addr = leaf.listen_addr().unwrap();
+
+    // Without workers of its own, the scheduler sends the child to the leaf. It's gone before
+    // the child finishes, so nobody claims the result.
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf_addr.to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(UNJOINED_CHILD).unwrap();
+    scheduler.execute(bytecode, &[]).unwrap();
+    drop(scheduler);
+
+    let started = Instant::now();
+    while leaf.handle().evicted_results() == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "Result was never evicted"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(leaf.handle().evicted_results(), 1);
+}
+
+#[test]
+fn results_the_running_program_joins_late_are_kept() {
+    // The parent naps past the TTL between forking the child and joining it.
+    let source = "
+  FORK
+  JMP f, $child
+  EXT 9
+  JOIN 1
+  HALT
+
+child:
+  PUSH 7
+  HALT
+";
+    let mut vm = Vm::builder()
+        .workers(1)
+        .finished_ttl(Duration::ZERO)
+        .build()
+        .unwrap();
+    vm.register_extension(
+        9,
+        flock_vm::extension::Extension::new("NAP", 0, 0, |_| {
+            std::thread::sleep(Duration::from_millis(2500));
+            Ok(Vec::new())
+        }),
+    );
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![7]);
+    assert_eq!(vm.handle().evicted_results(), 0);
+}
@@ -0,0 +1,121 @@
+use flock_vm::Vm;
+
+// A leaf doesn't listen, so the tests can run in parallel.
+fn run(source: &str) -> Vec<i64> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf().execute(bytecode, &[]).unwrap()
+}
+
+#[test]
+fn task_that_never_forked_is_not_a_child() {
+    assert_eq!(run("IS_CHILD\nHALT"), vec![0]);
+}
+
+#[test]
+fn fork_sets_flag_in_child_only() {
+    let source = "
+  FORK
+  IS_CHILD
+  JMP z, $parent
+  HALT
+
+parent:
+  BURY 1
+  JOIN 1
+  HALT
+";
+    assert_eq!(run(source), vec![0, 1]);
+}
+
+#[test]
+fn jumps_on_either_side_of_fork() {
+    let source = "
+  FORK
+  JMP !f, $parent
+  JMP f, $child
+  PANIC
+
+child:
+  POP
+  PUSH 1
+  HALT
+
+parent:
+  JOIN 1
+  PUSH 2
+  HALT
+";
+    assert_eq!(run(source), vec![1, 2]);
+}
+
+#[test]
+fn forking_again_clears_flag_in_new_parent() {
+    let source = "
+  FORK
+  JMP !f, $parent
+  POP
+  FORK
+  IS_CHILD
+  JMP z, $child_as_parent
+  HALT
+
+child_as_parent:
+  BURY 1
+  JOIN 1
+  HALT
+
+parent:
+  JOIN 2
+  HALT
+";
+    assert_eq!(run(source), vec![0, 1]);
+}
+
+#[test]
+fn join_status_tells_halting_from_running_off_the_end() {
+    let source = "
+  FORK
+  JMP f, $halts
+  JOIN_STATUS 0
+  FORK
+  JMP f, $halts_with_code
+  JOIN_STATUS 0
+  FORK
+  JMP f, $falls_off
+  JOIN_STATUS 0
+  HALT
+
+halts:
+  HALT
+
+halts_with_code:
+  HALT 3
+
+falls_off:
+  POP
+";
+    assert_eq!(run(source), vec![0, 1, 3, 1, 0, 0]);
+}
+
+#[test]
+fn joining_task_halted_with_nonzero_code_fails() {
+    let source = "
+  FORK
+  JMP f, $child
+  JOIN 0
+  HALT
+
+child:
+  HALT 2
+";
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let error = Vm::create_leaf().execute(bytecode, &[]).unwrap_err();
+    assert_eq!(error.to_string(), "Halted(2)");
+}
+
+#[test]
+fn program_halted_with_nonzero_code_fails() {
+    let bytecode = flock_vm::asm::assemble("PUSH 1\nHALT -1").unwrap();
+    let result = Vm::create_leaf().execute(bytecode, &[]);
+    assert_eq!(format!("{:?}", result), "Err(Halted(-1))");
+}
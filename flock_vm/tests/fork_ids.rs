@@ -0,0 +1,193 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+use flock_vm::Vm;
+
+// Every call reaching `fibonacci_0` emits 1, tagged with its task's id.
+const FIBONACCI_EMITTING: &str = "
+  PUSH 12
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  PUSH 1
+  DUP
+  EMIT
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+fn emitting_task_ids(mut vm: Vm) -> Vec<usize> {
+    let bytecode = flock_vm::asm::assemble(FIBONACCI_EMITTING).unwrap();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![233]);
+    let mut ids: Vec<_> = vm.emitted().try_iter().map(|e| e.task_id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+#[test]
+fn forked_tasks_get_the_same_ids_every_run() {
+    let first = emitting_task_ids(Vm::create_leaf());
+    assert_eq!(first.len(), 233);
+    assert_eq!(first.iter().collect::<BTreeSet<_>>().len(), 233);
+    assert!(!first.contains(&0));
+
+    assert_eq!(emitting_task_ids(Vm::create_leaf()), first);
+    let workers = Vm::builder().workers(4).build().unwrap();
+    assert_eq!(emitting_task_ids(workers), first);
+}
+
+#[test]
+fn forked_tasks_are_told_the_same_parent_id_every_run() {
+    // The grandchild leaves the id it was told for its parent, which ends up on the main task's
+    // stack.
+    let source = "
+  FORK
+  JMP f, $child
+  JOIN 1
+  HALT
+
+child:
+  FORK
+  JMP f, $grandchild
+  JOIN 1
+  HALT
+
+grandchild:
+  HALT
+";
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let first = Vm::create_leaf().execute(bytecode.clone(), &[]).unwrap();
+    let second = Vm::create_leaf().execute(bytecode, &[]).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first.len(), 1);
+    assert_ne!(first[0], 0);
+}
+
+// Every task emits, then those above depth 0 fork three children a level deeper and join them.
+const TREE: &str = "
+  PUSH 4
+node:
+  DUP
+  EMIT
+  JMP z, $leaf
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $child
+  BURY 1
+  FORK
+  JMP f, $child
+  BURY 1
+  FORK
+  JMP f, $child
+  BURY 1
+  POP
+  JOIN 1
+  POP
+  JOIN 1
+  POP
+  JOIN 1
+  POP
+  PUSH 0
+leaf:
+  HALT
+
+child:
+  POP
+  JMP $node
+";
+
+#[test]
+fn tasks_forked_by_different_parents_get_different_ids() {
+    let mut vm = Vm::builder().workers(4).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(TREE).unwrap();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![0]);
+    let ids: Vec<_> = vm.emitted().try_iter().map(|e| e.task_id).collect();
+    assert_eq!(ids.len(), 1 + 3 + 9 + 27 + 81);
+    assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), ids.len());
+}
+
+// Forks a child that doubles the input, both taking long enough that the children of programs
+// started together are at the leaf at the same time.
+const DOUBLED_BY_A_CHILD: &str = "
+  FORK
+  JMP f, $child
+
+  ; Gives the child time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  POP
+  MULI 2
+  PUSH 100000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  HALT
+";
+
+#[test]
+fn programs_from_different_nodes_get_their_own_results_from_a_shared_leaf() {
+    let leaf = Vm::builder()
+        .workers(2)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .unwrap();
+    let leaf_addr = leaf.listen_addr().unwrap().to_string();
+
+    // Without workers of their own, both schedulers send their child to the leaf, and both
+    // children get the same id, as the first forked by a main task.
+    let runs = [3, 5].map(|input| {
+        let peers = vec![leaf_addr.clone()];
+        std::thread::spawn(move || {
+            let mut scheduler = Vm::builder().workers(0).peers(peers).build().unwrap();
+            let bytecode = flock_vm::asm::assemble(DOUBLED_BY_A_CHILD).unwrap();
+            scheduler.execute(bytecode, &[input]).unwrap()
+        })
+    });
+    let [three, five] = runs.map(|run| run.join().unwrap());
+
+    assert_eq!(three, vec![3, 6]);
+    assert_eq!(five, vec![5, 10]);
+    assert_eq!(leaf.handle().served_requests(), 2);
+}
@@ -0,0 +1,53 @@
+use flock_vm::snapshot::Snapshot;
+use flock_vm::Vm;
+
+/// The `DUMP_DEBUG` listings an example prints. Set `UPDATE_GOLDEN=1` to rewrite them after an
+/// intended change.
+fn check_golden(example: &str) {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let source =
+        std::fs::read_to_string(format!("{}/../flock_asm/examples/{}.asm", dir, example)).unwrap();
+    let mut vm = Vm::builder().workers(1).build().unwrap();
+    vm.execute(flock_vm::asm::assemble(&source).unwrap(), &[])
+        .unwrap();
+    let listings: String = vm.debug_dumps().try_iter().map(|d| d.listing).collect();
+
+    let golden = format!("{}/tests/golden/{}.debug", dir, example);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &listings).unwrap();
+    }
+    assert_eq!(
+        listings,
+        std::fs::read_to_string(&golden).unwrap(),
+        "{}",
+        example
+    );
+}
+
+#[test]
+fn examples_match_golden_dumps() {
+    for example in ["01_add_numbers", "02_count_down_from_3", "03_fibonacci"] {
+        check_golden(example);
+    }
+}
+
+#[test]
+fn listings_parse_back() {
+    let listing = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/01_add_numbers.debug"
+    ))
+    .unwrap();
+    let snapshot: Snapshot = listing.parse().unwrap();
+
+    assert_eq!(snapshot.pc, 4);
+    assert_eq!(snapshot.ops[0], (-4, "PUSH 5".to_string()));
+    assert_eq!(snapshot.stack, vec![13]);
+    assert_eq!(snapshot.to_string(), listing);
+}
+
+#[test]
+fn rejects_other_versions() {
+    assert!("flock-debug 2\npc 0\n".parse::<Snapshot>().is_err());
+    assert!("Flock VM Debug\nPC: 0\n".parse::<Snapshot>().is_err());
+}
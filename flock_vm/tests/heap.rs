@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use flock_vm::{Vm, HEAP_BASE};
+
+fn run(source: &str) -> Result<Vec<i64>, String> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf()
+        .execute(bytecode, &[])
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn freed_blocks_are_handed_out_again() {
+    let source = "
+  ALLOC 4
+  ALLOC 2
+  DREDGE 1
+  FREE
+  ALLOC shared, 3
+  ALLOC 2
+  HALT
+";
+    let base = HEAP_BASE.flag as i64;
+    assert_eq!(run(source).unwrap(), vec![base + 4, base, base + 6]);
+}
+
+#[test]
+fn blocks_hold_what_tasks_store_relative_to_them() {
+    let source = "
+  ALLOC 3
+  DUP
+  PUSH 7
+  DREDGE 1
+  STORE_REL 2
+  DUP
+  LOAD_REL 2
+  DREDGE 1
+  FREE
+  HALT
+";
+    assert_eq!(run(source).unwrap(), vec![7]);
+}
+
+#[test]
+fn task_blocks_are_freed_when_the_task_ends() {
+    let source = "
+  FORK
+  JMP f, $child
+  JOIN 1
+  ALLOC 1
+  HALT
+
+child:
+  POP
+  ALLOC task, 1
+  HALT
+";
+    let base = HEAP_BASE.flag as i64;
+    assert_eq!(run(source).unwrap(), vec![base, base]);
+}
+
+#[test]
+fn only_blocks_can_be_freed() {
+    assert_eq!(
+        run("ALLOC 2\nADDI 1\nFREE").unwrap_err(),
+        format!("InvalidFree({})", HEAP_BASE.flag + 1)
+    );
+    assert_eq!(
+        run("ALLOC 1\nDUP\nFREE\nFREE").unwrap_err(),
+        format!("InvalidFree({})", HEAP_BASE.flag)
+    );
+}
+
+#[test]
+fn tasks_cannot_free_each_others_task_blocks() {
+    let source = "
+  ALLOC task, 1
+  FORK
+  JMP f, $child
+  JOIN 0
+  HALT
+
+child:
+  POP
+  FREE
+  HALT
+";
+    assert_eq!(
+        run(source).unwrap_err(),
+        format!("InvalidFree({})", HEAP_BASE.flag)
+    );
+}
+
+// Emits a block allocated by every call reaching `fibonacci_0`.
+const FIBONACCI_ALLOCATING: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  ALLOC 2
+  EMIT
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+#[test]
+fn peers_allocate_from_the_origin() {
+    let mut vm = Vm::builder()
+        .workers(2)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .simulate_cluster(2, Duration::ZERO)
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(FIBONACCI_ALLOCATING).unwrap();
+
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![4181]);
+    assert!(vm.handle().peer_status().iter().any(|p| p.requests > 0));
+
+    let blocks: Vec<u64> = vm.emitted().try_iter().map(|e| e.value as u64).collect();
+    assert!(blocks.len() >= 4181);
+    assert!(blocks.iter().all(|&b| b >= HEAP_BASE.flag));
+    let distinct: HashSet<_> = blocks.iter().collect();
+    assert_eq!(distinct.len(), blocks.len());
+}
@@ -0,0 +1,47 @@
+use flock_vm::coverage::HotSpot;
+use flock_vm::Vm;
+
+const FORKED_COUNTDOWNS: &str = "
+  FORK
+  JMP f, $child
+  PUSH 5
+  JMP $countdown
+joined:
+  POP
+  JOIN 0
+  HALT
+
+child:
+  POP
+  PUSH 5
+countdown:
+  PUSH -1
+  ADD
+  JMP !z, $countdown
+  POP
+  IS_CHILD
+  JMP z, $joined
+  HALT
+";
+
+#[test]
+fn hottest_instructions_are_labelled_with_counts_from_every_task() {
+    let mut vm = Vm::builder().workers(2).coverage(true).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(FORKED_COUNTDOWNS).unwrap();
+    vm.execute(bytecode, &[]).unwrap();
+
+    let countdown = |offset: usize| match offset {
+        0 => Some("countdown".to_string()),
+        n => Some(format!("countdown+{}", n)),
+    };
+    assert_eq!(
+        vm.hot_spots(3).unwrap(),
+        (0..3)
+            .map(|offset| HotSpot {
+                index: 9 + offset,
+                label: countdown(offset),
+                count: 10,
+            })
+            .collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,52 @@
+use flock_bytecode::disasm::disassemble;
+use flock_vm::asm::preprocess::{Defines, Extensions};
+use flock_vm::Vm;
+
+#[test]
+fn push_n_and_immediate_arithmetic() {
+    let bytecode = flock_vm::asm::assemble(
+        "
+  PUSHN 2, 3, 4
+  MULI 10
+  ADDI -1
+  PUSHN 5
+",
+    )
+    .unwrap();
+    let mut vm = Vm::create().unwrap();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![2, 3, 39, 5]);
+}
+
+#[test]
+fn optimizer_fuses_constants_into_the_instructions_using_them() {
+    let source = "
+  LOAD 0
+  PUSH 3
+  MUL
+  PUSH 1
+  ADD
+  PUSH 7
+  PUSH 8
+  PUSH 9
+  HALT
+";
+    let assemble = |optimize| {
+        flock_vm::asm::assemble_with(
+            "<source>",
+            source,
+            &Defines::new(),
+            &Extensions::new(),
+            optimize,
+        )
+        .unwrap()
+    };
+    let (plain, optimized) = (assemble(false), assemble(true));
+    assert_eq!(
+        disassemble(&optimized),
+        "  LOAD 0\n  MULI 3\n  ADDI 1\n  PUSHN 7, 8, 9\n  HALT\n"
+    );
+
+    let mut vm = Vm::create().unwrap();
+    assert_eq!(vm.execute(plain, &[]).unwrap(), vec![1, 7, 8, 9]);
+    assert_eq!(vm.execute(optimized, &[]).unwrap(), vec![1, 7, 8, 9]);
+}
@@ -0,0 +1,29 @@
+use flock_vm::Vm;
+
+#[test]
+fn inputs_start_on_the_stack_last_on_top() {
+    let bytecode = flock_vm::asm::assemble("DIV\nPUSH 1\nHALT").unwrap();
+    let stack = Vm::create_leaf().execute(bytecode, &[12, 4]).unwrap();
+    assert_eq!(stack, vec![3, 1]);
+}
+
+#[test]
+fn each_run_gets_its_own_inputs() {
+    let source = "
+  FORK
+  JMP f, $child
+  JOIN 1
+  ADD
+  HALT
+
+child:
+  POP
+  MULI 10
+  HALT
+";
+    let mut vm = Vm::create_leaf();
+    for input in [1, 7] {
+        let bytecode = flock_vm::asm::assemble(source).unwrap();
+        assert_eq!(vm.execute(bytecode, &[input]).unwrap(), vec![input * 11]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use flock_vm::intercept::{MemoryInterceptor, ReadOnly};
+use flock_vm::Vm;
+
+fn run(vm: &mut Vm, source: &str) -> Vec<i64> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    vm.execute(bytecode, &[]).unwrap()
+}
+
+/// Keeps stores to addresses from 2000 on the host.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<BTreeMap<u64, i64>>>);
+
+impl MemoryInterceptor for Output {
+    fn on_load(&self, addr: u64) -> Option<i64> {
+        self.0.lock().unwrap().get(&addr).cloned()
+    }
+
+    fn on_store(&self, addr: u64, value: i64) -> bool {
+        if addr < 2000 {
+            return false;
+        }
+        self.0.lock().unwrap().insert(addr, value);
+        true
+    }
+}
+
+#[test]
+fn tasks_read_host_values_in_place() {
+    let mut vm = Vm::create_leaf();
+    let values = Arc::new(vec![3, 5, 7]);
+    let input = ReadOnly::new(1000, values);
+    assert_eq!(input.addresses(), 1000..1003);
+    vm.intercept_memory(input);
+
+    let source = "
+  PUSH 0
+  LOAD_REL 1000
+  PUSH 1
+  LOAD_REL 1000
+  ADD
+  PUSH 2
+  LOAD_REL 1000
+  ADD
+  LOAD 1003
+  HALT
+";
+    assert_eq!(run(&mut vm, source), vec![15, 0]);
+}
+
+#[test]
+fn read_only_values_ignore_stores() {
+    let mut vm = Vm::create_leaf();
+    vm.intercept_memory(ReadOnly::new(1000, Arc::new(vec![3])));
+
+    let source = "
+  PUSH 9
+  STORE 1000
+  PUSH 9
+  STORE 1001
+  LOAD 1000
+  LOAD 1001
+  HALT
+";
+    assert_eq!(run(&mut vm, source), vec![3, 9]);
+    assert_eq!(vm.load(1000), 3);
+}
+
+#[test]
+fn interceptors_take_stores_they_handle() {
+    let mut vm = Vm::create_leaf();
+    let output = Output::default();
+    vm.intercept_memory(output.clone());
+
+    let source = "
+  PUSH 4
+  STORE 2000
+  PUSH 5
+  STORE 10
+  PUSH 300
+  PUSH 1
+  STORE_PACKED u8, 2000
+  LOAD 2000
+  HALT
+";
+    assert_eq!(run(&mut vm, source), vec![4 | 44 << 8]);
+    assert_eq!(
+        *output.0.lock().unwrap(),
+        vec![(2000, 4 | 44 << 8)].into_iter().collect()
+    );
+    assert_eq!(vm.load(10), 5);
+}
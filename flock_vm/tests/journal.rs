@@ -0,0 +1,86 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use flock_vm::Vm;
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Forks a child that counts down for a moment, then adds 1 at address 16. Nothing joins it.
+const INCREMENTING_CHILD: &str = "
+  FORK
+  JMP !f, $parent
+  PUSH 1000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  LOAD 16
+  ADDI 1
+  STORE 16
+  HALT
+
+parent:
+  HALT
+";
+
+/// Sends the child to a leaf journaling to `journal`, without workers to run it.
+fn leave_queued(journal: &Path) {
+    let leaf = Vm::builder()
+        .workers(0)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .journal(journal)
+        .build()
+        .unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(INCREMENTING_CHILD).unwrap();
+    scheduler.execute(bytecode, &[]).unwrap();
+    // Sends the child before returning.
+    drop(scheduler);
+}
+
+fn wait_for_store(vm: &Vm) {
+    let started = Instant::now();
+    while vm.load(16) == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "Recovered task never ran"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn queued_tasks_run_after_a_restart() {
+    let journal = scratch("queued_tasks_run_after_a_restart").join("journal");
+    leave_queued(&journal);
+
+    let restarted = Vm::builder().workers(1).journal(&journal).build().unwrap();
+    wait_for_store(&restarted);
+    assert_eq!(restarted.load(16), 1);
+}
+
+#[test]
+fn finished_tasks_are_not_run_again() {
+    let journal = scratch("finished_tasks_are_not_run_again").join("journal");
+    leave_queued(&journal);
+
+    let restarted = Vm::builder().workers(1).journal(&journal).build().unwrap();
+    wait_for_store(&restarted);
+    // Waits for the worker to record the task finished.
+    drop(restarted);
+
+    let again = Vm::builder().workers(1).journal(&journal).build().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(again.load(16), 0);
+}
@@ -0,0 +1,73 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use flock_vm::Vm;
+
+/// Keeps every message logged.
+struct Captured(Mutex<Vec<String>>);
+
+impl log::Log for Captured {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURED: Captured = Captured(Mutex::new(Vec::new()));
+
+// Forks a child carrying a recognizable value, which counts down long enough to be shipped.
+const SECRET_CHILD: &str = "
+  PUSH 987654321
+  FORK
+  JMP f, $child
+
+  ; Gives the child time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  PUSH 100000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  HALT
+";
+
+#[test]
+fn shipped_stacks_are_logged_as_sizes_by_default() {
+    log::set_logger(&CAPTURED).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(SECRET_CHILD).unwrap();
+    scheduler.execute(bytecode, &[]).unwrap();
+
+    let messages = CAPTURED.0.lock().unwrap();
+    assert!(
+        messages.iter().any(|m| m.contains("stack <len 2>")),
+        "{:#?}",
+        messages
+    );
+    assert!(!messages.iter().any(|m| m.contains("987654321")));
+}
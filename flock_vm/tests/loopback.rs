@@ -0,0 +1,60 @@
+use flock_vm::loopback::Loopback;
+
+const PARALLEL_FIBONACCI: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+#[test]
+fn forks_run_on_leaf_peer() {
+    let mut cluster = Loopback::start().unwrap();
+    let bytecode = flock_vm::asm::assemble(PARALLEL_FIBONACCI).unwrap();
+
+    assert_eq!(
+        cluster.scheduler.execute(bytecode, &[]).unwrap(),
+        vec![4181]
+    );
+    assert!(cluster.leaf.handle().served_requests() > 0);
+
+    let peers = cluster.scheduler.handle().peer_status();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(&peers[0].peer, cluster.leaf.handle().identity());
+    assert!(peers[0].requests > 0);
+    assert_eq!(peers[0].failures, 0);
+}
@@ -0,0 +1,179 @@
+use flock_bytecode::{ByteCode, OpCode};
+use flock_vm::asm::preprocess::{Defines, Extensions};
+use flock_vm::Vm;
+
+const INPUTS: &[i64] = &[1, 2, 3, 4, 5];
+
+fn assemble(source: &str, optimize: bool) -> ByteCode {
+    flock_vm::asm::assemble_with(
+        "<source>",
+        source,
+        &Defines::new(),
+        &Extensions::new(),
+        optimize,
+    )
+    .unwrap()
+}
+
+/// Assembles `source` with and without `--optimize`, checking both leave the same stack when run
+/// on `INPUTS`. Returns the optimized bytecode.
+fn assert_same_stack(source: &str) -> ByteCode {
+    let (plain, optimized) = (assemble(source, false), assemble(source, true));
+    let mut vm = Vm::create_leaf();
+    let expected = vm.execute(plain, INPUTS).unwrap();
+    assert_eq!(
+        vm.execute(optimized.clone(), INPUTS).unwrap(),
+        expected,
+        "{}",
+        source
+    );
+    optimized
+}
+
+fn opcodes(bytecode: &ByteCode) -> Vec<OpCode> {
+    (0..bytecode.len())
+        .map(|i| bytecode.get(i).unwrap().clone())
+        .collect()
+}
+
+fn stores(bytecode: &ByteCode) -> usize {
+    opcodes(bytecode)
+        .iter()
+        .filter(|op| matches!(op, OpCode::Store(_)))
+        .count()
+}
+
+#[test]
+fn shuffles_that_cancel_out_are_removed() {
+    for source in [
+        "DUP\nPOP\nHALT",
+        "BURY 1\nBURY 1\nHALT",
+        "DREDGE 2\nDREDGE 2\nDREDGE 2\nHALT",
+        "PUSH 7\nBURY 2\nDREDGE 2\nPOP\nHALT",
+    ] {
+        assert_eq!(assert_same_stack(source).len(), 1, "{}", source);
+    }
+}
+
+#[test]
+fn minimized_shuffles_leave_the_same_stack() {
+    for source in [
+        "PUSH 9\nDUP\nBURY 3\nDREDGE 1\nPOP\nDUP\nDREDGE 4\nHALT",
+        "DREDGE 1\nDREDGE 1\nDUP\nBURY 2\nPUSH 3\nPUSH 4\nBURY 1\nHALT",
+        "POP\nPOP\nPUSH 6\nDREDGE 2\nDUP\nDUP\nBURY 4\nHALT",
+        "DREDGE 4\nBURY 3\nDREDGE 1\nPOP\nHALT",
+    ] {
+        let plain = assemble(source, false);
+        let optimized = assert_same_stack(source);
+        assert!(optimized.len() <= plain.len(), "{}", source);
+    }
+
+    let source = "DREDGE 1\nDREDGE 1\nDUP\nBURY 2\nPUSH 3\nPUSH 4\nBURY 1\nHALT";
+    assert!(assert_same_stack(source).len() < assemble(source, false).len());
+}
+
+#[test]
+fn labels_split_shuffle_runs() {
+    // `PUSH 7` and `POP` would cancel out, but the jump lands between them.
+    let source = "
+  PUSH 0
+  JMP $target
+  PUSH 7
+target:
+  POP
+  HALT
+";
+    let optimized = assert_same_stack(source);
+    assert_eq!(optimized.len(), assemble(source, false).len());
+}
+
+#[test]
+fn comments_stay_inside_shuffle_runs() {
+    let source = "
+  DUP
+; Only needed for a moment.
+
+  POP
+  HALT
+";
+    assert_eq!(assert_same_stack(source).len(), 1);
+}
+
+#[test]
+fn folds_literal_arithmetic() {
+    let optimized = assert_same_stack("PUSH 6\nPUSH 7\nMUL\nPUSH -2\nADD\nHALT");
+    assert_eq!(opcodes(&optimized), vec![OpCode::Push(40), OpCode::Halt]);
+}
+
+#[test]
+fn folded_division_divides_the_lower_value_by_the_top() {
+    let optimized = assert_same_stack("PUSH 7\nPUSH 2\nDIV\nHALT");
+    assert_eq!(opcodes(&optimized), vec![OpCode::Push(3), OpCode::Halt]);
+}
+
+#[test]
+fn overflow_is_left_to_the_vm() {
+    for (source, op) in [
+        ("PUSH 9223372036854775807\nPUSH 1\nADD\nHALT", OpCode::AddImm(1)),
+        ("PUSH 4611686018427387904\nPUSH 2\nMUL\nHALT", OpCode::MulImm(2)),
+        ("PUSH -9223372036854775808\nPUSH -1\nDIV\nHALT", OpCode::Div),
+    ] {
+        let optimized = assert_same_stack(source);
+        assert!(opcodes(&optimized).contains(&op), "{}", source);
+    }
+}
+
+#[test]
+fn division_by_zero_is_left_to_the_vm() {
+    let source = "PUSH 1\nPUSH 0\nDIV\nHALT";
+    let optimized = assemble(source, true);
+    assert!(opcodes(&optimized).contains(&OpCode::Div));
+    assert!(Vm::create_leaf().execute(optimized, &[]).is_err());
+}
+
+#[test]
+fn overwritten_stores_are_dropped() {
+    let source = "
+  PUSH 1
+  STORE 16
+  LOAD 17
+  POP
+  PUSH 2
+  STORE 16
+  HALT
+";
+    assert_eq!(stores(&assert_same_stack(source)), 1);
+}
+
+#[test]
+fn loads_of_named_addresses_keep_earlier_stores() {
+    // Both names are the same address, so the load sees the first store.
+    let source = "
+a = 16
+b = 16
+  PUSH 1
+  STORE $a
+  LOAD $b
+  PUSH 2
+  STORE $a
+  HALT
+";
+    let optimized = assert_same_stack(source);
+    assert_eq!(stores(&optimized), 2);
+
+    let mut vm = Vm::create_leaf();
+    assert_eq!(vm.execute(optimized, &[]).unwrap(), vec![1]);
+}
+
+#[test]
+fn labels_keep_earlier_stores() {
+    let source = "
+  PUSH 1
+  STORE 16
+again:
+  PUSH 2
+  STORE 16
+  HALT
+";
+    assert_eq!(stores(&assert_same_stack(source)), 2);
+}
@@ -0,0 +1,76 @@
+use flock_vm::Vm;
+
+fn run(source: &str) -> (Vec<i64>, Vec<i64>) {
+    let mut vm = Vm::builder()
+        .workers(4)
+        .ordered_completion(true)
+        .build()
+        .unwrap();
+    let stack = vm
+        .execute(flock_vm::asm::assemble(source).unwrap(), &[])
+        .unwrap();
+    let emitted = vm.emitted().try_iter().map(|e| e.value).collect();
+    (stack, emitted)
+}
+
+#[test]
+fn effects_apply_in_join_order() {
+    let source = "
+  FORK
+  JMP f, $a
+  FORK
+  JMP f, $b
+  JOIN 0
+  JOIN 0
+  LOAD 0
+  HALT
+
+a:
+  POP
+  PUSH 1
+  STORE 0
+  LOAD 0
+  EMIT
+  HALT
+
+b:
+  POP
+  PUSH 2
+  EMIT
+  FORK
+  JMP f, $c
+  JOIN 0
+  LOAD 0
+  EMIT
+  HALT
+
+c:
+  POP
+  PUSH 3
+  STORE 0
+  HALT
+";
+    for _ in 0..20 {
+        // b, and c through b, are joined before a, so a's store is the one left.
+        assert_eq!(run(source), (vec![1], vec![2, 3, 1]));
+    }
+}
+
+#[test]
+fn children_never_joined_have_no_effect() {
+    let source = "
+  FORK
+  JMP f, $child
+  POP
+  LOAD 0
+  HALT
+
+child:
+  PUSH 7
+  EMIT
+  PUSH 7
+  STORE 0
+  HALT
+";
+    assert_eq!(run(source), (vec![0], vec![]));
+}
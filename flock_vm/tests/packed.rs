@@ -0,0 +1,75 @@
+use flock_vm::Vm;
+
+fn run(source: &str) -> Vec<i64> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf().execute(bytecode, &[]).unwrap()
+}
+
+#[test]
+fn elements_share_cells_without_clobbering() {
+    let source = "
+  PUSH 200
+  PUSH 0
+  STORE_PACKED u8, 16
+  PUSH 7
+  PUSH 9
+  STORE_PACKED u8, 16
+
+  PUSH 0
+  LOAD_PACKED u8, 16
+  PUSH 9
+  LOAD_PACKED u8, 16
+  PUSH 8
+  LOAD_PACKED u8, 16
+  HALT
+";
+    assert_eq!(run(source), vec![200, 7, 0]);
+}
+
+#[test]
+fn element_index_picks_cell_and_offset() {
+    let source = "
+  PUSH 0x1234
+  PUSH 5
+  STORE_PACKED i16, 16
+  LOAD 17
+  HALT
+";
+    assert_eq!(run(source), vec![0x1234 << 16]);
+}
+
+#[test]
+fn signed_widths_sign_extend() {
+    let source = "
+  PUSH -2
+  PUSH 3
+  STORE_PACKED i16, 0
+  PUSH -2
+  PUSH 1
+  STORE_PACKED i32, 8
+  PUSH -2
+  PUSH 1
+  STORE_PACKED u8, 24
+
+  PUSH 3
+  LOAD_PACKED i16, 0
+  PUSH 1
+  LOAD_PACKED i32, 8
+  PUSH 1
+  LOAD_PACKED u8, 24
+  HALT
+";
+    assert_eq!(run(source), vec![-2, -2, 254]);
+}
+
+#[test]
+fn stores_truncate_to_width() {
+    let source = "
+  PUSH 0x1ff
+  PUSH 0
+  STORE_PACKED u8, 0
+  LOAD 0
+  HALT
+";
+    assert_eq!(run(source), vec![0xff]);
+}
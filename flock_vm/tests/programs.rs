@@ -0,0 +1,48 @@
+use std::time::SystemTime;
+
+use flock_vm::programs::EntryPoint;
+use flock_vm::Vm;
+
+#[test]
+fn programs_lists_registered_bytecode_locally_and_over_rpc() {
+    let started = SystemTime::now();
+    let mut vm = Vm::builder()
+        .workers(1)
+        .listen(([127, 0, 0, 1], 0))
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(
+        "
+  PUSH 20
+  JSR $noop
+  HALT
+
+noop:
+  RET
+",
+    )
+    .unwrap();
+    let bytes = bytecode.to_bytes().len();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![20]);
+
+    let programs = vm.handle().programs();
+    assert_eq!(programs.len(), 1);
+    let program = &programs[0];
+    assert_eq!(program.instructions, 4);
+    assert_eq!(program.bytes, bytes);
+    assert_eq!(
+        program.entry_points,
+        vec![
+            EntryPoint { pc: 0, label: None },
+            EntryPoint {
+                pc: 3,
+                label: Some("noop".to_string())
+            },
+        ]
+    );
+    assert!(program.registered >= started);
+    assert_eq!(program.references, 0);
+
+    let addr = vm.listen_addr().unwrap().to_string();
+    assert_eq!(flock_vm::cluster::fetch_programs(&addr).unwrap(), programs);
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use flock_vm::{QueueOrder, Vm};
+
+const PARALLEL_FIBONACCI: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+#[test]
+fn every_order_runs_forks_to_completion() {
+    for order in [QueueOrder::Lifo, QueueOrder::Fifo, QueueOrder::Hybrid] {
+        let mut vm = Vm::builder()
+            .workers(2)
+            .queue_order(order, Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let bytecode = flock_vm::asm::assemble(PARALLEL_FIBONACCI).unwrap();
+        assert_eq!(
+            vm.execute(bytecode, &[]).unwrap(),
+            vec![4181],
+            "{:?}",
+            order
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use flock_vm::Vm;
+
+#[test]
+fn idle_vm_is_quiet() {
+    let vm = Vm::create_leaf();
+    futures::executor::block_on(vm.handle().quiesce());
+}
+
+// Forks a task for each of 0..64 that counts down from 10000 and stores 1 at its number,
+// without joining any of them.
+const FIRE_AND_FORGET: &str = "
+  PUSH 64
+
+spawn:
+  ADDI -1
+  FORK
+  JMP f, $child
+  POP
+  JMP !z, $spawn
+  HALT
+
+child:
+  POP
+  PUSH 10000
+
+count:
+  ADDI -1
+  JMP !z, $count
+  POP
+  PUSH 1
+  DREDGE 1
+  STORE_REL 0
+  HALT
+";
+
+#[test]
+fn waits_for_tasks_nothing_joins() {
+    // A VM without workers only runs forked tasks while joining them.
+    let mut vm = Vm::builder().workers(2).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(FIRE_AND_FORGET).unwrap();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![0]);
+
+    futures::executor::block_on(vm.handle().quiesce());
+    assert!((0..64).all(|addr| vm.load(addr) == 1));
+}
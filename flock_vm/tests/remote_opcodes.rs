@@ -0,0 +1,85 @@
+use flock_vm::sandbox::OpCodePolicy;
+use flock_vm::Vm;
+
+const PARALLEL_FIBONACCI: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+fn leaf(policy: OpCodePolicy) -> Vm {
+    Vm::builder()
+        .workers(1)
+        .listen(([127, 0, 0, 1], 0))
+        .remote_opcodes(policy)
+        .build()
+        .unwrap()
+}
+
+fn run_through(leaf: &Vm) -> Vec<i64> {
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(PARALLEL_FIBONACCI).unwrap();
+    scheduler.execute(bytecode, &[]).unwrap()
+}
+
+#[test]
+fn refused_bytecode_runs_on_origin() {
+    let leaf = leaf(OpCodePolicy {
+        allowed: None,
+        denied: std::iter::once("ADD".to_string()).collect(),
+    });
+    assert_eq!(run_through(&leaf), vec![4181]);
+    assert_eq!(leaf.handle().served_requests(), 0);
+}
+
+#[test]
+fn allowed_bytecode_runs_on_peer() {
+    let allowed = [
+        "PUSH", "FORK", "BURY", "JMP", "POP", "JOIN", "HALT", "DUP", "ADD", "DREDGE",
+    ];
+    let leaf = leaf(OpCodePolicy {
+        allowed: Some(allowed.iter().map(|m| m.to_string()).collect()),
+        denied: Default::default(),
+    });
+    assert_eq!(run_through(&leaf), vec![4181]);
+    assert!(leaf.handle().served_requests() > 0);
+}
@@ -0,0 +1,41 @@
+use flock_vm::asm::assemble;
+
+#[test]
+fn idempotent_regions_cover_instructions_up_to_the_end_directive() {
+    let bytecode = assemble(
+        "
+  PUSH 1
+.idempotent
+  FORK
+  POP
+.endidempotent
+  HALT
+",
+    )
+    .unwrap();
+    assert_eq!(
+        (0..4)
+            .map(|i| bytecode.is_idempotent(i))
+            .collect::<Vec<_>>(),
+        vec![false, true, true, false]
+    );
+    assert_eq!(bytecode.retry(), None);
+}
+
+#[test]
+fn retry_directive_sets_program_policy() {
+    let retry = assemble(".retry 5, 250\nHALT").unwrap().retry().unwrap();
+    assert_eq!((retry.attempts, retry.backoff_ms), (5, 250));
+}
+
+#[test]
+fn rejects_malformed_directives() {
+    for source in [
+        ".idempotent\nHALT",
+        ".endidempotent\nHALT",
+        ".retry 0, 100\nHALT",
+        ".retry 3\nHALT",
+    ] {
+        assert!(assemble(source).is_err(), "{}", source);
+    }
+}
@@ -0,0 +1,67 @@
+use flock_vm::sanitize::{Access, Kind, Race};
+use flock_vm::Vm;
+
+fn races(source: &str) -> Vec<Race> {
+    let mut vm = Vm::builder().workers(1).sanitize(true).build().unwrap();
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    vm.execute(bytecode, &[]).unwrap();
+    vm.races().unwrap()
+}
+
+#[test]
+fn reports_load_racing_a_forked_store() {
+    let source = "
+  FORK
+  JMP f, $child
+  LOAD 8
+  POP
+  JOIN 0
+  HALT
+
+child:
+  POP
+  PUSH 1
+  STORE 8
+  HALT
+";
+    let races = races(source);
+    assert_eq!(races.len(), 1);
+    assert_eq!(races[0].addr, 8);
+    let mut accesses = vec![races[0].first, races[0].second];
+    accesses.sort();
+    assert_eq!(
+        accesses,
+        vec![
+            Access {
+                kind: Kind::Read,
+                pc: 2
+            },
+            Access {
+                kind: Kind::Write,
+                pc: 8
+            },
+        ]
+    );
+}
+
+#[test]
+fn fork_and_join_order_accesses() {
+    let source = "
+  PUSH 1
+  STORE 8
+  FORK
+  JMP f, $child
+  JOIN 0
+  LOAD 8
+  HALT
+
+child:
+  POP
+  LOAD 8
+  PUSH 1
+  ADD
+  STORE 8
+  HALT
+";
+    assert_eq!(races(source), vec![]);
+}
@@ -0,0 +1,67 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use flock_vm::Vm;
+
+const STATE_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+// Forks a child that counts down for a moment, then stores 1 at address 16. Nothing joins it.
+const STORING_CHILD: &str = "
+  FORK
+  JMP !f, $parent
+  PUSH 1000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  PUSH 1
+  STORE 16
+  HALT
+
+parent:
+  HALT
+";
+
+#[test]
+fn journal_is_encrypted_and_recovered_with_the_key() {
+    std::env::set_var("FLOCK_STATE_KEY", STATE_KEY);
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("sealed_journal");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal = dir.join("journal");
+
+    // Without workers, the leaf only journals the child the scheduler sends it.
+    let leaf = Vm::builder()
+        .workers(0)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .journal(&journal)
+        .build()
+        .unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(STORING_CHILD).unwrap();
+    scheduler.execute(bytecode, &[]).unwrap();
+    drop(scheduler);
+    drop(leaf);
+
+    let written = std::fs::read_to_string(&journal).unwrap();
+    assert!(written.lines().count() > 0);
+    for line in written.lines() {
+        assert!(line.bytes().all(|b| b.is_ascii_hexdigit()), "{}", line);
+    }
+
+    let restarted = Vm::builder().workers(1).journal(&journal).build().unwrap();
+    let started = Instant::now();
+    while restarted.load(16) == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "Recovered task never ran"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+use flock_vm::Vm;
+
+fn region(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("flock-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn vms_mapping_the_same_file_share_its_cells() {
+    let path = region("shared");
+    let build = || {
+        Vm::builder()
+            .workers(1)
+            .shared_memory(&path, 100, 16)
+            .build()
+            .unwrap()
+    };
+    let mut a = build();
+    let mut b = build();
+
+    let store = flock_vm::asm::assemble("PUSH 7\nSTORE 104\nPUSH 8\nSTORE 200\nHALT").unwrap();
+    a.execute(store, &[]).unwrap();
+
+    let load = flock_vm::asm::assemble("LOAD 104\nLOAD 200\nHALT").unwrap();
+    assert_eq!(b.execute(load, &[]).unwrap(), vec![7, 0]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn vms_must_agree_on_size() {
+    let path = region("size");
+    Vm::builder().shared_memory(&path, 0, 16).build().unwrap();
+
+    let err = Vm::builder()
+        .shared_memory(&path, 0, 32)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,16 @@
+use std::net::{SocketAddr, TcpListener};
+
+use flock_vm::{cluster::listen_port, Vm};
+
+#[test]
+fn dropping_vm_frees_listen_port() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], listen_port()));
+    for _ in 0..3 {
+        let mut vm = Vm::builder().listen(addr).build().unwrap();
+        let bytecode = flock_vm::asm::assemble("PUSH 1\nHALT").unwrap();
+        assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![1]);
+        drop(vm);
+
+        TcpListener::bind(addr).unwrap();
+    }
+}
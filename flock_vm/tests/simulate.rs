@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use flock_vm::Vm;
+
+const PARALLEL_FIBONACCI: &str = "
+  PUSH 18
+  FORK
+  BURY 1
+  JMP f, $fibonacci
+  POP
+  JOIN 1
+  HALT
+
+fibonacci:
+  JMP z, $fibonacci_0
+  PUSH -1
+  ADD
+  JMP z, $fibonacci_0
+  DUP
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  FORK
+  JMP f, $fibonacci_fork
+  BURY 2
+  POP
+  JOIN 1
+  DREDGE 1
+  JOIN 1
+  ADD
+  HALT
+
+fibonacci_0:
+  POP
+  PUSH 1
+  HALT
+
+fibonacci_fork:
+  POP
+  JMP $fibonacci
+";
+
+#[test]
+fn simulated_cluster_runs_forks_on_leaves_with_latency() {
+    let latency = Duration::from_millis(20);
+    let mut vm = Vm::builder()
+        .workers(3)
+        .simulate_cluster(3, latency)
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(PARALLEL_FIBONACCI).unwrap();
+
+    let started = Instant::now();
+    assert_eq!(vm.execute(bytecode, &[]).unwrap(), vec![4181]);
+    let elapsed = started.elapsed();
+
+    // Only leaves that were sent work are listed.
+    let peers = vm.handle().peer_status();
+    assert!(!peers.is_empty() && peers.len() <= 2, "{:?}", peers);
+    assert!(peers.iter().all(|p| &p.peer != vm.handle().identity()));
+    assert!(peers.iter().any(|p| p.requests > 0));
+    // Submitting a task and collecting its result each cross the network both ways.
+    assert!(elapsed >= latency * 4, "{:?}", elapsed);
+}
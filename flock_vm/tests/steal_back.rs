@@ -0,0 +1,97 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use flock_vm::Vm;
+
+// The child emits once, then counts down long enough to still be running on the leaf when the
+// parent joins it.
+const SLOW_CHILD: &str = "
+  FORK
+  JMP f, $child
+
+  ; Gives the child time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  PUSH 1
+  EMIT
+  PUSH 1000000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  HALT
+";
+
+#[test]
+fn tasks_that_arent_idempotent_are_left_to_the_peer() {
+    let local = SocketAddr::from(([127, 0, 0, 1], 0));
+    let leaf = Vm::builder().workers(1).listen(local).build().unwrap();
+
+    // The scheduler's only worker is the one joining, which would steal the child right away.
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .listen(local)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .steal_back_after(Duration::ZERO)
+        .build()
+        .unwrap();
+    let emitted = scheduler.emitted();
+    let bytecode = flock_vm::asm::assemble(SLOW_CHILD).unwrap();
+
+    assert_eq!(scheduler.execute(bytecode, &[]).unwrap(), vec![0]);
+    assert_eq!(emitted.try_iter().count(), 1);
+    assert!(leaf.handle().served_requests() > 0);
+}
+
+// Like `SLOW_CHILD`, but the child may run twice and doesn't emit.
+const IDEMPOTENT_CHILD: &str = "
+.idempotent
+  FORK
+  JMP f, $child
+.endidempotent
+
+  ; Gives the child time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  PUSH 1000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  PUSH 42
+  HALT
+";
+
+#[test]
+fn idempotent_tasks_a_stalled_peer_holds_are_stolen_back() {
+    let local = SocketAddr::from(([127, 0, 0, 1], 0));
+    // Takes the child, but has no workers to ever run it.
+    let leaf = Vm::builder().workers(0).listen(local).build().unwrap();
+
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .steal_back_after(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(IDEMPOTENT_CHILD).unwrap();
+
+    assert_eq!(scheduler.execute(bytecode, &[]).unwrap(), vec![42]);
+    assert_eq!(leaf.handle().served_requests(), 0);
+}
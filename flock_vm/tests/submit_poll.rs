@@ -0,0 +1,117 @@
+use std::net::SocketAddr;
+
+use flock_vm::Vm;
+
+const CHILDREN: usize = 8;
+
+// Forks children 7 down to 0, each doubling its number after counting down, and sums what they
+// return.
+fn doubling_children() -> String {
+    format!(
+        "
+  PUSH {}
+fork:
+  PUSH -1
+  ADD
+  FORK
+  JMP f, $child
+  BURY 1
+  JMP !z, $fork
+  POP
+
+  ; Gives the children time to reach the leaf.
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+
+  JOIN 1
+{}
+  HALT
+
+child:
+  POP
+  MULI 2
+  PUSH 100000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  HALT
+",
+        CHILDREN,
+        "  BURY 1\n  JOIN 1\n  ADD\n".repeat(CHILDREN - 1)
+    )
+}
+
+#[test]
+fn more_tasks_than_the_peer_has_workers_are_in_flight_at_once() {
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .unwrap();
+    let mut scheduler = Vm::builder()
+        .workers(0)
+        .peers(vec![leaf.listen_addr().unwrap().to_string()])
+        .build()
+        .unwrap();
+    let bytecode = flock_vm::asm::assemble(&doubling_children()).unwrap();
+
+    let sum = (0..CHILDREN as i64).map(|i| i * 2).sum::<i64>();
+    assert_eq!(scheduler.execute(bytecode, &[]).unwrap(), vec![sum]);
+    assert_eq!(leaf.handle().served_requests(), CHILDREN);
+}
+
+// Forks a child that multiplies the input by `factor`, after giving it time to reach the leaf.
+fn multiplied_by_a_child(factor: i64) -> String {
+    format!(
+        "
+  FORK
+  JMP f, $child
+
+  PUSH 100000
+wait:
+  PUSH -1
+  ADD
+  JMP !z, $wait
+  POP
+  JOIN 1
+  HALT
+
+child:
+  POP
+  MULI {}
+  PUSH 100000
+count:
+  PUSH -1
+  ADD
+  JMP !z, $count
+  POP
+  HALT
+",
+        factor
+    )
+}
+
+#[test]
+fn each_program_sent_to_a_peer_runs_its_own_bytecode() {
+    let leaf = Vm::builder()
+        .workers(1)
+        .listen(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .unwrap();
+    let peers = vec![leaf.listen_addr().unwrap().to_string()];
+    let scheduler = || Vm::builder().workers(0).peers(peers.clone()).build();
+    let (mut first, mut second) = (scheduler().unwrap(), scheduler().unwrap());
+
+    let doubling = flock_vm::asm::assemble(&multiplied_by_a_child(2)).unwrap();
+    let tripling = flock_vm::asm::assemble(&multiplied_by_a_child(3)).unwrap();
+    assert_eq!(first.execute(doubling, &[5]).unwrap(), vec![5, 10]);
+    assert_eq!(first.execute(tripling.clone(), &[5]).unwrap(), vec![5, 15]);
+    assert_eq!(second.execute(tripling, &[7]).unwrap(), vec![7, 21]);
+    assert_eq!(leaf.handle().served_requests(), 3);
+}
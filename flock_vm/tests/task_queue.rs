@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use flock_vm::task_queue::{ControlFlow, Handle, TaskQueue};
+use flock_vm::QueueOrder;
+
+fn queue() -> TaskQueue<i32> {
+    TaskQueue::new(QueueOrder::Lifo, Duration::from_secs(60))
+}
+
+/// Pushes 0 to 7. Pushing spills the oldest half of a handle's items to the shared pool while it
+/// holds more than twice what's there, so 0 to 3 end up shared and 4 to 7 stay with `handle`.
+fn push_eight(handle: &mut Handle<i32>) {
+    for i in 0..8 {
+        handle.push(i);
+    }
+}
+
+/// Takes items until `handle` finds nothing for a few tries in a row.
+fn take_all(handle: &mut Handle<i32>) -> Vec<i32> {
+    let mut taken = Vec::new();
+    let mut misses = 0;
+    while misses < 3 {
+        match handle.next() {
+            ControlFlow::Continue(i) => {
+                taken.push(i);
+                misses = 0;
+            }
+            ControlFlow::Retry => misses += 1,
+            ControlFlow::Finish => panic!("Queue finished"),
+        }
+    }
+    taken
+}
+
+#[test]
+fn idle_worker_takes_work_from_a_busy_one() {
+    let queue = queue();
+    let mut busy = queue.worker_handle();
+    push_eight(&mut busy);
+
+    let mut idle = queue.worker_handle();
+    let mut taken = take_all(&mut idle);
+    taken.sort_unstable();
+    assert_eq!(taken, (0..8).collect::<Vec<_>>());
+    assert!(matches!(busy.next(), ControlFlow::Retry));
+}
+
+#[test]
+fn steals_prefer_the_matching_item() {
+    let queue = queue();
+    let mut busy = queue.worker_handle();
+    push_eight(&mut busy);
+
+    let mut idle = queue.worker_handle();
+    let shared = (0..4)
+        .map(|_| match idle.next() {
+            ControlFlow::Continue(i) => i,
+            other => panic!("{:?}", other),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(shared, vec![0, 1, 2, 3]);
+
+    // The oldest half of `busy`'s items, 4 and 5, are stolen before one is picked.
+    assert!(matches!(
+        idle.next_preferring(|i| *i == 5),
+        ControlFlow::Continue(5)
+    ));
+    assert!(matches!(idle.next(), ControlFlow::Continue(4)));
+    assert!(matches!(busy.next(), ControlFlow::Continue(7)));
+}
+
+#[test]
+fn items_of_handles_other_than_workers_are_not_stolen() {
+    let queue = queue();
+    let mut other = queue.handle();
+    for i in 0..3 {
+        other.push(i);
+    }
+
+    let mut worker = queue.worker_handle();
+    assert_eq!(take_all(&mut worker), vec![0]);
+    assert_eq!(take_all(&mut other), vec![2, 1]);
+}
+
+#[test]
+fn items_pushed_for_workers_are_only_taken_by_workers() {
+    let queue = queue();
+    let mut other = queue.handle();
+    other.push_for_workers(1);
+    other.push_nonworker(2);
+    assert_eq!(other.pending(), 2);
+
+    assert_eq!(take_all(&mut other), vec![2]);
+    let mut worker = queue.worker_handle();
+    assert_eq!(take_all(&mut worker), vec![1]);
+}
+
+#[test]
+fn finish_reaches_every_worker_while_they_steal() {
+    let queue = queue();
+    let mut busy = queue.worker_handle();
+    push_eight(&mut busy);
+
+    let workers = (0..2)
+        .map(|_| {
+            let mut idle = queue.worker_handle();
+            std::thread::spawn(move || {
+                let mut taken = Vec::new();
+                loop {
+                    match idle.next() {
+                        ControlFlow::Continue(i) => taken.push(i),
+                        ControlFlow::Retry => {}
+                        ControlFlow::Finish => return taken,
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut taken = queue.finish(|| {
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    taken.extend(take_all(&mut busy));
+    taken.sort_unstable();
+    assert_eq!(taken, (0..8).collect::<Vec<_>>());
+}
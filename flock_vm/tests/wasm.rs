@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use flock_vm::asm::wasm::{to_wasm, WasmError};
+use flock_vm::Vm;
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+#[derive(Default)]
+struct Host {
+    memory: HashMap<i64, i64>,
+    emitted: Vec<i64>,
+}
+
+/// Runs the lowered program, returning its halt code and stack, or `None` if it trapped.
+fn run_wasm(source: &str) -> (Option<(i64, Vec<i64>)>, Host) {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    let bytes = to_wasm(&bytecode).unwrap();
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &bytes[..]).unwrap();
+    let mut store = Store::new(&engine, Host::default());
+    let mut linker = <Linker<Host>>::new(&engine);
+    linker
+        .func_wrap("flock", "load", |caller: Caller<'_, Host>, addr: i64| {
+            caller.data().memory.get(&addr).cloned().unwrap_or(0)
+        })
+        .unwrap();
+    linker
+        .func_wrap(
+            "flock",
+            "store",
+            |mut caller: Caller<'_, Host>, addr: i64, value: i64| {
+                caller.data_mut().memory.insert(addr, value);
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "flock",
+            "emit",
+            |mut caller: Caller<'_, Host>, value: i64| {
+                caller.data_mut().emitted.push(value);
+            },
+        )
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+
+    let run = instance.get_typed_func::<(), i64>(&store, "run").unwrap();
+    let result = run.call(&mut store, ()).ok().map(|code| {
+        let len = instance
+            .get_typed_func::<(), i32>(&store, "stack_len")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+        let get = instance
+            .get_typed_func::<i32, i64>(&store, "stack_get")
+            .unwrap();
+        let stack = (0..len).map(|i| get.call(&mut store, i).unwrap()).collect();
+        (code, stack)
+    });
+    (result, store.into_data())
+}
+
+fn run_vm(source: &str) -> Result<Vec<i64>, String> {
+    let bytecode = flock_vm::asm::assemble(source).unwrap();
+    Vm::create_leaf()
+        .execute(bytecode, &[])
+        .map_err(|e| e.to_string())
+}
+
+/// Runs `source` both ways, expecting the same stack, or both to fail.
+fn agrees(source: &str) -> Vec<i64> {
+    let (wasm, _) = run_wasm(source);
+    match (run_vm(source), wasm) {
+        (Ok(stack), Some((0, wasm_stack))) => {
+            assert_eq!(wasm_stack, stack, "{}", source);
+            stack
+        }
+        (Err(_), None) => vec![],
+        (vm, wasm) => panic!("VM {:?}, WebAssembly {:?}:\n{}", vm, wasm, source),
+    }
+}
+
+#[test]
+fn loops_and_subroutines_match_the_vm() {
+    let source = "
+  PUSH 10
+  PUSH 0
+  STORE 7
+
+loop:
+  DUP
+  JSR $square
+  LOAD 7
+  ADD
+  STORE 7
+  ADDI -1
+  JMP !z, $loop
+
+  POP
+  LOAD 7
+  PUSH 3
+  PUSH 4
+  BURY 2
+  DREDGE 1
+  HALT
+
+square:
+  DREDGE 1
+  DUP
+  MUL
+  DREDGE 1
+  RET
+";
+    assert_eq!(agrees(source), vec![4, 3, 385]);
+}
+
+#[test]
+fn arithmetic_matches_the_vm() {
+    let source = "
+  PUSH 9223372036854775807
+  PUSH 1
+  ADD_CHECKED
+  PUSH -9223372036854775808
+  PUSH -1
+  ADD_SAT
+  PUSH 4611686018427387904
+  PUSH -4
+  MUL_CHECKED
+  PUSH -3
+  PUSH 9223372036854775807
+  MUL_SAT
+  PUSH -7
+  PUSH 2
+  DIV
+  PUSH 6
+  MULI -7
+  PUSH -1
+  PUSH 61
+  SHR
+  PUSH 5
+  PUSH 3
+  XOR
+  NOT
+  PUSH 9223372036854775807
+  ADDI 1
+  PUSH -9223372036854775808
+  PUSH -1
+  DIV
+  PUSH 3037000500
+  DUP
+  MUL
+  HALT
+";
+    agrees(source);
+
+    let compares = "
+  PUSH 1
+  PUSH 2
+  CMP
+  JMP c, $carried
+  PANIC
+carried:
+  PUSH -9223372036854775808
+  PUSH 1
+  CMP
+  JMP on, $overflowed
+  PANIC
+overflowed:
+  IS_CHILD
+  JMP f, $forked
+  HALT
+forked:
+  PANIC
+";
+    assert_eq!(agrees(compares), vec![-1, -1, 0]);
+}
+
+#[test]
+fn fails_where_the_vm_fails() {
+    for source in [
+        "PUSH 1\nPUSH 0\nDIV\nHALT",
+        "PUSH 1\nPUSH 64\nSHL\nHALT",
+        "POP\nHALT",
+        "PUSH 1\nDREDGE 1\nHALT",
+        "PUSH 1\nBURY 2\nHALT",
+        "PUSH 1\nPUSH 2\nASSERT_EQ\nHALT",
+        "PUSH 1\nASSERT_DEPTH 2\nHALT",
+        "PUSH 99\nRET",
+        "PANIC",
+    ] {
+        let (wasm, _) = run_wasm(source);
+        assert!(run_vm(source).is_err(), "{}", source);
+        assert_eq!(wasm, None, "{}", source);
+    }
+}
+
+#[test]
+fn imports_memory_and_emit() {
+    let source = "
+  PUSH 5
+  PUSH 2
+  STORE_REL 100
+  PUSH 2
+  LOAD_REL 100
+  DUP
+  EMIT
+  HALT 3
+";
+    let (result, host) = run_wasm(source);
+    assert_eq!(result, Some((3, vec![5])));
+    assert_eq!(host.memory.get(&102), Some(&5));
+    assert_eq!(host.emitted, vec![5]);
+}
+
+#[test]
+fn rejects_what_it_cannot_lower() {
+    let bytecode = flock_vm::asm::assemble("PUSH 1\nFORK\nHALT").unwrap();
+    assert_eq!(
+        to_wasm(&bytecode).unwrap_err(),
+        WasmError::Unsupported(1, "FORK")
+    );
+}